@@ -2,13 +2,54 @@ tonic::include_proto!("izdu");
 
 pub use image_processor_server::ImageProcessorServer;
 
+// A batch slice op carries the same fields as the unary request.
+impl From<SliceOp> for SliceRequest {
+    fn from(op: SliceOp) -> Self {
+        SliceRequest {
+            source: op.source,
+            scale: op.scale,
+            watermark: op.watermark,
+            invisible: op.invisible,
+            qr: op.qr,
+            tile: op.tile,
+            adjust: op.adjust,
+            tile_adjust: op.tile_adjust,
+            effects: op.effects,
+            preset: op.preset,
+            remainder: op.remainder,
+        }
+    }
+}
+
 pub mod server {
     use crate::image_processor;
-    use crate::image_processor::image_slicer;
-    use crate::image_processor::watermark;
+    use crate::image_processor::adjust::{AdjustArgs, Adjustments, TileAdjustments};
+    use crate::image_processor::atlas::{self, AtlasArgs, AtlasSpec};
+    use crate::image_processor::blend::Blend;
+    use crate::image_processor::carousel::{CarouselArgs, CarouselSpec};
+    use crate::image_processor::contact_sheet::{SheetArgs, SheetEntry, SheetSpec};
+    use crate::image_processor::effects::{EffectArgs, Effects};
+    use crate::image_processor::image_slicer::{Remainder, TileScale, GRID_COLS};
+    use crate::image_processor::invisible;
+    use crate::image_processor::pdf::{self, PdfArgs, PdfSpec};
+    use crate::image_processor::pipeline::{self, Op, Output, Pipeline, SliceOps, Step};
+    use crate::image_processor::poster::{PosterArgs, PosterSpec};
+    use crate::image_processor::presets::{self, Preset};
+    use crate::image_processor::qr::QrSpec;
+    use crate::image_processor::resize::{self, Rect, ResizeArgs, ResizeParams};
+    use crate::image_processor::stitch::{self, StitchArgs, StitchSpec};
+    use crate::image_processor::text::{self, TextStyle, TileContext};
+    use crate::image_processor::transform::Transform;
+    use crate::image_processor::variants::{self, VariantSpec};
+    use crate::image_processor::watermark::{
+        transparency_from, Placement, WatermarkContent, WatermarkSpec,
+    };
+    use crate::image_processor::LogoSource;
     use bytes::Bytes;
     use image::ImageBuffer;
+    use std::fmt::Display;
     use std::pin::Pin;
+    use std::str::FromStr;
     use tokio::sync::mpsc;
     use tokio_stream::wrappers::ReceiverStream;
     use tokio_stream::StreamExt;
@@ -19,16 +60,35 @@ pub mod server {
     use super::image_processor_server::ImageProcessor;
     use super::image_source::Source as ProtoSource;
     use super::operation::Op as ProtoOp;
+    use super::process_step::Op as ProtoStepOp;
     use super::{
+        AdjustConfig as ProtoAdjustConfig, AtlasConfig as ProtoAtlasConfig,
+        AtlasRequest as ProtoAtlasRequest, AtlasResponse as ProtoAtlasResponse,
         BatchRequest as ProtoBatchRequest, BatchResponse as ProtoBatchResponse,
-        BatchSliceResult as ProtoBatchSliceResult, ImageSource as ProtoImageSource,
-        ResizeRequest as ProtoResizeRequest, ResizeResponse as ProtoResizeResponse,
-        SliceRequest as ProtoSliceRequest, SliceResponse as ProtoSliceResponse,
+        BatchSliceResult as ProtoBatchSliceResult, CarouselConfig as ProtoCarouselConfig,
+        CarouselRequest as ProtoCarouselRequest, CarouselResponse as ProtoCarouselResponse,
+        CarouselSlide as ProtoCarouselSlide, ContactSheetRequest as ProtoContactSheetRequest,
+        ContactSheetResponse as ProtoContactSheetResponse, DetectRequest as ProtoDetectRequest,
+        DetectResponse as ProtoDetectResponse, EffectsConfig as ProtoEffectsConfig,
+        ImageSource as ProtoImageSource, PdfChunk as ProtoPdfChunk, PdfConfig as ProtoPdfConfig,
+        PosterConfig as ProtoPosterConfig, PosterPage as ProtoPosterPage,
+        PosterRequest as ProtoPosterRequest, PosterResponse as ProtoPosterResponse,
+        ProcessRequest as ProtoProcessRequest, ProcessResponse as ProtoProcessResponse,
+        ProcessStep as ProtoProcessStep, ProcessedImage as ProtoProcessedImage,
+        QrConfig as ProtoQrConfig, QrRequest as ProtoQrRequest, QrResponse as ProtoQrResponse,
+        Rect as ProtoRect, ResizeRequest as ProtoResizeRequest,
+        ResizeResponse as ProtoResizeResponse, SheetConfig as ProtoSheetConfig,
+        SlicePdfRequest as ProtoSlicePdfRequest, SliceRequest as ProtoSliceRequest,
+        SliceResponse as ProtoSliceResponse, StitchConfig as ProtoStitchConfig,
+        StitchRequest as ProtoStitchRequest, StitchResponse as ProtoStitchResponse,
+        TransformResponse as ProtoTransformResponse, Variant as ProtoVariant,
+        VariantsRequest as ProtoVariantsRequest, VariantsResponse as ProtoVariantsResponse,
         WatermarkConfig as ProtoWatermarkConfig, WatermarkRequest as ProtoWatermarkRequest,
         WatermarkResponse as ProtoWatermarkResponse,
     };
 
     // Convert proto ImageSource to our internal ImageSource
+    #[allow(clippy::result_large_err)]
     fn proto_to_image_source(
         src: Option<ProtoImageSource>,
     ) -> Result<crate::image_processor::ImageSource, Status> {
@@ -50,26 +110,710 @@ pub mod server {
         Ok(Bytes::from(buf))
     }
 
-    fn decode_wm_config(wm: Option<ProtoWatermarkConfig>, default_text: &str) -> (String, u32) {
-        wm.map(|w| (w.text, w.transparency))
-            .unwrap_or_else(|| (default_text.to_string(), 30))
+    // Resolve a proto watermark config into a spec. `None` means "no watermark":
+    // empty text and no logo.
+    async fn decode_wm_config(
+        wm: Option<ProtoWatermarkConfig>,
+        default_text: &str,
+    ) -> Result<Option<WatermarkSpec>, String> {
+        let Some(wm) = wm else {
+            if default_text.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(WatermarkSpec {
+                content: WatermarkContent::Text(default_text.to_string(), TextStyle::default()),
+                transparency: 30,
+                placement: Placement::default(),
+                blend: Blend::default(),
+            }));
+        };
+
+        let placement = Placement::parse(Some(&wm.position), Some(wm.scale), Some(&wm.scope))
+            .map_err(|e| e.to_string())?;
+        let style = TextStyle::parse(Some(&wm.align), Some(wm.line_spacing), Some(&wm.font))
+            .map_err(|e| e.to_string())?;
+        let blend =
+            Blend::parse(Some(&wm.blend), Some(wm.linear_light)).map_err(|e| e.to_string())?;
+        // Proto3 can't tell an unset transparency from 0, so only an explicit
+        // opacity takes precedence over it.
+        let transparency = match wm.opacity {
+            Some(o) => transparency_from(Some(o.min(100) as u16), None),
+            None => Ok(wm.transparency.min(100) as u16),
+        }
+        .map_err(|e| e.to_string())?;
+        let logo = match (wm.logo, wm.logo_name.is_empty()) {
+            (Some(src), _) => Some(LogoSource::Image(
+                proto_to_image_source(Some(src)).map_err(|e| e.message().to_string())?,
+            )),
+            (None, false) => Some(LogoSource::Named(wm.logo_name)),
+            (None, true) => None,
+        };
+        let content = match logo {
+            Some(logo) => WatermarkContent::Logo(
+                image_processor::load_logo(logo)
+                    .await
+                    .map_err(|e| format!("Error loading logo: {}", e))?,
+            ),
+            None if wm.text.is_empty() => return Ok(None),
+            None => WatermarkContent::Text(wm.text, style),
+        };
+        Ok(Some(WatermarkSpec {
+            content,
+            transparency,
+            placement,
+            blend,
+        }))
+    }
+
+    fn decode_qr_config(qr: Option<ProtoQrConfig>) -> Result<Option<QrSpec>, String> {
+        match qr {
+            Some(qr) if !qr.data.is_empty() => QrSpec::parse(
+                &qr.data,
+                Some(qr.size),
+                Some(&qr.position),
+                Some(&qr.ec_level),
+                qr.quiet_zone,
+                Some(&qr.symbology),
+            )
+            .map(Some)
+            .map_err(|e| e.to_string()),
+            _ => Ok(None),
+        }
+    }
+
+    // Quality fields are uint32 in the proto; anything past u8 is reported as sent.
+    fn proto_quality(quality: u32) -> Result<u8, String> {
+        u8::try_from(quality).map_err(|_| format!("quality must be 1-100, got {}", quality))
+    }
+
+    fn proto_rect(r: Rect) -> ProtoRect {
+        ProtoRect {
+            x: r.x,
+            y: r.y,
+            width: r.width,
+            height: r.height,
+        }
+    }
+
+    fn resize_args(r: &super::ResizeConfig) -> ResizeArgs<'_> {
+        ResizeArgs {
+            width: Some(r.width),
+            height: Some(r.height),
+            aspect_ratio: Some(&r.aspect_ratio),
+            fit: Some(&r.fit),
+            gravity: Some(&r.gravity),
+            background: Some(&r.background),
+            filter: Some(&r.filter),
+            allow_upscale: r.allow_upscale,
+            upscale_quality: Some(&r.upscale_quality),
+        }
+    }
+
+    fn decode_resize_config(resize: Option<super::ResizeConfig>) -> Result<ResizeParams, String> {
+        let r = resize.unwrap_or_default();
+        ResizeParams::from_args(&resize_args(&r)).map_err(|e| e.to_string())
+    }
+
+    fn adjust_args(a: &ProtoAdjustConfig) -> AdjustArgs<'_> {
+        AdjustArgs {
+            brightness: a.brightness,
+            contrast: a.contrast,
+            gamma: a.gamma,
+            saturation: a.saturation,
+            hue: a.hue,
+            grayscale: Some(a.grayscale),
+            sepia: a.sepia,
+            invert: Some(a.invert),
+            tint: Some(&a.tint),
+        }
+    }
+
+    fn decode_adjust_config(
+        global: Option<ProtoAdjustConfig>,
+        tiles: Vec<ProtoAdjustConfig>,
+    ) -> Result<TileAdjustments, String> {
+        let global = Adjustments::from_args(&adjust_args(&global.unwrap_or_default()))
+            .map_err(|e| e.to_string())?;
+        let tiles = tiles
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                Adjustments::from_args(&adjust_args(t))
+                    .map_err(|e| format!("Tile {} adjustments: {}", i, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        TileAdjustments::new(global, tiles).map_err(|e| e.to_string())
+    }
+
+    fn decode_effects_config(effects: Option<ProtoEffectsConfig>) -> Result<Effects, String> {
+        let e = effects.unwrap_or_default();
+        Effects::from_args(&EffectArgs {
+            blur: e.blur,
+            sharpen: e.sharpen,
+            unsharp_sigma: e.unsharp_sigma,
+            unsharp_amount: e.unsharp_amount,
+            unsharp_threshold: e.unsharp_threshold,
+            pixelate: e.pixelate,
+        })
+        .map_err(|e| e.to_string())
+    }
+
+    // Fills proto fields from a preset, by their HTTP param names. A field the
+    // request set itself (non-default: proto3 can't tell otherwise) is an
+    // override, which the preset has to allow.
+    struct PresetFill(&'static Preset);
+
+    impl PresetFill {
+        fn from_name(name: &str) -> Result<Option<PresetFill>, String> {
+            if name.is_empty() {
+                return Ok(None);
+            }
+            presets::get(name)
+                .map(|p| Some(PresetFill(p)))
+                .map_err(|e| e.to_string())
+        }
+
+        fn parse<T>(&self, param: &str, value: &str) -> Result<T, String>
+        where
+            T: FromStr,
+            T::Err: Display,
+        {
+            value.parse().map_err(|e| {
+                format!(
+                    "Preset {}: invalid {} {:?}: {}",
+                    self.0.name, param, value, e
+                )
+            })
+        }
+
+        fn field<T>(&self, param: &str, field: &mut T) -> Result<(), String>
+        where
+            T: FromStr + Default + PartialEq,
+            T::Err: Display,
+        {
+            match self.0.param(param) {
+                Some(_) if *field != T::default() => {
+                    self.0.check_override(param).map_err(|e| e.to_string())
+                }
+                Some(value) => {
+                    *field = self.parse(param, value)?;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+
+        fn optional<T>(&self, param: &str, field: &mut Option<T>) -> Result<(), String>
+        where
+            T: FromStr,
+            T::Err: Display,
+        {
+            match self.0.param(param) {
+                Some(_) if field.is_some() => {
+                    self.0.check_override(param).map_err(|e| e.to_string())
+                }
+                Some(value) => {
+                    *field = Some(self.parse(param, value)?);
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+
+        // Fill a sub-message, leaving it unset if neither side sets anything.
+        fn message<M: Default + PartialEq>(
+            &self,
+            message: &mut Option<M>,
+            fill: impl FnOnce(&Self, &mut M) -> Result<(), String>,
+        ) -> Result<(), String> {
+            let was_set = message.is_some();
+            let mut m = message.take().unwrap_or_default();
+            fill(self, &mut m)?;
+            if was_set || m != M::default() {
+                *message = Some(m);
+            }
+            Ok(())
+        }
+
+        fn resize(
+            &self,
+            (width, height): (&str, &str),
+            r: &mut super::ResizeConfig,
+        ) -> Result<(), String> {
+            self.field(width, &mut r.width)?;
+            self.field(height, &mut r.height)?;
+            self.field("fit", &mut r.fit)?;
+            self.field("gravity", &mut r.gravity)?;
+            self.field("background", &mut r.background)?;
+            self.field("filter", &mut r.filter)?;
+            self.optional("allow_upscale", &mut r.allow_upscale)?;
+            self.field("upscale_quality", &mut r.upscale_quality)
+        }
+
+        fn watermark(&self, text: &str, w: &mut ProtoWatermarkConfig) -> Result<(), String> {
+            self.field(text, &mut w.text)?;
+            self.field("logo", &mut w.logo_name)?;
+            self.field("transparency", &mut w.transparency)?;
+            self.optional("opacity", &mut w.opacity)?;
+            self.field("position", &mut w.position)?;
+            self.field("wm_scale", &mut w.scale)?;
+            self.field("align", &mut w.align)?;
+            self.field("line_spacing", &mut w.line_spacing)?;
+            self.field("font", &mut w.font)?;
+            self.field("blend", &mut w.blend)?;
+            self.field("linear_light", &mut w.linear_light)
+        }
+
+        fn qr(&self, q: &mut ProtoQrConfig) -> Result<(), String> {
+            self.field("qr", &mut q.data)?;
+            self.field("qr_size", &mut q.size)?;
+            self.field("qr_position", &mut q.position)?;
+            self.field("qr_ec", &mut q.ec_level)?;
+            self.optional("qr_quiet_zone", &mut q.quiet_zone)?;
+            self.field("qr_symbology", &mut q.symbology)
+        }
+
+        fn adjust(&self, a: &mut ProtoAdjustConfig) -> Result<(), String> {
+            self.optional("brightness", &mut a.brightness)?;
+            self.optional("contrast", &mut a.contrast)?;
+            self.optional("gamma", &mut a.gamma)?;
+            self.optional("saturation", &mut a.saturation)?;
+            self.optional("hue", &mut a.hue)?;
+            self.field("grayscale", &mut a.grayscale)?;
+            self.optional("sepia", &mut a.sepia)?;
+            self.field("invert", &mut a.invert)?;
+            self.field("tint", &mut a.tint)
+        }
+
+        fn carousel(&self, c: &mut ProtoCarouselConfig) -> Result<(), String> {
+            self.field("aspect", &mut c.aspect)?;
+            self.field("slides", &mut c.slides)?;
+            self.field("fit", &mut c.fit)?;
+            self.field("gravity", &mut c.gravity)?;
+            self.field("background", &mut c.background)?;
+            self.field("filter", &mut c.filter)?;
+            self.field("width", &mut c.width)?;
+            self.field("format", &mut c.format)?;
+            self.field("quality", &mut c.quality)
+        }
+
+        fn atlas(&self, c: &mut ProtoAtlasConfig) -> Result<(), String> {
+            self.field("packer", &mut c.packer)?;
+            self.optional("padding", &mut c.padding)?;
+            self.field("pot", &mut c.pot)?;
+            self.field("trim", &mut c.trim)?;
+            self.field("max_size", &mut c.max_size)?;
+            self.field("json", &mut c.json)?;
+            self.field("name", &mut c.name)
+        }
+
+        fn sheet(&self, c: &mut ProtoSheetConfig) -> Result<(), String> {
+            self.field("cols", &mut c.cols)?;
+            self.field("cell_width", &mut c.cell_width)?;
+            self.field("cell_height", &mut c.cell_height)?;
+            self.optional("gutter", &mut c.gutter)?;
+            self.field("background", &mut c.background)?;
+            self.field("fit", &mut c.fit)?;
+            self.field("filter", &mut c.filter)?;
+            self.field("caption", &mut c.caption)?;
+            self.field("caption_size", &mut c.caption_size)?;
+            self.field("caption_color", &mut c.caption_color)?;
+            self.field("font", &mut c.font)?;
+            self.field("format", &mut c.format)?;
+            self.field("quality", &mut c.quality)
+        }
+
+        fn stitch(&self, c: &mut ProtoStitchConfig) -> Result<(), String> {
+            self.field("grid", &mut c.grid)?;
+            self.field("overlap", &mut c.overlap)?;
+            self.field("bezel", &mut c.bezel)?;
+            self.field("background", &mut c.background)?;
+            self.field("format", &mut c.format)?;
+            self.field("quality", &mut c.quality)
+        }
+
+        fn pdf(&self, c: &mut ProtoPdfConfig) -> Result<(), String> {
+            self.field("paper", &mut c.paper)?;
+            self.field("orientation", &mut c.orientation)?;
+            self.optional("margin", &mut c.margin)?;
+            self.field("marks", &mut c.marks)?;
+            self.field("captions", &mut c.captions)?;
+            self.field("compression", &mut c.compression)?;
+            self.field("quality", &mut c.quality)
+        }
+
+        fn poster(&self, c: &mut ProtoPosterConfig) -> Result<(), String> {
+            self.field("paper", &mut c.paper)?;
+            self.field("orientation", &mut c.orientation)?;
+            self.field("dpi", &mut c.dpi)?;
+            self.optional("margin", &mut c.margin)?;
+            self.field("overlap", &mut c.overlap)?;
+            self.field("marks", &mut c.marks)?;
+            self.field("labels", &mut c.labels)?;
+            self.field("format", &mut c.format)?;
+            self.field("quality", &mut c.quality)
+        }
+
+        fn effects(&self, e: &mut ProtoEffectsConfig) -> Result<(), String> {
+            self.optional("blur", &mut e.blur)?;
+            self.optional("sharpen", &mut e.sharpen)?;
+            self.optional("unsharp_sigma", &mut e.unsharp_sigma)?;
+            self.optional("unsharp_amount", &mut e.unsharp_amount)?;
+            self.optional("unsharp_threshold", &mut e.unsharp_threshold)?;
+            self.optional("pixelate", &mut e.pixelate)
+        }
+    }
+
+    fn fill_resize_preset(
+        preset: &str,
+        resize: &mut Option<super::ResizeConfig>,
+    ) -> Result<(), String> {
+        let Some(p) = PresetFill::from_name(preset)? else {
+            return Ok(());
+        };
+        p.message(resize, |p, r| {
+            p.field("aspect_ratio", &mut r.aspect_ratio)?;
+            p.resize(("width", "height"), r)
+        })
+    }
+
+    fn fill_watermark_preset(
+        preset: &str,
+        wm: &mut Option<ProtoWatermarkConfig>,
+    ) -> Result<(), String> {
+        let Some(p) = PresetFill::from_name(preset)? else {
+            return Ok(());
+        };
+        p.message(wm, |p, w| p.watermark("text", w))
+    }
+
+    fn process_step_name(op: &ProtoStepOp) -> &'static str {
+        match op {
+            ProtoStepOp::Crop(_) => "crop",
+            ProtoStepOp::Rotate(_) => "rotate",
+            ProtoStepOp::Flip(_) => "flip",
+            ProtoStepOp::Pad(_) => "pad",
+            ProtoStepOp::Trim(_) => "trim",
+            ProtoStepOp::Resize(_) => "resize",
+            ProtoStepOp::Adjust(_) => "adjust",
+            ProtoStepOp::Effects(_) => "effects",
+            ProtoStepOp::Watermark(_) => "watermark",
+            ProtoStepOp::Qr(_) => "qr",
+            ProtoStepOp::Invisible(_) => "invisible",
+            ProtoStepOp::Slice(_) => "slice",
+            ProtoStepOp::Encode(_) => "encode",
+        }
+    }
+
+    async fn decode_process_op(op: ProtoStepOp) -> Result<Op, String> {
+        let background = |bg: &str| (!bg.is_empty()).then_some(bg.to_owned());
+        let transform = match op {
+            ProtoStepOp::Rotate(cfg) => {
+                let bg = background(&cfg.background);
+                Transform::rotate(cfg.degrees, bg.as_deref())
+            }
+            ProtoStepOp::Flip(cfg) => {
+                Transform::flip((!cfg.direction.is_empty()).then_some(cfg.direction.as_str()))
+            }
+            ProtoStepOp::Crop(cfg) => Transform::crop(cfg.x, cfg.y, cfg.width, cfg.height),
+            ProtoStepOp::Pad(cfg) => {
+                let bg = background(&cfg.background);
+                let sides = [cfg.top, cfg.right, cfg.bottom, cfg.left].map(Some);
+                Transform::pad(None, sides, bg.as_deref())
+            }
+            ProtoStepOp::Trim(cfg) => match cfg.threshold.map(u8::try_from).transpose() {
+                Ok(threshold) => Ok(Transform::trim(threshold)),
+                Err(_) => Err(anyhow::Error::msg("Trim threshold must be 0-255")),
+            },
+            ProtoStepOp::Resize(cfg) => return decode_resize_config(Some(cfg)).map(Op::Resize),
+            ProtoStepOp::Adjust(cfg) => {
+                return Adjustments::from_args(&adjust_args(&cfg))
+                    .map(Op::Adjust)
+                    .map_err(|e| e.to_string())
+            }
+            ProtoStepOp::Effects(cfg) => return decode_effects_config(Some(cfg)).map(Op::Effects),
+            ProtoStepOp::Watermark(cfg) => {
+                return decode_wm_config(Some(cfg), "")
+                    .await?
+                    .map(Op::Watermark)
+                    .ok_or_else(|| "watermark text or logo required".into())
+            }
+            ProtoStepOp::Qr(cfg) => {
+                return decode_qr_config(Some(cfg))?
+                    .map(Op::Qr)
+                    .ok_or_else(|| "QR code data required".into())
+            }
+            ProtoStepOp::Invisible(payload) => {
+                return pipeline::invisible_op(payload).map_err(|e| e.to_string())
+            }
+            ProtoStepOp::Slice(cfg) => {
+                return Remainder::parse(Some(&cfg.remainder))
+                    .map(Op::Slice)
+                    .map_err(|e| e.to_string())
+            }
+            ProtoStepOp::Encode(cfg) => {
+                let quality = proto_quality(cfg.quality)?;
+                return pipeline::encode_op(Some(&cfg.format), Some(quality))
+                    .map_err(|e| e.to_string());
+            }
+        };
+        transform.map(Op::Transform).map_err(|e| e.to_string())
+    }
+
+    // Steps from the request, or from the preset's `steps` (JSON, as for /process).
+    async fn decode_process_steps(
+        steps: Vec<ProtoProcessStep>,
+        preset: &str,
+    ) -> Result<Pipeline, String> {
+        if let Some(p) = PresetFill::from_name(preset)? {
+            match p.0.param("steps") {
+                Some(json) if steps.is_empty() => {
+                    let steps: Vec<serde_json::Value> = serde_json::from_str(json)
+                        .map_err(|e| format!("Preset {}: invalid steps: {}", p.0.name, e))?;
+                    return Pipeline::from_json(&steps, None).map_err(|e| e.to_string());
+                }
+                Some(_) => p.0.check_override("steps").map_err(|e| e.to_string())?,
+                None => {}
+            }
+        }
+        let mut decoded = Vec::with_capacity(steps.len());
+        for (i, step) in steps.into_iter().enumerate() {
+            let Some(op) = step.op else {
+                return Err(pipeline::step_error(i, "?", "no operation").to_string());
+            };
+            let name = process_step_name(&op);
+            let op = decode_process_op(op)
+                .await
+                .map_err(|e| pipeline::step_error(i, name, e).to_string())?;
+            decoded.push(Step {
+                op,
+                tile: step.tile.map(|t| t as usize),
+            });
+        }
+        Pipeline::new(decoded).map_err(|e| e.to_string())
     }
 
-    fn decode_resize_config(
-        resize: Option<super::ResizeConfig>,
-    ) -> (Option<u32>, Option<u32>, String) {
-        resize
-            .map(|r| {
-                let ar = if r.aspect_ratio.is_empty() {
-                    "preserve".to_string()
-                } else {
-                    r.aspect_ratio
+    // The legacy slice operation as pipeline steps: an image-scope watermark,
+    // slice, tile watermark, tile scaling, filters, adjustments, QR, invisible mark.
+    async fn slice_steps(mut s: ProtoSliceRequest) -> Result<Vec<Step>, String> {
+        let preset = PresetFill::from_name(&s.preset)?;
+        let mut preset_tile_adjust = None;
+        if let Some(p) = &preset {
+            p.field("scale", &mut s.scale)?;
+            p.message(&mut s.tile, |p, t| {
+                p.resize(("tile_width", "tile_height"), t)
+            })?;
+            p.message(&mut s.watermark, |p, w| {
+                p.field("watermark_scope", &mut w.scope)?;
+                p.watermark("watermark", w)
+            })?;
+            p.message(&mut s.qr, PresetFill::qr)?;
+            p.message(&mut s.adjust, PresetFill::adjust)?;
+            p.message(&mut s.effects, PresetFill::effects)?;
+            p.field("invisible", &mut s.invisible)?;
+            p.field("remainder", &mut s.remainder)?;
+            // Per-tile adjustments are kept in their compact HTTP form.
+            match p.0.param("tile_adjust") {
+                Some(_) if !s.tile_adjust.is_empty() => {
+                    p.0.check_override("tile_adjust")
+                        .map_err(|e| e.to_string())?
+                }
+                tile_adjust => preset_tile_adjust = tile_adjust,
+            }
+        }
+
+        let spec = decode_wm_config(s.watermark, "").await?;
+        let qr_spec = decode_qr_config(s.qr)?;
+        let tile = decode_tile_config(s.scale, s.tile)?;
+        let adjustments = match preset_tile_adjust {
+            Some(tile_adjust) => {
+                Adjustments::from_args(&adjust_args(&s.adjust.unwrap_or_default()))
+                    .and_then(|global| TileAdjustments::parse(global, Some(tile_adjust)))
+                    .map_err(|e| e.to_string())?
+            }
+            None => decode_adjust_config(s.adjust, s.tile_adjust)?,
+        };
+        let effects = decode_effects_config(s.effects)?;
+        let remainder = Remainder::parse(Some(&s.remainder)).map_err(|e| e.to_string())?;
+
+        SliceOps {
+            watermark: spec,
+            remainder,
+            tile,
+            effects,
+            adjustments,
+            qr: qr_spec,
+            invisible: s.invisible,
+        }
+        .steps()
+        .map_err(|e| e.to_string())
+    }
+
+    // Which response a batch operation gets back.
+    #[derive(Clone, Copy)]
+    enum BatchKind {
+        Slice,
+        Watermark,
+        Resize,
+        Transform,
+        Process,
+    }
+
+    // Every batch operation runs as a pipeline; split it into its kind, source and steps.
+    async fn decode_batch_op(
+        op: ProtoOp,
+    ) -> (
+        BatchKind,
+        Option<ProtoImageSource>,
+        Result<Pipeline, String>,
+    ) {
+        let single = |op: Result<Op, String>| {
+            op.and_then(|op| Pipeline::new(vec![Step::new(op)]).map_err(|e| e.to_string()))
+        };
+        let (source, step) = match op {
+            ProtoOp::Slice(s) => {
+                let mut s = ProtoSliceRequest::from(s);
+                let source = s.source.take();
+                let steps = slice_steps(s)
+                    .await
+                    .and_then(|steps| Pipeline::new(steps).map_err(|e| e.to_string()));
+                return (BatchKind::Slice, source, steps);
+            }
+            ProtoOp::Watermark(mut op) => {
+                let spec = match fill_watermark_preset(&op.preset, &mut op.watermark) {
+                    Ok(()) => decode_wm_config(op.watermark, "IZDU-Slicer")
+                        .await
+                        .and_then(|spec| {
+                            spec.ok_or_else(|| "watermark text or logo required".into())
+                        }),
+                    Err(e) => Err(e),
                 };
-                let width = (r.width > 0).then_some(r.width);
-                let height = (r.height > 0).then_some(r.height);
-                (width, height, ar)
+                return (
+                    BatchKind::Watermark,
+                    op.source,
+                    single(spec.map(Op::Watermark)),
+                );
+            }
+            ProtoOp::Resize(mut op) => {
+                let params = fill_resize_preset(&op.preset, &mut op.resize)
+                    .and_then(|()| decode_resize_config(op.resize));
+                return (BatchKind::Resize, op.source, single(params.map(Op::Resize)));
+            }
+            ProtoOp::Process(op) => {
+                let pipeline = decode_process_steps(op.steps, &op.preset).await;
+                return (BatchKind::Process, op.source, pipeline);
+            }
+            ProtoOp::Rotate(op) => (
+                op.source,
+                ProtoStepOp::Rotate(op.rotate.unwrap_or_default()),
+            ),
+            ProtoOp::Flip(op) => (op.source, ProtoStepOp::Flip(op.flip.unwrap_or_default())),
+            ProtoOp::Crop(op) => (op.source, ProtoStepOp::Crop(op.crop.unwrap_or_default())),
+            ProtoOp::Pad(op) => (op.source, ProtoStepOp::Pad(op.pad.unwrap_or_default())),
+            ProtoOp::Trim(op) => (op.source, ProtoStepOp::Trim(op.trim.unwrap_or_default())),
+            ProtoOp::Adjust(op) => (
+                op.source,
+                ProtoStepOp::Adjust(op.adjust.unwrap_or_default()),
+            ),
+            ProtoOp::Effects(op) => (
+                op.source,
+                ProtoStepOp::Effects(op.effects.unwrap_or_default()),
+            ),
+        };
+        (
+            BatchKind::Transform,
+            source,
+            single(decode_process_op(step).await),
+        )
+    }
+
+    async fn run_pipeline(
+        source: Option<ProtoImageSource>,
+        pipeline: Result<Pipeline, String>,
+    ) -> Result<Output, String> {
+        let source = proto_to_image_source(source).map_err(|e| e.message().to_string())?;
+        let pipeline = pipeline?;
+        let filename = source.file_name().unwrap_or_default();
+        let img = image_processor::load_image(source)
+            .await
+            .map_err(|e| e.to_string())?;
+        pipeline.run(img, &filename).map_err(|e| e.to_string())
+    }
+
+    fn process_response(output: &Output) -> Result<ProtoProcessResponse, String> {
+        let images = output
+            .images
+            .iter()
+            .enumerate()
+            .map(|(i, img)| {
+                Ok(ProtoProcessedImage {
+                    tile: output.tiled.then_some(i as u32),
+                    data: output.encode_image(i).map_err(|e| e.to_string())?,
+                    width: img.width(),
+                    height: img.height(),
+                    format: output.format.name().to_string(),
+                    crop: output.crops[i].map(proto_rect),
+                })
             })
-            .unwrap_or((None, None, "preserve".to_string()))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(ProtoProcessResponse {
+            images,
+            upscaled: output.upscaled,
+            error: String::new(),
+        })
+    }
+
+    // One response per tile; a tile that fails to encode carries the error.
+    fn slice_responses(output: &Output) -> Vec<ProtoSliceResponse> {
+        (0..output.images.len())
+            .map(|i| {
+                let (data, error) = match output.encode_image(i) {
+                    Ok(data) => (data, String::new()),
+                    Err(e) => (vec![], e.to_string()),
+                };
+                ProtoSliceResponse {
+                    index: i as u32,
+                    data,
+                    error,
+                    upscaled: output.upscaled,
+                    crop: output.crops[i].map(proto_rect),
+                }
+            })
+            .collect()
+    }
+
+    fn batch_result(kind: BatchKind, output: Output) -> Result<ProtoBatchResult, String> {
+        let encode = |i: usize| output.encode_image(i).map_err(|e| e.to_string());
+        Ok(match kind {
+            BatchKind::Slice => ProtoBatchResult::Slice(ProtoBatchSliceResult {
+                slices: slice_responses(&output),
+            }),
+            BatchKind::Watermark => ProtoBatchResult::Watermark(ProtoWatermarkResponse {
+                data: encode(0)?,
+                error: String::new(),
+            }),
+            BatchKind::Resize => ProtoBatchResult::Resize(ProtoResizeResponse {
+                data: encode(0)?,
+                error: String::new(),
+                upscaled: output.upscaled,
+                crop: output.crops[0].map(proto_rect),
+            }),
+            BatchKind::Transform => ProtoBatchResult::Transform(ProtoTransformResponse {
+                data: encode(0)?,
+                error: String::new(),
+                width: output.images[0].width(),
+                height: output.images[0].height(),
+            }),
+            BatchKind::Process => ProtoBatchResult::Process(process_response(&output)?),
+        })
+    }
+
+    fn decode_tile_config(
+        scale: u32,
+        tile: Option<super::ResizeConfig>,
+    ) -> Result<Option<TileScale>, String> {
+        let t = tile.unwrap_or_default();
+        TileScale::parse(scale, resize_args(&t)).map_err(|e| e.to_string())
     }
 
     pub struct GrpcServer;
@@ -84,6 +828,8 @@ pub mod server {
     impl ImageProcessor for GrpcServer {
         type SliceStream =
             Pin<Box<dyn tokio_stream::Stream<Item = Result<ProtoSliceResponse, Status>> + Send>>;
+        type SlicePdfStream =
+            Pin<Box<dyn tokio_stream::Stream<Item = Result<ProtoPdfChunk, Status>> + Send>>;
         type ProcessBatchStream =
             Pin<Box<dyn tokio_stream::Stream<Item = Result<ProtoBatchResponse, Status>> + Send>>;
 
@@ -91,51 +837,83 @@ pub mod server {
             &self,
             request: Request<ProtoSliceRequest>,
         ) -> Result<Response<<Self as ImageProcessor>::SliceStream>, Status> {
-            let req = request.into_inner();
-            let source = proto_to_image_source(req.source)?;
-            let scale = req.scale;
+            let mut req = request.into_inner();
+            let source = proto_to_image_source(req.source.take())?;
+            let pipeline = slice_steps(req)
+                .await
+                .and_then(|steps| Pipeline::new(steps).map_err(|e| e.to_string()))
+                .map_err(Status::invalid_argument)?;
+            let filename = source.file_name().unwrap_or_default();
 
             let img = image_processor::load_image(source)
                 .await
                 .map_err(|e| Status::internal(e.to_string()))?;
+            let output = pipeline
+                .run(img, &filename)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
-            let (wm_text, wm_alpha) = decode_wm_config(req.watermark, "");
-            let dim = image_slicer::get_single_image_dimensions(&img);
-
-            let mut sliced = if wm_text.is_empty() {
-                image_slicer::slice_images_view(img, &dim)
-            } else {
-                let wm = watermark::create_watermark(&wm_text, (dim.width, dim.height));
-                let mut out = image_slicer::slice_images_view(img, &dim);
-                for s in &mut out {
-                    *s = watermark::add_watermark(s.clone(), &wm, wm_alpha as f32 / 100.0);
+            let (tx, rx) = mpsc::channel(4);
+            tokio::spawn(async move {
+                for resp in slice_responses(&output) {
+                    let _ = tx.send(Ok(resp)).await;
                 }
-                out
-            };
+            });
 
-            if scale > 0 && scale < dim.smallest {
-                sliced = image_slicer::resize(sliced, scale);
+            Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+        }
+
+        async fn slice_pdf(
+            &self,
+            request: Request<ProtoSlicePdfRequest>,
+        ) -> Result<Response<Self::SlicePdfStream>, Status> {
+            let req = request.into_inner();
+            let mut slice = req.slice.unwrap_or_default();
+            let mut pdf_config = req.pdf;
+            if let Some(p) =
+                PresetFill::from_name(&slice.preset).map_err(Status::invalid_argument)?
+            {
+                p.message(&mut pdf_config, PresetFill::pdf)
+                    .map_err(Status::invalid_argument)?;
             }
+            let cfg = pdf_config.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let spec = PdfSpec::from_args(&PdfArgs {
+                paper: Some(&cfg.paper),
+                orientation: Some(&cfg.orientation),
+                margin: cfg.margin,
+                marks: Some(cfg.marks),
+                captions: Some(cfg.captions),
+                compression: Some(&cfg.compression),
+                quality: (quality > 0).then_some(quality),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let source = proto_to_image_source(slice.source.take())?;
+            let pipeline = slice_steps(slice)
+                .await
+                .and_then(|steps| Pipeline::new(steps).map_err(|e| e.to_string()))
+                .map_err(Status::invalid_argument)?;
+            let filename = source.file_name().unwrap_or_default();
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let output = pipeline
+                .run(img, &filename)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let data = pdf::render(&output.images, GRID_COLS, &filename, &spec)
+                .map_err(|e| Status::internal(e.to_string()))?;
 
             let (tx, rx) = mpsc::channel(4);
             tokio::spawn(async move {
-                for (i, img) in sliced.into_iter().enumerate() {
-                    let resp = match encode_png(img) {
-                        Ok(data) => Ok(ProtoSliceResponse {
-                            index: i as u32,
-                            data: data.to_vec(),
-                            error: String::new(),
-                        }),
-                        Err(e) => Ok(ProtoSliceResponse {
-                            index: i as u32,
-                            data: vec![],
-                            error: e,
-                        }),
-                    };
-                    let _ = tx.send(resp).await;
+                for chunk in data.chunks(pdf::STREAM_CHUNK) {
+                    let _ = tx
+                        .send(Ok(ProtoPdfChunk {
+                            data: chunk.to_vec(),
+                        }))
+                        .await;
                 }
             });
-
             Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
         }
 
@@ -143,17 +921,30 @@ pub mod server {
             &self,
             request: Request<ProtoWatermarkRequest>,
         ) -> Result<Response<ProtoWatermarkResponse>, Status> {
-            let req = request.into_inner();
+            let mut req = request.into_inner();
             let source = proto_to_image_source(req.source)?;
-            let (text, alpha) = decode_wm_config(req.watermark, "IZDU-Slicer");
+            // There are no tiles here, so a scope can't mean anything.
+            if req.watermark.as_ref().is_some_and(|w| !w.scope.is_empty()) {
+                return Err(Status::invalid_argument(
+                    "scope applies to slicing only; Watermark marks the whole image",
+                ));
+            }
+            fill_watermark_preset(&req.preset, &mut req.watermark)
+                .map_err(Status::invalid_argument)?;
+            let spec = decode_wm_config(req.watermark, "IZDU-Slicer")
+                .await
+                .map_err(Status::invalid_argument)?
+                .ok_or_else(|| Status::invalid_argument("watermark text or logo required"))?;
 
             let img = image_processor::load_image(source)
                 .await
                 .map_err(|e| Status::internal(e.to_string()))?;
 
             let (w, h) = (img.width(), img.height());
-            let wm = watermark::create_watermark(&text, (w, h));
-            let watermarked = watermark::add_watermark(img.to_rgba8(), &wm, alpha as f32 / 100.0);
+            let wm = spec
+                .render((w, h))
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let watermarked = spec.apply(img.into_rgba8(), &wm);
             let data = encode_png(watermarked).map_err(Status::internal)?;
 
             Ok(Response::new(ProtoWatermarkResponse {
@@ -162,29 +953,393 @@ pub mod server {
             }))
         }
 
+        async fn qr(
+            &self,
+            request: Request<ProtoQrRequest>,
+        ) -> Result<Response<ProtoQrResponse>, Status> {
+            let req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+            let spec = decode_qr_config(req.qr)
+                .map_err(Status::invalid_argument)?
+                .ok_or_else(|| Status::invalid_argument("QR code data required"))?;
+            let filename = source.file_name().unwrap_or_default();
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+
+            let date = text::today();
+            let tile = TileContext::for_grid(0, 1, 1, &filename, &date);
+            let code = spec
+                .render_for_tile((img.width(), img.height()), &tile)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let stamped = spec.apply(img.to_rgba8(), &code);
+            let data = encode_png(stamped).map_err(Status::internal)?;
+
+            Ok(Response::new(ProtoQrResponse {
+                data: data.to_vec(),
+                error: String::new(),
+            }))
+        }
+
+        async fn detect_watermark(
+            &self,
+            request: Request<ProtoDetectRequest>,
+        ) -> Result<Response<ProtoDetectResponse>, Status> {
+            let req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+
+            let payload =
+                invisible::detect(&img).map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            Ok(Response::new(ProtoDetectResponse {
+                found: payload.is_some(),
+                payload: payload
+                    .map(|p| String::from_utf8_lossy(&p).into_owned())
+                    .unwrap_or_default(),
+            }))
+        }
+
         async fn resize(
             &self,
             request: Request<ProtoResizeRequest>,
         ) -> Result<Response<ProtoResizeResponse>, Status> {
+            let mut req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+            fill_resize_preset(&req.preset, &mut req.resize).map_err(Status::invalid_argument)?;
+            let params = decode_resize_config(req.resize).map_err(Status::invalid_argument)?;
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+
+            let resized =
+                resize::resize(&img, &params).map_err(|e| Status::internal(e.to_string()))?;
+            let data = encode_png(resized.image.into_rgba8()).map_err(Status::internal)?;
+
+            Ok(Response::new(ProtoResizeResponse {
+                data: data.to_vec(),
+                error: String::new(),
+                upscaled: resized.upscaled,
+                crop: resized.crop.map(proto_rect),
+            }))
+        }
+
+        async fn variants(
+            &self,
+            request: Request<ProtoVariantsRequest>,
+        ) -> Result<Response<ProtoVariantsResponse>, Status> {
             let req = request.into_inner();
             let source = proto_to_image_source(req.source)?;
-            let (width, height, ar) = decode_resize_config(req.resize);
+            let cfg = req.variants.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let formats: Vec<&str> = cfg.formats.iter().map(String::as_str).collect();
+            let spec = VariantSpec::parse(
+                &cfg.widths,
+                &formats,
+                cfg.tiles,
+                Some(quality),
+                Some(&cfg.filter),
+                cfg.allow_upscale,
+            )
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let stem = variants::stem(source.file_name().as_deref());
 
-            if ar == "ignore" && (width.is_none() || height.is_none()) {
-                return Err(Status::invalid_argument(
-                    "aspect_ratio=ignore requires width and height",
-                ));
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+
+            let (generated, manifest) = variants::generate(img, &spec, &stem, &cfg.base_url)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let manifest =
+                serde_json::to_string(&manifest).map_err(|e| Status::internal(e.to_string()))?;
+
+            Ok(Response::new(ProtoVariantsResponse {
+                variants: generated
+                    .into_iter()
+                    .map(|v| ProtoVariant {
+                        name: v.name,
+                        tile: v.tile,
+                        width: v.width,
+                        height: v.height,
+                        format: v.format.name().to_string(),
+                        data: v.data,
+                    })
+                    .collect(),
+                manifest,
+                error: String::new(),
+            }))
+        }
+
+        async fn process(
+            &self,
+            request: Request<ProtoProcessRequest>,
+        ) -> Result<Response<ProtoProcessResponse>, Status> {
+            let req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+            let pipeline = decode_process_steps(req.steps, &req.preset)
+                .await
+                .map_err(Status::invalid_argument)?;
+            let filename = source.file_name().unwrap_or_default();
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+
+            let output = pipeline
+                .run(img, &filename)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            Ok(Response::new(
+                process_response(&output).map_err(Status::internal)?,
+            ))
+        }
+
+        async fn carousel(
+            &self,
+            request: Request<ProtoCarouselRequest>,
+        ) -> Result<Response<ProtoCarouselResponse>, Status> {
+            let mut req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+            if let Some(p) = PresetFill::from_name(&req.preset).map_err(Status::invalid_argument)? {
+                p.message(&mut req.carousel, PresetFill::carousel)
+                    .map_err(Status::invalid_argument)?;
             }
+            let cfg = req.carousel.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let spec = CarouselSpec::from_args(&CarouselArgs {
+                aspect: Some(&cfg.aspect),
+                slides: Some(cfg.slides),
+                fit: Some(&cfg.fit),
+                gravity: Some(&cfg.gravity),
+                background: Some(&cfg.background),
+                filter: Some(&cfg.filter),
+                width: Some(cfg.width),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let (format, quality) = variants::parse_encoding(Some(&cfg.format), Some(quality))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
 
             let img = image_processor::load_image(source)
                 .await
                 .map_err(|e| Status::internal(e.to_string()))?;
+            let carousel = spec
+                .split(&img)
+                .map_err(|e| Status::internal(e.to_string()))?;
 
-            let resized = image_slicer::resize_single(img, width, height, &ar);
-            let data = encode_png(resized.to_rgba8()).map_err(Status::internal)?;
+            let mut slides = Vec::with_capacity(carousel.slides.len());
+            for (i, slide) in carousel.slides.into_iter().enumerate() {
+                let (width, height) = slide.dimensions();
+                let data = format
+                    .encode(&slide.into(), quality)
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                slides.push(ProtoCarouselSlide {
+                    index: i as u32,
+                    data,
+                    width,
+                    height,
+                });
+            }
+            Ok(Response::new(ProtoCarouselResponse {
+                slides,
+                upscaled: carousel.upscaled,
+                crop: carousel.crop.map(proto_rect),
+                format: format.name().to_string(),
+                error: String::new(),
+            }))
+        }
 
-            Ok(Response::new(ProtoResizeResponse {
-                data: data.to_vec(),
+        async fn stitch(
+            &self,
+            request: Request<ProtoStitchRequest>,
+        ) -> Result<Response<ProtoStitchResponse>, Status> {
+            let mut req = request.into_inner();
+            if let Some(p) = PresetFill::from_name(&req.preset).map_err(Status::invalid_argument)? {
+                p.message(&mut req.stitch, PresetFill::stitch)
+                    .map_err(Status::invalid_argument)?;
+            }
+            let cfg = req.stitch.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let spec = StitchSpec::from_args(&StitchArgs {
+                grid: Some(&cfg.grid),
+                overlap: Some(&cfg.overlap),
+                bezel: Some(&cfg.bezel),
+                background: Some(&cfg.background),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let (format, quality) = variants::parse_encoding(Some(&cfg.format), Some(quality))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let stitched = stitch::decode_tiles(&req.tiles)
+                .and_then(|tiles| spec.stitch(&tiles))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let (width, height) = stitched.dimensions();
+            let data = format
+                .encode(&stitched.into(), quality)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            Ok(Response::new(ProtoStitchResponse {
+                data,
+                width,
+                height,
+                format: format.name().to_string(),
+                error: String::new(),
+            }))
+        }
+
+        async fn contact_sheet(
+            &self,
+            request: Request<ProtoContactSheetRequest>,
+        ) -> Result<Response<ProtoContactSheetResponse>, Status> {
+            let mut req = request.into_inner();
+            if let Some(p) = PresetFill::from_name(&req.preset).map_err(Status::invalid_argument)? {
+                p.message(&mut req.sheet, PresetFill::sheet)
+                    .map_err(Status::invalid_argument)?;
+            }
+            let cfg = req.sheet.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let spec = SheetSpec::from_args(&SheetArgs {
+                cols: Some(cfg.cols),
+                cell_width: Some(cfg.cell_width),
+                cell_height: Some(cfg.cell_height),
+                gutter: cfg.gutter,
+                background: Some(&cfg.background),
+                fit: Some(&cfg.fit),
+                filter: Some(&cfg.filter),
+                caption: Some(&cfg.caption),
+                caption_size: Some(cfg.caption_size),
+                caption_color: Some(&cfg.caption_color),
+                font: Some(&cfg.font),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let (format, quality) = variants::parse_encoding(Some(&cfg.format), Some(quality))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let mut entries = Vec::with_capacity(req.images.len());
+            for image in req.images {
+                let source = proto_to_image_source(image.source)?;
+                let caption = Some(image.caption).filter(|c| !c.is_empty());
+                entries.push(SheetEntry::new(source, caption));
+            }
+            let cells = spec
+                .load_all(entries)
+                .await
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let sheet = spec
+                .render(&cells)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let (cols, rows) = spec.grid(cells.len());
+            let (width, height) = sheet.dimensions();
+            let data = format
+                .encode(&sheet.into(), quality)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            Ok(Response::new(ProtoContactSheetResponse {
+                data,
+                width,
+                height,
+                format: format.name().to_string(),
+                cols,
+                rows,
+                error: String::new(),
+            }))
+        }
+
+        async fn atlas(
+            &self,
+            request: Request<ProtoAtlasRequest>,
+        ) -> Result<Response<ProtoAtlasResponse>, Status> {
+            let mut req = request.into_inner();
+            if let Some(p) = PresetFill::from_name(&req.preset).map_err(Status::invalid_argument)? {
+                p.message(&mut req.atlas, PresetFill::atlas)
+                    .map_err(Status::invalid_argument)?;
+            }
+            let cfg = req.atlas.unwrap_or_default();
+            let spec = AtlasSpec::from_args(&AtlasArgs {
+                packer: Some(&cfg.packer),
+                padding: cfg.padding,
+                pot: Some(cfg.pot),
+                trim: Some(cfg.trim),
+                max_size: Some(cfg.max_size),
+                json: Some(&cfg.json),
+                name: Some(&cfg.name),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let files = req.sprites.into_iter().map(|s| (s.name, s.data)).collect();
+            let packed = atlas::decode_sprites(files)
+                .and_then(|sprites| spec.pack(sprites))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            let json = spec
+                .json(&packed)
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let (width, height) = packed.image.dimensions();
+            let image = encode_png(packed.image).map_err(Status::internal)?;
+            Ok(Response::new(ProtoAtlasResponse {
+                image: image.to_vec(),
+                json: String::from_utf8_lossy(&json).into_owned(),
+                width,
+                height,
+                error: String::new(),
+            }))
+        }
+
+        async fn poster(
+            &self,
+            request: Request<ProtoPosterRequest>,
+        ) -> Result<Response<ProtoPosterResponse>, Status> {
+            let mut req = request.into_inner();
+            let source = proto_to_image_source(req.source)?;
+            if let Some(p) = PresetFill::from_name(&req.preset).map_err(Status::invalid_argument)? {
+                p.message(&mut req.poster, PresetFill::poster)
+                    .map_err(Status::invalid_argument)?;
+            }
+            let cfg = req.poster.unwrap_or_default();
+            let quality = proto_quality(cfg.quality).map_err(Status::invalid_argument)?;
+            let spec = PosterSpec::from_args(&PosterArgs {
+                paper: Some(&cfg.paper),
+                orientation: Some(&cfg.orientation),
+                dpi: Some(cfg.dpi),
+                margin: cfg.margin,
+                overlap: Some(cfg.overlap),
+                marks: Some(cfg.marks),
+                labels: Some(cfg.labels),
+                format: Some(&cfg.format),
+                quality: (quality > 0).then_some(quality),
+            })
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let img = image_processor::load_image(source)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?;
+            let layout = spec
+                .layout((img.width(), img.height()))
+                .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+            let mut pages = Vec::with_capacity(layout.cells.len());
+            for (i, cell) in layout.cells.iter().enumerate() {
+                let data = layout
+                    .render_page(&img, &spec, i)
+                    .and_then(|page| spec.encode(page))
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                pages.push(ProtoPosterPage {
+                    index: i as u32,
+                    row: i as u32 / layout.cols,
+                    col: i as u32 % layout.cols,
+                    data,
+                    width: layout.page.0,
+                    height: layout.page.1,
+                    region: Some(proto_rect(*cell)),
+                });
+            }
+            Ok(Response::new(ProtoPosterResponse {
+                pages,
+                cols: layout.cols,
+                rows: layout.rows,
+                orientation: layout.orientation.name().to_string(),
+                format: spec.format.name().to_string(),
+                dpi: spec.dpi,
                 error: String::new(),
             }))
         }
@@ -207,175 +1362,25 @@ pub mod server {
                     };
 
                     let rid = req.request_id.clone();
-                    let resp: ProtoBatchResponse = match req.operation {
-                        Some(operation) => match operation.op {
-                            Some(ProtoOp::Slice(s)) => match proto_to_image_source(s.source) {
-                                Ok(source) => {
-                                    let scale = s.scale;
-                                    let (wm_text, wm_alpha) = decode_wm_config(s.watermark, "");
-                                    match image_processor::load_image(source).await {
-                                        Ok(img) => {
-                                            let dim =
-                                                image_slicer::get_single_image_dimensions(&img);
-                                            let mut sliced =
-                                                image_slicer::slice_images_view(img, &dim);
-                                            if !wm_text.is_empty() {
-                                                let wm = watermark::create_watermark(
-                                                    &wm_text,
-                                                    (dim.width, dim.height),
-                                                );
-                                                for slice_img in &mut sliced {
-                                                    *slice_img = watermark::add_watermark(
-                                                        slice_img.clone(),
-                                                        &wm,
-                                                        wm_alpha as f32 / 100.0,
-                                                    );
-                                                }
-                                            }
-                                            if scale > 0 && scale < dim.smallest {
-                                                sliced = image_slicer::resize(sliced, scale);
-                                            }
-                                            let slices: Vec<_> = sliced
-                                                .into_iter()
-                                                .enumerate()
-                                                .map(|(i, img)| match encode_png(img) {
-                                                    Ok(data) => ProtoSliceResponse {
-                                                        index: i as u32,
-                                                        data: data.to_vec(),
-                                                        error: String::new(),
-                                                    },
-                                                    Err(e) => ProtoSliceResponse {
-                                                        index: i as u32,
-                                                        data: vec![],
-                                                        error: e,
-                                                    },
-                                                })
-                                                .collect();
-                                            ProtoBatchResponse {
-                                                request_id: rid,
-                                                error: String::new(),
-                                                result: Some(ProtoBatchResult::Slice(
-                                                    ProtoBatchSliceResult { slices },
-                                                )),
-                                            }
-                                        }
-                                        Err(e) => ProtoBatchResponse {
-                                            request_id: rid,
-                                            error: e.to_string(),
-                                            result: None,
-                                        },
-                                    }
-                                }
+                    let resp = match req.operation.and_then(|operation| operation.op) {
+                        Some(op) => {
+                            let (kind, source, pipeline) = decode_batch_op(op).await;
+                            match run_pipeline(source, pipeline)
+                                .await
+                                .and_then(|output| batch_result(kind, output))
+                            {
+                                Ok(result) => ProtoBatchResponse {
+                                    request_id: rid,
+                                    error: String::new(),
+                                    result: Some(result),
+                                },
                                 Err(e) => ProtoBatchResponse {
                                     request_id: rid,
-                                    error: e.message().to_string(),
+                                    error: e,
                                     result: None,
                                 },
-                            },
-                            Some(ProtoOp::Watermark(wm_op)) => {
-                                match proto_to_image_source(wm_op.source) {
-                                    Ok(source) => {
-                                        let (text, alpha) =
-                                            decode_wm_config(wm_op.watermark, "IZDU-Slicer");
-                                        match image_processor::load_image(source).await {
-                                            Ok(img) => {
-                                                let (w, h) = (img.width(), img.height());
-                                                let wm = watermark::create_watermark(&text, (w, h));
-                                                let watermarked = watermark::add_watermark(
-                                                    img.to_rgba8(),
-                                                    &wm,
-                                                    alpha as f32 / 100.0,
-                                                );
-                                                match encode_png(watermarked) {
-                                                    Ok(data) => ProtoBatchResponse {
-                                                        request_id: rid,
-                                                        error: String::new(),
-                                                        result: Some(ProtoBatchResult::Watermark(
-                                                            ProtoWatermarkResponse {
-                                                                data: data.to_vec(),
-                                                                error: String::new(),
-                                                            },
-                                                        )),
-                                                    },
-                                                    Err(e) => ProtoBatchResponse {
-                                                        request_id: rid,
-                                                        error: e,
-                                                        result: None,
-                                                    },
-                                                }
-                                            }
-                                            Err(e) => ProtoBatchResponse {
-                                                request_id: rid,
-                                                error: e.to_string(),
-                                                result: None,
-                                            },
-                                        }
-                                    }
-                                    Err(e) => ProtoBatchResponse {
-                                        request_id: rid,
-                                        error: e.message().to_string(),
-                                        result: None,
-                                    },
-                                }
-                            }
-                            Some(ProtoOp::Resize(rs_op)) => {
-                                match proto_to_image_source(rs_op.source) {
-                                    Ok(source) => {
-                                        let (width, height, ar) =
-                                            decode_resize_config(rs_op.resize);
-                                        if ar == "ignore" && (width.is_none() || height.is_none()) {
-                                            ProtoBatchResponse {
-                                                request_id: rid,
-                                                error:
-                                                    "aspect_ratio=ignore requires width and height"
-                                                        .into(),
-                                                result: None,
-                                            }
-                                        } else {
-                                            match image_processor::load_image(source).await {
-                                                Ok(img) => {
-                                                    let resized = image_slicer::resize_single(
-                                                        img, width, height, &ar,
-                                                    );
-                                                    match encode_png(resized.to_rgba8()) {
-                                                        Ok(data) => ProtoBatchResponse {
-                                                            request_id: rid,
-                                                            error: String::new(),
-                                                            result: Some(ProtoBatchResult::Resize(
-                                                                ProtoResizeResponse {
-                                                                    data: data.to_vec(),
-                                                                    error: String::new(),
-                                                                },
-                                                            )),
-                                                        },
-                                                        Err(e) => ProtoBatchResponse {
-                                                            request_id: rid,
-                                                            error: e,
-                                                            result: None,
-                                                        },
-                                                    }
-                                                }
-                                                Err(e) => ProtoBatchResponse {
-                                                    request_id: rid,
-                                                    error: e.to_string(),
-                                                    result: None,
-                                                },
-                                            }
-                                        }
-                                    }
-                                    Err(e) => ProtoBatchResponse {
-                                        request_id: rid,
-                                        error: e.message().to_string(),
-                                        result: None,
-                                    },
-                                }
                             }
-                            None => ProtoBatchResponse {
-                                request_id: rid,
-                                error: "no operation".into(),
-                                result: None,
-                            },
-                        },
+                        }
                         None => ProtoBatchResponse {
                             request_id: rid,
                             error: "no operation".into(),
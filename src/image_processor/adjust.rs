@@ -0,0 +1,347 @@
+use crate::image_processor::color::parse_color;
+use anyhow::{Error, Result};
+use image::{Rgba, RgbaImage};
+
+type Matrix = [[f32; 3]; 3];
+
+const IDENTITY: Matrix = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Raw colour adjustment parameters, as given over HTTP or gRPC.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AdjustArgs<'a> {
+    pub brightness: Option<f32>,
+    pub contrast: Option<f32>,
+    pub gamma: Option<f32>,
+    pub saturation: Option<f32>,
+    pub hue: Option<f32>,
+    pub grayscale: Option<bool>,
+    pub sepia: Option<f32>,
+    pub invert: Option<bool>,
+    pub tint: Option<&'a str>,
+}
+
+/// Colour adjustments, applied in field order. Alpha is left untouched.
+///
+/// * `brightness`: -100 to 100, percent of full scale added to every channel
+/// * `contrast`: -100 to 100, percent change of the distance from mid-grey
+/// * `gamma`: 0.1 to 10, values above 1 brighten mid-tones
+/// * `saturation`: -100 to 100, percent change; -100 is grey
+/// * `hue`: rotation in degrees
+/// * `grayscale`, `sepia` (0 to 100 percent), `invert`
+/// * `tint`: multiply by this colour; its alpha sets the strength
+#[derive(Clone, Debug, PartialEq)]
+pub struct Adjustments {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub gamma: f32,
+    pub saturation: f32,
+    pub hue: f32,
+    pub grayscale: bool,
+    pub sepia: f32,
+    pub invert: bool,
+    pub tint: Option<Rgba<u8>>,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            brightness: 0.0,
+            contrast: 0.0,
+            gamma: 1.0,
+            saturation: 0.0,
+            hue: 0.0,
+            grayscale: false,
+            sepia: 0.0,
+            invert: false,
+            tint: None,
+        }
+    }
+}
+
+fn in_range(name: &str, value: Option<f32>, min: f32, max: f32) -> Result<Option<f32>> {
+    match value {
+        Some(v) if !(min..=max).contains(&v) => Err(Error::msg(format!(
+            "{} must be between {} and {}, got {}",
+            name, min, max, v
+        ))),
+        _ => Ok(value),
+    }
+}
+
+impl Adjustments {
+    pub fn from_args(args: &AdjustArgs) -> Result<Adjustments> {
+        let neutral = Adjustments::default();
+        let hue = match args.hue {
+            Some(h) if !h.is_finite() => return Err(Error::msg(format!("Invalid hue: {}", h))),
+            h => h.map(|h| h.rem_euclid(360.0)),
+        };
+        Ok(Adjustments {
+            brightness: in_range("brightness", args.brightness, -100.0, 100.0)?
+                .unwrap_or(neutral.brightness),
+            contrast: in_range("contrast", args.contrast, -100.0, 100.0)?
+                .unwrap_or(neutral.contrast),
+            gamma: in_range("gamma", args.gamma, 0.1, 10.0)?.unwrap_or(neutral.gamma),
+            saturation: in_range("saturation", args.saturation, -100.0, 100.0)?
+                .unwrap_or(neutral.saturation),
+            hue: hue.unwrap_or(neutral.hue),
+            grayscale: args.grayscale.unwrap_or(false),
+            sepia: in_range("sepia", args.sepia, 0.0, 100.0)?.unwrap_or(neutral.sepia),
+            invert: args.invert.unwrap_or(false),
+            tint: args
+                .tint
+                .filter(|t| !t.is_empty())
+                .map(parse_color)
+                .transpose()?,
+        })
+    }
+
+    /// Parse a compact `name:value` list such as `brightness:5,gamma:1.1,grayscale`.
+    /// Flags may be given bare or as `name:true`.
+    pub fn parse(spec: &str) -> Result<Adjustments> {
+        let mut args = AdjustArgs::default();
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (name, value) = item.split_once(':').unwrap_or((item, "true"));
+            let number = || {
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|_| Error::msg(format!("Invalid value for {}: {}", name, value)))
+            };
+            let flag = || {
+                value
+                    .trim()
+                    .parse::<bool>()
+                    .map_err(|_| Error::msg(format!("Invalid value for {}: {}", name, value)))
+            };
+            match name.trim().to_lowercase().as_str() {
+                "brightness" => args.brightness = Some(number()?),
+                "contrast" => args.contrast = Some(number()?),
+                "gamma" => args.gamma = Some(number()?),
+                "saturation" => args.saturation = Some(number()?),
+                "hue" => args.hue = Some(number()?),
+                "grayscale" | "greyscale" => args.grayscale = Some(flag()?),
+                "sepia" => args.sepia = Some(number()?),
+                "invert" => args.invert = Some(flag()?),
+                "tint" => args.tint = Some(value.trim()),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "Unknown adjustment: {} (use brightness, contrast, gamma, saturation, hue, grayscale, sepia, invert or tint)",
+                        name
+                    )))
+                }
+            }
+        }
+        Adjustments::from_args(&args)
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    pub fn apply(&self, img: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+
+        // Brightness, contrast and gamma act on each channel alone: one lookup table.
+        let contrast = 1.0 + self.contrast / 100.0;
+        let lut: Vec<f32> = (0..256)
+            .map(|v| {
+                let x = v as f32 / 255.0 + self.brightness / 100.0;
+                let x = ((x - 0.5) * contrast + 0.5).clamp(0.0, 1.0);
+                x.powf(1.0 / self.gamma) * 255.0
+            })
+            .collect();
+
+        // Saturation, hue, grayscale and sepia mix channels: one matrix, after CSS filters.
+        let mut matrix = saturate(1.0 + self.saturation / 100.0);
+        if self.hue != 0.0 {
+            matrix = mul(&hue_rotate(self.hue), &matrix);
+        }
+        if self.grayscale {
+            matrix = mul(&saturate(0.0), &matrix);
+        }
+        if self.sepia > 0.0 {
+            matrix = mul(&sepia(self.sepia / 100.0), &matrix);
+        }
+
+        for p in img.pixels_mut() {
+            let rgb = [lut[p[0] as usize], lut[p[1] as usize], lut[p[2] as usize]];
+            for (c, row) in matrix.iter().enumerate() {
+                let mut v = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+                if self.invert {
+                    v = 255.0 - v;
+                }
+                if let Some(tint) = self.tint {
+                    let strength = tint[3] as f32 / 255.0;
+                    v += (v * tint[c] as f32 / 255.0 - v) * strength;
+                }
+                p[c] = v.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Adjustments for every slice, then per-slice offsets on top, e.g. to
+/// calibrate the panels of a video wall.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileAdjustments {
+    pub global: Adjustments,
+    pub tiles: [Adjustments; 4],
+}
+
+impl TileAdjustments {
+    pub fn new(global: Adjustments, tiles: Vec<Adjustments>) -> Result<TileAdjustments> {
+        let mut adjustments = TileAdjustments {
+            global,
+            ..TileAdjustments::default()
+        };
+        if tiles.len() > adjustments.tiles.len() {
+            return Err(Error::msg(format!(
+                "{} tile adjustments given, but there are only 4 tiles",
+                tiles.len()
+            )));
+        }
+        for (slot, tile) in adjustments.tiles.iter_mut().zip(tiles) {
+            *slot = tile;
+        }
+        Ok(adjustments)
+    }
+
+    /// `tiles` holds up to four `;`-separated specs in slice order, each as for
+    /// [`Adjustments::parse`]; empty or missing ones leave that tile alone.
+    pub fn parse(global: Adjustments, tiles: Option<&str>) -> Result<TileAdjustments> {
+        let tiles = tiles
+            .map(|t| t.split(';').collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                Adjustments::parse(spec)
+                    .map_err(|e| Error::msg(format!("Tile {} adjustments: {}", i, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        TileAdjustments::new(global, tiles)
+    }
+}
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn saturate(s: f32) -> Matrix {
+    if s == 1.0 {
+        return IDENTITY;
+    }
+    [
+        [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+        [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+        [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+    ]
+}
+
+fn hue_rotate(degrees: f32) -> Matrix {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    [
+        [
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ],
+        [
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ],
+        [
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ],
+    ]
+}
+
+fn sepia(amount: f32) -> Matrix {
+    let k = 1.0 - amount;
+    [
+        [0.393 + 0.607 * k, 0.769 - 0.769 * k, 0.189 - 0.189 * k],
+        [0.349 - 0.349 * k, 0.686 + 0.314 * k, 0.168 - 0.168 * k],
+        [0.272 - 0.272 * k, 0.534 - 0.534 * k, 0.131 + 0.869 * k],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageBuffer;
+
+    fn pixel(rgba: [u8; 4]) -> RgbaImage {
+        ImageBuffer::from_pixel(1, 1, Rgba(rgba))
+    }
+
+    fn adjusted(spec: &str, rgba: [u8; 4]) -> [u8; 4] {
+        let mut img = pixel(rgba);
+        Adjustments::parse(spec).unwrap().apply(&mut img);
+        img.get_pixel(0, 0).0
+    }
+
+    #[test]
+    fn neutral_adjustments_leave_pixels_alone() {
+        assert!(Adjustments::parse("").unwrap().is_identity());
+        assert_eq!(
+            adjusted("hue:360,saturation:0", [12, 200, 99, 7]),
+            [12, 200, 99, 7]
+        );
+    }
+
+    #[test]
+    fn tone_adjustments() {
+        assert_eq!(
+            adjusted("brightness:10", [100, 100, 100, 255]),
+            [126, 126, 126, 255]
+        );
+        assert_eq!(
+            adjusted("contrast:-100", [10, 240, 60, 255]),
+            [128, 128, 128, 255]
+        );
+        assert!(adjusted("gamma:2.2", [64, 64, 64, 255])[0] > 64);
+        assert_eq!(adjusted("invert", [0, 128, 255, 40]), [255, 127, 0, 40]);
+    }
+
+    #[test]
+    fn colour_adjustments() {
+        let [r, g, b, _] = adjusted("grayscale", [200, 50, 50, 255]);
+        assert!(r == g && g == b);
+        assert_eq!(
+            adjusted("saturation:-100", [200, 50, 50, 255]),
+            [r, g, b, 255]
+        );
+
+        let [r, g, b, _] = adjusted("sepia:100", [128, 128, 128, 255]);
+        assert!(r > g && g > b);
+
+        // Red rotated a third of the way round is mostly green.
+        let [r, g, _, _] = adjusted("hue:120", [255, 0, 0, 255]);
+        assert!(g > r);
+
+        assert_eq!(
+            adjusted("tint:ff000080", [200, 200, 200, 255]),
+            [200, 100, 100, 255]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert!(Adjustments::parse("brightness:150").is_err());
+        assert!(Adjustments::parse("gamma:0").is_err());
+        assert!(Adjustments::parse("glow:1").is_err());
+        assert!(Adjustments::parse("invert:maybe").is_err());
+        assert!(TileAdjustments::parse(Adjustments::default(), Some(";;;;")).is_err());
+    }
+}
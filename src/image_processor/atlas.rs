@@ -0,0 +1,785 @@
+use crate::image_processor::resize::Rect;
+use anyhow::{Error, Result};
+use image::{imageops, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+/// Upper bound on sprites per atlas.
+pub const MAX_SPRITES: usize = 1024;
+/// Default px of transparent space between sprites.
+pub const DEFAULT_PADDING: u32 = 2;
+/// Default and largest atlas side, in px.
+pub const DEFAULT_MAX_SIZE: u32 = 4096;
+const MAX_ATLAS_SIZE: u32 = 8192;
+const MAX_PADDING: u32 = 64;
+
+/// How sprites are arranged.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Packer {
+    /// MaxRects, best short side fit: tight, for sprites of mixed sizes.
+    MaxRects,
+    /// Rows of sprites sorted by height: fast, good for similar heights.
+    Shelf,
+}
+
+impl FromStr for Packer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "maxrects" => Ok(Packer::MaxRects),
+            "shelf" => Ok(Packer::Shelf),
+            _ => Err(Error::msg(format!(
+                "Unknown packer: {} (use maxrects or shelf)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Layout of the JSON map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonFormat {
+    /// TexturePacker "JSON (Hash)": frames keyed by name.
+    Hash,
+    /// TexturePacker "JSON (Array)": frames as a list with a `filename`.
+    Array,
+    /// Phaser 3 multi-atlas: a `textures` list, each with its frames.
+    Phaser,
+}
+
+impl FromStr for JsonFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "hash" | "texturepacker" => Ok(JsonFormat::Hash),
+            "array" => Ok(JsonFormat::Array),
+            "phaser" | "phaser3" => Ok(JsonFormat::Phaser),
+            _ => Err(Error::msg(format!(
+                "Unknown atlas json: {} (use hash, array or phaser)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Atlas params as they arrive in a request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AtlasArgs<'a> {
+    pub packer: Option<&'a str>,
+    pub padding: Option<u32>,
+    pub pot: Option<bool>,
+    pub trim: Option<bool>,
+    pub max_size: Option<u32>,
+    pub json: Option<&'a str>,
+    pub name: Option<&'a str>,
+}
+
+/// How to pack an atlas and describe it. `name` is the file stem of the
+/// atlas image and JSON map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasSpec {
+    pub packer: Packer,
+    pub padding: u32,
+    pub pot: bool,
+    pub trim: bool,
+    pub max_size: u32,
+    pub json: JsonFormat,
+    pub name: String,
+}
+
+/// One named image to pack.
+pub struct Sprite {
+    pub name: String,
+    pub image: RgbaImage,
+}
+
+/// Where a sprite went: `frame` in the atlas, holding the `source` part of
+/// the original `source_size` image (all of it unless trimmed).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    pub frame: Rect,
+    pub source: Rect,
+    pub source_size: (u32, u32),
+}
+
+impl Frame {
+    pub fn trimmed(&self) -> bool {
+        (self.source.width, self.source.height) != self.source_size
+    }
+}
+
+/// The packed image and its frames, in upload order.
+pub struct Atlas {
+    pub image: RgbaImage,
+    pub frames: Vec<Frame>,
+}
+
+impl AtlasSpec {
+    pub fn from_args(args: &AtlasArgs) -> Result<AtlasSpec> {
+        let packer = match args.packer.filter(|p| !p.is_empty()) {
+            Some(p) => p.parse()?,
+            None => Packer::MaxRects,
+        };
+        let json = match args.json.filter(|j| !j.is_empty()) {
+            Some(j) => j.parse()?,
+            None => JsonFormat::Hash,
+        };
+        let padding = args.padding.unwrap_or(DEFAULT_PADDING);
+        if padding > MAX_PADDING {
+            return Err(Error::msg(format!(
+                "padding must be 0-{}, got {}",
+                MAX_PADDING, padding
+            )));
+        }
+        let max_size = match args.max_size {
+            None | Some(0) => DEFAULT_MAX_SIZE,
+            Some(s) if s <= MAX_ATLAS_SIZE => s,
+            Some(s) => {
+                return Err(Error::msg(format!(
+                    "max_size must be 1-{}, got {}",
+                    MAX_ATLAS_SIZE, s
+                )))
+            }
+        };
+        let name = args.name.filter(|n| !n.is_empty()).unwrap_or("atlas");
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(Error::msg(format!(
+                "Invalid atlas name: {} (use letters, digits, - and _)",
+                name
+            )));
+        }
+        Ok(AtlasSpec {
+            packer,
+            padding,
+            pot: args.pot.unwrap_or(false),
+            trim: args.trim.unwrap_or(false),
+            max_size,
+            json,
+            name: name.to_string(),
+        })
+    }
+
+    /// Trim the sprites if asked, then pack them into the smallest atlas the
+    /// packer finds among a range of widths (powers of two with `pot`).
+    pub fn pack(&self, sprites: Vec<Sprite>) -> Result<Atlas> {
+        if sprites.is_empty() {
+            return Err(Error::msg("No sprites to pack"));
+        }
+        if sprites.len() > MAX_SPRITES {
+            return Err(Error::msg(format!(
+                "At most {} sprites, got {}",
+                MAX_SPRITES,
+                sprites.len()
+            )));
+        }
+        let mut names = HashSet::new();
+        if let Some(sprite) = sprites.iter().find(|s| !names.insert(s.name.as_str())) {
+            return Err(Error::msg(format!(
+                "Duplicate sprite name: {}",
+                sprite.name
+            )));
+        }
+
+        let sources: Vec<Rect> = sprites
+            .iter()
+            .map(|s| {
+                let whole = Rect {
+                    x: 0,
+                    y: 0,
+                    width: s.image.width(),
+                    height: s.image.height(),
+                };
+                if self.trim {
+                    // A fully transparent sprite keeps one pixel, as TexturePacker does.
+                    opaque_bounds(&s.image).unwrap_or(Rect {
+                        width: 1,
+                        height: 1,
+                        ..whole
+                    })
+                } else {
+                    whole
+                }
+            })
+            .collect();
+        let sizes: Vec<(u32, u32)> = sources
+            .iter()
+            .map(|r| (r.width + self.padding, r.height + self.padding))
+            .collect();
+        let widest = sources.iter().map(|r| r.width).max().unwrap_or(1);
+        let tallest = sources.iter().map(|r| r.height).max().unwrap_or(1);
+        if widest > self.max_size || tallest > self.max_size {
+            return Err(Error::msg(format!(
+                "Sprites up to {}x{} px don't fit in a {}px atlas",
+                widest, tallest, self.max_size
+            )));
+        }
+
+        let mut best: Option<Packing> = None;
+        for width in self.candidate_widths(widest, &sizes) {
+            let Some(positions) = place(
+                self.packer,
+                &sizes,
+                width + self.padding,
+                self.max_size + self.padding,
+            ) else {
+                continue;
+            };
+            let used = |axis: fn(&(u32, u32)) -> u32| {
+                positions
+                    .iter()
+                    .zip(&sizes)
+                    .map(|(p, s)| axis(p) + axis(s))
+                    .max()
+                    .unwrap_or(0)
+                    - self.padding
+            };
+            let mut size = (used(|v| v.0), used(|v| v.1));
+            if self.pot {
+                size = (size.0.next_power_of_two(), size.1.next_power_of_two());
+            }
+            if size.0 > self.max_size || size.1 > self.max_size {
+                continue;
+            }
+            let packing = Packing { size, positions };
+            if best.as_ref().is_none_or(|b| packing.score() < b.score()) {
+                best = Some(packing);
+            }
+        }
+        let Some(Packing {
+            size: (width, height),
+            positions,
+        }) = best
+        else {
+            return Err(Error::msg(format!(
+                "{} sprites don't fit in a {}x{} atlas",
+                sprites.len(),
+                self.max_size,
+                self.max_size
+            )));
+        };
+
+        let mut image = ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 0]));
+        let mut frames = Vec::with_capacity(sprites.len());
+        for ((sprite, source), (x, y)) in sprites.into_iter().zip(sources).zip(positions) {
+            let kept = sprite
+                .image
+                .view(source.x, source.y, source.width, source.height)
+                .to_image();
+            imageops::replace(&mut image, &kept, x as i64, y as i64);
+            frames.push(Frame {
+                name: sprite.name,
+                frame: Rect {
+                    x,
+                    y,
+                    width: source.width,
+                    height: source.height,
+                },
+                source,
+                source_size: sprite.image.dimensions(),
+            });
+        }
+        Ok(Atlas { image, frames })
+    }
+
+    // Atlas widths to try: powers of two with `pot`, otherwise a spread
+    // around the square root of the sprites' total area.
+    fn candidate_widths(&self, widest: u32, sizes: &[(u32, u32)]) -> Vec<u32> {
+        let mut widths: Vec<u32> = if self.pot {
+            std::iter::successors(Some(widest.next_power_of_two()), |w| w.checked_mul(2)).collect()
+        } else {
+            let area: u64 = sizes.iter().map(|s| s.0 as u64 * s.1 as u64).sum();
+            let side = (area as f64).sqrt();
+            (8..=24)
+                .map(|k| (side * k as f64 / 16.0).ceil() as u32)
+                .chain([widest])
+                .collect()
+        };
+        widths.retain(|w| (widest..=self.max_size).contains(w));
+        widths.sort_unstable();
+        widths.dedup();
+        widths
+    }
+
+    /// File names of the atlas image and its JSON map.
+    pub fn file_names(&self) -> (String, String) {
+        (format!("{}.png", self.name), format!("{}.json", self.name))
+    }
+
+    /// The JSON map of `atlas` in this spec's format.
+    pub fn json(&self, atlas: &Atlas) -> Result<Vec<u8>> {
+        let (image, _) = self.file_names();
+        let size = Size {
+            w: atlas.image.width(),
+            h: atlas.image.height(),
+        };
+        let frames = atlas
+            .frames
+            .iter()
+            .map(|f| (f.name.as_str(), FrameJson::from(f)));
+        let meta = Meta {
+            app: "IZDU Slicer",
+            version: "1.0",
+            image: &image,
+            format: "RGBA8888",
+            size,
+            scale: "1",
+        };
+        let named = |(filename, frame)| NamedFrame { filename, frame };
+        let json = match self.json {
+            JsonFormat::Hash => serde_json::to_vec_pretty(&HashJson {
+                frames: frames.collect(),
+                meta,
+            }),
+            JsonFormat::Array => serde_json::to_vec_pretty(&ArrayJson {
+                frames: frames.map(named).collect(),
+                meta,
+            }),
+            JsonFormat::Phaser => serde_json::to_vec_pretty(&PhaserJson {
+                textures: vec![Texture {
+                    image: &image,
+                    format: "RGBA8888",
+                    size,
+                    scale: 1,
+                    frames: frames.map(named).collect(),
+                }],
+                meta,
+            }),
+        };
+        json.map_err(Error::new)
+    }
+}
+
+/// Decode uploaded files into sprites, naming the one that fails.
+pub fn decode_sprites(files: Vec<(String, Vec<u8>)>) -> Result<Vec<Sprite>> {
+    files
+        .into_iter()
+        .map(|(name, data)| {
+            let image = image::load_from_memory(&data)
+                .map_err(|e| Error::msg(format!("Sprite {}: {}", name, e)))?
+                .into_rgba8();
+            Ok(Sprite { name, image })
+        })
+        .collect()
+}
+
+// One packer run: the atlas size and each sprite's position.
+struct Packing {
+    size: (u32, u32),
+    positions: Vec<(u32, u32)>,
+}
+
+impl Packing {
+    // Smaller area wins, then the squarer atlas.
+    fn score(&self) -> (u64, u32) {
+        (
+            self.size.0 as u64 * self.size.1 as u64,
+            self.size.0.max(self.size.1),
+        )
+    }
+}
+
+// The smallest rectangle of pixels that aren't fully transparent.
+fn opaque_bounds(img: &RgbaImage) -> Option<Rect> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, p) in img.enumerate_pixels() {
+        if p.0[3] > 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (min_x != u32::MAX).then(|| Rect {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    })
+}
+
+/// Top-left corners for `sizes` in a `width` x `height` bin, in input order,
+/// or `None` if they don't all fit. Larger sprites are placed first.
+fn place(packer: Packer, sizes: &[(u32, u32)], width: u32, height: u32) -> Option<Vec<(u32, u32)>> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    let mut positions = vec![(0, 0); sizes.len()];
+    match packer {
+        Packer::Shelf => {
+            order.sort_by_key(|&i| std::cmp::Reverse((sizes[i].1, sizes[i].0)));
+            let (mut x, mut y, mut shelf) = (0, 0, 0);
+            for i in order {
+                let (w, h) = sizes[i];
+                if x + w > width {
+                    (x, y, shelf) = (0, y + shelf, 0);
+                }
+                if x + w > width || y + h > height {
+                    return None;
+                }
+                positions[i] = (x, y);
+                x += w;
+                shelf = shelf.max(h);
+            }
+        }
+        Packer::MaxRects => {
+            order.sort_by_key(|&i| {
+                std::cmp::Reverse((sizes[i].0.max(sizes[i].1), sizes[i].0 * sizes[i].1))
+            });
+            let mut free = vec![Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            }];
+            for i in order {
+                let (w, h) = sizes[i];
+                // Best short side fit: the free rect leaving the least on its tighter side.
+                let spot = free
+                    .iter()
+                    .filter(|f| f.width >= w && f.height >= h)
+                    .min_by_key(|f| {
+                        let (dw, dh) = (f.width - w, f.height - h);
+                        (dw.min(dh), dw.max(dh), f.y, f.x)
+                    })
+                    .map(|f| (f.x, f.y))?;
+                positions[i] = spot;
+                split_free(
+                    &mut free,
+                    Rect {
+                        x: spot.0,
+                        y: spot.1,
+                        width: w,
+                        height: h,
+                    },
+                );
+            }
+        }
+    }
+    Some(positions)
+}
+
+// Carve `used` out of every free rect it overlaps, then drop free rects
+// contained in others.
+fn split_free(free: &mut Vec<Rect>, used: Rect) {
+    let mut next = Vec::with_capacity(free.len() + 4);
+    for f in free.drain(..) {
+        let overlaps = used.x < f.x + f.width
+            && used.x + used.width > f.x
+            && used.y < f.y + f.height
+            && used.y + used.height > f.y;
+        if !overlaps {
+            next.push(f);
+            continue;
+        }
+        if used.x > f.x {
+            next.push(Rect {
+                width: used.x - f.x,
+                ..f
+            });
+        }
+        if used.x + used.width < f.x + f.width {
+            let x = used.x + used.width;
+            next.push(Rect {
+                x,
+                width: f.x + f.width - x,
+                ..f
+            });
+        }
+        if used.y > f.y {
+            next.push(Rect {
+                height: used.y - f.y,
+                ..f
+            });
+        }
+        if used.y + used.height < f.y + f.height {
+            let y = used.y + used.height;
+            next.push(Rect {
+                y,
+                height: f.y + f.height - y,
+                ..f
+            });
+        }
+    }
+    let contains = |a: &Rect, b: &Rect| {
+        a.x <= b.x
+            && a.y <= b.y
+            && a.x + a.width >= b.x + b.width
+            && a.y + a.height >= b.y + b.height
+    };
+    let mut kept: Vec<Rect> = Vec::with_capacity(next.len());
+    for (i, r) in next.iter().enumerate() {
+        // Of identical rects keep the first.
+        let redundant = next
+            .iter()
+            .enumerate()
+            .any(|(j, o)| j != i && contains(o, r) && (o != r || j < i));
+        if !redundant {
+            kept.push(*r);
+        }
+    }
+    *free = kept;
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct Size {
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct RectJson {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl From<Rect> for RectJson {
+    fn from(r: Rect) -> Self {
+        RectJson {
+            x: r.x,
+            y: r.y,
+            w: r.width,
+            h: r.height,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FrameJson {
+    frame: RectJson,
+    rotated: bool,
+    trimmed: bool,
+    sprite_source_size: RectJson,
+    source_size: Size,
+}
+
+impl From<&Frame> for FrameJson {
+    fn from(f: &Frame) -> Self {
+        FrameJson {
+            frame: f.frame.into(),
+            rotated: false,
+            trimmed: f.trimmed(),
+            sprite_source_size: f.source.into(),
+            source_size: Size {
+                w: f.source_size.0,
+                h: f.source_size.1,
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct NamedFrame<'a> {
+    filename: &'a str,
+    #[serde(flatten)]
+    frame: FrameJson,
+}
+
+#[derive(Serialize)]
+struct Meta<'a> {
+    app: &'static str,
+    version: &'static str,
+    image: &'a str,
+    format: &'static str,
+    size: Size,
+    scale: &'static str,
+}
+
+#[derive(Serialize)]
+struct HashJson<'a> {
+    frames: BTreeMap<&'a str, FrameJson>,
+    meta: Meta<'a>,
+}
+
+#[derive(Serialize)]
+struct ArrayJson<'a> {
+    frames: Vec<NamedFrame<'a>>,
+    meta: Meta<'a>,
+}
+
+#[derive(Serialize)]
+struct Texture<'a> {
+    image: &'a str,
+    format: &'static str,
+    size: Size,
+    scale: u32,
+    frames: Vec<NamedFrame<'a>>,
+}
+
+#[derive(Serialize)]
+struct PhaserJson<'a> {
+    textures: Vec<Texture<'a>>,
+    meta: Meta<'a>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(args: AtlasArgs) -> AtlasSpec {
+        AtlasSpec::from_args(&args).unwrap()
+    }
+
+    // Sprites of mixed sizes, each a flat colour of its index.
+    fn sprites(sizes: &[(u32, u32)]) -> Vec<Sprite> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| Sprite {
+                name: format!("s{}.png", i),
+                image: ImageBuffer::from_pixel(w, h, Rgba([i as u8, 0, 0, 255])),
+            })
+            .collect()
+    }
+
+    fn disjoint(frames: &[Frame], padding: u32) -> bool {
+        frames.iter().enumerate().all(|(i, a)| {
+            frames[i + 1..].iter().all(|b| {
+                a.frame.x + a.frame.width + padding <= b.frame.x
+                    || b.frame.x + b.frame.width + padding <= a.frame.x
+                    || a.frame.y + a.frame.height + padding <= b.frame.y
+                    || b.frame.y + b.frame.height + padding <= a.frame.y
+            })
+        })
+    }
+
+    const SIZES: [(u32, u32); 7] = [
+        (64, 32),
+        (32, 32),
+        (16, 48),
+        (40, 10),
+        (10, 40),
+        (24, 24),
+        (8, 8),
+    ];
+
+    #[test]
+    fn both_packers_place_every_sprite_apart() {
+        for packer in ["maxrects", "shelf"] {
+            let spec = spec(AtlasArgs {
+                packer: Some(packer),
+                ..AtlasArgs::default()
+            });
+            let atlas = spec.pack(sprites(&SIZES)).unwrap();
+            assert_eq!(atlas.frames.len(), SIZES.len());
+            assert!(
+                disjoint(&atlas.frames, DEFAULT_PADDING),
+                "{} overlaps",
+                packer
+            );
+            for (i, f) in atlas.frames.iter().enumerate() {
+                assert!(f.frame.x + f.frame.width <= atlas.image.width());
+                assert!(f.frame.y + f.frame.height <= atlas.image.height());
+                assert_eq!(
+                    atlas.image.get_pixel(f.frame.x, f.frame.y).0,
+                    [i as u8, 0, 0, 255]
+                );
+            }
+            // Within twice the sprites' area, padding included.
+            let area: u32 = SIZES.iter().map(|(w, h)| (w + 2) * (h + 2)).sum();
+            assert!(
+                atlas.image.width() * atlas.image.height() < 2 * area,
+                "{} is loose",
+                packer
+            );
+        }
+    }
+
+    #[test]
+    fn pot_and_trim() {
+        let mut sprites = sprites(&[(30, 20), (20, 20)]);
+        // Opaque only at 5..15 x 4..10.
+        let mut padded = ImageBuffer::from_pixel(20, 12, Rgba([0, 0, 0, 0]));
+        for (x, y, p) in padded.enumerate_pixels_mut() {
+            if (5..15).contains(&x) && (4..10).contains(&y) {
+                *p = Rgba([9, 9, 9, 255]);
+            }
+        }
+        sprites.push(Sprite {
+            name: "padded.png".into(),
+            image: padded,
+        });
+
+        let spec = spec(AtlasArgs {
+            pot: Some(true),
+            trim: Some(true),
+            ..AtlasArgs::default()
+        });
+        let atlas = spec.pack(sprites).unwrap();
+        assert!(atlas.image.width().is_power_of_two() && atlas.image.height().is_power_of_two());
+        let trimmed = &atlas.frames[2];
+        assert!(trimmed.trimmed());
+        assert_eq!(trimmed.source.to_string(), "5,4,10,6");
+        assert_eq!((trimmed.frame.width, trimmed.frame.height), (10, 6));
+        assert!(!atlas.frames[0].trimmed());
+    }
+
+    #[test]
+    fn json_maps_follow_texturepacker_and_phaser() {
+        let pack = |json: &str| {
+            let spec = spec(AtlasArgs {
+                json: Some(json),
+                name: Some("heroes"),
+                ..AtlasArgs::default()
+            });
+            let atlas = spec.pack(sprites(&[(8, 8), (4, 4)])).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&spec.json(&atlas).unwrap()).unwrap()
+        };
+        let hash = pack("hash");
+        assert_eq!(
+            hash["frames"]["s1.png"]["sourceSize"],
+            serde_json::json!({"w": 4, "h": 4})
+        );
+        assert_eq!(hash["frames"]["s0.png"]["trimmed"], false);
+        assert_eq!(hash["meta"]["image"], "heroes.png");
+
+        let array = pack("array");
+        assert_eq!(array["frames"][1]["filename"], "s1.png");
+        assert_eq!(array["frames"][1]["frame"]["w"], 4);
+
+        let phaser = pack("phaser");
+        assert_eq!(phaser["textures"][0]["image"], "heroes.png");
+        assert_eq!(
+            phaser["textures"][0]["frames"][0]["spriteSourceSize"]["w"],
+            8
+        );
+    }
+
+    #[test]
+    fn rejects_bad_params_and_sprites() {
+        let bad = |args: AtlasArgs| AtlasSpec::from_args(&args).is_err();
+        assert!(bad(AtlasArgs {
+            packer: Some("guillotine"),
+            ..AtlasArgs::default()
+        }));
+        assert!(bad(AtlasArgs {
+            json: Some("xml"),
+            ..AtlasArgs::default()
+        }));
+        assert!(bad(AtlasArgs {
+            max_size: Some(10000),
+            ..AtlasArgs::default()
+        }));
+        assert!(bad(AtlasArgs {
+            name: Some("../x"),
+            ..AtlasArgs::default()
+        }));
+
+        let small = spec(AtlasArgs {
+            max_size: Some(32),
+            ..AtlasArgs::default()
+        });
+        assert!(small.pack(sprites(&[(40, 8)])).is_err());
+        assert!(small.pack(sprites(&[(30, 30), (30, 30)])).is_err());
+        let mut twins = sprites(&[(4, 4), (4, 4)]);
+        twins[1].name = twins[0].name.clone();
+        assert!(small.pack(twins).is_err());
+    }
+}
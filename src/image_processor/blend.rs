@@ -0,0 +1,256 @@
+use anyhow::{Error, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, Rgba};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Separable blend modes, as defined by the W3C Compositing and Blending spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Difference,
+}
+
+impl FromStr for BlendMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            "difference" => Ok(BlendMode::Difference),
+            _ => Err(Error::msg(format!("Unknown blend mode: {}", s))),
+        }
+    }
+}
+
+impl BlendMode {
+    // `cb` is the backdrop channel, `cs` the source channel, both in 0..=1.
+    fn apply(self, cb: f32, cs: f32) -> f32 {
+        match self {
+            BlendMode::Normal => cs,
+            BlendMode::Multiply => cb * cs,
+            BlendMode::Screen => cb + cs - cb * cs,
+            BlendMode::Overlay => {
+                if cb <= 0.5 {
+                    2.0 * cb * cs
+                } else {
+                    1.0 - 2.0 * (1.0 - cb) * (1.0 - cs)
+                }
+            }
+            BlendMode::Difference => (cb - cs).abs(),
+        }
+    }
+}
+
+/// How a watermark is mixed into the image. With `linear` set, channels are
+/// converted from sRGB to linear light before blending and back afterwards.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Blend {
+    pub mode: BlendMode,
+    pub linear: bool,
+}
+
+impl Blend {
+    pub fn parse(mode: Option<&str>, linear: Option<bool>) -> Result<Blend> {
+        let mode = match mode {
+            Some(m) if !m.is_empty() => m.parse()?,
+            _ => BlendMode::Normal,
+        };
+        Ok(Blend {
+            mode,
+            linear: linear.unwrap_or(false),
+        })
+    }
+
+    /// Source-over composite of `src` onto `dst`, with the source alpha scaled by
+    /// `opacity`. Works on premultiplied values internally, so the output alpha
+    /// is correct for transparent backdrops.
+    pub fn composite(&self, dst: Rgba<u8>, src: Rgba<u8>, opacity: f32) -> Rgba<u8> {
+        let a_s = src[3] as f32 / 255.0 * opacity;
+        if a_s <= 0.0 {
+            return dst;
+        }
+        let a_b = dst[3] as f32 / 255.0;
+        let a_o = a_s + a_b * (1.0 - a_s);
+
+        let mut out = [0u8; 4];
+        for c in 0..3 {
+            let cs = self.decode(src[c]);
+            let cb = self.decode(dst[c]);
+            let mixed = (1.0 - a_b) * cs + a_b * self.mode.apply(cb, cs);
+            let co = a_s * mixed + (1.0 - a_s) * a_b * cb;
+            out[c] = self.encode(co / a_o);
+        }
+        out[3] = to_u8(a_o);
+        Rgba(out)
+    }
+
+    fn decode(&self, v: u8) -> f32 {
+        if self.linear {
+            srgb_to_linear_table()[v as usize]
+        } else {
+            v as f32 / 255.0
+        }
+    }
+
+    fn encode(&self, v: f32) -> u8 {
+        if self.linear {
+            to_u8(linear_to_srgb(v))
+        } else {
+            to_u8(v)
+        }
+    }
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn srgb_to_linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *v = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        table
+    })
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Resize with premultiplied alpha, so transparent pixels don't bleed their
+/// (usually black) colour into the edges of cut-outs.
+pub fn resize_premultiplied(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+) -> DynamicImage {
+    let mut premultiplied = img.to_rgba32f();
+    premultiplied.pixels_mut().for_each(|p| {
+        let a = p[3];
+        p[0] *= a;
+        p[1] *= a;
+        p[2] *= a;
+    });
+
+    let resized = image::imageops::resize(&premultiplied, width, height, filter);
+    let out = ImageBuffer::from_fn(width, height, |x, y| {
+        let p = resized.get_pixel(x, y);
+        let a = p[3].clamp(0.0, 1.0);
+        if a <= 0.0 {
+            return Rgba([0u8, 0, 0, 0]);
+        }
+        Rgba([to_u8(p[0] / a), to_u8(p[1] / a), to_u8(p[2] / a), to_u8(a)])
+    });
+    DynamicImage::ImageRgba8(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    fn blend(mode: &str) -> Blend {
+        Blend::parse(Some(mode), None).unwrap()
+    }
+
+    #[test]
+    fn normal_opaque_replaces_backdrop() {
+        assert_eq!(blend("normal").composite(BLUE, WHITE, 1.0), WHITE);
+    }
+
+    #[test]
+    fn multiply_with_white_keeps_backdrop() {
+        assert_eq!(blend("multiply").composite(BLUE, WHITE, 1.0), BLUE);
+    }
+
+    #[test]
+    fn screen_with_white_is_white() {
+        assert_eq!(blend("screen").composite(BLUE, WHITE, 1.0), WHITE);
+    }
+
+    #[test]
+    fn difference_with_white_inverts() {
+        assert_eq!(
+            blend("difference").composite(BLUE, WHITE, 1.0),
+            Rgba([255, 255, 0, 255])
+        );
+    }
+
+    #[test]
+    fn overlay_keeps_extreme_backdrop_channels() {
+        let grey = Rgba([128, 128, 128, 255]);
+        assert_eq!(blend("overlay").composite(BLUE, grey, 1.0)[0], 0);
+        assert_eq!(blend("overlay").composite(BLUE, grey, 1.0)[2], 255);
+    }
+
+    #[test]
+    fn transparent_backdrop_takes_source_alpha() {
+        let red = Rgba([255, 0, 0, 255]);
+        let out = blend("normal").composite(Rgba([0, 0, 0, 0]), red, 0.5);
+        assert_eq!(out, Rgba([255, 0, 0, 128]));
+    }
+
+    #[test]
+    fn zero_opacity_leaves_backdrop() {
+        assert_eq!(blend("multiply").composite(BLUE, WHITE, 0.0), BLUE);
+    }
+
+    #[test]
+    fn linear_light_blend_is_brighter_at_half_opacity() {
+        let black = Rgba([0, 0, 0, 255]);
+        let srgb = blend("normal").composite(black, WHITE, 0.5);
+        let linear = Blend::parse(None, Some(true))
+            .unwrap()
+            .composite(black, WHITE, 0.5);
+        assert_eq!(srgb[0], 128);
+        assert!(
+            linear[0] > 180,
+            "linear-light mid grey should be ~188, got {}",
+            linear[0]
+        );
+    }
+
+    #[test]
+    fn resize_premultiplied_has_no_dark_fringe() {
+        let img = DynamicImage::ImageRgba8(ImageBuffer::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                WHITE
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        }));
+        let out = resize_premultiplied(&img, 1, 1, FilterType::Triangle).to_rgba8();
+        let px = out.get_pixel(0, 0);
+        assert_eq!(px[0], 255, "colour should stay white, got {:?}", px);
+        assert!(px[3] > 0 && px[3] < 255);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_mode() {
+        assert!(Blend::parse(Some("dodge"), None).is_err());
+    }
+}
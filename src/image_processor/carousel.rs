@@ -0,0 +1,314 @@
+use crate::image_processor::image_slicer;
+use crate::image_processor::resize::{self, Fit, Rect, ResizeParams};
+use anyhow::{Error, Result};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use std::str::FromStr;
+
+/// Instagram's limit on slides per post.
+pub const MAX_SLIDES: u32 = 20;
+/// Slide width the platforms recommend, in px.
+pub const DEFAULT_SLIDE_WIDTH: u32 = 1080;
+const MAX_SLIDE_WIDTH: u32 = 4096;
+/// Largest strip of slides, in pixels, before it is cut up.
+pub const MAX_PIXELS: u64 = 100_000_000;
+
+/// Slide shape: a platform format with its recommended size, or any `W:H`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    /// 1:1, 1080x1080.
+    Square,
+    /// 4:5, 1080x1350.
+    Portrait,
+    /// 1.91:1, 1080x566.
+    Landscape,
+    /// 9:16, 1080x1920.
+    Story,
+    Ratio(f64, f64),
+}
+
+impl Shape {
+    /// Slide size in px at `width`, or at the recommended width.
+    pub fn slide_size(self, width: Option<u32>) -> (u32, u32) {
+        let (w, h) = match self {
+            Shape::Square => (1080.0, 1080.0),
+            Shape::Portrait => (1080.0, 1350.0),
+            Shape::Landscape => (1080.0, 566.0),
+            Shape::Story => (1080.0, 1920.0),
+            Shape::Ratio(w, h) => (w, h),
+        };
+        let width = width.unwrap_or(DEFAULT_SLIDE_WIDTH);
+        (width, ((width as f64 * h / w).round() as u32).max(1))
+    }
+}
+
+impl FromStr for Shape {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let shape = match s.trim().to_lowercase().as_str() {
+            "square" | "1:1" => Shape::Square,
+            "portrait" | "4:5" => Shape::Portrait,
+            "landscape" | "1.91:1" => Shape::Landscape,
+            "story" | "9:16" => Shape::Story,
+            ratio => {
+                let parts = ratio.split_once(':').and_then(|(w, h)| {
+                    Some((w.trim().parse::<f64>().ok()?, h.trim().parse::<f64>().ok()?))
+                });
+                match parts {
+                    Some((w, h)) if w > 0.0 && h > 0.0 && (0.1..=10.0).contains(&(w / h)) => Shape::Ratio(w, h),
+                    _ => {
+                        return Err(Error::msg(format!(
+                            "Unknown aspect: {} (use square, portrait, landscape, story or W:H between 1:10 and 10:1)",
+                            s
+                        )))
+                    }
+                }
+            }
+        };
+        Ok(shape)
+    }
+}
+
+/// Carousel params as they arrive in a request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CarouselArgs<'a> {
+    pub aspect: Option<&'a str>,
+    pub slides: Option<u32>,
+    pub fit: Option<&'a str>,
+    pub gravity: Option<&'a str>,
+    pub background: Option<&'a str>,
+    pub filter: Option<&'a str>,
+    pub width: Option<u32>,
+}
+
+/// How to cut an image into equal slides of one shape. With no slide count,
+/// it is whatever count best matches the image's aspect ratio.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CarouselSpec {
+    pub shape: Shape,
+    pub slides: Option<u32>,
+    pub slide_size: (u32, u32),
+    /// `Cover` crops the image to the strip of slides, `Contain` pads it.
+    pub params: ResizeParams,
+}
+
+/// The slides, whether the source was enlarged to make them, and for crops
+/// the part of the source they show.
+pub struct Carousel {
+    pub slides: Vec<ImageBuffer<Rgba<u8>, Vec<u8>>>,
+    pub upscaled: bool,
+    pub crop: Option<Rect>,
+}
+
+impl CarouselSpec {
+    pub fn from_args(args: &CarouselArgs) -> Result<CarouselSpec> {
+        let shape: Shape = args
+            .aspect
+            .filter(|a| !a.is_empty())
+            .unwrap_or("portrait")
+            .parse()?;
+        let slides = match args.slides {
+            None | Some(0) => None,
+            Some(n) if n <= MAX_SLIDES => Some(n),
+            Some(n) => {
+                return Err(Error::msg(format!(
+                    "slides must be 1-{}, got {}",
+                    MAX_SLIDES, n
+                )))
+            }
+        };
+        let width = match args.width {
+            None | Some(0) => None,
+            Some(w) if w <= MAX_SLIDE_WIDTH => Some(w),
+            Some(w) => {
+                return Err(Error::msg(format!(
+                    "width must be 1-{}, got {}",
+                    MAX_SLIDE_WIDTH, w
+                )))
+            }
+        };
+        let fit = match args.fit.map(|f| f.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("crop") | Some("cover") => "cover",
+            Some("pad") | Some("contain") => "contain",
+            Some(_) => {
+                return Err(Error::msg(format!(
+                    "Unknown carousel fit: {} (use crop or pad)",
+                    args.fit.unwrap_or_default()
+                )))
+            }
+        };
+        let slide_size = shape.slide_size(width);
+        // Sized per image in `split`; the slide size stands in for validation.
+        let params =
+            ResizeParams::parse(Some(slide_size.0), Some(slide_size.1), None, args.filter)?
+                .with_fit(Some(fit), args.gravity, args.background)?;
+        Ok(CarouselSpec {
+            shape,
+            slides,
+            slide_size,
+            params,
+        })
+    }
+
+    /// Slides for an image of `size`: the requested count, or the one whose
+    /// strip is closest to the image's aspect ratio.
+    pub fn slide_count(&self, size: (u32, u32)) -> u32 {
+        self.slides.unwrap_or_else(|| {
+            let image = size.0 as f64 / size.1.max(1) as f64;
+            let slide = self.slide_size.0 as f64 / self.slide_size.1 as f64;
+            ((image / slide).round() as u32).clamp(1, MAX_SLIDES)
+        })
+    }
+
+    /// Crop or pad `img` to a strip exactly `n` slides wide, then cut it up
+    /// with `image_slicer::view_cells`.
+    pub fn split(&self, img: &DynamicImage) -> Result<Carousel> {
+        let n = self.slide_count((img.width(), img.height()));
+        let (w, h) = self.slide_size;
+        let width = n as u64 * w as u64;
+        if width * h as u64 > MAX_PIXELS {
+            return Err(Error::msg(format!(
+                "Carousel strip {}x{} is larger than {} megapixels",
+                width,
+                h,
+                MAX_PIXELS / 1_000_000
+            )));
+        }
+        // Slides must come out at exactly the slide size, so small sources
+        // are always enlarged; the `allow_upscale` policy doesn't apply and
+        // `upscaled` reports it instead.
+        let params = ResizeParams {
+            width: Some(n * w),
+            height: Some(h),
+            allow_upscale: true,
+            ..self.params
+        };
+        let strip = resize::resize(img, &params)?;
+        let cells = (0..n).map(|i| Rect {
+            x: i * w,
+            y: 0,
+            width: w,
+            height: h,
+        });
+        Ok(Carousel {
+            slides: image_slicer::view_cells(&strip.image, cells),
+            upscaled: strip.upscaled,
+            crop: strip.crop.filter(|_| self.params.fit == Fit::Cover),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(args: CarouselArgs) -> CarouselSpec {
+        CarouselSpec::from_args(&args).unwrap()
+    }
+
+    // Left half red, right half blue.
+    fn panorama(w: u32, h: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_fn(w, h, |x, _| {
+            if x < w / 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn shapes_use_platform_sizes() {
+        assert_eq!(
+            "square".parse::<Shape>().unwrap().slide_size(None),
+            (1080, 1080)
+        );
+        assert_eq!(
+            "4:5".parse::<Shape>().unwrap().slide_size(None),
+            (1080, 1350)
+        );
+        assert_eq!(
+            "landscape".parse::<Shape>().unwrap().slide_size(None),
+            (1080, 566)
+        );
+        assert_eq!(
+            "3:2".parse::<Shape>().unwrap().slide_size(Some(300)),
+            (300, 200)
+        );
+        assert!("0:1".parse::<Shape>().is_err());
+        assert!("wide".parse::<Shape>().is_err());
+    }
+
+    #[test]
+    fn auto_count_crops_to_exact_strip() {
+        let spec = spec(CarouselArgs {
+            aspect: Some("square"),
+            width: Some(40),
+            ..CarouselArgs::default()
+        });
+        // 3.2:1 rounds to 3 square slides; the sides are cropped.
+        let carousel = spec.split(&panorama(320, 100)).unwrap();
+        assert_eq!(carousel.slides.len(), 3);
+        assert!(carousel.slides.iter().all(|s| s.dimensions() == (40, 40)));
+        assert_eq!(carousel.crop.unwrap().to_string(), "10,0,300,100");
+        assert_eq!(carousel.slides[0].get_pixel(20, 20).0, [255, 0, 0, 255]);
+        assert_eq!(carousel.slides[2].get_pixel(20, 20).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn fixed_count_pads_with_background() {
+        let spec = spec(CarouselArgs {
+            aspect: Some("square"),
+            slides: Some(4),
+            fit: Some("pad"),
+            background: Some("#ffffff"),
+            width: Some(25),
+            ..CarouselArgs::default()
+        });
+        let carousel = spec.split(&panorama(200, 100)).unwrap();
+        assert_eq!(carousel.slides.len(), 4);
+        assert_eq!(carousel.crop, None);
+        // 200x100 scaled into 100x25 leaves 25 px bars at both ends.
+        assert_eq!(carousel.slides[0].get_pixel(5, 12).0, [255, 255, 255, 255]);
+        assert_eq!(carousel.slides[1].get_pixel(5, 12).0, [255, 0, 0, 255]);
+        assert_eq!(carousel.slides[3].get_pixel(20, 12).0, [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn oversized_strips_are_rejected() {
+        let spec = spec(CarouselArgs {
+            aspect: Some("1:2"),
+            slides: Some(MAX_SLIDES),
+            width: Some(MAX_SLIDE_WIDTH),
+            ..CarouselArgs::default()
+        });
+        let err = spec.split(&panorama(20, 10)).err().unwrap();
+        assert!(err.to_string().contains("megapixels"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_bad_params() {
+        let bad = |args: CarouselArgs| CarouselSpec::from_args(&args).is_err();
+        assert!(bad(CarouselArgs {
+            slides: Some(21),
+            ..CarouselArgs::default()
+        }));
+        assert!(bad(CarouselArgs {
+            width: Some(5000),
+            ..CarouselArgs::default()
+        }));
+        assert!(bad(CarouselArgs {
+            aspect: Some("1:10"),
+            width: Some(MAX_SLIDE_WIDTH),
+            ..CarouselArgs::default()
+        }));
+        assert!(bad(CarouselArgs {
+            fit: Some("stretch"),
+            ..CarouselArgs::default()
+        }));
+        assert!(bad(CarouselArgs {
+            gravity: Some("up"),
+            ..CarouselArgs::default()
+        }));
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{Error, Result};
+use image::Rgba;
+
+/// Parse a colour as `#rgb`, `#rrggbb` or `#rrggbbaa` (the `#` is optional),
+/// or one of the names `transparent`, `black` and `white`.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>> {
+    match s.trim().to_lowercase().as_str() {
+        "transparent" => return Ok(Rgba([0, 0, 0, 0])),
+        "black" => return Ok(Rgba([0, 0, 0, 255])),
+        "white" => return Ok(Rgba([255, 255, 255, 255])),
+        _ => {}
+    }
+
+    let hex = s.trim().trim_start_matches('#');
+    let invalid = || Error::msg(format!("Invalid colour: {} (use #rrggbb or #rrggbbaa)", s));
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize, len: usize| {
+        let v = u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).map_err(|_| invalid())?;
+        Ok::<u8, Error>(if len == 1 { v * 17 } else { v })
+    };
+    match hex.len() {
+        3 => Ok(Rgba([channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 255])),
+        6 => Ok(Rgba([channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255])),
+        8 => Ok(Rgba([
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        ])),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_forms_and_names() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("f80").unwrap(), Rgba([255, 136, 0, 255]));
+        assert_eq!(parse_color("#00000080").unwrap(), Rgba([0, 0, 0, 128]));
+        assert_eq!(parse_color("Transparent").unwrap(), Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn rejects_malformed_colours() {
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#gg0000").is_err());
+        assert!(parse_color("red").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+use crate::image_processor::color::parse_color;
+use crate::image_processor::resize::{self, Fit, ResizeParams};
+use crate::image_processor::text::{self, TextStyle, TileContext};
+use crate::image_processor::{load_image, watermark, ImageSource};
+use anyhow::{Error, Result};
+use futures::{StreamExt, TryStreamExt};
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+
+/// Upper bound on images per sheet.
+pub const MAX_IMAGES: usize = 100;
+/// How many sources are fetched and decoded at once.
+pub const MAX_CONCURRENT_LOADS: usize = 8;
+/// Upper bound on the sheet, in pixels.
+pub const MAX_PIXELS: u64 = 100_000_000;
+pub const DEFAULT_CELL: u32 = 256;
+pub const MAX_CELL: u32 = 2048;
+pub const DEFAULT_GUTTER: u32 = 8;
+const MAX_GUTTER: u32 = 512;
+
+/// One image of a sheet and the caption to put under it, if not the sheet's.
+pub struct SheetEntry {
+    pub source: ImageSource,
+    pub caption: Option<String>,
+    pub filename: String,
+}
+
+impl SheetEntry {
+    pub fn new(source: ImageSource, caption: Option<String>) -> SheetEntry {
+        let filename = source.file_name().unwrap_or_default();
+        SheetEntry {
+            source,
+            caption,
+            filename,
+        }
+    }
+}
+
+/// A loaded entry, already scaled by the sheet's `resize`, ready to lay out.
+pub struct SheetCell {
+    pub image: DynamicImage,
+    pub caption: Option<String>,
+    pub filename: String,
+}
+
+/// Contact sheet params as they arrive in a request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SheetArgs<'a> {
+    pub cols: Option<u32>,
+    pub cell_width: Option<u32>,
+    pub cell_height: Option<u32>,
+    pub gutter: Option<u32>,
+    pub background: Option<&'a str>,
+    pub fit: Option<&'a str>,
+    pub filter: Option<&'a str>,
+    pub caption: Option<&'a str>,
+    pub caption_size: Option<u32>,
+    pub caption_color: Option<&'a str>,
+    pub font: Option<&'a str>,
+}
+
+/// How to lay images out: `cols` columns (square-ish when unset) of
+/// `cell`-sized cells, `gutter` px of `background` around and between them,
+/// and a `caption_size` px strip under each cell when there are captions.
+#[derive(Clone, Debug)]
+pub struct SheetSpec {
+    pub cols: Option<u32>,
+    pub cell: (u32, u32),
+    pub gutter: u32,
+    pub background: Rgba<u8>,
+    pub resize: ResizeParams,
+    pub caption: Option<String>,
+    pub caption_size: Option<u32>,
+    pub caption_color: Rgba<u8>,
+    pub style: TextStyle,
+}
+
+impl SheetSpec {
+    pub fn from_args(args: &SheetArgs) -> Result<SheetSpec> {
+        let cell_width = args.cell_width.filter(|w| *w > 0).unwrap_or(DEFAULT_CELL);
+        let cell_height = args.cell_height.filter(|h| *h > 0).unwrap_or(cell_width);
+        if cell_width > MAX_CELL || cell_height > MAX_CELL {
+            return Err(Error::msg(format!(
+                "Cells can be at most {}px a side, got {}x{}",
+                MAX_CELL, cell_width, cell_height
+            )));
+        }
+        let gutter = args.gutter.unwrap_or(DEFAULT_GUTTER);
+        if gutter > MAX_GUTTER {
+            return Err(Error::msg(format!(
+                "Gutter can be at most {}px, got {}",
+                MAX_GUTTER, gutter
+            )));
+        }
+        if let Some(size) = args.caption_size.filter(|s| *s > MAX_CELL) {
+            return Err(Error::msg(format!(
+                "Caption size can be at most {}px, got {}",
+                MAX_CELL, size
+            )));
+        }
+        let background = args.background.filter(|b| !b.is_empty()).unwrap_or("white");
+        let caption_color = match args.caption_color.filter(|c| !c.is_empty()) {
+            Some(c) => parse_color(c)?,
+            None => Rgba([0, 0, 0, 255]),
+        };
+        let mut resize =
+            ResizeParams::parse(Some(cell_width), Some(cell_height), None, args.filter)?;
+        resize.fit = Fit::Contain;
+        Ok(SheetSpec {
+            cols: args.cols.filter(|c| *c > 0),
+            cell: (cell_width, cell_height),
+            gutter,
+            background: parse_color(background)?,
+            resize: resize.with_fit(args.fit, None, Some(background))?,
+            caption: args.caption.filter(|c| !c.is_empty()).map(str::to_string),
+            caption_size: args.caption_size.filter(|s| *s > 0),
+            caption_color,
+            style: TextStyle::parse(None, None, args.font)?,
+        })
+    }
+
+    /// Columns and rows for `count` images.
+    pub fn grid(&self, count: usize) -> (u32, u32) {
+        let count = count.max(1) as u32;
+        let cols = self
+            .cols
+            .unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+            .min(count);
+        (cols, count.div_ceil(cols))
+    }
+
+    /// Load every entry and scale it for its cell, at most
+    /// `MAX_CONCURRENT_LOADS` at a time, keeping their order. The first
+    /// failure names the image it came from and drops the loads still in flight.
+    pub async fn load_all(&self, entries: Vec<SheetEntry>) -> Result<Vec<SheetCell>> {
+        if entries.len() > MAX_IMAGES {
+            return Err(Error::msg(format!(
+                "A contact sheet holds at most {} images, got {}",
+                MAX_IMAGES,
+                entries.len()
+            )));
+        }
+        let params = self.resize;
+        futures::stream::iter(entries.into_iter().enumerate())
+            .map(|(i, entry)| async move {
+                let named = |e: Error| Error::msg(format!("Image {}: {}", i + 1, e));
+                let image = load_image(entry.source).await.map_err(named)?;
+                let image = resize::resize(&image, &params).map_err(named)?.image;
+                Ok(SheetCell {
+                    image,
+                    caption: entry.caption,
+                    filename: entry.filename,
+                })
+            })
+            .buffered(MAX_CONCURRENT_LOADS)
+            .try_collect()
+            .await
+    }
+
+    /// Lay `cells` out in reading order, each centred in its cell, with its
+    /// caption (the entry's own, else the sheet's template) in the strip below.
+    pub fn render(&self, cells: &[SheetCell]) -> Result<RgbaImage> {
+        if cells.is_empty() {
+            return Err(Error::msg("A contact sheet needs at least one image"));
+        }
+        if cells.len() > MAX_IMAGES {
+            return Err(Error::msg(format!(
+                "A contact sheet holds at most {} images, got {}",
+                MAX_IMAGES,
+                cells.len()
+            )));
+        }
+        let (cols, rows) = self.grid(cells.len());
+        let date = text::today();
+        let captions: Vec<Option<String>> = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                let tile = TileContext::for_grid(
+                    i as u32,
+                    cols,
+                    cells.len() as u32,
+                    &cell.filename,
+                    &date,
+                );
+                cell.caption
+                    .as_deref()
+                    .or(self.caption.as_deref())
+                    .map(|c| text::expand_template(c, &tile))
+                    .filter(|c| !c.trim().is_empty())
+            })
+            .collect();
+        // Every row gets a strip as soon as one cell has a caption, so rows stay even.
+        let strip = if captions.iter().any(Option::is_some) {
+            self.caption_size.unwrap_or((self.cell.1 / 8).max(12))
+        } else {
+            0
+        };
+
+        let pitch = (self.cell.0 + self.gutter, self.cell.1 + strip + self.gutter);
+        let width = self.gutter as u64 + cols as u64 * pitch.0 as u64;
+        let height = self.gutter as u64 + rows as u64 * pitch.1 as u64;
+        if width * height > MAX_PIXELS {
+            return Err(Error::msg(format!(
+                "Contact sheet {}x{} is larger than {} megapixels",
+                width,
+                height,
+                MAX_PIXELS / 1_000_000
+            )));
+        }
+
+        let mut sheet = RgbaImage::from_pixel(width as u32, height as u32, self.background);
+        for (i, (cell, caption)) in cells.iter().zip(&captions).enumerate() {
+            let x = self.gutter + (i as u32 % cols) * pitch.0;
+            let y = self.gutter + (i as u32 / cols) * pitch.1;
+            let fitted = &cell.image;
+            // Fits other than contain may come out larger or smaller than the cell.
+            let (w, h) = (
+                fitted.width().min(self.cell.0),
+                fitted.height().min(self.cell.1),
+            );
+            let kept = fitted.view((fitted.width() - w) / 2, (fitted.height() - h) / 2, w, h);
+            let (dx, dy) = ((self.cell.0 - w) / 2, (self.cell.1 - h) / 2);
+            imageops::overlay(
+                &mut sheet,
+                &kept.to_image(),
+                (x + dx) as i64,
+                (y + dy) as i64,
+            );
+            if let Some(caption) = caption {
+                self.draw_caption(&mut sheet, caption, (x, y + self.cell.1), strip)?;
+            }
+        }
+        Ok(sheet)
+    }
+
+    // The caption goes centred in the strip, with a little air above and below.
+    fn draw_caption(
+        &self,
+        sheet: &mut RgbaImage,
+        text: &str,
+        at: (u32, u32),
+        strip: u32,
+    ) -> Result<()> {
+        let pad = strip / 8;
+        let box_size = (self.cell.0, strip.saturating_sub(2 * pad).max(1));
+        let mut label = watermark::fit_text(text, box_size, &self.style)?.to_rgba8();
+        // Rendered text is white with coverage in alpha; paint it in the caption colour.
+        let c = self.caption_color;
+        for px in label.pixels_mut() {
+            *px = Rgba([c[0], c[1], c[2], (px[3] as u16 * c[3] as u16 / 255) as u8]);
+        }
+        let x = at.0 + self.cell.0.saturating_sub(label.width()) / 2;
+        let y = at.1 + (strip.saturating_sub(label.height())) / 2;
+        imageops::overlay(sheet, &label, x as i64, y as i64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A cell as `load_all` leaves it: scaled by the sheet's `resize`.
+    fn cell(spec: &SheetSpec, w: u32, h: u32, caption: Option<&str>) -> SheetCell {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(w, h, Rgba([255, 0, 0, 255])));
+        SheetCell {
+            image: resize::resize(&image, &spec.resize).unwrap().image,
+            caption: caption.map(str::to_string),
+            filename: "photo.png".to_string(),
+        }
+    }
+
+    #[test]
+    fn grid_defaults_to_square_ish() {
+        let spec = SheetSpec::from_args(&SheetArgs::default()).unwrap();
+        assert_eq!(spec.grid(1), (1, 1));
+        assert_eq!(spec.grid(5), (3, 2));
+        assert_eq!(spec.grid(9), (3, 3));
+        let spec = SheetSpec::from_args(&SheetArgs {
+            cols: Some(4),
+            ..SheetArgs::default()
+        })
+        .unwrap();
+        assert_eq!(spec.grid(2), (2, 1));
+        assert_eq!(spec.grid(9), (4, 3));
+    }
+
+    #[test]
+    fn lays_out_cells_with_gutters() {
+        let spec = SheetSpec::from_args(&SheetArgs {
+            cols: Some(2),
+            cell_width: Some(20),
+            cell_height: Some(10),
+            gutter: Some(4),
+            background: Some("#0000ff"),
+            ..SheetArgs::default()
+        })
+        .unwrap();
+        let sheet = spec
+            .render(&[
+                cell(&spec, 40, 20, None),
+                cell(&spec, 20, 20, None),
+                cell(&spec, 10, 5, None),
+            ])
+            .unwrap();
+        assert_eq!(sheet.dimensions(), (4 + 2 * 24, 4 + 2 * 14));
+        assert_eq!(*sheet.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(*sheet.get_pixel(4, 4), Rgba([255, 0, 0, 255]));
+        // The square image is letterboxed to 10x10 in the middle of its 20x10 cell.
+        assert_eq!(*sheet.get_pixel(28, 8), Rgba([0, 0, 255, 255]));
+        assert_eq!(*sheet.get_pixel(38, 8), Rgba([255, 0, 0, 255]));
+        // The last cell of the second row stays background.
+        assert_eq!(*sheet.get_pixel(38, 26), Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn captions_add_a_strip_to_every_row() {
+        let spec = SheetSpec::from_args(&SheetArgs {
+            cols: Some(2),
+            cell_width: Some(64),
+            gutter: Some(0),
+            caption: Some("{index}. {filename}"),
+            caption_size: Some(16),
+            ..SheetArgs::default()
+        })
+        .unwrap();
+        let sheet = spec
+            .render(&[cell(&spec, 64, 64, None), cell(&spec, 64, 64, Some("Own"))])
+            .unwrap();
+        assert_eq!(sheet.dimensions(), (128, 80));
+        let strip_has_ink =
+            |x0: u32| (x0..x0 + 64).any(|x| (64..80).any(|y| sheet.get_pixel(x, y)[0] < 128));
+        assert!(strip_has_ink(0));
+        assert!(strip_has_ink(64));
+    }
+
+    #[test]
+    fn rejects_bad_args() {
+        let bad = [
+            SheetArgs {
+                cell_width: Some(MAX_CELL + 1),
+                ..SheetArgs::default()
+            },
+            SheetArgs {
+                gutter: Some(MAX_GUTTER + 1),
+                ..SheetArgs::default()
+            },
+            SheetArgs {
+                background: Some("nope"),
+                ..SheetArgs::default()
+            },
+            SheetArgs {
+                fit: Some("sideways"),
+                ..SheetArgs::default()
+            },
+        ];
+        for args in bad {
+            assert!(SheetSpec::from_args(&args).is_err());
+        }
+        let spec = SheetSpec::from_args(&SheetArgs::default()).unwrap();
+        assert!(spec.render(&[]).is_err());
+    }
+}
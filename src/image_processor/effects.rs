@@ -0,0 +1,333 @@
+use anyhow::{Error, Result};
+use image::{Rgba, RgbaImage};
+use imageproc::filter::{filter3x3, separable_filter_equal};
+
+const MAX_SIGMA: f32 = 100.0;
+const MAX_AMOUNT: f32 = 10.0;
+const MAX_BLOCK: u32 = 1024;
+
+/// Raw filter parameters, as given over HTTP or gRPC. Unsharp masking is on
+/// when any of its parameters is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EffectArgs {
+    pub blur: Option<f32>,
+    pub sharpen: Option<f32>,
+    pub unsharp_sigma: Option<f32>,
+    pub unsharp_amount: Option<f32>,
+    pub unsharp_threshold: Option<u32>,
+    pub pixelate: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    /// Gaussian blur with standard deviation `sigma` in pixels.
+    Blur { sigma: f32 },
+    /// 3x3 Laplacian sharpening; `amount` 1 is a classic sharpen kernel.
+    Sharpen { amount: f32 },
+    /// Add back `amount` times the difference from a Gaussian blur, where it
+    /// exceeds `threshold`, so flat areas and noise are left alone.
+    Unsharp {
+        sigma: f32,
+        amount: f32,
+        threshold: u8,
+    },
+    /// Mosaic of `block` x `block` squares, each the average of its pixels.
+    Pixelate { block: u32 },
+}
+
+/// Filters, applied in the order blur, sharpen, unsharp mask, pixelate.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Effects(pub Vec<Effect>);
+
+fn checked(name: &str, value: f32, min: f32, max: f32) -> Result<f32> {
+    if value.is_finite() && value > min && value <= max {
+        Ok(value)
+    } else {
+        Err(Error::msg(format!(
+            "{} must be greater than {} and at most {}, got {}",
+            name, min, max, value
+        )))
+    }
+}
+
+impl Effects {
+    pub fn from_args(args: &EffectArgs) -> Result<Effects> {
+        let mut effects = Vec::new();
+        if let Some(sigma) = args.blur {
+            effects.push(Effect::Blur {
+                sigma: checked("blur", sigma, 0.0, MAX_SIGMA)?,
+            });
+        }
+        if let Some(amount) = args.sharpen {
+            effects.push(Effect::Sharpen {
+                amount: checked("sharpen", amount, 0.0, MAX_AMOUNT)?,
+            });
+        }
+        if args.unsharp_sigma.is_some()
+            || args.unsharp_amount.is_some()
+            || args.unsharp_threshold.is_some()
+        {
+            let threshold = args.unsharp_threshold.unwrap_or(0);
+            effects.push(Effect::Unsharp {
+                sigma: checked(
+                    "unsharp_sigma",
+                    args.unsharp_sigma.unwrap_or(1.0),
+                    0.0,
+                    MAX_SIGMA,
+                )?,
+                amount: checked(
+                    "unsharp_amount",
+                    args.unsharp_amount.unwrap_or(1.0),
+                    0.0,
+                    MAX_AMOUNT,
+                )?,
+                threshold: u8::try_from(threshold).map_err(|_| {
+                    Error::msg(format!(
+                        "unsharp_threshold must be 0-255, got {}",
+                        threshold
+                    ))
+                })?,
+            });
+        }
+        if let Some(block) = args.pixelate {
+            if !(2..=MAX_BLOCK).contains(&block) {
+                return Err(Error::msg(format!(
+                    "pixelate block size must be 2-{}, got {}",
+                    MAX_BLOCK, block
+                )));
+            }
+            effects.push(Effect::Pixelate { block });
+        }
+        Ok(Effects(effects))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply(&self, img: RgbaImage) -> RgbaImage {
+        self.0.iter().fold(img, |img, effect| effect.apply(img))
+    }
+}
+
+/// Short summary for logs, e.g. `blur 2, pixelate 8`.
+impl std::fmt::Display for Effects {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, effect) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match *effect {
+                Effect::Blur { sigma } => write!(f, "blur {}", sigma)?,
+                Effect::Sharpen { amount } => write!(f, "sharpen {}", amount)?,
+                Effect::Unsharp {
+                    sigma,
+                    amount,
+                    threshold,
+                } => write!(f, "unsharp {}/{}/{}", sigma, amount, threshold)?,
+                Effect::Pixelate { block } => write!(f, "pixelate {}", block)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Effect {
+    pub fn apply(&self, img: RgbaImage) -> RgbaImage {
+        match *self {
+            Effect::Blur { sigma } => gaussian_blur(img, sigma),
+            Effect::Sharpen { amount } => {
+                let kernel = [
+                    0.0,
+                    -amount,
+                    0.0,
+                    -amount,
+                    1.0 + 4.0 * amount,
+                    -amount,
+                    0.0,
+                    -amount,
+                    0.0,
+                ];
+                let sharpened: imageproc::image::RgbaImage =
+                    filter3x3::<_, f32, u8>(&to_imageproc(img), &kernel);
+                from_imageproc(sharpened)
+            }
+            Effect::Unsharp {
+                sigma,
+                amount,
+                threshold,
+            } => unsharp(img, sigma, amount, threshold),
+            Effect::Pixelate { block } => pixelate(img, block),
+        }
+    }
+}
+
+// imageproc is built on a newer `image` than the service; the pixel buffers are
+// the same, so images cross over without copying.
+fn to_imageproc(img: RgbaImage) -> imageproc::image::RgbaImage {
+    let (w, h) = img.dimensions();
+    imageproc::image::RgbaImage::from_raw(w, h, img.into_raw()).expect("buffer matches dimensions")
+}
+
+fn from_imageproc(img: imageproc::image::RgbaImage) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    RgbaImage::from_raw(w, h, img.into_raw()).expect("buffer matches dimensions")
+}
+
+// imageproc's own `gaussian_blur_f32` kernel doesn't sum to one, which
+// darkens flat areas by a few levels; normalize it here.
+fn gaussian_blur(img: RgbaImage, sigma: f32) -> RgbaImage {
+    let radius = (3.0 * sigma).ceil() as i32;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|x| (-((x * x) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+    let kernel: Vec<f32> = weights.iter().map(|w| w / total).collect();
+    from_imageproc(separable_filter_equal(&to_imageproc(img), &kernel))
+}
+
+fn unsharp(img: RgbaImage, sigma: f32, amount: f32, threshold: u8) -> RgbaImage {
+    let blurred = gaussian_blur(img.clone(), sigma);
+    let mut out = img;
+    for (p, b) in out.pixels_mut().zip(blurred.pixels()) {
+        for c in 0..3 {
+            let diff = p[c] as f32 - b[c] as f32;
+            if diff.abs() > threshold as f32 {
+                p[c] = (p[c] as f32 + amount * diff).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    out
+}
+
+fn pixelate(mut img: RgbaImage, block: u32) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    for by in (0..h).step_by(block as usize) {
+        for bx in (0..w).step_by(block as usize) {
+            let (bw, bh) = (block.min(w - bx), block.min(h - by));
+            let mut sum = [0u64; 4];
+            for y in by..by + bh {
+                for x in bx..bx + bw {
+                    for (s, v) in sum.iter_mut().zip(img.get_pixel(x, y).0) {
+                        *s += v as u64;
+                    }
+                }
+            }
+            let n = (bw * bh) as u64;
+            let average = Rgba(sum.map(|s| ((s + n / 2) / n) as u8));
+            for y in by..by + bh {
+                for x in bx..bx + bw {
+                    img.put_pixel(x, y, average);
+                }
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageBuffer;
+
+    // Left half `lo`, right half `hi`.
+    fn edge(w: u32, h: u32, lo: u8, hi: u8) -> RgbaImage {
+        ImageBuffer::from_fn(w, h, |x, _| {
+            let v = if x < w / 2 { lo } else { hi };
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    fn effects(args: EffectArgs) -> Effects {
+        Effects::from_args(&args).unwrap()
+    }
+
+    #[test]
+    fn display_summarises_in_order() {
+        let effects = effects(EffectArgs {
+            pixelate: Some(8),
+            blur: Some(2.5),
+            unsharp_threshold: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(effects.to_string(), "blur 2.5, unsharp 1/1/3, pixelate 8");
+    }
+
+    #[test]
+    fn blur_softens_and_sharpening_overshoots_an_edge() {
+        let blurred = effects(EffectArgs {
+            blur: Some(2.0),
+            ..Default::default()
+        })
+        .apply(edge(20, 4, 0, 255));
+        let v = blurred.get_pixel(9, 2)[0];
+        assert!(v > 0 && v < 128, "blurred edge pixel is {}", v);
+
+        let sharpened = effects(EffectArgs {
+            sharpen: Some(1.0),
+            ..Default::default()
+        })
+        .apply(edge(20, 4, 100, 150));
+        assert_eq!(sharpened.get_pixel(9, 2)[0], 50);
+        assert_eq!(sharpened.get_pixel(10, 2)[0], 200);
+        assert_eq!(sharpened.get_pixel(2, 2)[0], 100);
+
+        let unsharp = effects(EffectArgs {
+            unsharp_amount: Some(1.0),
+            ..Default::default()
+        })
+        .apply(edge(20, 4, 100, 150));
+        assert!(unsharp.get_pixel(9, 2)[0] < 100 && unsharp.get_pixel(10, 2)[0] > 150);
+        assert_eq!(unsharp.get_pixel(2, 2)[0], 100);
+    }
+
+    #[test]
+    fn unsharp_threshold_protects_flat_areas() {
+        let mut img = edge(20, 4, 0, 255);
+        img.put_pixel(3, 2, Rgba([4, 4, 4, 255]));
+        let args = EffectArgs {
+            unsharp_sigma: Some(1.0),
+            unsharp_amount: Some(2.0),
+            unsharp_threshold: Some(10),
+            ..Default::default()
+        };
+        let out = effects(args).apply(img);
+        assert_eq!(out.get_pixel(3, 2)[0], 4);
+        assert!(out.get_pixel(10, 2)[0] == 255 && out.get_pixel(9, 2)[0] == 0);
+    }
+
+    #[test]
+    fn pixelate_averages_blocks() {
+        let out = effects(EffectArgs {
+            pixelate: Some(4),
+            ..Default::default()
+        })
+        .apply(edge(10, 4, 0, 255));
+        assert_eq!(out.get_pixel(0, 0), out.get_pixel(3, 3));
+        // Block 4..8 straddles the edge: one black column, three white.
+        assert_eq!(out.get_pixel(4, 0)[0], 191);
+        assert_eq!(out.get_pixel(9, 3)[0], 255);
+    }
+
+    #[test]
+    fn rejects_out_of_range_parameters() {
+        assert!(Effects::from_args(&EffectArgs {
+            blur: Some(0.0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(Effects::from_args(&EffectArgs {
+            pixelate: Some(1),
+            ..Default::default()
+        })
+        .is_err());
+        let args = EffectArgs {
+            unsharp_threshold: Some(300),
+            ..Default::default()
+        };
+        assert!(Effects::from_args(&args).is_err());
+        assert!(Effects::from_args(&EffectArgs::default())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use ab_glyph::{Font, FontVec};
+use anyhow::{Error, Result};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const BUILTIN_NAME: &str = "open-sans";
+const BUILTIN_DATA: &[u8] = include_bytes!("../../resources/OpenSans-Regular.ttf");
+// Covers Arabic and Hebrew, which Open Sans doesn't.
+const FALLBACK_NAME: &str = "dejavu-sans";
+const FALLBACK_DATA: &[u8] = include_bytes!("../../resources/DejaVuSans.ttf");
+
+/// Handle to a registered font. The default is the bundled Open Sans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FontId(usize);
+
+/// A font that owns its data, for both rasterising (ab_glyph) and shaping (rustybuzz).
+pub struct LoadedFont {
+    pub name: String,
+    pub font: FontVec,
+}
+
+impl LoadedFont {
+    fn parse(name: &str, data: Vec<u8>) -> Result<LoadedFont> {
+        if rustybuzz::Face::from_slice(&data, 0).is_none() {
+            return Err(Error::msg(format!(
+                "Failed to parse font {} for shaping",
+                name
+            )));
+        }
+        let font = FontVec::try_from_vec(data)
+            .map_err(|e| Error::msg(format!("Failed to parse font {}: {}", name, e)))?;
+        Ok(LoadedFont {
+            name: name.to_string(),
+            font,
+        })
+    }
+
+    /// The shaping face, borrowed from the font data. Cheap: it only reads the table directory.
+    pub fn face(&self) -> rustybuzz::Face<'_> {
+        rustybuzz::Face::from_slice(self.font.as_slice(), 0).expect("font data checked in parse")
+    }
+
+    /// Whether the font has a glyph for `c`.
+    pub fn covers(&self, c: char) -> bool {
+        self.font.glyph_id(c).0 != 0
+    }
+}
+
+struct Registry {
+    // Index 0 is the built-in font and 1 the fallback, or `None` if one failed to parse.
+    fonts: Vec<Option<LoadedFont>>,
+    by_name: HashMap<String, FontId>,
+}
+
+impl Registry {
+    fn with_builtin() -> Registry {
+        let mut registry = Registry {
+            fonts: Vec::new(),
+            by_name: HashMap::new(),
+        };
+        for (name, data) in [(BUILTIN_NAME, BUILTIN_DATA), (FALLBACK_NAME, FALLBACK_DATA)] {
+            match LoadedFont::parse(name, data.to_vec()).and_then(|font| registry.add(font)) {
+                Ok(()) => {}
+                Err(e) => {
+                    println!("Error: {}", e);
+                    // Keep the slot so the built-in font stays at index 0.
+                    registry.fonts.push(None);
+                }
+            }
+        }
+        registry
+    }
+
+    fn add(&mut self, font: LoadedFont) -> Result<()> {
+        if self.by_name.contains_key(&font.name) {
+            return Err(Error::msg(format!(
+                "Duplicate font name: {} (font names come from file stems and must be unique)",
+                font.name
+            )));
+        }
+        self.by_name
+            .insert(font.name.clone(), FontId(self.fonts.len()));
+        self.fonts.push(Some(font));
+        Ok(())
+    }
+}
+
+static FONTS: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    FONTS.get_or_init(Registry::with_builtin)
+}
+
+/// Register the bundled fonts plus every TTF/OTF in `FONT_DIR`, keyed by file stem.
+/// Without `FONT_DIR` only the bundled fonts are available.
+pub fn init_from_env() -> Result<()> {
+    let mut registry = Registry::with_builtin();
+    match std::env::var("FONT_DIR") {
+        Ok(dir) => load_dir(&mut registry, Path::new(dir.trim()))?,
+        Err(_) => println!("FONT_DIR not set, using the bundled fonts only"),
+    }
+    println!("Registered {} font(s)", registry.by_name.len());
+    FONTS
+        .set(registry)
+        .map_err(|_| Error::msg("Font registry already initialized"))
+}
+
+fn load_dir(registry: &mut Registry, dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| Error::msg(format!("Failed to read FONT_DIR {}: {}", dir.display(), e)))?
+    {
+        let path = entry?.path();
+        let is_font = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("ttf") || e.eq_ignore_ascii_case("otf"));
+        let name = path.file_stem().and_then(|s| s.to_str());
+        if let (true, Some(name)) = (is_font, name) {
+            let data = std::fs::read(&path).map_err(|e| {
+                Error::msg(format!("Failed to read font {}: {}", path.display(), e))
+            })?;
+            registry.add(LoadedFont::parse(name, data)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Look up a font by name. `None` or an empty name selects the bundled font.
+pub fn find(name: Option<&str>) -> Result<FontId> {
+    match name {
+        Some(name) if !name.is_empty() => registry()
+            .by_name
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::msg(format!("Unknown font: {}", name))),
+        _ => Ok(FontId::default()),
+    }
+}
+
+/// The parsed font behind `id`.
+pub fn get(id: FontId) -> Result<&'static LoadedFont> {
+    registry()
+        .fonts
+        .get(id.0)
+        .and_then(Option::as_ref)
+        .ok_or_else(|| Error::msg("Font is not available"))
+}
+
+/// `id` followed by the fonts to try for characters it has no glyph for:
+/// the bundled ones, then every `FONT_DIR` font in the order they were loaded.
+pub fn chain(id: FontId) -> Result<Vec<&'static LoadedFont>> {
+    let mut chain = vec![get(id)?];
+    chain.extend(
+        registry()
+            .fonts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != id.0)
+            .filter_map(|(_, font)| font.as_ref()),
+    );
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_font_is_the_default() {
+        let id = find(None).unwrap();
+        assert_eq!(id, find(Some(BUILTIN_NAME)).unwrap());
+        assert_eq!(get(id).unwrap().name, BUILTIN_NAME);
+    }
+
+    #[test]
+    fn chain_starts_with_the_font_and_falls_back_to_dejavu() {
+        let chain = chain(FontId::default()).unwrap();
+        assert_eq!(chain[0].name, BUILTIN_NAME);
+        assert_eq!(chain[1].name, FALLBACK_NAME);
+        assert!(!chain[0].covers('\u{05e9}'));
+        assert!(chain[1].covers('\u{05e9}'));
+        assert!(chain[1].covers('\u{0645}'));
+    }
+
+    #[test]
+    fn unknown_font_is_an_error() {
+        assert!(find(Some("comic-sans")).is_err());
+        assert!(get(FontId(99)).is_err());
+    }
+
+    #[test]
+    fn invalid_font_data_is_an_error() {
+        assert!(LoadedFont::parse("broken", b"not a font".to_vec()).is_err());
+    }
+
+    #[test]
+    fn load_dir_registers_fonts_and_rejects_duplicate_names() {
+        let dir = std::env::temp_dir().join(format!("izdu-fonts-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("brand.ttf"), FALLBACK_DATA).unwrap();
+
+        let mut registry = Registry::with_builtin();
+        load_dir(&mut registry, &dir).unwrap();
+        let id = registry.by_name["brand"];
+        assert!(registry.fonts[id.0].as_ref().unwrap().covers('\u{05e9}'));
+
+        // Same stem with another extension, and a bundled font's name.
+        std::fs::write(dir.join("brand.otf"), FALLBACK_DATA).unwrap();
+        assert!(load_dir(&mut Registry::with_builtin(), &dir).is_err());
+        std::fs::remove_file(dir.join("brand.otf")).unwrap();
+        std::fs::write(dir.join(format!("{}.ttf", BUILTIN_NAME)), FALLBACK_DATA).unwrap();
+        assert!(load_dir(&mut Registry::with_builtin(), &dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,105 @@
+use crate::image_processor::resize::{Rect, ResizeArgs, ResizeParams};
+use crate::image_processor::transform::MAX_SIDE;
+use anyhow::{Error, Result};
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
 
+/// Columns of the slice grid; the four slices make two rows of them.
+pub const GRID_COLS: u32 = 2;
+
+/// How slices are scaled after splitting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileScale {
+    pub params: ResizeParams,
+    /// Legacy `scale`: only slices whose shorter side is longer get scaled.
+    pub shrink_above: Option<u32>,
+}
+
+impl TileScale {
+    /// Build from request values. A tile width or height in `args` wins over
+    /// the legacy square `scale`, which only shrinks slices whose shorter side
+    /// is longer than it, unless upscaling is asked for; with neither there is
+    /// no scaling. The filter defaults to `nearest`, as slice scaling always used.
+    pub fn parse(scale: u32, mut args: ResizeArgs) -> Result<Option<TileScale>> {
+        args.width = args.width.filter(|w| *w > 0);
+        args.height = args.height.filter(|h| *h > 0);
+        args.filter = args.filter.filter(|f| !f.is_empty()).or(Some("nearest"));
+        let mut shrink_above = None;
+        if args.width.is_none() && args.height.is_none() {
+            if scale == 0 {
+                return Ok(None);
+            }
+            // Slices are never larger than `MAX_SIDE`, so a bigger box changes nothing.
+            args.width = Some(scale.min(MAX_SIDE));
+            args.height = Some(scale.min(MAX_SIDE));
+            if args.allow_upscale != Some(true) {
+                args.allow_upscale = Some(false);
+                shrink_above = Some(scale);
+            }
+        }
+        let params = ResizeParams::from_args(&args)
+            .map_err(|e| Error::msg(format!("Invalid tile size: {}", e)))?;
+        Ok(Some(TileScale {
+            params,
+            shrink_above,
+        }))
+    }
+
+    /// The params to scale a slice of `size` with, or `None` to keep it as is.
+    pub fn params_for(&self, size: (u32, u32)) -> Option<&ResizeParams> {
+        match self.shrink_above {
+            Some(scale) if scale >= size.0.min(size.1) => None,
+            _ => Some(&self.params),
+        }
+    }
+}
+
+/// What happens to the last pixel column or row of an odd-sized image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Remainder {
+    /// Dropped, so all four slices are the same size.
+    #[default]
+    Drop,
+    /// Kept in the right column and bottom row of slices, so stitching the
+    /// slices gives back the whole image.
+    Extend,
+}
+
+impl Remainder {
+    pub fn parse(s: Option<&str>) -> Result<Remainder> {
+        match s.map(|s| s.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("drop") => Ok(Remainder::Drop),
+            Some("extend") => Ok(Remainder::Extend),
+            Some(_) => Err(Error::msg(format!(
+                "Unknown remainder: {} (use drop or extend)",
+                s.unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// Slice size for `img`, plus the odd pixels the last column and row keep.
+    pub fn dimensions(self, img: &DynamicImage) -> Dimension {
+        let extra = match self {
+            Remainder::Drop => (0, 0),
+            Remainder::Extend => (img.width() % 2, img.height() % 2),
+        };
+        Dimension {
+            height: img.height() / 2,
+            width: img.width() / 2,
+            extra,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Dimension {
     pub height: u32,
     pub width: u32,
-    pub smallest: u32,
+    /// Extra width of the right slices and height of the bottom ones.
+    pub extra: (u32, u32),
 }
 
 pub fn get_single_image_dimensions(img: &DynamicImage) -> Dimension {
-    let h = img.height() / 2;
-    let w = img.width() / 2;
-    Dimension {
-        height: h,
-        width: w,
-        smallest: std::cmp::min(h, w),
-    }
+    Remainder::Drop.dimensions(img)
 }
 
 pub fn initialize_output(w: u32, h: u32) -> [ImageBuffer<Rgba<u8>, Vec<u8>>; 4] {
@@ -32,15 +117,29 @@ pub fn slice_images_view(
     new_img_size: &Dimension,
 ) -> [ImageBuffer<Rgba<u8>, Vec<u8>>; 4] {
     let mut output = initialize_output(new_img_size.width, new_img_size.height);
-    output.iter_mut().enumerate().for_each(|(pic, new_img)| {
-        let x = (pic as u32 % 2) * new_img_size.width;
-        let y = (pic as u32 / 2) * new_img_size.height;
-        let image = img.view(x, y, new_img_size.width, new_img_size.height);
-        *new_img = image.to_image();
+    let cells = (0..4).map(|pic| Rect {
+        x: (pic % 2) * new_img_size.width,
+        y: (pic / 2) * new_img_size.height,
+        width: new_img_size.width + (pic % 2) * new_img_size.extra.0,
+        height: new_img_size.height + (pic / 2) * new_img_size.extra.1,
     });
+    for (new_img, image) in output.iter_mut().zip(view_cells(&img, cells)) {
+        *new_img = image;
+    }
     output
 }
 
+/// Copy each of `cells` out of `img` through a sub-view.
+pub fn view_cells(
+    img: &DynamicImage,
+    cells: impl IntoIterator<Item = Rect>,
+) -> Vec<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+    cells
+        .into_iter()
+        .map(|cell| img.view(cell.x, cell.y, cell.width, cell.height).to_image())
+        .collect()
+}
+
 // Split image by copying pixels one by one - initial approach.
 // Might be usable in future to alter some pixels while copying (watermarking?)
 // Leaving it here as-is for now.
@@ -68,46 +167,157 @@ pub fn slice_images_copy_px(
     images
 }
 
-pub fn resize(
-    images: [ImageBuffer<Rgba<u8>, Vec<u8>>; 4],
-    size: u32,
-) -> [ImageBuffer<Rgba<u8>, Vec<u8>>; 4] {
-    let mut resized = initialize_output(size, size);
-    resized.iter_mut().enumerate().for_each(|(i, img)| {
-        let resized_img = DynamicImage::ImageRgba8(images[i].clone()).resize(
-            size,
-            size,
-            image::imageops::FilterType::Nearest,
-        );
-        *img = resized_img.to_rgba8();
-    });
-    resized
+/// Cells of `cell` px covering an image of `size` in reading order, each
+/// starting `overlap` px before the previous one ends. Edge cells are clipped
+/// to the image. Returns the grid's columns and rows, and the cells.
+pub fn grid(
+    size: (u32, u32),
+    cell: (u32, u32),
+    overlap: (u32, u32),
+) -> Result<(u32, u32, Vec<Rect>)> {
+    if cell.0 <= overlap.0 || cell.1 <= overlap.1 {
+        return Err(Error::msg(format!(
+            "Grid cell {}x{} must be larger than its overlap {}x{}",
+            cell.0, cell.1, overlap.0, overlap.1
+        )));
+    }
+    let step = (cell.0 - overlap.0, cell.1 - overlap.1);
+    let count = |len: u32, cell: u32, step: u32| 1 + len.saturating_sub(cell).div_ceil(step);
+    let cols = count(size.0, cell.0, step.0);
+    let rows = count(size.1, cell.1, step.1);
+    let cells = (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| (col * step.0, row * step.1)))
+        .map(|(x, y)| Rect {
+            x,
+            y,
+            width: cell.0.min(size.0 - x),
+            height: cell.1.min(size.1 - y),
+        })
+        .collect();
+    Ok((cols, rows, cells))
 }
 
-/// Resize a single DynamicImage to fit within the given bounds.
-/// If width and height are both provided and aspect_ratio is "ignore",
-/// resizes to exact dimensions. Otherwise scales to fit within bounds.
-pub fn resize_single(
-    img: DynamicImage,
-    width: Option<u32>,
-    height: Option<u32>,
-    aspect_ratio: &str,
-) -> DynamicImage {
-    match (width, height, aspect_ratio) {
-        (None, None, _) => img,
-        (None, Some(_h), "ignore") => img, // caller must validate: ignore requires both dims
-        (Some(_w), None, "ignore") => img, // caller must validate: ignore requires both dims
-        (Some(w), Some(h), "ignore") => img.resize_exact(w, h, image::imageops::FilterType::Triangle),
-        (Some(w), Some(h), _) => img.resize(w, h, image::imageops::FilterType::Triangle),
-        (Some(w), None, _) => {
-            let ratio = w as f64 / img.width() as f64;
-            let new_h = (img.height() as f64 * ratio) as u32;
-            img.resize(w, new_h, image::imageops::FilterType::Triangle)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::resize::{self, Filter, Fit};
+
+    fn scale(tile: &TileScale, w: u32, h: u32) -> resize::Resized {
+        let img =
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(w, h, Rgba([10u8, 20, 30, 255])));
+        match tile.params_for((w, h)) {
+            Some(params) => resize::resize(&img, params).unwrap(),
+            None => resize::Resized {
+                image: img,
+                upscaled: false,
+                crop: None,
+            },
         }
-        (None, Some(h), _) => {
-            let ratio = h as f64 / img.height() as f64;
-            let new_w = (img.width() as f64 * ratio) as u32;
-            img.resize(new_w, h, image::imageops::FilterType::Triangle)
+    }
+
+    fn tile_args<'a>(
+        width: Option<u32>,
+        height: Option<u32>,
+        fit: Option<&'a str>,
+    ) -> ResizeArgs<'a> {
+        ResizeArgs {
+            width,
+            height,
+            fit,
+            ..ResizeArgs::default()
         }
     }
+
+    #[test]
+    fn tile_targets_override_scale() {
+        let tile = TileScale::parse(300, tile_args(Some(80), Some(40), Some("cover")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(tile.params.fit, Fit::Cover);
+        let out = scale(&tile, 100, 100);
+        assert!(!out.upscaled);
+        assert_eq!(out.crop.unwrap().to_string(), "0,25,100,50");
+        assert_eq!(out.image.dimensions(), (80, 40));
+    }
+
+    #[test]
+    fn legacy_scale_only_shrinks() {
+        let tile = TileScale::parse(300, ResizeArgs::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(scale(&tile, 200, 100).image.dimensions(), (200, 100));
+        assert_eq!(scale(&tile, 400, 400).image.dimensions(), (300, 300));
+        assert_eq!(scale(&tile, 900, 400).image.dimensions(), (300, 133));
+
+        let args = ResizeArgs {
+            allow_upscale: Some(true),
+            ..ResizeArgs::default()
+        };
+        let tile = TileScale::parse(300, args).unwrap().unwrap();
+        let out = scale(&tile, 200, 100);
+        assert!(out.upscaled);
+        assert_eq!(out.crop, None);
+        assert_eq!(out.image.dimensions(), (300, 150));
+    }
+
+    #[test]
+    fn legacy_scale_keeps_slices_whose_shorter_side_fits() {
+        let tile = TileScale::parse(300, ResizeArgs::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(tile.params_for((600, 300)), None);
+        assert_eq!(scale(&tile, 600, 300).image.dimensions(), (600, 300));
+        assert_eq!(scale(&tile, 250, 1000).image.dimensions(), (250, 1000));
+        assert_eq!(scale(&tile, 301, 1000).image.dimensions(), (90, 300));
+    }
+
+    #[test]
+    fn tile_filter_defaults_to_nearest() {
+        let tile = TileScale::parse(300, ResizeArgs::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(tile.params.filter, Filter::Nearest);
+        let args = ResizeArgs {
+            filter: Some("lanczos3"),
+            ..tile_args(Some(80), None, None)
+        };
+        let tile = TileScale::parse(0, args).unwrap().unwrap();
+        assert_eq!(tile.params.filter, Filter::Lanczos3);
+    }
+
+    #[test]
+    fn extend_keeps_odd_pixels_in_last_column_and_row() {
+        let img = DynamicImage::ImageRgba8(ImageBuffer::new(5, 3));
+        let sizes = |remainder: Remainder| {
+            let dim = remainder.dimensions(&img);
+            slice_images_view(img.clone(), &dim).map(|s| s.dimensions())
+        };
+        assert_eq!(sizes(Remainder::Drop), [(2, 1); 4]);
+        assert_eq!(sizes(Remainder::Extend), [(2, 1), (3, 1), (2, 2), (3, 2)]);
+        assert!(Remainder::parse(Some("pad")).is_err());
+    }
+
+    #[test]
+    fn grid_covers_image_with_overlap() {
+        let (cols, rows, cells) = grid((100, 40), (40, 40), (10, 0)).unwrap();
+        assert_eq!((cols, rows), (3, 1));
+        let cells: Vec<String> = cells.iter().map(Rect::to_string).collect();
+        assert_eq!(cells, ["0,0,40,40", "30,0,40,40", "60,0,40,40"]);
+
+        let (cols, rows, cells) = grid((10, 10), (40, 40), (0, 0)).unwrap();
+        assert_eq!(
+            (cols, rows, cells[0].to_string()),
+            (1, 1, "0,0,10,10".to_string())
+        );
+        assert!(grid((100, 100), (10, 10), (10, 0)).is_err());
+    }
+
+    #[test]
+    fn no_targets_means_no_scaling() {
+        assert_eq!(
+            TileScale::parse(0, tile_args(Some(0), None, None)).unwrap(),
+            None
+        );
+        assert!(TileScale::parse(0, tile_args(Some(80), None, Some("fill"))).is_err());
+    }
 }
@@ -0,0 +1,372 @@
+//! Invisible watermark: a short payload hidden in the luminance of an image.
+//!
+//! The image is divided into a `GRID` x `GRID` array of cells whose bounds are
+//! proportional to the image size, so the same cells are found again after
+//! resizing. Each cell carries one bit of the framed payload as the sign of
+//! the luminance difference between its two diagonals (top-left + bottom-right
+//! vs. top-right + bottom-left). Bits are spread over the cells in a fixed
+//! pseudo-random order and repeated, and detection sums the differences of all
+//! cells per bit. The embedder pushes a bit's cells only until that sum clears a
+//! margin, by at most a few levels, and more on busy texture than on flat areas,
+//! where a change would show. Cell averages survive moderate JPEG compression and
+//! resampling well; cropping does not, which is why every tile gets its own copy.
+
+use anyhow::{Error, Result};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+
+/// Longest payload that fits in a frame, in bytes.
+pub const MAX_PAYLOAD: usize = 12;
+
+// Frame: length byte, payload padded to MAX_PAYLOAD, CRC-16 over the rest.
+const FRAME_BYTES: usize = MAX_PAYLOAD + 3;
+const FRAME_BITS: usize = FRAME_BYTES * 8;
+const GRID: u32 = 24;
+// Smallest image that still has 2x2 pixels per cell.
+const MIN_SIZE: u32 = GRID * 2;
+// Luminance difference targeted per cell, on average over a bit's cells.
+const TARGET: f32 = 4.0;
+// How far a cell may be pushed, in levels per channel: `MIN_SHIFT` on flat
+// areas, up to `MAX_SHIFT` where busy texture masks the change.
+const MIN_SHIFT: f32 = 2.0;
+const MAX_SHIFT: f32 = 6.0;
+// Shift allowed per level of a cell's luminance standard deviation.
+const MASKING: f32 = 0.6;
+
+/// Hide `payload` in `img`. The payload must be 1 to `MAX_PAYLOAD` bytes.
+pub fn embed(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, payload: &[u8]) -> Result<()> {
+    if payload.is_empty() || payload.len() > MAX_PAYLOAD {
+        return Err(Error::msg(format!(
+            "Invisible watermark payload must be 1-{} bytes, got {}",
+            MAX_PAYLOAD,
+            payload.len()
+        )));
+    }
+    check_size(img.width(), img.height())?;
+
+    let bits = frame_bits(payload);
+    let cell_bits = cell_bit_indices();
+    let (w, h) = img.dimensions();
+
+    // Detection only sees the sum over a bit's cells, so push each bit's
+    // cells together until the sum clears the target, and no further.
+    let mut cells_of_bit: Vec<Vec<(Cell, f32, f32)>> =
+        (0..FRAME_BITS).map(|_| Vec::new()).collect();
+    for (index, &bit_idx) in cell_bits.iter().enumerate() {
+        let cell = Cell::new(index as u32, w, h);
+        let d = cell.difference(|x, y| luma(img.get_pixel(x, y)));
+        let cap = cell.max_shift(|x, y| luma(img.get_pixel(x, y)));
+        cells_of_bit[bit_idx].push((cell, d, cap));
+    }
+    for (bit_idx, cells) in cells_of_bit.iter().enumerate() {
+        let sign = if bits[bit_idx] { 1.0 } else { -1.0 };
+        let sum: f32 = cells.iter().map(|(_, d, _)| sign * d).sum();
+        let shifts = spread(cells.len() as f32 * TARGET - sum, cells);
+        for ((cell, _, _), shift) in cells.iter().zip(shifts) {
+            if shift <= 0.0 {
+                continue;
+            }
+            let shift = sign * shift;
+            cell.for_each(|x, y, diagonal| {
+                let delta = if diagonal { shift } else { -shift };
+                let px = img.get_pixel_mut(x, y);
+                for c in 0..3 {
+                    px[c] = (px[c] as f32 + delta).round().clamp(0.0, 255.0) as u8;
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+// Split a shortfall in a bit's summed difference over its cells. Each px of
+// shift moves a cell's difference by 2; cells at their cap pass the rest on.
+fn spread(shortfall: f32, cells: &[(Cell, f32, f32)]) -> Vec<f32> {
+    let mut shifts = vec![0.0f32; cells.len()];
+    let mut left = shortfall / 2.0;
+    while left > 0.01 {
+        let open: Vec<usize> = (0..cells.len())
+            .filter(|&i| shifts[i] < cells[i].2)
+            .collect();
+        if open.is_empty() {
+            break;
+        }
+        let each = left / open.len() as f32;
+        for i in open {
+            let add = each.min(cells[i].2 - shifts[i]);
+            shifts[i] += add;
+            left -= add;
+        }
+    }
+    shifts
+}
+
+/// Recover a payload hidden with `embed`. `Ok(None)` means no valid payload was found.
+pub fn detect(img: &DynamicImage) -> Result<Option<Vec<u8>>> {
+    let (w, h) = img.dimensions();
+    check_size(w, h)?;
+
+    let cell_bits = cell_bit_indices();
+    let mut sums = [0.0f32; FRAME_BITS];
+    for (cell, &bit_idx) in cell_bits.iter().enumerate() {
+        let cell = Cell::new(cell as u32, w, h);
+        sums[bit_idx] += cell.difference(|x, y| luma(&img.get_pixel(x, y)));
+    }
+
+    let mut frame = [0u8; FRAME_BYTES];
+    for (i, sum) in sums.iter().enumerate() {
+        if *sum > 0.0 {
+            frame[i / 8] |= 0x80 >> (i % 8);
+        }
+    }
+
+    let len = frame[0] as usize;
+    let crc = crc16(&frame[..FRAME_BYTES - 2]).to_be_bytes();
+    if len == 0 || len > MAX_PAYLOAD || frame[FRAME_BYTES - 2..] != crc {
+        return Ok(None);
+    }
+    Ok(Some(frame[1..=len].to_vec()))
+}
+
+fn check_size(w: u32, h: u32) -> Result<()> {
+    if w < MIN_SIZE || h < MIN_SIZE {
+        return Err(Error::msg(format!(
+            "Image too small for an invisible watermark: {}x{}, need at least {}x{}",
+            w, h, MIN_SIZE, MIN_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn frame_bits(payload: &[u8]) -> [bool; FRAME_BITS] {
+    let mut frame = [0u8; FRAME_BYTES];
+    frame[0] = payload.len() as u8;
+    frame[1..=payload.len()].copy_from_slice(payload);
+    let crc = crc16(&frame[..FRAME_BYTES - 2]);
+    frame[FRAME_BYTES - 2..].copy_from_slice(&crc.to_be_bytes());
+
+    let mut bits = [false; FRAME_BITS];
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = frame[i / 8] & (0x80 >> (i % 8)) != 0;
+    }
+    bits
+}
+
+// Which frame bit each cell carries: a fixed shuffle, so repeats of one bit
+// are spread across the image instead of clustering in a band.
+fn cell_bit_indices() -> Vec<usize> {
+    let cells = (GRID * GRID) as usize;
+    let mut order: Vec<usize> = (0..cells).collect();
+    let mut state: u64 = 0x1d2d_5eed_c0ff_ee01;
+    for i in (1..cells).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        order.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    let mut bit_of_cell = vec![0; cells];
+    for (i, &cell) in order.iter().enumerate() {
+        bit_of_cell[cell] = i % FRAME_BITS;
+    }
+    bit_of_cell
+}
+
+// CRC-16/CCITT-FALSE. With the length check, about one unmarked image in a
+// million decodes to something that passes.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffffu16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn luma(px: &Rgba<u8>) -> f32 {
+    0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32
+}
+
+// One grid cell, in pixel coordinates of an image of the given size.
+struct Cell {
+    x0: u32,
+    xm: u32,
+    x1: u32,
+    y0: u32,
+    ym: u32,
+    y1: u32,
+}
+
+impl Cell {
+    fn new(index: u32, w: u32, h: u32) -> Cell {
+        let (cx, cy) = (index % GRID, index / GRID);
+        let x0 = cx * w / GRID;
+        let x1 = (cx + 1) * w / GRID;
+        let y0 = cy * h / GRID;
+        let y1 = (cy + 1) * h / GRID;
+        Cell {
+            x0,
+            xm: (x0 + x1) / 2,
+            x1,
+            y0,
+            ym: (y0 + y1) / 2,
+            y1,
+        }
+    }
+
+    // Calls `f(x, y, on_main_diagonal)` for every pixel in the cell.
+    fn for_each(&self, mut f: impl FnMut(u32, u32, bool)) {
+        for y in self.y0..self.y1 {
+            for x in self.x0..self.x1 {
+                f(x, y, (x < self.xm) == (y < self.ym));
+            }
+        }
+    }
+
+    // How far this cell may be pushed: more on busy texture, which hides it.
+    fn max_shift(&self, mut value: impl FnMut(u32, u32) -> f32) -> f32 {
+        let (mut sum, mut sq, mut n) = (0.0, 0.0, 0u32);
+        self.for_each(|x, y, _| {
+            let v = value(x, y);
+            sum += v;
+            sq += v * v;
+            n += 1;
+        });
+        let n = n.max(1) as f32;
+        let std = (sq / n - (sum / n).powi(2)).max(0.0).sqrt();
+        (MASKING * std).clamp(MIN_SHIFT, MAX_SHIFT)
+    }
+
+    // Mean of the main diagonal quadrants minus mean of the other two.
+    fn difference(&self, mut value: impl FnMut(u32, u32) -> f32) -> f32 {
+        let (mut main, mut main_n, mut anti, mut anti_n) = (0.0, 0u32, 0.0, 0u32);
+        self.for_each(|x, y, diagonal| {
+            if diagonal {
+                main += value(x, y);
+                main_n += 1;
+            } else {
+                anti += value(x, y);
+                anti_n += 1;
+            }
+        });
+        main / main_n.max(1) as f32 - anti / anti_n.max(1) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::FilterType;
+
+    fn textured_image(w: u32, h: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(w, h, |x, y| {
+            let v = ((x * 7 + y * 13) % 64) as u8;
+            Rgba([60 + v, 90 + v / 2, 140 + v / 3, 255])
+        })
+    }
+
+    #[test]
+    fn embed_then_detect_roundtrips() {
+        let mut img = textured_image(200, 150);
+        embed(&mut img, b"cust-4711").unwrap();
+        let found = detect(&DynamicImage::ImageRgba8(img)).unwrap();
+        assert_eq!(found.as_deref(), Some(&b"cust-4711"[..]));
+    }
+
+    // Largest per-channel change and PSNR between two images of the same size.
+    fn distortion(
+        a: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+        b: &ImageBuffer<Rgba<u8>, Vec<u8>>,
+    ) -> (u8, f64) {
+        let (mut max, mut squared) = (0u8, 0.0f64);
+        for (p, q) in a.pixels().zip(b.pixels()) {
+            for c in 0..3 {
+                let d = p[c].abs_diff(q[c]);
+                max = max.max(d);
+                squared += (d as f64).powi(2);
+            }
+        }
+        let mse = squared / (a.width() * a.height() * 3) as f64;
+        (max, 10.0 * (255.0f64.powi(2) / mse).log10())
+    }
+
+    #[test]
+    fn embedding_is_imperceptible() {
+        let original = textured_image(200, 150);
+        let mut img = original.clone();
+        embed(&mut img, b"cust-4711").unwrap();
+        let (max, psnr) = distortion(&original, &img);
+        assert!(max as f32 <= MAX_SHIFT, "max delta {}", max);
+        assert!(psnr >= 40.0, "PSNR {:.1} dB", psnr);
+    }
+
+    #[test]
+    fn flat_areas_get_the_smallest_shift() {
+        let original = ImageBuffer::from_fn(200, 150, |x, y| {
+            Rgba([100 + (x / 8) as u8, 120 + (y / 8) as u8, 140, 255])
+        });
+        let mut img = original.clone();
+        embed(&mut img, b"ID42").unwrap();
+        let (max, _) = distortion(&original, &img);
+        assert!(max as f32 <= MIN_SHIFT, "max delta {}", max);
+        let found = detect(&DynamicImage::ImageRgba8(img)).unwrap();
+        assert_eq!(found.as_deref(), Some(&b"ID42"[..]));
+    }
+
+    #[test]
+    fn survives_jpeg_compression() {
+        let mut img = textured_image(320, 240);
+        embed(&mut img, b"ID42").unwrap();
+
+        let mut jpeg = Vec::new();
+        let rgb = DynamicImage::ImageRgba8(img).to_rgb8();
+        image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 75)
+            .encode_image(&rgb)
+            .unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+
+        assert_eq!(detect(&decoded).unwrap().as_deref(), Some(&b"ID42"[..]));
+    }
+
+    #[test]
+    fn survives_downscaling() {
+        let mut img = textured_image(400, 300);
+        embed(&mut img, b"ID42").unwrap();
+        let smaller = DynamicImage::ImageRgba8(img).resize_exact(200, 150, FilterType::Triangle);
+        assert_eq!(detect(&smaller).unwrap().as_deref(), Some(&b"ID42"[..]));
+    }
+
+    #[test]
+    fn unmarked_image_has_no_payload() {
+        let img = DynamicImage::ImageRgba8(textured_image(200, 150));
+        assert_eq!(detect(&img).unwrap(), None);
+    }
+
+    #[test]
+    fn unmarked_images_do_not_false_positive() {
+        for seed in 1..=64u32 {
+            let img = ImageBuffer::from_fn(120, 100, |x, y| {
+                let v = ((x * (seed % 11 + 1) + y * (seed % 17 + 3) + seed * 31) % 97) as u8;
+                Rgba([40 + v, 70 + v / 2, 90 + v / 3, 255])
+            });
+            assert_eq!(
+                detect(&DynamicImage::ImageRgba8(img)).unwrap(),
+                None,
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_payloads_and_small_images() {
+        let mut img = textured_image(200, 150);
+        assert!(embed(&mut img, b"").is_err());
+        assert!(embed(&mut img, &[b'x'; MAX_PAYLOAD + 1]).is_err());
+        let mut tiny = textured_image(20, 20);
+        assert!(embed(&mut tiny, b"ID").is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Error, Result};
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Logos registered by the operator, keyed by file stem (e.g. `brand.png` -> `brand`).
+static LOGOS: OnceLock<HashMap<String, DynamicImage>> = OnceLock::new();
+
+/// Load every PNG in `LOGO_DIR` into the process-wide registry.
+/// Does nothing when the variable is unset.
+pub fn init_from_env() -> Result<()> {
+    let logos = match std::env::var("LOGO_DIR") {
+        Ok(dir) => load_dir(Path::new(dir.trim()))?,
+        Err(_) => {
+            println!("LOGO_DIR not set, no named logos registered");
+            HashMap::new()
+        }
+    };
+    println!("Registered {} logo(s)", logos.len());
+    LOGOS
+        .set(logos)
+        .map_err(|_| Error::msg("Logo registry already initialized"))
+}
+
+fn load_dir(dir: &Path) -> Result<HashMap<String, DynamicImage>> {
+    let mut logos = HashMap::new();
+    for entry in std::fs::read_dir(dir)
+        .map_err(|e| Error::msg(format!("Failed to read LOGO_DIR {}: {}", dir.display(), e)))?
+    {
+        let path = entry?.path();
+        let is_png = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("png"));
+        let name = path.file_stem().and_then(|s| s.to_str());
+        if let (true, Some(name)) = (is_png, name) {
+            let img = image::open(&path).map_err(|e| {
+                Error::msg(format!("Failed to load logo {}: {}", path.display(), e))
+            })?;
+            logos.insert(name.to_string(), img);
+        }
+    }
+    Ok(logos)
+}
+
+/// Look up a registered logo by name.
+pub fn get(name: &str) -> Result<DynamicImage> {
+    LOGOS
+        .get()
+        .and_then(|logos| logos.get(name))
+        .cloned()
+        .ok_or_else(|| Error::msg(format!("Unknown logo: {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn load_dir_registers_png_files_by_stem() {
+        let dir = std::env::temp_dir().join(format!("izdu-logos-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        ImageBuffer::from_pixel(3, 2, Rgba([255u8, 0, 0, 255]))
+            .save(dir.join("brand.png"))
+            .unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not a logo").unwrap();
+
+        let logos = load_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(logos.len(), 1);
+        assert_eq!(logos["brand"].width(), 3);
+    }
+
+    #[test]
+    fn get_unknown_logo_is_an_error() {
+        assert!(get("does-not-exist").is_err());
+    }
+}
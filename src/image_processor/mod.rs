@@ -1,16 +1,82 @@
+pub mod adjust;
+pub mod atlas;
+pub mod blend;
+pub mod carousel;
+pub mod color;
+pub mod contact_sheet;
+pub mod effects;
+pub mod fonts;
 pub mod image_slicer;
+pub mod invisible;
+pub mod logo;
+pub mod pdf;
+pub mod pipeline;
+pub mod poster;
+pub mod presets;
+pub mod qr;
+pub mod render_cache;
+pub mod resize;
+pub mod smartcrop;
+pub mod stitch;
+pub mod text;
+pub mod transform;
+pub mod variants;
 pub mod watermark;
 
+use crate::image_processor::contact_sheet::SheetEntry;
+use crate::image_processor::resize::{ResizeParams, Resized};
+pub use crate::image_processor::watermark::Watermark;
+use crate::{ImagePayload, SheetPayload};
+use actix_multipart::Multipart;
 use actix_web::{web, HttpRequest};
 use anyhow::{Error, Result};
-use image::{DynamicImage, ImageBuffer, Rgba};
-use crate::ImagePayload;
-pub use crate::image_processor::watermark::Watermark;
+use futures::StreamExt;
+use image::DynamicImage;
 
 pub enum ImageSource {
     Url(String),
     Binary(Vec<u8>),
     Base64(String),
+    /// A multipart file part, with the file name the client sent.
+    Upload {
+        name: String,
+        data: Vec<u8>,
+    },
+}
+
+impl ImageSource {
+    /// A multipart part's file name, or else a part that has none as bare bytes.
+    fn from_part(filename: Option<String>, data: Vec<u8>) -> ImageSource {
+        match filename.filter(|name| !name.is_empty()) {
+            Some(name) => ImageSource::Upload { name, data },
+            None => ImageSource::Binary(data),
+        }
+    }
+
+    /// Last path segment of a URL source or an upload's file name, used for
+    /// the `{filename}` placeholder.
+    pub fn file_name(&self) -> Option<String> {
+        match self {
+            ImageSource::Url(url) => url
+                .split(['?', '#'])
+                .next()
+                .and_then(|path| path.rsplit('/').next())
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+            ImageSource::Upload { name, .. } => name
+                .rsplit(['/', '\\'])
+                .next()
+                .filter(|name| !name.is_empty())
+                .map(str::to_string),
+            _ => None,
+        }
+    }
+}
+
+/// Where a logo watermark comes from: sent with the request or registered by name.
+pub enum LogoSource {
+    Image(ImageSource),
+    Named(String),
 }
 
 fn get_content_type(req: &HttpRequest) -> &str {
@@ -20,19 +86,32 @@ fn get_content_type(req: &HttpRequest) -> &str {
         .unwrap_or("")
 }
 
-pub async fn get_source(
+pub async fn get_source(req: HttpRequest, body: web::Bytes) -> Result<ImageSource> {
+    get_source_with_logo(req, body)
+        .await
+        .map(|(source, _)| source)
+}
+
+/// Same as `get_source`, but also picks up an optional logo sent with the image:
+/// `logo_url`/`logo_base64` in JSON, or a `logo` part in `multipart/form-data`.
+pub async fn get_source_with_logo(
     req: HttpRequest,
     body: web::Bytes,
-) -> Result<ImageSource> {
+) -> Result<(ImageSource, Option<ImageSource>)> {
     let content_type = get_content_type(&req);
 
-    let source = if content_type.starts_with("application/json") {
+    let sources = if content_type.starts_with("application/json") {
         match serde_json::from_slice::<ImagePayload>(&body) {
             Ok(payload) => {
+                let logo = if let Some(url) = payload.logo_url {
+                    Some(ImageSource::Url(url))
+                } else {
+                    payload.logo_base64.map(ImageSource::Base64)
+                };
                 if let Some(url) = payload.image_url {
-                    ImageSource::Url(url)
+                    (ImageSource::Url(url), logo)
                 } else if let Some(base64) = payload.image_base64 {
-                    ImageSource::Base64(base64)
+                    (ImageSource::Base64(base64), logo)
                 } else {
                     return Err(Error::msg(
                         "No image source in JSON: provide image_url or image_base64",
@@ -43,89 +122,222 @@ pub async fn get_source(
                 return Err(Error::msg(format!("Unrecognized JSON: {}", e)));
             }
         }
+    } else if content_type.starts_with("multipart/form-data") {
+        get_multipart_sources(&req, body).await?
     } else if content_type.starts_with("image/")
         || content_type == "application/octet-stream"
         || !body.is_empty()
     {
-        ImageSource::Binary(body.to_vec())
+        (ImageSource::Binary(body.to_vec()), None)
     } else {
         return Err(Error::msg(
             "Unsupported content type: provide JSON with image_url/image_base64 or binary image data",
         ));
     };
-    Ok(source)
+    Ok(sources)
 }
 
-#[allow(dead_code)]
-pub async fn slice(source: ImageSource, scale_px: u32) -> Result<[ImageBuffer<Rgba<u8>, Vec<u8>>; 4]> {
-    let img = load_image(source).await?;
-    let single_img_size = image_slicer::get_single_image_dimensions(&img);
-    let sliced = image_slicer::slice_images_view(img, &single_img_size);
+// Reads an `image` part (or the first unnamed part) and an optional `logo` part.
+async fn get_multipart_sources(
+    req: &HttpRequest,
+    body: web::Bytes,
+) -> Result<(ImageSource, Option<ImageSource>)> {
+    let mut image = None;
+    let mut logo = None;
+
+    for part in read_multipart(req, body).await? {
+        match part.name.as_str() {
+            "logo" => logo = Some(ImageSource::Binary(part.data)),
+            "image" => image = Some(ImageSource::from_part(part.filename, part.data)),
+            _ if image.is_none() => image = Some(ImageSource::from_part(part.filename, part.data)),
+            _ => {}
+        }
+    }
 
-    if scale_px > 0 && scale_px < single_img_size.smallest {
-        return Ok(image_slicer::resize(sliced, scale_px));
+    match image {
+        Some(image) => Ok((image, logo)),
+        None => Err(Error::msg("No image part in multipart body")),
     }
-    Ok(sliced)
 }
 
-#[allow(dead_code)]
-pub async fn slice_with_watermark_text(
-    source: ImageSource,
-    scale_px: u32,
-    watermark_text: &str,
-    transparency: u16,
-) -> Result<[ImageBuffer<Rgba<u8>, Vec<u8>>; 4]> {
-    let img = load_image(source).await?;
-    let single_img_size = image_slicer::get_single_image_dimensions(&img);
-    let mut sliced = image_slicer::slice_images_view(img, &single_img_size);
+// One part of a multipart body.
+struct Part {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
 
-    let wm_image = watermark::create_watermark(watermark_text, (single_img_size.width, single_img_size.height));
-    sliced.iter_mut().for_each(|slice_img| {
-        *slice_img = watermark::add_watermark(slice_img.clone(), &wm_image, transparency as f32 / 100.0);
-    });
+// Every part of a multipart body in order.
+async fn read_multipart(req: &HttpRequest, body: web::Bytes) -> Result<Vec<Part>> {
+    let stream = futures::stream::once(async move { Ok(body) });
+    let mut multipart = Multipart::new(req.headers(), stream);
+    let mut parts = Vec::new();
 
-    if scale_px > 0 && scale_px < single_img_size.smallest {
-        return Ok(image_slicer::resize(sliced, scale_px));
+    while let Some(field) = multipart.next().await {
+        let mut field = field.map_err(|e| Error::msg(format!("Invalid multipart body: {}", e)))?;
+        let name = field.name().unwrap_or("").to_string();
+        let filename = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| Error::msg(format!("Invalid multipart body: {}", e)))?;
+            data.extend_from_slice(&chunk);
+        }
+        parts.push(Part {
+            name,
+            filename,
+            data,
+        });
     }
-    Ok(sliced)
+    Ok(parts)
 }
 
-#[allow(dead_code)]
-pub async fn slice_with_watermark(
-    source: ImageSource,
-    scale_px: u32,
-    watermark: Watermark,
-    transparency: u16,
-) -> Result<[ImageBuffer<Rgba<u8>, Vec<u8>>; 4]> {
-    let img = load_image(source).await?;
-    let single_img_size = image_slicer::get_single_image_dimensions(&img);
-    let mut sliced = image_slicer::slice_images_view(img, &single_img_size);
+/// The tile files of a `/stitch` request, in order: the parts of a multipart
+/// body (`form-data`, or `mixed` as `/poster` and `/carousel` return), the
+/// files of a ZIP, or PNGs back to back as `/slice` returns them.
+pub async fn get_tiles(req: &HttpRequest, body: web::Bytes) -> Result<Vec<Vec<u8>>> {
+    let content_type = get_content_type(req);
+    if content_type.starts_with("multipart/") {
+        let parts = read_multipart(req, body).await?;
+        Ok(parts.into_iter().map(|part| part.data).collect())
+    } else if is_zip(content_type, &body) {
+        let files = stitch::read_zip(&body)?;
+        Ok(files.into_iter().map(|(_, data)| data).collect())
+    } else {
+        let tiles = stitch::split_png_stream(&body)?;
+        Ok(tiles.into_iter().map(<[u8]>::to_vec).collect())
+    }
+}
+
+/// The images of an `/atlas` request with their names, in order: the parts
+/// of a multipart body, named by file name or else field name, or the files
+/// of a ZIP, named by path.
+pub async fn get_sprites(req: &HttpRequest, body: web::Bytes) -> Result<Vec<(String, Vec<u8>)>> {
+    let content_type = get_content_type(req);
+    if content_type.starts_with("multipart/") {
+        let parts = read_multipart(req, body).await?;
+        Ok(parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, part)| {
+                let name = part
+                    .filename
+                    .filter(|f| !f.is_empty())
+                    .or_else(|| Some(part.name).filter(|n| !n.is_empty()))
+                    .unwrap_or_else(|| format!("sprite-{}.png", i + 1));
+                (name, part.data)
+            })
+            .collect())
+    } else if is_zip(content_type, &body) {
+        stitch::read_zip(&body)
+    } else {
+        Err(Error::msg("Send sprites as multipart parts or a ZIP"))
+    }
+}
+
+/// The images of a `/contact-sheet` request in order: the `images` of a JSON
+/// body, each with `image_url` or `image_base64` and an optional `caption`,
+/// or the parts of a multipart body. Parts have no caption of their own; their
+/// file name fills `{filename}` in the sheet's caption.
+pub async fn get_sheet_entries(req: &HttpRequest, body: web::Bytes) -> Result<Vec<SheetEntry>> {
+    let content_type = get_content_type(req);
+    if content_type.starts_with("application/json") {
+        let payload = serde_json::from_slice::<SheetPayload>(&body)
+            .map_err(|e| Error::msg(format!("Unrecognized JSON: {}", e)))?;
+        payload
+            .images
+            .into_iter()
+            .enumerate()
+            .map(|(i, image)| {
+                let source = if let Some(url) = image.image_url {
+                    ImageSource::Url(url)
+                } else if let Some(base64) = image.image_base64 {
+                    ImageSource::Base64(base64)
+                } else {
+                    return Err(Error::msg(format!(
+                        "Image {}: provide image_url or image_base64",
+                        i + 1
+                    )));
+                };
+                Ok(SheetEntry::new(source, image.caption))
+            })
+            .collect()
+    } else if content_type.starts_with("multipart/") {
+        let parts = read_multipart(req, body).await?;
+        Ok(parts
+            .into_iter()
+            .map(|part| SheetEntry::new(ImageSource::from_part(part.filename, part.data), None))
+            .collect())
+    } else {
+        Err(Error::msg(
+            "Send images as a JSON list of sources or multipart parts",
+        ))
+    }
+}
+
+fn is_zip(content_type: &str, body: &[u8]) -> bool {
+    content_type.starts_with("application/zip") || body.starts_with(b"PK\x03\x04")
+}
 
-    sliced.iter_mut().for_each(|slice_img| {
-        *slice_img = watermark::add_watermark(slice_img.clone(), &watermark, transparency as f32 / 100.0);
-    });
+/// A fresh `multipart/mixed` boundary with 128 random bits, so a part is very
+/// unlikely to contain it. Parts are not scanned for it.
+pub fn multipart_boundary(prefix: &str) -> String {
+    use std::hash::{BuildHasher, Hasher};
+    // RandomState is seeded from the OS, so its hashers make cheap random numbers.
+    let random = || {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u128(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default(),
+        );
+        hasher.finish()
+    };
+    format!("izdu-{}-{:016x}{:016x}", prefix, random(), random())
+}
 
-    if scale_px > 0 && scale_px < single_img_size.smallest {
-        return Ok(image_slicer::resize(sliced, scale_px));
+/// Append one `multipart/mixed` part; close the body with `--{boundary}--`.
+pub fn write_multipart_part(
+    body: &mut Vec<u8>,
+    boundary: &str,
+    mime: &str,
+    filename: &str,
+    data: &[u8],
+) {
+    if !body.is_empty() {
+        body.extend_from_slice(b"\r\n");
     }
-    Ok(sliced)
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Disposition: attachment; filename=\"{}\"\r\n\r\n",
+            boundary, mime, filename
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(data);
 }
 
-#[allow(dead_code)]
-pub async fn resize_image(
-    source: ImageSource,
-    width: Option<u32>,
-    height: Option<u32>,
-    aspect_ratio: &str,
-) -> Result<DynamicImage> {
+pub async fn resize_image(source: ImageSource, params: &ResizeParams) -> Result<Resized> {
     let img = load_image(source).await?;
-    Ok(image_slicer::resize_single(img, width, height, aspect_ratio))
+    resize::resize(&img, params)
+}
+
+pub async fn load_logo(source: LogoSource) -> Result<Watermark> {
+    match source {
+        LogoSource::Image(source) => load_image(source).await,
+        LogoSource::Named(name) => logo::get(&name),
+    }
 }
 
 pub async fn load_image(source: ImageSource) -> Result<DynamicImage> {
     match source {
         ImageSource::Url(url) => download_image(url).await,
-        ImageSource::Binary(bytes) => load_from_bytes(bytes),
+        ImageSource::Binary(bytes) | ImageSource::Upload { data: bytes, .. } => {
+            load_from_bytes(bytes)
+        }
         ImageSource::Base64(base64_str) => load_from_base64(base64_str),
     }
 }
@@ -0,0 +1,511 @@
+use crate::image_processor::poster::{Orientation, Paper, DEFAULT_MARGIN_MM};
+use crate::image_processor::variants::DEFAULT_QUALITY;
+use anyhow::{Error, Result};
+use flate2::write::ZlibEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, RgbaImage};
+use std::io::Write;
+use std::str::FromStr;
+
+/// Finished PDFs are streamed in pieces of this many bytes.
+pub const STREAM_CHUNK: usize = 64 * 1024;
+const PT_PER_MM: f32 = 72.0 / 25.4;
+/// Largest caption font size, in points.
+const MAX_CAPTION_PT: f32 = 10.0;
+/// Captions shrink to this size, in points, before they are cut short.
+const MIN_CAPTION_PT: f32 = 5.0;
+/// Helvetica advance widths of ASCII 32-126, in 1/1000 em.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// How tile pixels are stored in the PDF.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Compression {
+    /// Lossless, like PNG.
+    #[default]
+    Flate,
+    Jpeg,
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "flate" | "png" => Ok(Compression::Flate),
+            "jpeg" | "jpg" => Ok(Compression::Jpeg),
+            _ => Err(Error::msg(format!(
+                "Unknown PDF compression: {} (use flate or jpeg)",
+                s
+            ))),
+        }
+    }
+}
+
+/// PDF params as they arrive in a request; the margin is in mm.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PdfArgs<'a> {
+    pub paper: Option<&'a str>,
+    pub orientation: Option<&'a str>,
+    pub margin: Option<f32>,
+    pub marks: Option<bool>,
+    pub captions: Option<bool>,
+    pub compression: Option<&'a str>,
+    pub quality: Option<u8>,
+}
+
+/// One tile per page, scaled to fit inside the margins and centred. With
+/// `auto` orientation each page turns to match its tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PdfSpec {
+    pub paper: Paper,
+    pub orientation: Orientation,
+    pub margin: f32,
+    pub marks: bool,
+    pub captions: bool,
+    pub compression: Compression,
+    pub quality: u8,
+}
+
+impl PdfSpec {
+    pub fn from_args(args: &PdfArgs) -> Result<PdfSpec> {
+        let paper: Paper = args
+            .paper
+            .filter(|p| !p.is_empty())
+            .unwrap_or("a4")
+            .parse()?;
+        let orientation = match args.orientation {
+            Some(o) if !o.is_empty() => o.parse()?,
+            _ => Orientation::Auto,
+        };
+        let margin = args.margin.unwrap_or(DEFAULT_MARGIN_MM);
+        if !margin.is_finite() || margin < 0.0 {
+            return Err(Error::msg(format!(
+                "margin must be a length in mm of 0 or more, got {}",
+                margin
+            )));
+        }
+        let (w, h) = paper.size_mm();
+        if 2.0 * margin >= w.min(h) {
+            return Err(Error::msg(format!(
+                "A {}mm margin leaves no room on {:.0}x{:.0}mm paper",
+                margin, w, h
+            )));
+        }
+        let (marks, captions) = (args.marks.unwrap_or(false), args.captions.unwrap_or(false));
+        if (marks || captions) && margin <= 0.0 {
+            return Err(Error::msg(
+                "Crop marks and captions are drawn in the margin, which is 0",
+            ));
+        }
+        let compression = match args.compression {
+            Some(c) if !c.is_empty() => c.parse()?,
+            _ => Compression::default(),
+        };
+        let quality = match args.quality {
+            None => DEFAULT_QUALITY,
+            Some(q) if (1..=100).contains(&q) => q,
+            Some(q) => return Err(Error::msg(format!("quality must be 1-100, got {}", q))),
+        };
+        Ok(PdfSpec {
+            paper,
+            orientation,
+            margin,
+            marks,
+            captions,
+            compression,
+            quality,
+        })
+    }
+
+    // Page size in points for a tile of `size` px.
+    fn page_size(&self, size: (u32, u32)) -> (f32, f32) {
+        let (w, h) = self.paper.size_mm();
+        let landscape = match self.orientation {
+            Orientation::Portrait => false,
+            Orientation::Landscape => true,
+            Orientation::Auto => size.0 > size.1,
+        };
+        let (w, h) = if landscape { (h, w) } else { (w, h) };
+        (w * PT_PER_MM, h * PT_PER_MM)
+    }
+}
+
+/// Write `tiles` as a PDF, one per page. Captions name each tile's index,
+/// row and column in a grid `cols` wide, after `title` if there is one.
+pub fn render(tiles: &[RgbaImage], cols: u32, title: &str, spec: &PdfSpec) -> Result<Vec<u8>> {
+    let mut pdf = Writer::new();
+    let (catalog, pages, font, info) = (pdf.reserve(), pdf.reserve(), pdf.reserve(), pdf.reserve());
+
+    let mut kids = Vec::with_capacity(tiles.len());
+    for (i, tile) in tiles.iter().enumerate() {
+        let image = pdf.image(tile, spec)?;
+        let (pw, ph) = spec.page_size(tile.dimensions());
+        let m = spec.margin * PT_PER_MM;
+        let ratio =
+            ((pw - 2.0 * m) / tile.width() as f32).min((ph - 2.0 * m) / tile.height() as f32);
+        let (dw, dh) = (tile.width() as f32 * ratio, tile.height() as f32 * ratio);
+        let (x, y) = ((pw - dw) / 2.0, (ph - dh) / 2.0);
+
+        let mut content = format!("q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im0 Do Q\n", dw, dh, x, y);
+        if spec.marks {
+            content.push_str(&crop_marks((x, y, x + dw, y + dh), m));
+        }
+        if spec.captions {
+            let (row, col) = (i as u32 / cols.max(1), i as u32 % cols.max(1));
+            let caption = format!(
+                "{}Tile {} of {} - row {}, col {}",
+                if title.is_empty() {
+                    String::new()
+                } else {
+                    format!("{} - ", title)
+                },
+                i + 1,
+                tiles.len(),
+                row + 1,
+                col + 1
+            );
+            let (caption, size) =
+                fit_caption(&caption, (m * 0.4).min(MAX_CAPTION_PT), dw - m * 0.5);
+            content.push_str(&format!(
+                "BT /F1 {:.2} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+                size,
+                x + m * 0.5,
+                m * 0.35,
+                pdf_string(&caption)
+            ));
+        }
+        let content_id = pdf.reserve();
+        pdf.stream(content_id, "", content.as_bytes());
+
+        let page = pdf.reserve();
+        pdf.object(
+            page,
+            &format!(
+                "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents {} 0 R \
+                 /Resources << /XObject << /Im0 {} 0 R >> /Font << /F1 {} 0 R >> >> >>",
+                pages, pw, ph, content_id, image, font
+            ),
+        );
+        kids.push(format!("{} 0 R", page));
+    }
+
+    pdf.object(
+        pages,
+        &format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        ),
+    );
+    pdf.object(
+        font,
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+    );
+    pdf.object(info, "<< /Producer (IZDU-Slicer) >>");
+    pdf.object(
+        catalog,
+        &format!("<< /Type /Catalog /Pages {} 0 R >>", pages),
+    );
+    Ok(pdf.finish(catalog, info))
+}
+
+// Lines off each corner of the image, pointing along its edges, in the margin.
+fn crop_marks((x0, y0, x1, y1): (f32, f32, f32, f32), margin: f32) -> String {
+    let (gap, len) = (margin * 0.15, margin * 0.6);
+    let mut marks = String::from("q 0 G 0.25 w\n");
+    for (x, y, dx, dy) in [
+        (x0, y0, -1.0, -1.0),
+        (x1, y0, 1.0, -1.0),
+        (x0, y1, -1.0, 1.0),
+        (x1, y1, 1.0, 1.0),
+    ] {
+        marks.push_str(&format!(
+            "{:.2} {:.2} m {:.2} {:.2} l S {:.2} {:.2} m {:.2} {:.2} l S\n",
+            x + dx * gap,
+            y,
+            x + dx * (gap + len),
+            y,
+            x,
+            y + dy * gap,
+            x,
+            y + dy * (gap + len)
+        ));
+    }
+    marks.push_str("Q\n");
+    marks
+}
+
+// Width of `text` in Helvetica at 1 pt, as `pdf_string` writes it.
+fn text_width(text: &str) -> f32 {
+    text.chars()
+        .map(|c| {
+            let c = if c.is_ascii() && !c.is_ascii_control() {
+                c
+            } else {
+                '?'
+            };
+            HELVETICA_WIDTHS[c as usize - 32] as f32 / 1000.0
+        })
+        .sum()
+}
+
+// Shrink a caption from `size` until it fits in `width` points; below
+// MIN_CAPTION_PT, cut it short with "..." instead.
+fn fit_caption(text: &str, size: f32, width: f32) -> (String, f32) {
+    let size = size
+        .min(width / text_width(text))
+        .max(MIN_CAPTION_PT.min(size));
+    if text_width(text) * size <= width {
+        return (text.to_string(), size);
+    }
+    let budget = width / size - text_width("...");
+    let mut used = 0.0;
+    let kept: String = text
+        .chars()
+        .take_while(|c| {
+            used += text_width(c.encode_utf8(&mut [0; 4]));
+            used <= budget
+        })
+        .collect();
+    (format!("{}...", kept.trim_end()), size)
+}
+
+// A literal string for Helvetica: ASCII only, with the delimiters escaped.
+fn pdf_string(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+// Objects are numbered up front so they can refer to each other, then
+// written in any order; the cross-reference table records where each went.
+struct Writer {
+    out: Vec<u8>,
+    offsets: Vec<usize>,
+}
+
+impl Writer {
+    fn new() -> Writer {
+        // The binary comment marks the file as binary for transfer tools.
+        let mut out = b"%PDF-1.4\n%".to_vec();
+        out.extend_from_slice(&[0xe2, 0xe3, 0xcf, 0xd3, b'\n']);
+        Writer {
+            out,
+            offsets: Vec::new(),
+        }
+    }
+
+    fn reserve(&mut self) -> usize {
+        self.offsets.push(0);
+        self.offsets.len()
+    }
+
+    fn object(&mut self, id: usize, body: &str) {
+        self.offsets[id - 1] = self.out.len();
+        self.out
+            .extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", id, body).as_bytes());
+    }
+
+    fn stream(&mut self, id: usize, dict: &str, data: &[u8]) {
+        self.offsets[id - 1] = self.out.len();
+        self.out.extend_from_slice(
+            format!(
+                "{} 0 obj\n<< {} /Length {} >>\nstream\n",
+                id,
+                dict,
+                data.len()
+            )
+            .as_bytes(),
+        );
+        self.out.extend_from_slice(data);
+        self.out.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    // An image XObject, with a soft mask if the tile has transparency.
+    fn image(&mut self, tile: &RgbaImage, spec: &PdfSpec) -> Result<usize> {
+        let (w, h) = tile.dimensions();
+        let smask = if tile.pixels().any(|p| p[3] < 255) {
+            let alpha: Vec<u8> = tile.pixels().map(|p| p[3]).collect();
+            let id = self.reserve();
+            let dict = format!(
+                "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray \
+                 /BitsPerComponent 8 /Filter /FlateDecode",
+                w, h
+            );
+            self.stream(id, &dict, &deflate(&alpha)?);
+            format!(" /SMask {} 0 R", id)
+        } else {
+            String::new()
+        };
+
+        let rgb = DynamicImage::ImageRgba8(tile.clone()).into_rgb8();
+        let (filter, data) = match spec.compression {
+            Compression::Flate => ("FlateDecode", deflate(rgb.as_raw())?),
+            Compression::Jpeg => {
+                let mut data = Vec::new();
+                JpegEncoder::new_with_quality(&mut data, spec.quality).encode_image(&rgb)?;
+                ("DCTDecode", data)
+            }
+        };
+        let id = self.reserve();
+        let dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+             /BitsPerComponent 8 /Filter /{}{}",
+            w, h, filter, smask
+        );
+        self.stream(id, &dict, &data);
+        Ok(id)
+    }
+
+    fn finish(mut self, root: usize, info: usize) -> Vec<u8> {
+        let xref = self.out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.offsets.len() + 1);
+        for offset in &self.offsets {
+            table.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        table.push_str(&format!(
+            "trailer\n<< /Size {} /Root {} 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            root,
+            info,
+            xref
+        ));
+        self.out.extend_from_slice(table.as_bytes());
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    fn spec(args: PdfArgs) -> PdfSpec {
+        PdfSpec::from_args(&args).unwrap()
+    }
+
+    fn count(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .filter(|w| *w == needle.as_bytes())
+            .count()
+    }
+
+    #[test]
+    fn one_page_per_tile_with_a_valid_xref() {
+        let tile = ImageBuffer::from_pixel(40, 20, Rgba([10u8, 20, 30, 255]));
+        let spec = spec(PdfArgs {
+            marks: Some(true),
+            captions: Some(true),
+            ..PdfArgs::default()
+        });
+        let pdf = render(&vec![tile; 4], 2, "photo (1)", &spec).unwrap();
+
+        assert!(pdf.starts_with(b"%PDF-1.4"));
+        assert_eq!(count(&pdf, "/Type /Page "), 4);
+        assert_eq!(count(&pdf, "/FlateDecode"), 4);
+        // Wide tiles on landscape A4.
+        assert_eq!(count(&pdf, "/MediaBox [0 0 841.89 595.28]"), 4);
+        assert_eq!(
+            count(&pdf, "(photo \\(1\\) - Tile 4 of 4 - row 2, col 2) Tj"),
+            1
+        );
+
+        // Every xref entry points at its object.
+        let text = String::from_utf8_lossy(&pdf);
+        let start: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        let xref = String::from_utf8_lossy(&pdf[start..]);
+        assert!(xref.starts_with("xref\n0 "));
+        for (id, line) in xref
+            .lines()
+            .skip(3)
+            .take_while(|l| l.ends_with(" n "))
+            .enumerate()
+        {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(
+                pdf[offset..].starts_with(format!("{} 0 obj", id + 1).as_bytes()),
+                "object {}",
+                id + 1
+            );
+        }
+    }
+
+    #[test]
+    fn long_captions_shrink_then_get_cut_short() {
+        let (caption, size) = fit_caption("Tile 1 of 4", 10.0, 200.0);
+        assert_eq!((caption.as_str(), size), ("Tile 1 of 4", 10.0));
+
+        let title = "a very long file name - Tile 1 of 4 - row 1, col 1";
+        let (caption, size) = fit_caption(title, 10.0, 150.0);
+        assert_eq!(caption, title);
+        assert!((MIN_CAPTION_PT..10.0).contains(&size));
+        assert!(text_width(&caption) * size <= 150.0);
+
+        let (caption, size) = fit_caption(&title.repeat(4), 10.0, 150.0);
+        assert_eq!(size, MIN_CAPTION_PT);
+        assert!(caption.starts_with("a very long") && caption.ends_with("..."));
+        assert!(text_width(&caption) * size <= 150.0);
+    }
+
+    #[test]
+    fn jpeg_tiles_and_soft_masks() {
+        let mut tile = ImageBuffer::from_pixel(10, 30, Rgba([10u8, 20, 30, 255]));
+        tile.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let spec = spec(PdfArgs {
+            paper: Some("letter"),
+            compression: Some("jpeg"),
+            ..PdfArgs::default()
+        });
+        let pdf = render(&[tile], 1, "", &spec).unwrap();
+        assert_eq!(count(&pdf, "/DCTDecode"), 1);
+        assert_eq!(count(&pdf, "/SMask"), 1);
+        assert_eq!(count(&pdf, "/MediaBox [0 0 612.00 792.00]"), 1);
+        assert_eq!(count(&pdf, " Tj"), 0);
+    }
+
+    #[test]
+    fn rejects_bad_params() {
+        let bad = |args: PdfArgs| PdfSpec::from_args(&args).is_err();
+        assert!(bad(PdfArgs {
+            compression: Some("lzw"),
+            ..PdfArgs::default()
+        }));
+        assert!(bad(PdfArgs {
+            margin: Some(150.0),
+            ..PdfArgs::default()
+        }));
+        assert!(bad(PdfArgs {
+            margin: Some(0.0),
+            captions: Some(true),
+            ..PdfArgs::default()
+        }));
+        assert!(bad(PdfArgs {
+            quality: Some(0),
+            ..PdfArgs::default()
+        }));
+    }
+}
@@ -0,0 +1,673 @@
+use crate::image_processor::adjust::{AdjustArgs, Adjustments, TileAdjustments};
+use crate::image_processor::blend::Blend;
+use crate::image_processor::effects::{EffectArgs, Effects};
+use crate::image_processor::image_slicer::{self, Remainder, TileScale};
+use crate::image_processor::invisible;
+use crate::image_processor::logo;
+use crate::image_processor::qr::QrSpec;
+use crate::image_processor::resize::{self, Rect, ResizeArgs, ResizeParams};
+use crate::image_processor::text::{self, TextStyle, TileContext};
+use crate::image_processor::transform::Transform;
+use crate::image_processor::variants::{self, Format, DEFAULT_QUALITY};
+use crate::image_processor::watermark::{
+    transparency_from, Placement, Scope, Watermark, WatermarkContent, WatermarkSpec,
+};
+use anyhow::{Error, Result};
+use image::{DynamicImage, RgbaImage};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Upper bound on steps per pipeline.
+pub const MAX_STEPS: usize = 32;
+
+/// One operation of a pipeline, already validated.
+pub enum Op {
+    Transform(Transform),
+    Resize(ResizeParams),
+    /// Slice scaling, which may leave some tiles as they are.
+    ScaleTile(TileScale),
+    Adjust(Adjustments),
+    Effects(Effects),
+    Watermark(WatermarkSpec),
+    Qr(QrSpec),
+    Invisible(String),
+    /// Split into four tiles; later steps apply to every tile.
+    Slice(Remainder),
+    /// Output format; only allowed as the last step.
+    Encode {
+        format: Format,
+        quality: u8,
+    },
+}
+
+impl Op {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Transform(Transform::Rotate { .. }) => "rotate",
+            Op::Transform(Transform::Flip(_)) => "flip",
+            Op::Transform(Transform::Crop(_)) => "crop",
+            Op::Transform(Transform::Pad { .. }) => "pad",
+            Op::Transform(Transform::Trim { .. }) => "trim",
+            Op::Resize(_) | Op::ScaleTile(_) => "resize",
+            Op::Adjust(_) => "adjust",
+            Op::Effects(_) => "effects",
+            Op::Watermark(_) => "watermark",
+            Op::Qr(_) => "qr",
+            Op::Invisible(_) => "invisible",
+            Op::Slice(_) => "slice",
+            Op::Encode { .. } => "encode",
+        }
+    }
+}
+
+/// An operation and, after slicing, the one tile (0-3) it is limited to.
+pub struct Step {
+    pub op: Op,
+    pub tile: Option<usize>,
+}
+
+impl Step {
+    pub fn new(op: Op) -> Step {
+        Step { op, tile: None }
+    }
+}
+
+/// A step as written in JSON: `{"op": "resize", "width": 800, ...}`, plus an
+/// optional `"tile"`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+enum StepSpec {
+    Crop {
+        x: Option<u32>,
+        y: Option<u32>,
+        width: u32,
+        height: u32,
+    },
+    Rotate {
+        #[serde(alias = "angle")]
+        degrees: f32,
+        background: Option<String>,
+    },
+    Flip {
+        direction: Option<String>,
+    },
+    Pad {
+        padding: Option<u32>,
+        top: Option<u32>,
+        right: Option<u32>,
+        bottom: Option<u32>,
+        left: Option<u32>,
+        background: Option<String>,
+    },
+    Trim {
+        threshold: Option<u8>,
+    },
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+        aspect_ratio: Option<String>,
+        fit: Option<String>,
+        gravity: Option<String>,
+        background: Option<String>,
+        filter: Option<String>,
+        allow_upscale: Option<bool>,
+        upscale_quality: Option<String>,
+    },
+    Adjust {
+        brightness: Option<f32>,
+        contrast: Option<f32>,
+        gamma: Option<f32>,
+        saturation: Option<f32>,
+        hue: Option<f32>,
+        grayscale: Option<bool>,
+        sepia: Option<f32>,
+        invert: Option<bool>,
+        tint: Option<String>,
+    },
+    Effects {
+        blur: Option<f32>,
+        sharpen: Option<f32>,
+        unsharp_sigma: Option<f32>,
+        unsharp_amount: Option<f32>,
+        unsharp_threshold: Option<u32>,
+        pixelate: Option<u32>,
+    },
+    Watermark {
+        text: Option<String>,
+        logo: Option<String>,
+        transparency: Option<u16>,
+        opacity: Option<u16>,
+        position: Option<String>,
+        scale: Option<u32>,
+        align: Option<String>,
+        line_spacing: Option<f32>,
+        font: Option<String>,
+        blend: Option<String>,
+        linear_light: Option<bool>,
+    },
+    Qr {
+        data: String,
+        size: Option<u32>,
+        position: Option<String>,
+        ec: Option<String>,
+        quiet_zone: Option<u32>,
+        symbology: Option<String>,
+    },
+    Invisible {
+        payload: String,
+    },
+    Slice {
+        remainder: Option<String>,
+    },
+    Encode {
+        format: Option<String>,
+        quality: Option<u8>,
+    },
+}
+
+impl StepSpec {
+    // `uploaded_logo` is used by watermark steps with neither text nor a logo name.
+    fn into_op(self, uploaded_logo: Option<&Watermark>) -> Result<Op> {
+        Ok(match self {
+            StepSpec::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => Op::Transform(Transform::crop(
+                x.unwrap_or(0),
+                y.unwrap_or(0),
+                width,
+                height,
+            )?),
+            StepSpec::Rotate {
+                degrees,
+                background,
+            } => Op::Transform(Transform::rotate(degrees, background.as_deref())?),
+            StepSpec::Flip { direction } => Op::Transform(Transform::flip(direction.as_deref())?),
+            StepSpec::Pad {
+                padding,
+                top,
+                right,
+                bottom,
+                left,
+                background,
+            } => Op::Transform(Transform::pad(
+                padding,
+                [top, right, bottom, left],
+                background.as_deref(),
+            )?),
+            StepSpec::Trim { threshold } => Op::Transform(Transform::trim(threshold)),
+            StepSpec::Resize {
+                width,
+                height,
+                aspect_ratio,
+                fit,
+                gravity,
+                background,
+                filter,
+                allow_upscale,
+                upscale_quality,
+            } => Op::Resize(ResizeParams::from_args(&ResizeArgs {
+                width,
+                height,
+                aspect_ratio: aspect_ratio.as_deref(),
+                fit: fit.as_deref(),
+                gravity: gravity.as_deref(),
+                background: background.as_deref(),
+                filter: filter.as_deref(),
+                allow_upscale,
+                upscale_quality: upscale_quality.as_deref(),
+            })?),
+            StepSpec::Adjust {
+                brightness,
+                contrast,
+                gamma,
+                saturation,
+                hue,
+                grayscale,
+                sepia,
+                invert,
+                tint,
+            } => Op::Adjust(Adjustments::from_args(&AdjustArgs {
+                brightness,
+                contrast,
+                gamma,
+                saturation,
+                hue,
+                grayscale,
+                sepia,
+                invert,
+                tint: tint.as_deref(),
+            })?),
+            StepSpec::Effects {
+                blur,
+                sharpen,
+                unsharp_sigma,
+                unsharp_amount,
+                unsharp_threshold,
+                pixelate,
+            } => {
+                let effects = Effects::from_args(&EffectArgs {
+                    blur,
+                    sharpen,
+                    unsharp_sigma,
+                    unsharp_amount,
+                    unsharp_threshold,
+                    pixelate,
+                })?;
+                if effects.is_empty() {
+                    return Err(Error::msg(
+                        "Provide at least one of blur, sharpen, unsharp_* or pixelate",
+                    ));
+                }
+                Op::Effects(effects)
+            }
+            StepSpec::Watermark {
+                text,
+                logo: logo_name,
+                transparency,
+                opacity,
+                position,
+                scale,
+                align,
+                line_spacing,
+                font,
+                blend,
+                linear_light,
+            } => {
+                let content = match (text.filter(|t| !t.is_empty()), logo_name, uploaded_logo) {
+                    (Some(_), Some(_), _) => {
+                        return Err(Error::msg(
+                            "Provide either watermark text or a logo, not both",
+                        ))
+                    }
+                    (Some(text), None, _) => WatermarkContent::Text(
+                        text,
+                        TextStyle::parse(align.as_deref(), line_spacing, font.as_deref())?,
+                    ),
+                    (None, Some(name), _) => WatermarkContent::Logo(logo::get(&name)?),
+                    (None, None, Some(upload)) => WatermarkContent::Logo(upload.clone()),
+                    (None, None, None) => {
+                        return Err(Error::msg("Watermark text or logo required"))
+                    }
+                };
+                Op::Watermark(WatermarkSpec {
+                    content,
+                    transparency: transparency_from(opacity, transparency)?,
+                    placement: Placement::parse(position.as_deref(), scale, None)?,
+                    blend: Blend::parse(blend.as_deref(), linear_light)?,
+                })
+            }
+            StepSpec::Qr {
+                data,
+                size,
+                position,
+                ec,
+                quiet_zone,
+                symbology,
+            } => Op::Qr(QrSpec::parse(
+                &data,
+                size,
+                position.as_deref(),
+                ec.as_deref(),
+                quiet_zone,
+                symbology.as_deref(),
+            )?),
+            StepSpec::Invisible { payload } => invisible_op(payload)?,
+            StepSpec::Slice { remainder } => Op::Slice(Remainder::parse(remainder.as_deref())?),
+            StepSpec::Encode { format, quality } => encode_op(format.as_deref(), quality)?,
+        })
+    }
+}
+
+pub fn invisible_op(payload: String) -> Result<Op> {
+    if payload.is_empty() || payload.len() > invisible::MAX_PAYLOAD {
+        return Err(Error::msg(format!(
+            "Invisible payload must be 1-{} bytes",
+            invisible::MAX_PAYLOAD
+        )));
+    }
+    Ok(Op::Invisible(payload))
+}
+
+/// `format` defaults to PNG, `quality` (JPEG only, 1-100) to 80.
+pub fn encode_op(format: Option<&str>, quality: Option<u8>) -> Result<Op> {
+    let (format, quality) = variants::parse_encoding(format, quality)?;
+    Ok(Op::Encode { format, quality })
+}
+
+/// What `/slice` and the Slice RPCs ask for, already validated.
+pub struct SliceOps {
+    pub watermark: Option<WatermarkSpec>,
+    pub remainder: Remainder,
+    pub tile: Option<TileScale>,
+    pub effects: Effects,
+    pub adjustments: TileAdjustments,
+    pub qr: Option<QrSpec>,
+    /// Empty for no invisible watermark.
+    pub invisible: String,
+}
+
+impl SliceOps {
+    /// The steps in their fixed order: an image-scope watermark, the slice,
+    /// a tile-scope watermark, then scaling, filters, adjustments, the QR
+    /// code and the invisible watermark on every tile.
+    pub fn steps(self) -> Result<Vec<Step>> {
+        let mut steps = Vec::new();
+        let (before, after) = match self.watermark {
+            Some(spec) if spec.placement.scope == Scope::Image => (Some(spec), None),
+            spec => (None, spec),
+        };
+        steps.extend(before.map(|spec| Step::new(Op::Watermark(spec))));
+        steps.push(Step::new(Op::Slice(self.remainder)));
+        steps.extend(after.map(|spec| Step::new(Op::Watermark(spec))));
+        steps.extend(self.tile.map(|tile| Step::new(Op::ScaleTile(tile))));
+        if !self.effects.is_empty() {
+            steps.push(Step::new(Op::Effects(self.effects)));
+        }
+        let TileAdjustments { global, tiles } = self.adjustments;
+        if !global.is_identity() {
+            steps.push(Step::new(Op::Adjust(global)));
+        }
+        for (i, adjust) in tiles.into_iter().enumerate() {
+            if !adjust.is_identity() {
+                steps.push(Step {
+                    op: Op::Adjust(adjust),
+                    tile: Some(i),
+                });
+            }
+        }
+        steps.extend(self.qr.map(|qr| Step::new(Op::Qr(qr))));
+        if !self.invisible.is_empty() {
+            steps.push(Step::new(invisible_op(self.invisible)?));
+        }
+        Ok(steps)
+    }
+}
+
+/// An ordered list of steps run on one decoded image.
+pub struct Pipeline {
+    steps: Vec<Step>,
+}
+
+/// What a pipeline produced: one image, or four tiles in reading order.
+pub struct Output {
+    pub images: Vec<RgbaImage>,
+    pub tiled: bool,
+    pub format: Format,
+    pub quality: u8,
+    /// Whether any resize step enlarged an image.
+    pub upscaled: bool,
+    /// Per image: the part kept by the last `cover` resize, in its own pixels.
+    pub crops: Vec<Option<Rect>>,
+}
+
+impl Output {
+    pub fn encode(&self) -> Result<Vec<Vec<u8>>> {
+        (0..self.images.len())
+            .map(|i| self.encode_image(i))
+            .collect()
+    }
+
+    pub fn encode_image(&self, index: usize) -> Result<Vec<u8>> {
+        let img = DynamicImage::ImageRgba8(self.images[index].clone());
+        self.format.encode(&img, self.quality)
+    }
+
+    /// `image.png`, or `tile-0.png` to `tile-3.png`.
+    pub fn file_name(&self, index: usize) -> String {
+        if self.tiled {
+            format!("tile-{}.{}", index, self.format.extension())
+        } else {
+            format!("image.{}", self.format.extension())
+        }
+    }
+}
+
+/// Prefix an error with the step it came from: `Step 2 (resize): ...`.
+pub fn step_error(index: usize, name: &str, e: impl std::fmt::Display) -> Error {
+    Error::msg(format!("Step {} ({}): {}", index, name, e))
+}
+
+impl Pipeline {
+    /// Check the order of `steps`: at most one slice, `tile` only after it,
+    /// and encode only at the end.
+    pub fn new(steps: Vec<Step>) -> Result<Pipeline> {
+        if steps.is_empty() {
+            return Err(Error::msg("Pipeline has no steps"));
+        }
+        if steps.len() > MAX_STEPS {
+            return Err(Error::msg(format!(
+                "Pipeline has more than {} steps",
+                MAX_STEPS
+            )));
+        }
+        let mut sliced = false;
+        for (i, step) in steps.iter().enumerate() {
+            let name = step.op.name();
+            match (&step.op, step.tile) {
+                (Op::Slice(_), _) if sliced => {
+                    return Err(step_error(i, name, "the image is already sliced"))
+                }
+                (Op::Slice(_) | Op::Encode { .. }, Some(_)) => {
+                    return Err(step_error(i, name, "tile is not allowed on this step"))
+                }
+                (Op::Encode { .. }, _) if i + 1 != steps.len() => {
+                    return Err(step_error(i, name, "encode must be the last step"))
+                }
+                (_, Some(_)) if !sliced => {
+                    return Err(step_error(i, name, "tile needs an earlier slice step"))
+                }
+                (_, Some(tile)) if tile > 3 => {
+                    return Err(step_error(
+                        i,
+                        name,
+                        format!("tile must be 0-3, got {}", tile),
+                    ))
+                }
+                _ => {}
+            }
+            sliced |= matches!(step.op, Op::Slice(_));
+        }
+        Ok(Pipeline { steps })
+    }
+
+    /// Parse JSON steps, e.g. `[{"op": "crop", ...}, {"op": "slice"}]`.
+    pub fn from_json(steps: &[Value], uploaded_logo: Option<&Watermark>) -> Result<Pipeline> {
+        let steps = steps
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let mut value = value.clone();
+                let name = value
+                    .get("op")
+                    .and_then(Value::as_str)
+                    .unwrap_or("?")
+                    .to_string();
+                let tile = match value.as_object_mut().and_then(|o| o.remove("tile")) {
+                    None | Some(Value::Null) => None,
+                    Some(t) => Some(
+                        serde_json::from_value::<usize>(t).map_err(|e| step_error(i, &name, e))?,
+                    ),
+                };
+                let op = serde_json::from_value::<StepSpec>(value)
+                    .map_err(|e| step_error(i, &name, e))?
+                    .into_op(uploaded_logo)
+                    .map_err(|e| step_error(i, &name, e))?;
+                Ok(Step { op, tile })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Pipeline::new(steps)
+    }
+
+    pub fn run(&self, img: DynamicImage, filename: &str) -> Result<Output> {
+        let mut output = Output {
+            images: vec![img.into_rgba8()],
+            tiled: false,
+            format: Format::Png,
+            quality: DEFAULT_QUALITY,
+            upscaled: false,
+            crops: vec![None],
+        };
+        let date = text::today();
+        for (i, step) in self.steps.iter().enumerate() {
+            apply_step(step, &mut output, filename, &date)
+                .map_err(|e| step_error(i, step.op.name(), e))?;
+        }
+        Ok(output)
+    }
+}
+
+fn apply_step(step: &Step, output: &mut Output, filename: &str, date: &str) -> Result<()> {
+    match &step.op {
+        Op::Slice(remainder) => {
+            let img = DynamicImage::ImageRgba8(std::mem::take(&mut output.images[0]));
+            let dim = remainder.dimensions(&img);
+            output.images = image_slicer::slice_images_view(img, &dim).into();
+            output.crops = vec![None; 4];
+            output.tiled = true;
+            return Ok(());
+        }
+        Op::Encode { format, quality } => {
+            output.format = *format;
+            output.quality = *quality;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let (cols, total) = if output.tiled {
+        (image_slicer::GRID_COLS, 4)
+    } else {
+        (1, 1)
+    };
+    for (i, img) in output.images.iter_mut().enumerate() {
+        if step.tile.is_some_and(|t| t != i) {
+            continue;
+        }
+        let tile = TileContext::for_grid(i as u32, cols, total, filename, date);
+        let size = (img.width(), img.height());
+        match &step.op {
+            Op::Transform(transform) => {
+                let source = DynamicImage::ImageRgba8(std::mem::take(img));
+                *img = transform.apply(&source)?.into_rgba8();
+            }
+            Op::ScaleTile(scale) if scale.params_for(size).is_none() => {}
+            Op::Resize(params) | Op::ScaleTile(TileScale { params, .. }) => {
+                let resized =
+                    resize::resize(&DynamicImage::ImageRgba8(std::mem::take(img)), params)?;
+                output.upscaled |= resized.upscaled;
+                output.crops[i] = resized.crop;
+                *img = resized.image.into_rgba8();
+            }
+            Op::Adjust(adjustments) => adjustments.apply(img),
+            Op::Effects(effects) => *img = effects.apply(std::mem::take(img)),
+            Op::Watermark(spec) => {
+                let wm = spec.render_for_tile(size, &tile)?;
+                spec.apply_in_place(img, &wm);
+            }
+            Op::Qr(spec) => {
+                let code = spec.render_for_tile(size, &tile)?;
+                spec.apply_in_place(img, &code);
+            }
+            Op::Invisible(payload) => invisible::embed(img, payload.as_bytes())?,
+            Op::Slice(_) | Op::Encode { .. } => unreachable!("handled above"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+    use serde_json::json;
+
+    fn image(w: u32, h: u32) -> DynamicImage {
+        ImageBuffer::from_fn(w, h, |x, y| Rgba([(x * 3) as u8, (y * 3) as u8, 90, 255])).into()
+    }
+
+    fn run(steps: Value) -> Result<Output> {
+        let steps = steps.as_array().unwrap().clone();
+        Pipeline::from_json(&steps, None)?.run(image(80, 60), "photo.png")
+    }
+
+    #[test]
+    fn steps_run_in_order_on_one_image() {
+        let output = run(json!([
+            {"op": "crop", "x": 10, "y": 10, "width": 60, "height": 40},
+            {"op": "resize", "width": 30},
+            {"op": "rotate", "degrees": 90},
+            {"op": "encode", "format": "jpeg", "quality": 70}
+        ]))
+        .unwrap();
+        assert!(!output.tiled);
+        assert_eq!(output.images[0].dimensions(), (20, 30));
+        assert_eq!(output.format, Format::Jpeg);
+        assert_eq!(output.file_name(0), "image.jpg");
+        assert_eq!(output.encode().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn steps_after_slice_apply_per_tile() {
+        let output = run(json!([
+            {"op": "slice"},
+            {"op": "resize", "width": 20, "height": 20, "fit": "cover"},
+            {"op": "adjust", "invert": true, "tile": 2}
+        ]))
+        .unwrap();
+        assert!(output.tiled);
+        assert_eq!(output.images.len(), 4);
+        assert!(output.images.iter().all(|t| t.dimensions() == (20, 20)));
+        assert!(output.crops.iter().all(Option::is_some));
+        // Only the bottom-left tile was inverted: its blue channel is 255 - 90.
+        assert_eq!(output.images[2].get_pixel(0, 0)[2], 165);
+        assert_eq!(output.images[3].get_pixel(0, 0)[2], 90);
+    }
+
+    #[test]
+    fn slice_ops_adjust_tiles_after_the_global_adjustments() {
+        let global = Adjustments::parse("brightness:10").unwrap();
+        let ops = SliceOps {
+            watermark: None,
+            remainder: Remainder::Drop,
+            tile: None,
+            effects: Effects::default(),
+            adjustments: TileAdjustments::parse(global, Some(";invert")).unwrap(),
+            qr: None,
+            invisible: String::new(),
+        };
+        let gray = ImageBuffer::from_pixel(2, 2, Rgba([100u8, 100, 100, 255]));
+        let output = Pipeline::new(ops.steps().unwrap())
+            .unwrap()
+            .run(gray.into(), "photo.png")
+            .unwrap();
+        assert_eq!(output.images[0].get_pixel(0, 0).0, [126, 126, 126, 255]);
+        assert_eq!(output.images[1].get_pixel(0, 0).0, [129, 129, 129, 255]);
+        assert_eq!(output.images[3].get_pixel(0, 0).0, [126, 126, 126, 255]);
+    }
+
+    #[test]
+    fn errors_name_the_failing_step() {
+        let message = |steps| run(steps).err().unwrap().to_string();
+        assert!(
+            message(json!([{"op": "slice"}, {"op": "blur", "sigma": 2}]))
+                .starts_with("Step 1 (blur):")
+        );
+        assert!(message(json!([{"op": "crop", "width": 10}])).starts_with("Step 0 (crop):"));
+        assert!(
+            message(json!([{"op": "resize", "width": 10, "fit": "cover"}]))
+                .starts_with("Step 0 (resize):")
+        );
+        assert!(message(json!([{"op": "adjust", "gamma": 1.2, "tile": 1}]))
+            .contains("earlier slice step"));
+        assert!(message(json!([{"op": "encode"}, {"op": "slice"}])).starts_with("Step 0 (encode):"));
+        assert!(message(json!([{"op": "slice"}, {"op": "slice"}])).starts_with("Step 1 (slice):"));
+        // Runtime failures point at their step too.
+        assert!(message(
+            json!([{"op": "resize", "width": 10}, {"op": "crop", "width": 20, "height": 20}])
+        )
+        .starts_with("Step 1 (crop): Crop rectangle"));
+    }
+}
@@ -0,0 +1,542 @@
+use crate::image_processor::image_slicer;
+use crate::image_processor::resize::Rect;
+use crate::image_processor::text::{TextAlign, TextStyle};
+use crate::image_processor::variants::{Format, DEFAULT_QUALITY};
+use crate::image_processor::watermark;
+use anyhow::{Error, Result};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use std::str::FromStr;
+
+pub const DEFAULT_DPI: u32 = 300;
+pub const DEFAULT_MARGIN_MM: f32 = 10.0;
+const MAX_DPI: u32 = 1200;
+/// Upper bound on pages, so one request can't render hundreds of sheets.
+const MAX_PAGES: usize = 64;
+/// Upper bound on one page's pixels; A1 at 300 DPI is about 70 MP.
+const MAX_PAGE_PIXELS: u64 = 100_000_000;
+const MM_PER_INCH: f32 = 25.4;
+const INK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Sheet size; ISO A sizes, the common US sizes, or `WxH` in mm.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Paper {
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+    Tabloid,
+    Custom(f32, f32),
+}
+
+impl Paper {
+    /// Portrait width and height in mm.
+    pub fn size_mm(self) -> (f32, f32) {
+        match self {
+            Paper::A0 => (841.0, 1189.0),
+            Paper::A1 => (594.0, 841.0),
+            Paper::A2 => (420.0, 594.0),
+            Paper::A3 => (297.0, 420.0),
+            Paper::A4 => (210.0, 297.0),
+            Paper::A5 => (148.0, 210.0),
+            Paper::Letter => (215.9, 279.4),
+            Paper::Legal => (215.9, 355.6),
+            Paper::Tabloid => (279.4, 431.8),
+            Paper::Custom(w, h) => (w.min(h), w.max(h)),
+        }
+    }
+}
+
+impl FromStr for Paper {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let paper = match s.trim().to_lowercase().as_str() {
+            "a0" => Paper::A0,
+            "a1" => Paper::A1,
+            "a2" => Paper::A2,
+            "a3" => Paper::A3,
+            "a4" => Paper::A4,
+            "a5" => Paper::A5,
+            "letter" => Paper::Letter,
+            "legal" => Paper::Legal,
+            "tabloid" => Paper::Tabloid,
+            custom => {
+                let size = custom
+                    .trim_end_matches("mm")
+                    .split_once('x')
+                    .and_then(|(w, h)| {
+                        Some((w.trim().parse::<f32>().ok()?, h.trim().parse::<f32>().ok()?))
+                    });
+                match size {
+                    Some((w, h)) if (10.0..=5000.0).contains(&w) && (10.0..=5000.0).contains(&h) => {
+                        Paper::Custom(w, h)
+                    }
+                    _ => {
+                        return Err(Error::msg(format!(
+                            "Unknown paper: {} (use a0-a5, letter, legal, tabloid or WxH in mm, 10-5000)",
+                            s
+                        )))
+                    }
+                }
+            }
+        };
+        Ok(paper)
+    }
+}
+
+/// How pages are turned; `auto` picks whichever needs fewer pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Orientation {
+    #[default]
+    Portrait,
+    Landscape,
+    Auto,
+}
+
+impl Orientation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Orientation::Portrait => "portrait",
+            Orientation::Landscape => "landscape",
+            Orientation::Auto => "auto",
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "portrait" => Ok(Orientation::Portrait),
+            "landscape" => Ok(Orientation::Landscape),
+            "auto" => Ok(Orientation::Auto),
+            _ => Err(Error::msg(format!(
+                "Unknown orientation: {} (use portrait, landscape or auto)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Poster params as they arrive in a request; lengths are in mm.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PosterArgs<'a> {
+    pub paper: Option<&'a str>,
+    pub orientation: Option<&'a str>,
+    pub dpi: Option<u32>,
+    pub margin: Option<f32>,
+    pub overlap: Option<f32>,
+    pub marks: Option<bool>,
+    pub labels: Option<bool>,
+    pub format: Option<&'a str>,
+    pub quality: Option<u8>,
+}
+
+/// How to print an image across sheets: the image is placed at `dpi`, so its
+/// pixel size sets the poster's physical size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PosterSpec {
+    pub paper: Paper,
+    pub orientation: Orientation,
+    pub dpi: u32,
+    pub margin: f32,
+    pub overlap: f32,
+    pub marks: bool,
+    pub labels: bool,
+    pub format: Format,
+    pub quality: u8,
+}
+
+/// The sheets for one image: page size and margin in px, and the part of the
+/// image each page carries, in reading order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub orientation: Orientation,
+    pub page: (u32, u32),
+    pub margin: u32,
+    pub overlap: u32,
+    pub cols: u32,
+    pub rows: u32,
+    pub cells: Vec<Rect>,
+}
+
+impl PosterSpec {
+    pub fn from_args(args: &PosterArgs) -> Result<PosterSpec> {
+        let paper = args
+            .paper
+            .filter(|p| !p.is_empty())
+            .unwrap_or("a4")
+            .parse()?;
+        let orientation = match args.orientation {
+            Some(o) if !o.is_empty() => o.parse()?,
+            _ => Orientation::default(),
+        };
+        let dpi = match args.dpi {
+            None | Some(0) => DEFAULT_DPI,
+            Some(d) if d <= MAX_DPI => d,
+            Some(d) => return Err(Error::msg(format!("dpi must be 1-{}, got {}", MAX_DPI, d))),
+        };
+        let margin = args.margin.unwrap_or(DEFAULT_MARGIN_MM);
+        let overlap = args.overlap.unwrap_or(0.0);
+        for (name, value) in [("margin", margin), ("overlap", overlap)] {
+            if !value.is_finite() || value < 0.0 {
+                return Err(Error::msg(format!(
+                    "{} must be a length in mm of 0 or more, got {}",
+                    name, value
+                )));
+            }
+        }
+        let (marks, labels) = (args.marks.unwrap_or(false), args.labels.unwrap_or(false));
+        if (marks || labels) && margin <= 0.0 {
+            return Err(Error::msg(
+                "Crop marks and labels are drawn in the margin, which is 0",
+            ));
+        }
+        let quality = match args.quality {
+            None => DEFAULT_QUALITY,
+            Some(q) if (1..=100).contains(&q) => q,
+            Some(q) => return Err(Error::msg(format!("quality must be 1-100, got {}", q))),
+        };
+        let spec = PosterSpec {
+            paper,
+            orientation,
+            dpi,
+            margin,
+            overlap,
+            marks,
+            labels,
+            format: args
+                .format
+                .filter(|f| !f.is_empty())
+                .unwrap_or("png")
+                .parse()?,
+            quality,
+        };
+
+        let (w, h) = paper.size_mm();
+        let printable = w.min(h) - 2.0 * margin;
+        if printable <= overlap {
+            return Err(Error::msg(format!(
+                "Margins of {}mm and {}mm overlap leave no room on {:.0}x{:.0}mm paper",
+                margin, overlap, w, h
+            )));
+        }
+        let pixels = spec.px(w) as u64 * spec.px(h) as u64;
+        if pixels > MAX_PAGE_PIXELS {
+            return Err(Error::msg(format!(
+                "A {:.0}x{:.0}mm page at {} DPI is {} pixels, more than {}",
+                w, h, dpi, pixels, MAX_PAGE_PIXELS
+            )));
+        }
+        Ok(spec)
+    }
+
+    fn px(&self, mm: f32) -> u32 {
+        (mm / MM_PER_INCH * self.dpi as f32).round() as u32
+    }
+
+    /// Pages needed for an image of `size` px.
+    pub fn layout(&self, size: (u32, u32)) -> Result<Layout> {
+        let layout = match self.orientation {
+            Orientation::Auto => {
+                let portrait = self.layout_for(size, Orientation::Portrait)?;
+                let landscape = self.layout_for(size, Orientation::Landscape)?;
+                if landscape.cells.len() < portrait.cells.len() {
+                    landscape
+                } else {
+                    portrait
+                }
+            }
+            orientation => self.layout_for(size, orientation)?,
+        };
+        if layout.cells.len() > MAX_PAGES {
+            return Err(Error::msg(format!(
+                "Poster needs {} pages at {} DPI, more than {}",
+                layout.cells.len(),
+                self.dpi,
+                MAX_PAGES
+            )));
+        }
+        Ok(layout)
+    }
+
+    fn layout_for(&self, size: (u32, u32), orientation: Orientation) -> Result<Layout> {
+        let (w, h) = self.paper.size_mm();
+        let page = match orientation {
+            Orientation::Landscape => (self.px(h), self.px(w)),
+            _ => (self.px(w), self.px(h)),
+        };
+        let (margin, overlap) = (self.px(self.margin), self.px(self.overlap));
+        let printable = (
+            page.0.saturating_sub(2 * margin),
+            page.1.saturating_sub(2 * margin),
+        );
+        let (cols, rows, cells) =
+            image_slicer::grid(size, printable, (overlap, overlap)).map_err(|_| {
+                Error::msg(format!(
+                    "At {} DPI the overlap fills the printable area",
+                    self.dpi
+                ))
+            })?;
+        Ok(Layout {
+            orientation,
+            page,
+            margin,
+            overlap,
+            cols,
+            rows,
+            cells,
+        })
+    }
+
+    /// Encode a rendered page, recording the DPI in the file.
+    pub fn encode(&self, page: RgbaImage) -> Result<Vec<u8>> {
+        self.format
+            .encode_with_dpi(&DynamicImage::ImageRgba8(page), self.quality, self.dpi)
+    }
+
+    /// `page-1.png` …, numbered as on the labels.
+    pub fn file_name(&self, index: usize) -> String {
+        format!("page-{}.{}", index + 1, self.format.extension())
+    }
+}
+
+impl Layout {
+    /// Render page `index`: its part of the image on white paper inside the
+    /// margins, plus crop marks and a label if asked for.
+    pub fn render_page(
+        &self,
+        img: &DynamicImage,
+        spec: &PosterSpec,
+        index: usize,
+    ) -> Result<RgbaImage> {
+        let cell = self.cells[index];
+        let mut page =
+            ImageBuffer::from_pixel(self.page.0, self.page.1, Rgba([255, 255, 255, 255]));
+        let part = img.view(cell.x, cell.y, cell.width, cell.height).to_image();
+        image::imageops::overlay(&mut page, &part, self.margin as i64, self.margin as i64);
+
+        let (row, col) = (index as u32 / self.cols, index as u32 % self.cols);
+        if spec.marks {
+            self.draw_marks(&mut page, cell, row, col, spec.px(0.25).max(1));
+        }
+        if spec.labels {
+            let text = format!(
+                "{}{} · {}/{}",
+                row_name(row),
+                col + 1,
+                index + 1,
+                self.cells.len()
+            );
+            self.draw_label(&mut page, &text)?;
+        }
+        Ok(page)
+    }
+
+    // Crop marks at the corners of the printed part, ticks where the
+    // neighbouring page's overlap ends, and registration targets on the sides.
+    fn draw_marks(&self, page: &mut RgbaImage, cell: Rect, row: u32, col: u32, stroke: u32) {
+        let m = self.margin;
+        let (len, gap) = ((m * 2 / 3).max(1), m / 6);
+        let (x0, y0) = (m, m);
+        let (x1, y1) = (m + cell.width, m + cell.height);
+        let half = stroke / 2;
+
+        let vertical_tick = |page: &mut RgbaImage, x: u32| {
+            fill(
+                page,
+                x.saturating_sub(half),
+                y0.saturating_sub(gap + len),
+                stroke,
+                len,
+            );
+            fill(page, x.saturating_sub(half), y1 + gap, stroke, len);
+        };
+        vertical_tick(page, x0);
+        vertical_tick(page, x1);
+        if col > 0 && self.overlap > 0 {
+            vertical_tick(page, x0 + self.overlap);
+        }
+        let horizontal_tick = |page: &mut RgbaImage, y: u32| {
+            fill(
+                page,
+                x0.saturating_sub(gap + len),
+                y.saturating_sub(half),
+                len,
+                stroke,
+            );
+            fill(page, x1 + gap, y.saturating_sub(half), len, stroke);
+        };
+        horizontal_tick(page, y0);
+        horizontal_tick(page, y1);
+        if row > 0 && self.overlap > 0 {
+            horizontal_tick(page, y0 + self.overlap);
+        }
+
+        // The bottom margin holds the label.
+        let radius = m / 4;
+        let (cx, cy) = (self.page.0 / 2, self.page.1 / 2);
+        for (x, y) in [(cx, m / 2), (m / 2, cy), (self.page.0 - m / 2, cy)] {
+            draw_target(page, (x, y), radius, stroke);
+        }
+    }
+
+    // The label goes centred in the bottom margin, a third of its height.
+    fn draw_label(&self, page: &mut RgbaImage, text: &str) -> Result<()> {
+        let style = TextStyle {
+            align: TextAlign::Center,
+            ..TextStyle::default()
+        };
+        let box_size = (
+            self.page.0.saturating_sub(2 * self.margin).max(1),
+            (self.margin / 3).max(1),
+        );
+        let mut label = watermark::fit_text(text, box_size, &style)?.to_rgba8();
+        // Rendered text is white with coverage in alpha; print it in ink.
+        for px in label.pixels_mut() {
+            *px = Rgba([INK[0], INK[1], INK[2], px[3]]);
+        }
+        let x = (self.page.0 - label.width()) / 2;
+        let y = self.page.1 - self.margin + (self.margin - label.height()) / 2;
+        image::imageops::overlay(page, &label, x as i64, y as i64);
+        Ok(())
+    }
+}
+
+fn fill(page: &mut RgbaImage, x: u32, y: u32, width: u32, height: u32) {
+    for py in y..(y + height).min(page.height()) {
+        for px in x..(x + width).min(page.width()) {
+            page.put_pixel(px, py, INK);
+        }
+    }
+}
+
+// A circle with a cross through it.
+fn draw_target(page: &mut RgbaImage, centre: (u32, u32), radius: u32, stroke: u32) {
+    if radius < 2 {
+        return;
+    }
+    let (cx, cy) = (centre.0 as i64, centre.1 as i64);
+    let (outer, inner) = (radius as i64, radius.saturating_sub(stroke) as i64);
+    for dy in -outer..=outer {
+        for dx in -outer..=outer {
+            let d = dx * dx + dy * dy;
+            let on_ring = d <= outer * outer && d > inner * inner;
+            let on_cross = dx.abs() < stroke as i64 || dy.abs() < stroke as i64;
+            let (x, y) = (cx + dx, cy + dy);
+            if (on_ring || on_cross)
+                && x >= 0
+                && y >= 0
+                && (x as u32) < page.width()
+                && (y as u32) < page.height()
+            {
+                page.put_pixel(x as u32, y as u32, INK);
+            }
+        }
+    }
+}
+
+// Rows are lettered like spreadsheet columns: A … Z, AA, AB, …
+fn row_name(row: u32) -> String {
+    let mut n = row + 1;
+    let mut name = Vec::new();
+    while n > 0 {
+        n -= 1;
+        name.push(b'A' + (n % 26) as u8);
+        n /= 26;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args<'a>(paper: &'a str, dpi: u32, margin: f32, overlap: f32) -> PosterArgs<'a> {
+        PosterArgs {
+            paper: Some(paper),
+            dpi: Some(dpi),
+            margin: Some(margin),
+            overlap: Some(overlap),
+            ..PosterArgs::default()
+        }
+    }
+
+    #[test]
+    fn grid_follows_paper_margins_and_overlap() {
+        let spec = PosterSpec::from_args(&args("a4", 25, 0.0, 0.0)).unwrap();
+        assert_eq!(spec.layout((400, 300)).unwrap().page, (207, 292));
+
+        // 1000x2000 px pages, 100 px margins and overlap: 800x1800 printable, step 700.
+        let spec = PosterSpec::from_args(&args("100x200", 254, 10.0, 10.0)).unwrap();
+        let layout = spec.layout((2200, 1800)).unwrap();
+        assert_eq!(
+            (layout.page, layout.margin, layout.cols, layout.rows),
+            ((1000, 2000), 100, 3, 1)
+        );
+        assert_eq!(layout.cells[1].to_string(), "700,0,800,1800");
+        assert_eq!(layout.cells[2].to_string(), "1400,0,800,1800");
+
+        let auto = PosterSpec {
+            orientation: Orientation::Auto,
+            ..spec
+        };
+        let layout = auto.layout((2300, 800)).unwrap();
+        assert_eq!(
+            (layout.orientation, layout.cols, layout.rows),
+            (Orientation::Landscape, 2, 1)
+        );
+    }
+
+    #[test]
+    fn pages_carry_image_marks_and_labels() {
+        let spec = PosterSpec::from_args(&PosterArgs {
+            marks: Some(true),
+            labels: Some(true),
+            ..args("a5", 50, 12.0, 0.0)
+        })
+        .unwrap();
+        let img =
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(600, 100, Rgba([200, 0, 0, 255])));
+        let layout = spec.layout(img.dimensions()).unwrap();
+        assert_eq!((layout.cols, layout.rows), (3, 1));
+
+        let page = layout.render_page(&img, &spec, 2).unwrap();
+        assert_eq!(page.dimensions(), layout.page);
+        let m = layout.margin;
+        assert_eq!(page.get_pixel(m, m).0, [200, 0, 0, 255]);
+        // Past the last page's part of the image the paper stays white.
+        assert_eq!(
+            page.get_pixel(m + layout.cells[2].width + 2, m + 2).0,
+            [255, 255, 255, 255]
+        );
+        // A crop mark above the top-left corner, and some label ink below the image.
+        assert_eq!(page.get_pixel(m, m / 2).0, INK.0);
+        let bottom = page.height() - m;
+        assert!((bottom..page.height())
+            .any(|y| (0..page.width()).any(|x| page.get_pixel(x, y)[0] < 128)));
+    }
+
+    #[test]
+    fn rejects_bad_params() {
+        assert!(PosterSpec::from_args(&args("b4", 300, 10.0, 0.0)).is_err());
+        assert!(PosterSpec::from_args(&args("a4", 5000, 10.0, 0.0)).is_err());
+        assert!(PosterSpec::from_args(&args("a4", 300, 110.0, 0.0)).is_err());
+        assert!(PosterSpec::from_args(&args("a0", 1200, 10.0, 0.0)).is_err());
+        let marks_without_margin = PosterArgs {
+            marks: Some(true),
+            ..args("a4", 300, 0.0, 0.0)
+        };
+        assert!(PosterSpec::from_args(&marks_without_margin).is_err());
+        let spec = PosterSpec::from_args(&args("a4", 10, 0.0, 0.0)).unwrap();
+        assert!(spec.layout((10_000, 10_000)).is_err());
+        assert_eq!(row_name(0), "A");
+        assert_eq!(row_name(27), "AB");
+    }
+}
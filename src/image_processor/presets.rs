@@ -0,0 +1,290 @@
+use anyhow::{Error, Result};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Presets defined by the operator, keyed by name (e.g. `instagram-carousel`).
+static PRESETS: OnceLock<HashMap<String, Preset>> = OnceLock::new();
+
+/// A named set of request params, e.g. `tile_width = 1080` and `fit = "cover"`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub description: String,
+    params: BTreeMap<String, String>,
+    overridable: Overridable,
+}
+
+/// Which of its params a preset lets a request replace.
+#[derive(Clone, Debug, PartialEq)]
+enum Overridable {
+    All,
+    Only(BTreeSet<String>),
+}
+
+/// Config file syntax, picked from the file extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Toml,
+    Yaml,
+}
+
+impl Preset {
+    /// The preset's value for `param`, in query string form.
+    pub fn param(&self, param: &str) -> Option<&str> {
+        self.params.get(param).map(String::as_str)
+    }
+
+    /// Fails if the request may not replace this preset's `param`.
+    pub fn check_override(&self, param: &str) -> Result<()> {
+        let allowed = match &self.overridable {
+            Overridable::All => true,
+            Overridable::Only(params) => params.contains(param),
+        };
+        if allowed || !self.params.contains_key(param) {
+            Ok(())
+        } else {
+            Err(Error::msg(format!(
+                "Preset {} does not allow overriding {}",
+                self.name, param
+            )))
+        }
+    }
+
+    /// The preset's params followed by the request's. Params the preset sets
+    /// may only be given if it allows overriding them; other params are free.
+    pub fn merge(&self, request: Vec<(String, String)>) -> Result<Vec<(String, String)>> {
+        let mut merged = self.params.clone();
+        for (param, value) in request {
+            self.check_override(&param)?;
+            merged.insert(param, value);
+        }
+        Ok(merged.into_iter().collect())
+    }
+}
+
+/// Load presets from the file in `PRESETS_FILE` (`.toml`, `.yaml` or `.yml`).
+/// Does nothing when the variable is unset.
+pub fn init_from_env() -> Result<()> {
+    let presets = match std::env::var("PRESETS_FILE") {
+        Ok(path) => load_file(Path::new(path.trim()))?,
+        Err(_) => {
+            println!("PRESETS_FILE not set, no presets defined");
+            HashMap::new()
+        }
+    };
+    println!("Defined {} preset(s)", presets.len());
+    init(presets)
+}
+
+/// Install `presets` as the process-wide registry.
+pub fn init(presets: HashMap<String, Preset>) -> Result<()> {
+    PRESETS
+        .set(presets)
+        .map_err(|_| Error::msg("Preset registry already initialized"))
+}
+
+fn load_file(path: &Path) -> Result<HashMap<String, Preset>> {
+    let syntax = match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("toml") => Syntax::Toml,
+        Some("yaml" | "yml") => Syntax::Yaml,
+        _ => {
+            return Err(Error::msg(format!(
+                "PRESETS_FILE {} must end in .toml, .yaml or .yml",
+                path.display()
+            )))
+        }
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Error::msg(format!(
+            "Failed to read PRESETS_FILE {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    parse(&text, syntax)
+}
+
+/// Parse a presets file: one table per preset, holding request params plus
+/// an optional `description` and `overridable` (a list of params, or `"*"`).
+pub fn parse(text: &str, syntax: Syntax) -> Result<HashMap<String, Preset>> {
+    let tables: BTreeMap<String, BTreeMap<String, Value>> = match syntax {
+        Syntax::Toml => {
+            toml::from_str(text).map_err(|e| Error::msg(format!("Invalid presets TOML: {}", e)))?
+        }
+        Syntax::Yaml => serde_yaml_ng::from_str(text)
+            .map_err(|e| Error::msg(format!("Invalid presets YAML: {}", e)))?,
+    };
+
+    let mut presets = HashMap::new();
+    for (name, mut table) in tables {
+        let description = match table.remove("description") {
+            None => String::new(),
+            Some(Value::String(s)) => s,
+            Some(_) => {
+                return Err(Error::msg(format!(
+                    "Preset {}: description must be a string",
+                    name
+                )))
+            }
+        };
+        let overridable = match table.remove("overridable") {
+            None => Overridable::Only(BTreeSet::new()),
+            Some(Value::String(s)) if s == "*" => Overridable::All,
+            Some(Value::Array(params)) => Overridable::Only(
+                params
+                    .into_iter()
+                    .map(|p| match p {
+                        Value::String(p) => Ok(p),
+                        _ => Err(Error::msg(format!(
+                            "Preset {}: overridable must list param names",
+                            name
+                        ))),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Some(_) => {
+                return Err(Error::msg(format!(
+                    "Preset {}: overridable must be a list of params or \"*\"",
+                    name
+                )))
+            }
+        };
+        if table.contains_key("preset") {
+            return Err(Error::msg(format!(
+                "Preset {} can't refer to another preset",
+                name
+            )));
+        }
+        let params = table
+            .into_iter()
+            .map(|(param, value)| (param, query_value(value)))
+            .collect();
+        presets.insert(
+            name.clone(),
+            Preset {
+                name,
+                description,
+                params,
+                overridable,
+            },
+        );
+    }
+    Ok(presets)
+}
+
+// Scalars as written, lists of scalars comma-separated (e.g. variant
+// `widths`), anything nested as JSON (e.g. `/process` `steps`).
+fn query_value(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Array(items) if items.iter().all(|v| !v.is_array() && !v.is_object()) => items
+            .into_iter()
+            .map(query_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Look up a preset by name.
+pub fn get(name: &str) -> Result<&'static Preset> {
+    PRESETS
+        .get()
+        .and_then(|presets| presets.get(name))
+        .ok_or_else(|| Error::msg(format!("Unknown preset: {}", name)))
+}
+
+/// Expand `preset=name` in a query string into that preset's params merged
+/// with the rest of the query, and return the preset used.
+pub fn expand_query(query: &str) -> Result<(String, Option<&'static Preset>)> {
+    let mut pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)
+        .map_err(|e| Error::msg(format!("Invalid query string: {}", e)))?;
+    let Some(index) = pairs.iter().position(|(param, _)| param == "preset") else {
+        return Ok((query.to_string(), None));
+    };
+    let (_, name) = pairs.remove(index);
+    let preset = get(&name)?;
+    let merged = serde_urlencoded::to_string(preset.merge(pairs)?)?;
+    Ok((merged, Some(preset)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+[instagram-carousel]
+description = "Four 1080x1350 tiles"
+tile_width = 1080
+tile_height = 1350
+fit = "cover"
+watermark = "@studio"
+overridable = ["watermark", "opacity"]
+
+[web]
+widths = [320, 640]
+allow_upscale = false
+steps = [{ op = "resize", width = 800 }]
+overridable = "*"
+"#;
+
+    #[test]
+    fn toml_and_yaml_presets_parse_to_query_values() {
+        let presets = parse(TOML, Syntax::Toml).unwrap();
+        let carousel = &presets["instagram-carousel"];
+        assert_eq!(carousel.description, "Four 1080x1350 tiles");
+        assert_eq!(carousel.param("tile_width"), Some("1080"));
+        assert_eq!(carousel.param("fit"), Some("cover"));
+        assert_eq!(carousel.param("overridable"), None);
+
+        let web = &presets["web"];
+        assert_eq!(web.param("widths"), Some("320,640"));
+        assert_eq!(web.param("allow_upscale"), Some("false"));
+        assert_eq!(web.param("steps"), Some(r#"[{"op":"resize","width":800}]"#));
+
+        let yaml =
+            "videowall:\n  tile_width: 1920\n  tile_height: 1080\n  overridable: [watermark]\n";
+        let presets = parse(yaml, Syntax::Yaml).unwrap();
+        assert_eq!(presets["videowall"].param("tile_height"), Some("1080"));
+    }
+
+    #[test]
+    fn requests_override_only_what_the_preset_allows() {
+        let presets = parse(TOML, Syntax::Toml).unwrap();
+        let carousel = &presets["instagram-carousel"];
+        let request = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let merged = carousel
+            .merge(request(&[("watermark", "mine"), ("blur", "2")]))
+            .unwrap();
+        assert!(merged.contains(&("watermark".into(), "mine".into())));
+        assert!(merged.contains(&("blur".into(), "2".into())));
+        assert!(merged.contains(&("fit".into(), "cover".into())));
+
+        let err = carousel.merge(request(&[("fit", "contain")])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Preset instagram-carousel does not allow overriding fit"
+        );
+        assert!(presets["web"].merge(request(&[("widths", "100")])).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_presets() {
+        assert!(parse("[a]\noverridable = 3\n", Syntax::Toml).is_err());
+        assert!(parse("[a]\npreset = \"b\"\n", Syntax::Toml).is_err());
+        assert!(parse("a: [1, 2]\n", Syntax::Yaml).is_err());
+    }
+}
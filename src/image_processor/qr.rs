@@ -0,0 +1,354 @@
+use crate::image_processor::blend::Blend;
+use crate::image_processor::text::{self, TileContext};
+use crate::image_processor::watermark::{composite_at, Placement, Position, Scope};
+use anyhow::{Error, Result};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use qrcode::{Color, EcLevel, QrCode};
+use std::str::FromStr;
+
+const MAX_QUIET_ZONE: u32 = 16;
+
+/// Which code to draw.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Symbology {
+    #[default]
+    Qr,
+    /// Code 128, code set B: printable ASCII only.
+    Code128,
+}
+
+impl Symbology {
+    /// Quiet zone recommended by each spec, in modules.
+    fn default_quiet_zone(self) -> u32 {
+        match self {
+            Symbology::Qr => 4,
+            Symbology::Code128 => 10,
+        }
+    }
+}
+
+impl FromStr for Symbology {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "qr" => Ok(Symbology::Qr),
+            "code128" => Ok(Symbology::Code128),
+            other => Err(Error::msg(format!(
+                "Unknown symbology: {} (use qr or code128)",
+                other
+            ))),
+        }
+    }
+}
+
+/// A QR code or barcode stamped onto an image. `data` may contain the same
+/// per-tile placeholders as watermark text, e.g. `https://example.com/wall/{index}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QrSpec {
+    pub data: String,
+    pub symbology: Symbology,
+    /// QR only; Code 128 has no error correction levels.
+    pub ec_level: EcLevel,
+    pub quiet_zone: u32,
+    pub placement: Placement,
+}
+
+impl QrSpec {
+    /// `size` is the side of the code as a percentage (1-100) of the target's
+    /// shorter side, default 20; a barcode is that wide and a quarter as tall.
+    /// `position` defaults to the bottom-right corner, `symbology` to QR.
+    pub fn parse(
+        data: &str,
+        size: Option<u32>,
+        position: Option<&str>,
+        ec_level: Option<&str>,
+        quiet_zone: Option<u32>,
+        symbology: Option<&str>,
+    ) -> Result<QrSpec> {
+        if data.is_empty() {
+            return Err(Error::msg("QR code data must not be empty"));
+        }
+        let symbology = match symbology {
+            Some(s) if !s.is_empty() => s.parse()?,
+            _ => Symbology::Qr,
+        };
+        let scale = match size {
+            None | Some(0) => 20,
+            Some(s) if s <= 100 => s,
+            Some(s) => return Err(Error::msg(format!("QR code size must be 1-100, got {}", s))),
+        };
+        let position = match position {
+            Some(p) if !p.is_empty() => p.parse()?,
+            _ => Position::BottomRight,
+        };
+        let ec_level = match ec_level.map(str::to_uppercase).as_deref() {
+            None | Some("") | Some("M") => EcLevel::M,
+            Some("L") => EcLevel::L,
+            Some("Q") => EcLevel::Q,
+            Some("H") => EcLevel::H,
+            Some(other) => {
+                return Err(Error::msg(format!(
+                    "Unknown QR error correction level: {} (use L, M, Q or H)",
+                    other
+                )))
+            }
+        };
+        let quiet_zone = quiet_zone.unwrap_or(symbology.default_quiet_zone());
+        if quiet_zone > MAX_QUIET_ZONE {
+            return Err(Error::msg(format!(
+                "QR quiet zone must be 0-{} modules, got {}",
+                MAX_QUIET_ZONE, quiet_zone
+            )));
+        }
+        Ok(QrSpec {
+            data: data.to_string(),
+            symbology,
+            ec_level,
+            quiet_zone,
+            placement: Placement {
+                position,
+                scale,
+                scope: Scope::Tile,
+            },
+        })
+    }
+
+    pub fn is_templated(&self) -> bool {
+        text::is_template(&self.data)
+    }
+
+    /// Render the code for a target of `size`. Modules are whole pixels, so the
+    /// code may come out slightly smaller than the requested box but stays sharp.
+    pub fn render(&self, size: (u32, u32)) -> Result<DynamicImage> {
+        self.render_data(&self.data, size)
+    }
+
+    /// Render the code for one tile, expanding placeholders in `data` first.
+    pub fn render_for_tile(&self, size: (u32, u32), tile: &TileContext) -> Result<DynamicImage> {
+        if !self.is_templated() {
+            return self.render(size);
+        }
+        self.render_data(&text::expand_template(&self.data, tile), size)
+    }
+
+    fn render_data(&self, data: &str, size: (u32, u32)) -> Result<DynamicImage> {
+        match self.symbology {
+            Symbology::Qr => render_qr(data, self.ec_level, self.quiet_zone, self.side(size)),
+            Symbology::Code128 => render_code128(data, self.quiet_zone, self.side(size)),
+        }
+    }
+
+    /// Composite an already rendered code onto `img`, fully opaque.
+    pub fn apply(
+        &self,
+        mut img: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        code: &DynamicImage,
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        self.apply_in_place(&mut img, code);
+        img
+    }
+
+    /// Same as `apply`, without taking ownership of the image.
+    pub fn apply_in_place(&self, img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, code: &DynamicImage) {
+        composite_at(img, code, 0.0, self.placement, Blend::default());
+    }
+
+    fn side(&self, size: (u32, u32)) -> u32 {
+        let (w, h) = self.placement.target_size(size);
+        w.min(h)
+    }
+}
+
+fn render_qr(data: &str, ec_level: EcLevel, quiet_zone: u32, side: u32) -> Result<DynamicImage> {
+    let code = QrCode::with_error_correction_level(data.as_bytes(), ec_level)
+        .map_err(|e| Error::msg(format!("Cannot encode QR code: {}", e)))?;
+    let width = code.width() as u32;
+    let modules = width + 2 * quiet_zone;
+    let module_px = side / modules;
+    if module_px == 0 {
+        return Err(Error::msg(format!(
+            "QR code needs at least {}x{} px, only {}x{} available; increase the size or shorten the data",
+            modules, modules, side, side
+        )));
+    }
+
+    let colors = code.to_colors();
+    let img = ImageBuffer::from_fn(modules * module_px, modules * module_px, |x, y| {
+        let (mx, my) = (x / module_px, y / module_px);
+        let dark = mx >= quiet_zone
+            && my >= quiet_zone
+            && mx < quiet_zone + width
+            && my < quiet_zone + width
+            && colors[((my - quiet_zone) * width + (mx - quiet_zone)) as usize] == Color::Dark;
+        if dark {
+            Rgba([0u8, 0, 0, 255])
+        } else {
+            Rgba([255u8, 255, 255, 255])
+        }
+    });
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+// Bar and space widths of Code 128 symbols 0-105, in modules.
+const CODE128_PATTERNS: [&str; 106] = [
+    "212222", "222122", "222221", "121223", "121322", "131222", "122213", "122312", "132212",
+    "221213", "221312", "231212", "112232", "122132", "122231", "113222", "123122", "123221",
+    "223211", "221132", "221231", "213212", "223112", "312131", "311222", "321122", "321221",
+    "312212", "322112", "322211", "212123", "212321", "232121", "111323", "131123", "131321",
+    "112313", "132113", "132311", "211313", "231113", "231311", "112133", "112331", "132131",
+    "113123", "113321", "133121", "313121", "211331", "231131", "213113", "213311", "213131",
+    "311123", "311321", "331121", "312113", "312311", "332111", "314111", "221411", "431111",
+    "111224", "111422", "121124", "121421", "141122", "141221", "112214", "112412", "122114",
+    "122411", "142112", "142211", "241211", "221114", "413111", "241112", "134111", "111242",
+    "121142", "121241", "114212", "124112", "124211", "411212", "421112", "421211", "212141",
+    "214121", "412121", "111143", "111341", "131141", "114113", "114311", "411113", "411311",
+    "113141", "114131", "311141", "411131", "211412", "211214", "211232",
+];
+const CODE128_START_B: usize = 104;
+const CODE128_STOP: &str = "2331112";
+
+// Module widths of `data` as Code 128 set B: start, data, checksum, stop.
+fn code128_modules(data: &str) -> Result<Vec<u32>> {
+    let mut symbols = vec![CODE128_START_B];
+    for c in data.chars() {
+        if !(' '..='~').contains(&c) {
+            return Err(Error::msg(format!(
+                "Code 128 data must be printable ASCII, got {:?}",
+                c
+            )));
+        }
+        symbols.push(c as usize - ' ' as usize);
+    }
+    let checksum = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| i.max(1) * v)
+        .sum::<usize>()
+        % 103;
+    symbols.push(checksum);
+
+    let widths = symbols
+        .iter()
+        .flat_map(|&v| CODE128_PATTERNS[v].bytes())
+        .chain(CODE128_STOP.bytes())
+        .map(|b| (b - b'0') as u32)
+        .collect();
+    Ok(widths)
+}
+
+fn render_code128(data: &str, quiet_zone: u32, width: u32) -> Result<DynamicImage> {
+    let widths = code128_modules(data)?;
+    let modules = widths.iter().sum::<u32>() + 2 * quiet_zone;
+    let module_px = width / modules;
+    if module_px == 0 {
+        return Err(Error::msg(format!(
+            "Barcode needs at least {} px width, only {} available; increase the size or shorten the data",
+            modules, width
+        )));
+    }
+
+    // Widths alternate bar, space, bar, ... starting with a bar.
+    let mut dark = vec![false; quiet_zone as usize];
+    for (i, &w) in widths.iter().enumerate() {
+        dark.extend(std::iter::repeat_n(i % 2 == 0, w as usize));
+    }
+    dark.resize(modules as usize, false);
+
+    let height = (modules * module_px / 4).max(1);
+    let img = ImageBuffer::from_fn(modules * module_px, height, |x, _| {
+        if dark[(x / module_px) as usize] {
+            Rgba([0u8, 0, 0, 255])
+        } else {
+            Rgba([255u8, 255, 255, 255])
+        }
+    });
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn render_has_quiet_zone_and_finder_pattern() {
+        let spec = QrSpec::parse("tile-1", Some(100), None, None, None, None).unwrap();
+        let code = spec.render((200, 200)).unwrap();
+        // "tile-1" fits a version 1 code: 21 modules + 2 * 4 quiet zone = 29.
+        let module_px = 200 / 29;
+        assert_eq!(code.width(), 29 * module_px);
+        assert_eq!(code.get_pixel(0, 0), WHITE);
+        // Top-left finder pattern starts right after the quiet zone.
+        assert_eq!(code.get_pixel(4 * module_px, 4 * module_px), BLACK);
+    }
+
+    #[test]
+    fn default_placement_is_bottom_right() {
+        let spec = QrSpec::parse("tile-1", Some(50), None, Some("h"), Some(0), None).unwrap();
+        let base = ImageBuffer::from_pixel(100, 100, Rgba([0u8, 0, 255, 255]));
+        let code = spec.render((100, 100)).unwrap();
+        let out = spec.apply(base, &code);
+        // No quiet zone, so the finder pattern sits at the code's top-left corner.
+        let origin = 100 - code.width();
+        assert_eq!(*out.get_pixel(origin, origin), BLACK);
+        assert_eq!(*out.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn templated_data_renders_per_tile() {
+        let spec =
+            QrSpec::parse("https://example.com/{index}", None, None, None, None, None).unwrap();
+        assert!(spec.is_templated());
+        let first = TileContext::for_grid(0, 2, 4, "", "");
+        let last = TileContext::for_grid(3, 2, 4, "", "");
+        let a = spec.render_for_tile((500, 500), &first).unwrap();
+        let b = spec.render_for_tile((500, 500), &last).unwrap();
+        assert_ne!(a.to_rgba8().into_raw(), b.to_rgba8().into_raw());
+    }
+
+    #[test]
+    fn too_small_target_is_an_error() {
+        let spec = QrSpec::parse("tile-1", Some(10), None, None, None, None).unwrap();
+        assert!(spec.render((100, 100)).is_err());
+    }
+
+    #[test]
+    fn code128_encodes_start_data_checksum_and_stop() {
+        // Start B, "A" (33), checksum (104 + 33) % 103 = 34, stop.
+        let expected: Vec<u32> = "211214111323131123"
+            .chars()
+            .chain("2331112".chars())
+            .map(|c| c.to_digit(10).unwrap())
+            .collect();
+        assert_eq!(code128_modules("A").unwrap(), expected);
+        assert!(code128_modules("caf\u{e9}").is_err());
+    }
+
+    #[test]
+    fn code128_renders_a_wide_barcode_with_quiet_zone() {
+        let spec = QrSpec::parse("tile-1", Some(100), None, None, None, Some("code128")).unwrap();
+        assert_eq!(spec.quiet_zone, 10);
+        let code = spec.render((400, 300)).unwrap();
+        // 8 symbols of 11 modules, a 13-module stop and 10 quiet modules per side.
+        let modules = 8 * 11 + 13 + 20;
+        let module_px = 300 / modules;
+        assert_eq!(code.width(), modules * module_px);
+        assert!(code.height() < code.width());
+        assert_eq!(code.get_pixel(10 * module_px - 1, 0), WHITE);
+        assert_eq!(code.get_pixel(10 * module_px, 0), BLACK);
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        assert!(QrSpec::parse("", None, None, None, None, None).is_err());
+        assert!(QrSpec::parse("x", Some(101), None, None, None, None).is_err());
+        assert!(QrSpec::parse("x", None, Some("middle"), None, None, None).is_err());
+        assert!(QrSpec::parse("x", None, None, Some("X"), None, None).is_err());
+        assert!(QrSpec::parse("x", None, None, None, Some(17), None).is_err());
+        assert!(QrSpec::parse("x", None, None, None, None, Some("ean13")).is_err());
+    }
+}
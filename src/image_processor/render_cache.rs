@@ -0,0 +1,208 @@
+use crate::image_processor::fonts::FontId;
+use crate::image_processor::text::{TextAlign, TextStyle};
+use crate::image_processor::watermark::Watermark;
+use anyhow::Result;
+use lru::LruCache;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Default budget for cached renders: 256 MB of RGBA pixels.
+const DEFAULT_CAPACITY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Everything that changes the pixels of a rendered text watermark.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextKey {
+    pub text: String,
+    pub font: FontId,
+    pub px: u32,
+    pub align: TextAlign,
+    // f32 isn't Hash; the bit pattern is exact for identical inputs.
+    pub line_spacing: u32,
+    pub target: (u32, u32),
+}
+
+impl TextKey {
+    pub fn new(text: &str, px: u32, style: &TextStyle, target: (u32, u32)) -> TextKey {
+        TextKey {
+            text: text.to_string(),
+            font: style.font,
+            px,
+            align: style.align,
+            line_spacing: style.line_spacing.to_bits(),
+            target,
+        }
+    }
+}
+
+/// Hit/miss counters and occupancy, as served by `GET /cache/stats`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity_bytes: usize,
+}
+
+// Renders by recency, evicted oldest first once their pixels exceed
+// `capacity` bytes. Image-scope watermarks are as large as the source, so
+// the budget is in bytes rather than entries.
+struct Store {
+    entries: LruCache<TextKey, Arc<Watermark>>,
+    bytes: usize,
+    capacity: usize,
+}
+
+impl Store {
+    fn new(capacity: usize) -> Store {
+        Store {
+            entries: LruCache::unbounded(),
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    // A render larger than the whole budget is not stored at all.
+    fn put(&mut self, key: TextKey, render: Arc<Watermark>) {
+        let size = render.as_bytes().len();
+        if size > self.capacity {
+            return;
+        }
+        if let Some(old) = self.entries.put(key, render) {
+            self.bytes -= old.as_bytes().len();
+        }
+        self.bytes += size;
+        while self.bytes > self.capacity {
+            match self.entries.pop_lru() {
+                Some((_, evicted)) => self.bytes -= evicted.as_bytes().len(),
+                None => break,
+            }
+        }
+    }
+}
+
+struct Cache {
+    // `None` when caching is disabled.
+    store: Option<Mutex<Store>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+// Sized by `WATERMARK_CACHE_BYTES` on first use; 0 disables caching.
+fn cache() -> &'static Cache {
+    CACHE.get_or_init(|| {
+        let capacity = std::env::var("WATERMARK_CACHE_BYTES")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_CAPACITY_BYTES);
+        println!("Watermark cache capacity: {} bytes", capacity);
+        Cache {
+            store: (capacity > 0).then(|| Mutex::new(Store::new(capacity))),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    })
+}
+
+/// Return the cached bitmap for `key`, or render and remember it. Failed
+/// renders are not cached.
+pub fn get_or_render(
+    key: TextKey,
+    render: impl FnOnce() -> Result<Watermark>,
+) -> Result<Arc<Watermark>> {
+    let cache = cache();
+    let Some(store) = &cache.store else {
+        return render().map(Arc::new);
+    };
+    if let Some(hit) = store
+        .lock()
+        .ok()
+        .and_then(|mut s| s.entries.get(&key).cloned())
+    {
+        cache.hits.fetch_add(1, Ordering::Relaxed);
+        return Ok(hit);
+    }
+    cache.misses.fetch_add(1, Ordering::Relaxed);
+
+    // Render outside the lock so one slow rasterisation doesn't block other requests.
+    let rendered = Arc::new(render()?);
+    if let Ok(mut store) = store.lock() {
+        store.put(key, rendered.clone());
+    }
+    Ok(rendered)
+}
+
+pub fn stats() -> CacheStats {
+    let cache = cache();
+    let hits = cache.hits.load(Ordering::Relaxed);
+    let misses = cache.misses.load(Ordering::Relaxed);
+    let (entries, bytes, capacity_bytes) = cache
+        .store
+        .as_ref()
+        .and_then(|s| {
+            s.lock()
+                .ok()
+                .map(|s| (s.entries.len(), s.bytes, s.capacity))
+        })
+        .unwrap_or((0, 0, 0));
+    CacheStats {
+        hits,
+        misses,
+        hit_rate: if hits + misses == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        },
+        entries,
+        bytes,
+        capacity_bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::DynamicImage;
+
+    #[test]
+    fn second_lookup_is_a_hit_and_skips_rendering() {
+        let key = TextKey::new("render-cache-test", 40, &TextStyle::default(), (3, 3));
+        let before = stats();
+
+        get_or_render(key.clone(), || Ok(DynamicImage::new_rgba8(3, 3))).unwrap();
+        get_or_render(key, || panic!("cached entry should be reused")).unwrap();
+
+        let after = stats();
+        assert!(after.hits > before.hits);
+        assert!(after.misses > before.misses);
+        assert!(after.entries >= 1);
+    }
+
+    #[test]
+    fn store_evicts_by_bytes_and_skips_oversized_renders() {
+        let key = |text: &str| TextKey::new(text, 40, &TextStyle::default(), (10, 10));
+        // 10x10 RGBA is 400 bytes; the budget holds two of them.
+        let render = || Arc::new(DynamicImage::new_rgba8(10, 10));
+        let mut store = Store::new(800);
+        store.put(key("a"), render());
+        store.put(key("b"), render());
+        store.put(key("c"), render());
+        assert_eq!((store.entries.len(), store.bytes), (2, 800));
+        assert!(!store.entries.contains(&key("a")));
+
+        store.put(key("huge"), Arc::new(DynamicImage::new_rgba8(20, 20)));
+        assert!(!store.entries.contains(&key("huge")));
+        assert_eq!((store.entries.len(), store.bytes), (2, 800));
+    }
+
+    #[test]
+    fn failed_render_is_not_cached() {
+        let key = TextKey::new("render-cache-failure", 40, &TextStyle::default(), (3, 3));
+        assert!(get_or_render(key.clone(), || Err(anyhow::Error::msg("boom"))).is_err());
+        assert!(get_or_render(key, || Ok(DynamicImage::new_rgba8(3, 3))).is_ok());
+    }
+}
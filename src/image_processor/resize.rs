@@ -1,86 +1,516 @@
-use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use crate::image_processor::color::parse_color;
+use crate::image_processor::smartcrop::{self, Saliency};
+use crate::image_processor::transform::MAX_SIDE;
 use anyhow::{Error, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Serialize;
+use std::str::FromStr;
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum AspectRatio {
+    #[default]
     Preserve,
     Ignore,
 }
 
-impl AspectRatio {
-    pub fn from_str(s: &str) -> Option<AspectRatio> {
+impl FromStr for AspectRatio {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "preserve" => Ok(AspectRatio::Preserve),
+            "ignore" => Ok(AspectRatio::Ignore),
+            _ => Err(Error::msg(format!(
+                "Unknown aspect_ratio: {} (use preserve or ignore)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Resampling filter, from fastest and blockiest to slowest and sharpest.
+/// Triangle is the default; slice tiles default to `Nearest` (see `TileScale`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    Nearest,
+    #[default]
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "nearest" => Ok(Filter::Nearest),
+            "triangle" | "bilinear" => Ok(Filter::Triangle),
+            "catmull-rom" | "catmullrom" | "bicubic" => Ok(Filter::CatmullRom),
+            "gaussian" => Ok(Filter::Gaussian),
+            "lanczos3" | "lanczos" => Ok(Filter::Lanczos3),
+            _ => Err(Error::msg(format!(
+                "Unknown filter: {} (use nearest, triangle, catmull-rom, gaussian or lanczos3)",
+                s
+            ))),
+        }
+    }
+}
+
+impl From<Filter> for FilterType {
+    fn from(filter: Filter) -> FilterType {
+        match filter {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// How the image is fitted into a `width` x `height` box, after CSS `object-fit`.
+///
+/// * `inside`: keep the aspect ratio, fit within the box (may be smaller on one side)
+/// * `outside`: keep the aspect ratio, cover the box (may be larger on one side)
+/// * `cover`: like `outside`, then crop to the box at the gravity
+/// * `contain`: like `inside`, then pad to the box with the background colour
+/// * `fill`: stretch to the box, ignoring the aspect ratio
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Fit {
+    #[default]
+    Inside,
+    Outside,
+    Cover,
+    Contain,
+    Fill,
+}
+
+impl Fit {
+    /// Whether the output is exactly the requested box, which needs both sides.
+    fn is_exact(self) -> bool {
+        matches!(self, Fit::Cover | Fit::Contain | Fit::Fill)
+    }
+}
+
+impl FromStr for Fit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
         match s.to_lowercase().as_str() {
-            "preserve" => Some(AspectRatio::Preserve),
-            "ignore" => Some(AspectRatio::Ignore),
+            "inside" => Ok(Fit::Inside),
+            "outside" => Ok(Fit::Outside),
+            "cover" => Ok(Fit::Cover),
+            "contain" => Ok(Fit::Contain),
+            "fill" => Ok(Fit::Fill),
+            _ => Err(Error::msg(format!(
+                "Unknown fit: {} (use inside, outside, cover, contain or fill)",
+                s
+            ))),
+        }
+    }
+}
+
+impl From<AspectRatio> for Fit {
+    fn from(aspect_ratio: AspectRatio) -> Fit {
+        match aspect_ratio {
+            AspectRatio::Preserve => Fit::Inside,
+            AspectRatio::Ignore => Fit::Fill,
+        }
+    }
+}
+
+/// Which part of the image is kept by a `cover` crop, or where the image sits
+/// inside a `contain` letterbox. The content-aware gravities pick the crop
+/// window from the image itself and centre letterboxed images.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Gravity {
+    #[default]
+    Center,
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+    Entropy,
+    Attention,
+    Smart,
+}
+
+impl Gravity {
+    pub fn saliency(self) -> Option<Saliency> {
+        match self {
+            Gravity::Entropy => Some(Saliency::Entropy),
+            Gravity::Attention => Some(Saliency::Attention),
+            Gravity::Smart => Some(Saliency::Smart),
             _ => None,
         }
     }
+
+    /// Top-left offset of a box inside a larger one, given the spare room on each axis.
+    pub fn offset(self, spare_w: u32, spare_h: u32) -> (u32, u32) {
+        let (fx, fy) = match self {
+            Gravity::Center | Gravity::Entropy | Gravity::Attention | Gravity::Smart => (1, 1),
+            Gravity::North => (1, 0),
+            Gravity::NorthEast => (2, 0),
+            Gravity::East => (2, 1),
+            Gravity::SouthEast => (2, 2),
+            Gravity::South => (1, 2),
+            Gravity::SouthWest => (0, 2),
+            Gravity::West => (0, 1),
+            Gravity::NorthWest => (0, 0),
+        };
+        (spare_w * fx / 2, spare_h * fy / 2)
+    }
 }
 
+impl FromStr for Gravity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().replace('_', "-").as_str() {
+            "center" | "centre" => Ok(Gravity::Center),
+            "north" | "top" => Ok(Gravity::North),
+            "north-east" | "northeast" | "top-right" => Ok(Gravity::NorthEast),
+            "east" | "right" => Ok(Gravity::East),
+            "south-east" | "southeast" | "bottom-right" => Ok(Gravity::SouthEast),
+            "south" | "bottom" => Ok(Gravity::South),
+            "south-west" | "southwest" | "bottom-left" => Ok(Gravity::SouthWest),
+            "west" | "left" => Ok(Gravity::West),
+            "north-west" | "northwest" | "top-left" => Ok(Gravity::NorthWest),
+            "entropy" => Ok(Gravity::Entropy),
+            "attention" => Ok(Gravity::Attention),
+            "smart" => Ok(Gravity::Smart),
+            _ => Err(Error::msg(format!(
+                "Unknown gravity: {} (use center, north, north-east, east, south-east, south, south-west, west, north-west, entropy, attention or smart)",
+                s
+            ))),
+        }
+    }
+}
+
+/// How images are resampled when they get larger.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UpscaleQuality {
+    /// The requested filter, same as for downscaling.
+    #[default]
+    Standard,
+    /// Lanczos3 followed by an unsharp mask to restore some edge contrast.
+    High,
+}
+
+impl FromStr for UpscaleQuality {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(UpscaleQuality::Standard),
+            "high" => Ok(UpscaleQuality::High),
+            _ => Err(Error::msg(format!(
+                "Unknown upscale_quality: {} (use standard or high)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Letterbox colour used by `contain` when none is given.
+pub const DEFAULT_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct ResizeParams {
     pub width: Option<u32>,
     pub height: Option<u32>,
-    pub aspect_ratio: AspectRatio,
+    pub fit: Fit,
+    pub gravity: Gravity,
+    pub background: Rgba<u8>,
+    pub filter: Filter,
+    /// When false, the image is never enlarged; fits that would enlarge scale
+    /// by 1 instead, and `contain` shrinks its box by the same factor.
+    pub allow_upscale: bool,
+    pub upscale_quality: UpscaleQuality,
+}
+
+/// Raw resize values as they arrive in a query string or proto message.
+/// Empty strings and zero sizes count as unset.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResizeArgs<'a> {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub aspect_ratio: Option<&'a str>,
+    pub fit: Option<&'a str>,
+    pub gravity: Option<&'a str>,
+    pub background: Option<&'a str>,
+    pub filter: Option<&'a str>,
+    pub allow_upscale: Option<bool>,
+    pub upscale_quality: Option<&'a str>,
 }
 
 impl ResizeParams {
     pub fn new(width: Option<u32>, height: Option<u32>, aspect_ratio: AspectRatio) -> Self {
-        ResizeParams { width, height, aspect_ratio }
+        ResizeParams {
+            width,
+            height,
+            fit: aspect_ratio.into(),
+            gravity: Gravity::default(),
+            background: DEFAULT_BACKGROUND,
+            filter: Filter::default(),
+            allow_upscale: true,
+            upscale_quality: UpscaleQuality::default(),
+        }
     }
-}
 
-pub fn resize_image(img: &DynamicImage, params: &ResizeParams) -> Result<DynamicImage> {
-    let (orig_w, orig_h) = (img.width(), img.height());
+    /// Parse and validate all request values.
+    pub fn from_args(args: &ResizeArgs) -> Result<ResizeParams> {
+        ResizeParams::parse(args.width, args.height, args.aspect_ratio, args.filter)?
+            .with_fit(args.fit, args.gravity, args.background)?
+            .with_upscale(args.allow_upscale, args.upscale_quality)
+    }
 
-    let (target_w, target_h) = match (&params.width, &params.height, &params.aspect_ratio) {
-        (None, None, _) => {
-            return Ok(img.clone());
+    /// Build params from request values. A width or height of 0 counts as unset,
+    /// neither may exceed `MAX_SIDE`, and `ignore` needs both, since there is
+    /// nothing to stretch to otherwise.
+    pub fn parse(
+        width: Option<u32>,
+        height: Option<u32>,
+        aspect_ratio: Option<&str>,
+        filter: Option<&str>,
+    ) -> Result<ResizeParams> {
+        let width = width.filter(|w| *w > 0);
+        let height = height.filter(|h| *h > 0);
+        if width.max(height).is_some_and(|side| side > MAX_SIDE) {
+            return Err(Error::msg(format!(
+                "width and height must be at most {}px",
+                MAX_SIDE
+            )));
         }
-        (Some(w), None, AspectRatio::Preserve) => {
-            let ratio = *w as f64 / orig_w as f64;
-            let h = (orig_h as f64 * ratio).round() as u32;
-            (*w, h)
+        let aspect_ratio = match aspect_ratio {
+            Some(ar) if !ar.is_empty() => ar.parse()?,
+            _ => AspectRatio::Preserve,
+        };
+        let filter = match filter {
+            Some(f) if !f.is_empty() => f.parse()?,
+            _ => Filter::default(),
+        };
+        if aspect_ratio == AspectRatio::Ignore && (width.is_none() || height.is_none()) {
+            return Err(Error::msg(
+                "aspect_ratio=ignore requires both width and height",
+            ));
         }
-        (None, Some(h), AspectRatio::Preserve) => {
-            let ratio = *h as f64 / orig_h as f64;
-            let w = (orig_w as f64 * ratio).round() as u32;
-            (w, *h)
+        Ok(ResizeParams {
+            filter,
+            ..ResizeParams::new(width, height, aspect_ratio)
+        })
+    }
+
+    /// Apply the `fit`, `gravity` and `background` request values. A `fit`
+    /// takes precedence over `aspect_ratio`; unset values keep what `parse` chose.
+    pub fn with_fit(
+        mut self,
+        fit: Option<&str>,
+        gravity: Option<&str>,
+        background: Option<&str>,
+    ) -> Result<ResizeParams> {
+        if let Some(fit) = fit.filter(|f| !f.is_empty()) {
+            self.fit = fit.parse()?;
         }
-        (Some(w), Some(h), AspectRatio::Preserve) => {
-            let ratio_w = *w as f64 / orig_w as f64;
-            let ratio_h = *h as f64 / orig_h as f64;
-            let ratio = ratio_w.min(ratio_h);
-            let new_w = (orig_w as f64 * ratio).round() as u32;
-            let new_h = (orig_h as f64 * ratio).round() as u32;
-            (new_w, new_h)
+        if let Some(gravity) = gravity.filter(|g| !g.is_empty()) {
+            self.gravity = gravity.parse()?;
         }
-        (Some(w), Some(h), AspectRatio::Ignore) => {
-            (*w, *h)
+        if let Some(background) = background.filter(|b| !b.is_empty()) {
+            self.background = parse_color(background)?;
         }
-        (Some(w), None, AspectRatio::Ignore) => {
-            (*w, orig_h)
+        if self.fit.is_exact() && (self.width.is_none() || self.height.is_none()) {
+            return Err(Error::msg(
+                format!("fit={:?} requires both width and height", self.fit).to_lowercase(),
+            ));
         }
-        (None, Some(h), AspectRatio::Ignore) => {
-            (orig_w, *h)
+        Ok(self)
+    }
+
+    /// Apply the `allow_upscale` and `upscale_quality` request values.
+    pub fn with_upscale(
+        mut self,
+        allow_upscale: Option<bool>,
+        upscale_quality: Option<&str>,
+    ) -> Result<ResizeParams> {
+        if let Some(allow_upscale) = allow_upscale {
+            self.allow_upscale = allow_upscale;
+        }
+        if let Some(quality) = upscale_quality.filter(|q| !q.is_empty()) {
+            self.upscale_quality = quality.parse()?;
+        }
+        Ok(self)
+    }
+}
+
+/// A rectangle in source image pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::fmt::Display for Rect {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// A resized image, whether either side got larger than the source, and for
+/// `cover` the part of the source that was kept.
+pub struct Resized {
+    pub image: DynamicImage,
+    pub upscaled: bool,
+    pub crop: Option<Rect>,
+}
+
+fn scaled(orig: (u32, u32), ratio: f64) -> (u32, u32) {
+    (
+        ((orig.0 as f64 * ratio).round() as u32).max(1),
+        ((orig.1 as f64 * ratio).round() as u32).max(1),
+    )
+}
+
+pub fn resize_image(img: &DynamicImage, params: &ResizeParams) -> Result<DynamicImage> {
+    resize(img, params).map(|resized| resized.image)
+}
+
+pub fn resize(img: &DynamicImage, params: &ResizeParams) -> Result<Resized> {
+    let orig = (img.width(), img.height());
+    let limit = |ratio: f64| {
+        if params.allow_upscale {
+            ratio
+        } else {
+            ratio.min(1.0)
         }
     };
 
-    Ok(img.resize_exact(
-        target_w,
-        target_h,
-        image::imageops::FilterType::Lanczos3,
-    ))
+    let (w, h) = match (params.width, params.height) {
+        (None, None) => {
+            return Ok(Resized {
+                image: img.clone(),
+                upscaled: false,
+                crop: None,
+            })
+        }
+        (Some(w), None) if params.fit == Fit::Fill => (w, orig.1),
+        (None, Some(h)) if params.fit == Fit::Fill => (orig.0, h),
+        // With a single side every other fit is a plain proportional scale.
+        (Some(w), None) => {
+            return Ok(scale_to(
+                img,
+                scaled(orig, limit(w as f64 / orig.0 as f64)),
+                params,
+            ))
+        }
+        (None, Some(h)) => {
+            return Ok(scale_to(
+                img,
+                scaled(orig, limit(h as f64 / orig.1 as f64)),
+                params,
+            ))
+        }
+        (Some(w), Some(h)) => (w, h),
+    };
+    let ratio_w = w as f64 / orig.0 as f64;
+    let ratio_h = h as f64 / orig.1 as f64;
+    // When the upscale limit kicks in, shrink the box by the same factor, so
+    // the output keeps the box's shape without outgrowing the image.
+    let shrunk = |ratio: f64| scaled((w, h), limit(ratio) / ratio);
+
+    Ok(match params.fit {
+        Fit::Fill if params.allow_upscale => scale_to(img, (w, h), params),
+        Fit::Fill if params.width.is_some() && params.height.is_some() => {
+            scale_to(img, shrunk(ratio_w.max(ratio_h)), params)
+        }
+        // A one-sided fill has no box shape to keep, only its side to limit.
+        Fit::Fill => scale_to(img, (w.min(orig.0), h.min(orig.1)), params),
+        Fit::Inside => scale_to(img, scaled(orig, limit(ratio_w.min(ratio_h))), params),
+        Fit::Outside => scale_to(img, scaled(orig, limit(ratio_w.max(ratio_h))), params),
+        Fit::Cover => {
+            let ratio = ratio_w.max(ratio_h);
+            let Resized {
+                image, upscaled, ..
+            } = scale_to(img, scaled(orig, limit(ratio)), params);
+            let (w, h) = shrunk(ratio);
+            let (cw, ch) = (w.min(image.width()), h.min(image.height()));
+            let (x, y) = match params.gravity.saliency() {
+                Some(saliency) => smartcrop::best_offset(&image, (cw, ch), saliency),
+                None => params
+                    .gravity
+                    .offset(image.width() - cw, image.height() - ch),
+            };
+            // Map the window back to source pixels for reporting.
+            let (sx, sy) = (
+                orig.0 as f64 / image.width() as f64,
+                orig.1 as f64 / image.height() as f64,
+            );
+            let to_source = |v: u32, s: f64, max: u32| ((v as f64 * s).round() as u32).min(max);
+            let crop = Rect {
+                x: to_source(x, sx, orig.0),
+                y: to_source(y, sy, orig.1),
+                width: to_source(cw, sx, orig.0).max(1),
+                height: to_source(ch, sy, orig.1).max(1),
+            };
+            Resized {
+                image: image.crop_imm(x, y, cw, ch),
+                upscaled,
+                crop: Some(crop),
+            }
+        }
+        Fit::Contain => {
+            let ratio = ratio_w.min(ratio_h);
+            let Resized {
+                image, upscaled, ..
+            } = scale_to(img, scaled(orig, limit(ratio)), params);
+            let (w, h) = shrunk(ratio);
+            let (w, h) = (w.max(image.width()), h.max(image.height()));
+            let mut canvas = RgbaImage::from_pixel(w, h, params.background);
+            let (x, y) = params.gravity.offset(
+                w.saturating_sub(image.width()),
+                h.saturating_sub(image.height()),
+            );
+            imageops::overlay(&mut canvas, &image.to_rgba8(), x as i64, y as i64);
+            Resized {
+                image: DynamicImage::ImageRgba8(canvas),
+                upscaled,
+                crop: None,
+            }
+        }
+    })
 }
 
-pub fn resize_image_to_bytes(img: &DynamicImage, params: &ResizeParams) -> Result<Vec<u8>> {
-    let resized = resize_image(img, params)?;
-    let mut buf = std::io::Cursor::new(Vec::new());
-    resized.write_to(&mut buf, image::ImageFormat::Png)?;
-    Ok(buf.into_inner())
+fn scale_to(img: &DynamicImage, (w, h): (u32, u32), params: &ResizeParams) -> Resized {
+    let upscaled = w > img.width() || h > img.height();
+    let image = if (w, h) == (img.width(), img.height()) {
+        img.clone()
+    } else if upscaled && params.upscale_quality == UpscaleQuality::High {
+        // Sharpen harder the more the image was blown up, within reason.
+        let factor = (w as f32 / img.width() as f32).max(h as f32 / img.height() as f32);
+        img.resize_exact(w, h, FilterType::Lanczos3)
+            .unsharpen((0.5 * factor).clamp(0.5, 2.0), 2)
+    } else {
+        img.resize_exact(w, h, params.filter.into())
+    };
+    Resized {
+        image,
+        upscaled,
+        crop: None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use image::{GenericImageView, ImageBuffer};
 
     fn make_test_image(w: u32, h: u32) -> DynamicImage {
         ImageBuffer::from_fn(w, h, |x, y| {
@@ -88,7 +518,8 @@ mod tests {
             let g = (y % 256) as u8;
             let b = ((x + y) % 256) as u8;
             Rgba([r, g, b, 255])
-        }).into()
+        })
+        .into()
     }
 
     #[test]
@@ -141,9 +572,261 @@ mod tests {
 
     #[test]
     fn test_aspect_ratio_from_str() {
-        assert!(matches!(AspectRatio::from_str("preserve"), Some(AspectRatio::Preserve)));
-        assert!(matches!(AspectRatio::from_str("ignore"), Some(AspectRatio::Ignore)));
-        assert!(matches!(AspectRatio::from_str("PRESERVE"), Some(AspectRatio::Preserve)));
-        assert!(matches!(AspectRatio::from_str("invalid"), None));
+        assert!(matches!(
+            AspectRatio::from_str("preserve"),
+            Ok(AspectRatio::Preserve)
+        ));
+        assert!(matches!(
+            AspectRatio::from_str("ignore"),
+            Ok(AspectRatio::Ignore)
+        ));
+        assert!(matches!(
+            AspectRatio::from_str("PRESERVE"),
+            Ok(AspectRatio::Preserve)
+        ));
+        assert!(AspectRatio::from_str("invalid").is_err());
+    }
+
+    #[test]
+    fn test_filter_from_str() {
+        assert_eq!(Filter::from_str("nearest").unwrap(), Filter::Nearest);
+        assert_eq!(Filter::from_str("Catmull-Rom").unwrap(), Filter::CatmullRom);
+        assert_eq!(Filter::from_str("lanczos3").unwrap(), Filter::Lanczos3);
+        assert!(Filter::from_str("bspline").is_err());
+    }
+
+    #[test]
+    fn test_parse_validates_ignore_and_zero_dimensions() {
+        assert!(ResizeParams::parse(Some(100), None, Some("ignore"), None).is_err());
+        assert!(ResizeParams::parse(Some(MAX_SIDE + 1), None, None, None).is_err());
+        assert!(ResizeParams::parse(Some(10), Some(MAX_SIDE + 1), None, None).is_err());
+        assert!(ResizeParams::parse(Some(MAX_SIDE), Some(MAX_SIDE), None, None).is_ok());
+        let params = ResizeParams::parse(Some(0), Some(50), None, Some("gaussian")).unwrap();
+        assert_eq!(params.width, None);
+        assert_eq!(params.height, Some(50));
+        assert_eq!(params.filter, Filter::Gaussian);
+        let params = ResizeParams::parse(Some(50), None, None, None).unwrap();
+        assert_eq!(params.filter, Filter::Triangle);
+    }
+
+    #[test]
+    fn test_nearest_filter_keeps_hard_edges() {
+        let img: DynamicImage = ImageBuffer::from_fn(2, 1, |x, _| {
+            if x == 0 {
+                Rgba([0u8, 0, 0, 255])
+            } else {
+                Rgba([255u8, 255, 255, 255])
+            }
+        })
+        .into();
+        let params = ResizeParams {
+            filter: Filter::Nearest,
+            ..ResizeParams::new(Some(8), Some(1), AspectRatio::Ignore)
+        };
+        let resized = resize_image(&img, &params).unwrap().to_rgba8();
+        assert!(resized.pixels().all(|p| p[0] == 0 || p[0] == 255));
+    }
+
+    #[test]
+    fn test_tiny_result_is_at_least_one_pixel() {
+        let img = make_test_image(200, 1);
+        let params = ResizeParams::new(Some(10), None, AspectRatio::Preserve);
+        let resized = resize_image(&img, &params).unwrap();
+        assert_eq!((resized.width(), resized.height()), (10, 1));
+    }
+
+    #[test]
+    fn test_fit_and_gravity_from_str() {
+        assert_eq!(Fit::from_str("Cover").unwrap(), Fit::Cover);
+        assert!(Fit::from_str("stretch").is_err());
+        assert_eq!(Gravity::from_str("north_east").unwrap(), Gravity::NorthEast);
+        assert_eq!(Gravity::from_str("bottom").unwrap(), Gravity::South);
+        assert!(Gravity::from_str("middle").is_err());
+    }
+
+    #[test]
+    fn test_exact_fits_require_both_dimensions() {
+        let params = ResizeParams::parse(Some(100), None, None, None).unwrap();
+        assert!(params.with_fit(Some("cover"), None, None).is_err());
+        assert!(params.with_fit(Some("outside"), None, None).is_ok());
+        assert!(params.with_fit(None, None, Some("#nothex")).is_err());
+    }
+
+    #[test]
+    fn test_outside_covers_the_box() {
+        let img = make_test_image(200, 100);
+        let params = ResizeParams {
+            fit: Fit::Outside,
+            ..ResizeParams::new(Some(60), Some(60), AspectRatio::Preserve)
+        };
+        let resized = resize_image(&img, &params).unwrap();
+        assert_eq!((resized.width(), resized.height()), (120, 60));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_cover_crops_at_gravity() {
+        // Left half black, right half white.
+        let img: DynamicImage = ImageBuffer::from_fn(200, 100, |x, _| {
+            if x < 100 {
+                Rgba([0u8, 0, 0, 255])
+            } else {
+                Rgba([255u8, 255, 255, 255])
+            }
+        })
+        .into();
+        let params = ResizeParams {
+            fit: Fit::Cover,
+            gravity: Gravity::West,
+            filter: Filter::Nearest,
+            ..ResizeParams::new(Some(50), Some(50), AspectRatio::Preserve)
+        };
+        let west = resize_image(&img, &params).unwrap().to_rgba8();
+        assert_eq!(west.dimensions(), (50, 50));
+        assert!(west.pixels().all(|p| p[0] == 0));
+
+        let east = resize_image(
+            &img,
+            &ResizeParams {
+                gravity: Gravity::East,
+                ..params
+            },
+        )
+        .unwrap()
+        .to_rgba8();
+        assert!(east.pixels().all(|p| p[0] == 255));
+    }
+
+    #[test]
+    fn test_contain_letterboxes_with_background() {
+        let img: DynamicImage = ImageBuffer::from_pixel(200, 100, Rgba([0u8, 0, 255, 255])).into();
+        let params = ResizeParams {
+            fit: Fit::Contain,
+            background: Rgba([255, 0, 0, 255]),
+            ..ResizeParams::new(Some(100), Some(100), AspectRatio::Preserve)
+        };
+        let out = resize_image(&img, &params).unwrap().to_rgba8();
+        assert_eq!(out.dimensions(), (100, 100));
+        // Centred 100x50 image leaves 25px bands above and below.
+        assert_eq!(*out.get_pixel(50, 10), Rgba([255, 0, 0, 255]));
+        assert_eq!(*out.get_pixel(50, 50), Rgba([0, 0, 255, 255]));
+        assert_eq!(*out.get_pixel(50, 90), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_upscale_policy() {
+        let img = make_test_image(40, 20);
+        let params = ResizeParams::new(Some(80), Some(80), AspectRatio::Preserve);
+        let resized = resize(&img, &params).unwrap();
+        assert!(resized.upscaled);
+        assert_eq!(resized.image.dimensions(), (80, 40));
+
+        let no_upscale = params.with_upscale(Some(false), None).unwrap();
+        let resized = resize(&img, &no_upscale).unwrap();
+        assert!(!resized.upscaled);
+        assert_eq!(resized.image.dimensions(), (40, 20));
+
+        // Contain pads to the box's shape around the unscaled image, not to the box.
+        let contain = ResizeParams {
+            fit: Fit::Contain,
+            ..no_upscale
+        };
+        let resized = resize(&img, &contain).unwrap();
+        assert!(!resized.upscaled);
+        assert_eq!(resized.image.dimensions(), (40, 40));
+    }
+
+    #[test]
+    fn test_cover_and_fill_keep_box_shape_without_upscale() {
+        let img = make_test_image(40, 20);
+        let no_upscale = |w: u32, h: u32, fit: Fit| ResizeParams {
+            fit,
+            allow_upscale: false,
+            ..ResizeParams::new(Some(w), Some(h), AspectRatio::Preserve)
+        };
+
+        let resized = resize(&img, &no_upscale(80, 80, Fit::Cover)).unwrap();
+        assert!(!resized.upscaled);
+        assert_eq!(resized.image.dimensions(), (20, 20));
+        assert_eq!(resized.crop.unwrap().to_string(), "10,0,20,20");
+        let resized = resize(&img, &no_upscale(60, 120, Fit::Cover)).unwrap();
+        assert_eq!(resized.image.dimensions(), (10, 20));
+
+        let resized = resize(&img, &no_upscale(80, 80, Fit::Fill)).unwrap();
+        assert!(!resized.upscaled);
+        assert_eq!(resized.image.dimensions(), (20, 20));
+        let resized = resize(&img, &no_upscale(120, 30, Fit::Fill)).unwrap();
+        assert_eq!(resized.image.dimensions(), (40, 10));
+        // A single side still only stops at the source size.
+        let one_side = ResizeParams {
+            height: None,
+            ..no_upscale(80, 80, Fit::Fill)
+        };
+        assert_eq!(
+            resize(&img, &one_side).unwrap().image.dimensions(),
+            (40, 20)
+        );
+    }
+
+    #[test]
+    fn test_high_quality_upscale_sharpens_edges() {
+        let img: DynamicImage = ImageBuffer::from_fn(8, 8, |x, _| {
+            if x < 4 {
+                Rgba([40u8, 40, 40, 255])
+            } else {
+                Rgba([200u8, 200, 200, 255])
+            }
+        })
+        .into();
+        let params = ResizeParams::new(Some(64), Some(64), AspectRatio::Preserve);
+        let plain = resize_image(&img, &params).unwrap().to_rgba8();
+        let high = resize_image(&img, &params.with_upscale(None, Some("high")).unwrap())
+            .unwrap()
+            .to_rgba8();
+        let contrast = |im: &RgbaImage| {
+            (0..63)
+                .map(|x| im.get_pixel(x + 1, 32)[0].abs_diff(im.get_pixel(x, 32)[0]))
+                .max()
+        };
+        assert!(contrast(&high) > contrast(&plain));
+        assert!(UpscaleQuality::from_str("ultra").is_err());
+    }
+
+    #[test]
+    fn test_cover_reports_crop_in_source_pixels() {
+        // Detail on the right third of an otherwise flat image.
+        let img: DynamicImage = ImageBuffer::from_fn(300, 100, |x, y| {
+            if x >= 200 {
+                let v = ((x * 37 + y * 91) % 256) as u8;
+                Rgba([v, 255 - v, v / 2, 255])
+            } else {
+                Rgba([100u8, 100, 100, 255])
+            }
+        })
+        .into();
+        let params = ResizeParams {
+            fit: Fit::Cover,
+            ..ResizeParams::new(Some(50), Some(50), AspectRatio::Preserve)
+        };
+        for gravity in ["smart", "attention"] {
+            let params = ResizeParams {
+                gravity: Gravity::from_str(gravity).unwrap(),
+                ..params
+            };
+            let resized = resize(&img, &params).unwrap();
+            assert_eq!(resized.image.dimensions(), (50, 50));
+            let crop = resized.crop.unwrap();
+            assert_eq!((crop.width, crop.height), (100, 100));
+            assert!(crop.x >= 180, "{} crop at {}", gravity, crop);
+        }
+
+        let centred = resize(
+            &img,
+            &ResizeParams {
+                gravity: Gravity::Center,
+                ..params
+            },
+        )
+        .unwrap();
+        assert_eq!(centred.crop.unwrap().to_string(), "100,0,100,100");
+    }
+}
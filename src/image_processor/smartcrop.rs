@@ -0,0 +1,225 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView};
+
+/// Longest side of the downscaled copy the crop window is searched on.
+const ANALYSIS_SIZE: u32 = 64;
+/// Luma bins for local entropy.
+const BINS: usize = 16;
+/// Neighbourhood radius for local entropy, in analysis pixels.
+const RADIUS: i64 = 2;
+
+/// How "interesting" regions are scored when choosing a crop window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Saliency {
+    /// Local luma entropy: busy texture and detail.
+    Entropy,
+    /// Edges, skin tones and saturated colour, after libvips' `attention`.
+    Attention,
+    /// Entropy and attention, equally weighted.
+    Smart,
+}
+
+/// Top-left corner of the `width` x `height` window of `img` with the highest
+/// saliency. Ties go to the window nearest the centre.
+pub fn best_offset(
+    img: &DynamicImage,
+    (width, height): (u32, u32),
+    saliency: Saliency,
+) -> (u32, u32) {
+    let (img_w, img_h) = img.dimensions();
+    if width >= img_w && height >= img_h {
+        return (0, 0);
+    }
+
+    let small = img
+        .resize(ANALYSIS_SIZE, ANALYSIS_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    let (sw, sh) = small.dimensions();
+    let scores = match saliency {
+        Saliency::Entropy => entropy_map(&small),
+        Saliency::Attention => attention_map(&small),
+        Saliency::Smart => {
+            let entropy = normalized(entropy_map(&small));
+            let attention = normalized(attention_map(&small));
+            entropy.iter().zip(&attention).map(|(e, a)| e + a).collect()
+        }
+    };
+
+    // Nothing stands out: behave like centre gravity.
+    let (lo, hi) = scores
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(lo, hi), s| (lo.min(*s), hi.max(*s)));
+    if hi - lo <= f64::EPSILON {
+        return (
+            img_w.saturating_sub(width) / 2,
+            img_h.saturating_sub(height) / 2,
+        );
+    }
+
+    let scale_x = sw as f64 / img_w as f64;
+    let scale_y = sh as f64 / img_h as f64;
+    let win_w = ((width as f64 * scale_x).round() as u32).clamp(1, sw);
+    let win_h = ((height as f64 * scale_y).round() as u32).clamp(1, sh);
+    let (best_x, best_y) = best_window(&scores, (sw, sh), (win_w, win_h));
+
+    // Map the position across the free range, so centre and edges stay exact.
+    let place = |best: u32, free_small: u32, free: u32| {
+        if free_small == 0 {
+            free / 2
+        } else {
+            (best as f64 * free as f64 / free_small as f64).round() as u32
+        }
+    };
+    (
+        place(best_x, sw - win_w, img_w.saturating_sub(width)),
+        place(best_y, sh - win_h, img_h.saturating_sub(height)),
+    )
+}
+
+// Exhaustive search over window positions using a summed-area table.
+fn best_window(scores: &[f64], (w, h): (u32, u32), (win_w, win_h): (u32, u32)) -> (u32, u32) {
+    let (w, h) = (w as usize, h as usize);
+    let mut sat = vec![0.0; (w + 1) * (h + 1)];
+    for y in 0..h {
+        for x in 0..w {
+            sat[(y + 1) * (w + 1) + x + 1] =
+                scores[y * w + x] + sat[y * (w + 1) + x + 1] + sat[(y + 1) * (w + 1) + x]
+                    - sat[y * (w + 1) + x];
+        }
+    }
+    let window_sum = |x: usize, y: usize| {
+        let (x2, y2) = (x + win_w as usize, y + win_h as usize);
+        sat[y2 * (w + 1) + x2] - sat[y * (w + 1) + x2] - sat[y2 * (w + 1) + x]
+            + sat[y * (w + 1) + x]
+    };
+
+    let max_x = w - win_w as usize;
+    let max_y = h - win_h as usize;
+    let centre_distance = |x: usize, y: usize| (2 * x).abs_diff(max_x) + (2 * y).abs_diff(max_y);
+    let mut best = (max_x / 2, max_y / 2);
+    let mut best_sum = window_sum(best.0, best.1);
+    for y in 0..=max_y {
+        for x in 0..=max_x {
+            let sum = window_sum(x, y);
+            let better = sum > best_sum + f64::EPSILON
+                || ((sum - best_sum).abs() <= f64::EPSILON
+                    && centre_distance(x, y) < centre_distance(best.0, best.1));
+            if better {
+                best = (x, y);
+                best_sum = sum;
+            }
+        }
+    }
+    (best.0 as u32, best.1 as u32)
+}
+
+fn luma(p: &image::Rgb<u8>) -> f64 {
+    0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64
+}
+
+fn entropy_map(img: &image::RgbImage) -> Vec<f64> {
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let bins: Vec<usize> = img
+        .pixels()
+        .map(|p| (luma(p) as usize * BINS / 256).min(BINS - 1))
+        .collect();
+    let mut out = Vec::with_capacity(bins.len());
+    for y in 0..h {
+        for x in 0..w {
+            let mut hist = [0u32; BINS];
+            let mut n = 0u32;
+            for ny in (y - RADIUS).max(0)..=(y + RADIUS).min(h - 1) {
+                for nx in (x - RADIUS).max(0)..=(x + RADIUS).min(w - 1) {
+                    hist[bins[(ny * w + nx) as usize]] += 1;
+                    n += 1;
+                }
+            }
+            let entropy: f64 = hist
+                .iter()
+                .filter(|c| **c > 0)
+                .map(|c| {
+                    let p = *c as f64 / n as f64;
+                    -p * p.log2()
+                })
+                .sum();
+            out.push(entropy);
+        }
+    }
+    out
+}
+
+fn attention_map(img: &image::RgbImage) -> Vec<f64> {
+    let (w, h) = img.dimensions();
+    let mut out = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let p = img.get_pixel(x, y);
+            let l = luma(p);
+            let dx = luma(img.get_pixel((x + 1).min(w - 1), y))
+                - luma(img.get_pixel(x.saturating_sub(1), y));
+            let dy = luma(img.get_pixel(x, (y + 1).min(h - 1)))
+                - luma(img.get_pixel(x, y.saturating_sub(1)));
+            let edge = (dx.abs() + dy.abs()) / 510.0;
+
+            let (r, g, b) = (p[0] as f64, p[1] as f64, p[2] as f64);
+            let max = r.max(g).max(b);
+            let min = r.min(g).min(b);
+            let saturation = if max > 0.0 { (max - min) / max } else { 0.0 };
+            // Classic RGB skin rule (Kovac et al.), skipping very dark pixels.
+            let skin =
+                r > 95.0 && g > 40.0 && b > 20.0 && r > g && r > b && r - min > 15.0 && l > 40.0;
+
+            out.push(edge + 0.5 * if skin { 1.0 } else { 0.0 } + 0.3 * saturation);
+        }
+    }
+    out
+}
+
+fn normalized(scores: Vec<f64>) -> Vec<f64> {
+    let max = scores.iter().cloned().fold(0.0, f64::max);
+    if max <= 0.0 {
+        return scores;
+    }
+    scores.into_iter().map(|s| s / max).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    // Flat grey with a noisy patch at `patch_x`.
+    fn image_with_detail(w: u32, h: u32, patch_x: u32) -> DynamicImage {
+        ImageBuffer::from_fn(w, h, |x, y| {
+            if x >= patch_x && x < patch_x + h {
+                let v = ((x * 73 + y * 151) % 256) as u8;
+                Rgba([v, v.wrapping_mul(3), 255 - v, 255])
+            } else {
+                Rgba([128u8, 128, 128, 255])
+            }
+        })
+        .into()
+    }
+
+    #[test]
+    fn window_follows_the_detail() {
+        let img = image_with_detail(400, 100, 280);
+        for saliency in [Saliency::Entropy, Saliency::Attention, Saliency::Smart] {
+            let (x, y) = best_offset(&img, (100, 100), saliency);
+            assert_eq!(y, 0);
+            assert!((260..=300).contains(&x), "{:?} picked x={}", saliency, x);
+        }
+    }
+
+    #[test]
+    fn flat_image_crops_the_centre() {
+        let img: DynamicImage = ImageBuffer::from_pixel(300, 100, Rgba([90u8, 90, 90, 255])).into();
+        assert_eq!(best_offset(&img, (100, 100), Saliency::Smart), (100, 0));
+    }
+
+    #[test]
+    fn window_larger_than_image_is_at_origin() {
+        let img = image_with_detail(50, 50, 0);
+        assert_eq!(best_offset(&img, (60, 50), Saliency::Entropy), (0, 0));
+    }
+}
@@ -0,0 +1,516 @@
+use crate::image_processor::color::parse_color;
+use anyhow::{Error, Result};
+use flate2::read::DeflateDecoder;
+use image::{imageops, GenericImageView, ImageBuffer, Rgba, RgbaImage};
+use std::io::Read;
+
+/// Upper bound on tiles per request.
+pub const MAX_TILES: usize = 256;
+/// Upper bound on the stitched image, in pixels.
+pub const MAX_PIXELS: u64 = 100_000_000;
+/// Largest overlap or bezel, in px.
+const MAX_GAP: u32 = 4096;
+// Largest ZIP entry we inflate.
+const MAX_ENTRY_BYTES: u64 = 256 * 1024 * 1024;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Stitch params as they arrive in a request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StitchArgs<'a> {
+    pub grid: Option<&'a str>,
+    pub overlap: Option<&'a str>,
+    pub bezel: Option<&'a str>,
+    pub background: Option<&'a str>,
+}
+
+/// How to put tiles back together: a grid filled in reading order, the px
+/// each tile repeats from its left and upper neighbours, and the px of
+/// `background` between tiles (e.g. a video wall's bezels).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StitchSpec {
+    pub cols: u32,
+    pub rows: u32,
+    /// Tiles the grid takes, `cols * rows`.
+    pub tiles: usize,
+    pub overlap: (u32, u32),
+    pub bezel: (u32, u32),
+    pub background: Rgba<u8>,
+}
+
+impl StitchSpec {
+    pub fn from_args(args: &StitchArgs) -> Result<StitchSpec> {
+        let (cols, rows, tiles) = match args.grid.filter(|g| !g.is_empty()) {
+            None => (2, 2, 4),
+            Some(grid) => match pair(grid).and_then(|(c, r)| Some((c, r, c.checked_mul(r)?))) {
+                Some((c, r, n)) if n > 0 && n as usize <= MAX_TILES => (c, r, n as usize),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "Invalid grid: {} (use COLSxROWS, at most {} tiles)",
+                        grid, MAX_TILES
+                    )))
+                }
+            },
+        };
+        let px = |name: &str, value: Option<&str>| match value.filter(|v| !v.is_empty()) {
+            None => Ok((0, 0)),
+            Some(v) => match v
+                .trim()
+                .parse::<u32>()
+                .map(|n| (n, n))
+                .ok()
+                .or_else(|| pair(v))
+            {
+                Some((x, y)) if x <= MAX_GAP && y <= MAX_GAP => Ok((x, y)),
+                _ => Err(Error::msg(format!(
+                    "Invalid {}: {} (use N or XxY, 0-{} px)",
+                    name, v, MAX_GAP
+                ))),
+            },
+        };
+        let background = match args.background.filter(|b| !b.is_empty()) {
+            Some(b) => parse_color(b)?,
+            None => Rgba([0, 0, 0, 0]),
+        };
+        Ok(StitchSpec {
+            cols,
+            rows,
+            tiles,
+            overlap: px("overlap", args.overlap)?,
+            bezel: px("bezel", args.bezel)?,
+            background,
+        })
+    }
+
+    /// Place `tiles` in reading order. Every tile but those in the first
+    /// column (row) loses `overlap` px on its left (top) edge; the rest is
+    /// drawn `bezel` px after its neighbour. Columns take their widest tile and
+    /// rows their tallest, so uneven edge tiles come out as they were cut.
+    pub fn stitch(&self, tiles: &[RgbaImage]) -> Result<RgbaImage> {
+        if tiles.len() != self.tiles {
+            return Err(Error::msg(format!(
+                "A {}x{} grid needs {} tiles, got {}",
+                self.cols,
+                self.rows,
+                self.tiles,
+                tiles.len()
+            )));
+        }
+        let cols = self.cols as usize;
+        let mut widths = vec![0u32; cols];
+        let mut heights = vec![0u32; self.rows as usize];
+        for (i, tile) in tiles.iter().enumerate() {
+            let (c, r) = (i % cols, i / cols);
+            let skip = self.skip(c, r);
+            if tile.width() <= skip.0 || tile.height() <= skip.1 {
+                return Err(Error::msg(format!(
+                    "Tile {} ({}x{}) is not larger than the overlap {}x{}",
+                    i + 1,
+                    tile.width(),
+                    tile.height(),
+                    self.overlap.0,
+                    self.overlap.1
+                )));
+            }
+            widths[c] = widths[c].max(tile.width() - skip.0);
+            heights[r] = heights[r].max(tile.height() - skip.1);
+        }
+
+        let xs = offsets(&widths, self.bezel.0);
+        let ys = offsets(&heights, self.bezel.1);
+        let width = xs[cols - 1] + widths[cols - 1] as u64;
+        let height = ys[heights.len() - 1] + heights[heights.len() - 1] as u64;
+        if width.saturating_mul(height) > MAX_PIXELS {
+            return Err(Error::msg(format!(
+                "Stitched image {}x{} is larger than {} megapixels",
+                width,
+                height,
+                MAX_PIXELS / 1_000_000
+            )));
+        }
+
+        let mut canvas = ImageBuffer::from_pixel(width as u32, height as u32, self.background);
+        for (i, tile) in tiles.iter().enumerate() {
+            let (c, r) = (i % cols, i / cols);
+            let skip = self.skip(c, r);
+            let kept = tile.view(
+                skip.0,
+                skip.1,
+                tile.width() - skip.0,
+                tile.height() - skip.1,
+            );
+            imageops::replace(&mut canvas, &kept.to_image(), xs[c] as i64, ys[r] as i64);
+        }
+        Ok(canvas)
+    }
+
+    // Overlap cut from the tile at column `c`, row `r`.
+    fn skip(&self, c: usize, r: usize) -> (u32, u32) {
+        (
+            if c > 0 { self.overlap.0 } else { 0 },
+            if r > 0 { self.overlap.1 } else { 0 },
+        )
+    }
+}
+
+// "AxB" as two numbers.
+fn pair(s: &str) -> Option<(u32, u32)> {
+    let (a, b) = s.trim().split_once(['x', 'X'])?;
+    Some((a.trim().parse().ok()?, b.trim().parse().ok()?))
+}
+
+// Start of each column (row) of `sizes` with `gap` px between them. In u64,
+// so huge tiles reach the size check instead of overflowing.
+fn offsets(sizes: &[u32], gap: u32) -> Vec<u64> {
+    let mut at = 0;
+    sizes
+        .iter()
+        .map(|&size| {
+            let start = at;
+            at += size as u64 + gap as u64;
+            start
+        })
+        .collect()
+}
+
+/// Decode every tile, naming the one that fails.
+pub fn decode_tiles<T: AsRef<[u8]>>(parts: &[T]) -> Result<Vec<RgbaImage>> {
+    if parts.is_empty() {
+        return Err(Error::msg("No tiles in request"));
+    }
+    if parts.len() > MAX_TILES {
+        return Err(Error::msg(format!(
+            "At most {} tiles, got {}",
+            MAX_TILES,
+            parts.len()
+        )));
+    }
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, data)| {
+            image::load_from_memory(data.as_ref())
+                .map(|img| img.into_rgba8())
+                .map_err(|e| Error::msg(format!("Tile {}: {}", i + 1, e)))
+        })
+        .collect()
+}
+
+/// Split PNGs sent back to back, as `/slice` streams them, at each `IEND`.
+pub fn split_png_stream(data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut images = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        if !data[start..].starts_with(&PNG_SIGNATURE) {
+            return Err(Error::msg(format!(
+                "Tile {} is not a PNG: send PNGs back to back, multipart parts or a ZIP",
+                images.len() + 1
+            )));
+        }
+        let mut at = start + PNG_SIGNATURE.len();
+        loop {
+            let header = data
+                .get(at..at + 8)
+                .ok_or_else(|| Error::msg(format!("Tile {} is truncated", images.len() + 1)))?;
+            let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            // Length, type, data, CRC.
+            at += 12 + len;
+            if at > data.len() {
+                return Err(Error::msg(format!(
+                    "Tile {} is truncated",
+                    images.len() + 1
+                )));
+            }
+            if &header[4..8] == b"IEND" {
+                break;
+            }
+        }
+        images.push(&data[start..at]);
+        start = at;
+    }
+    Ok(images)
+}
+
+/// The files in a ZIP archive with their paths, in archive order, skipping
+/// directories. Entries may be stored or deflated; ZIP64 and encryption
+/// aren't supported.
+pub fn read_zip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>> {
+    let invalid = |what: &str| Error::msg(format!("Invalid ZIP: {}", what));
+    let u16_at = |at: usize| -> Result<usize> {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| invalid("truncated"))
+    };
+    let u32_at = |at: usize| -> Result<u32> {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| invalid("truncated"))
+    };
+
+    // The end of central directory record is the last 22 bytes plus a comment.
+    let eocd = (0..=data.len().saturating_sub(22))
+        .rev()
+        .take(22 + u16::MAX as usize)
+        .find(|&at| data[at..].starts_with(&[b'P', b'K', 5, 6]))
+        .ok_or_else(|| invalid("no end of central directory"))?;
+    let entries = u16_at(eocd + 10)?;
+    let mut at = u32_at(eocd + 16)? as usize;
+
+    let mut files = Vec::new();
+    for _ in 0..entries {
+        if u32_at(at)? != 0x0201_4b50 {
+            return Err(invalid("bad central directory"));
+        }
+        let flags = u16_at(at + 8)?;
+        let method = u16_at(at + 10)?;
+        let crc = u32_at(at + 16)?;
+        let compressed = u32_at(at + 20)?;
+        let size = u32_at(at + 24)?;
+        let name_len = u16_at(at + 28)?;
+        let extra_len = u16_at(at + 30)?;
+        let comment_len = u16_at(at + 32)?;
+        let local = u32_at(at + 42)? as usize;
+        let name = data
+            .get(at + 46..at + 46 + name_len)
+            .ok_or_else(|| invalid("truncated"))?;
+        let name = String::from_utf8_lossy(name).to_string();
+        at += 46 + name_len + extra_len + comment_len;
+
+        if name.ends_with('/') {
+            continue;
+        }
+        if flags & 1 != 0 || compressed == u32::MAX || size == u32::MAX {
+            return Err(Error::msg(format!(
+                "ZIP entry {}: encrypted and ZIP64 entries are not supported",
+                name
+            )));
+        }
+        if size as u64 > MAX_ENTRY_BYTES {
+            return Err(Error::msg(format!(
+                "ZIP entry {}: larger than {} MB",
+                name,
+                MAX_ENTRY_BYTES >> 20
+            )));
+        }
+        if files.len() == MAX_TILES {
+            return Err(Error::msg(format!(
+                "At most {} tiles, got more in ZIP",
+                MAX_TILES
+            )));
+        }
+        if u32_at(local)? != 0x0403_4b50 {
+            return Err(invalid("bad local header"));
+        }
+        let start = local + 30 + u16_at(local + 26)? + u16_at(local + 28)?;
+        let raw = data
+            .get(start..start + compressed as usize)
+            .ok_or_else(|| invalid("truncated"))?;
+        let file = match method {
+            0 => raw.to_vec(),
+            8 => {
+                // `size` is checked against the CRC below, not trusted here.
+                let mut out = Vec::with_capacity(size as usize);
+                DeflateDecoder::new(raw)
+                    .take(size as u64 + 1)
+                    .read_to_end(&mut out)
+                    .map_err(|e| Error::msg(format!("ZIP entry {}: {}", name, e)))?;
+                out
+            }
+            m => {
+                return Err(Error::msg(format!(
+                    "ZIP entry {}: unsupported compression method {}",
+                    name, m
+                )))
+            }
+        };
+        if file.len() != size as usize || crc32fast::hash(&file) != crc {
+            return Err(Error::msg(format!(
+                "ZIP entry {}: size or CRC-32 doesn't match",
+                name
+            )));
+        }
+        files.push((name, file));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::image_slicer::{self, Remainder};
+    use flate2::{write::DeflateEncoder, Compression};
+    use image::DynamicImage;
+    use std::io::Write;
+
+    fn spec(args: StitchArgs) -> StitchSpec {
+        StitchSpec::from_args(&args).unwrap()
+    }
+
+    fn gradient(w: u32, h: u32) -> RgbaImage {
+        ImageBuffer::from_fn(w, h, |x, y| Rgba([x as u8, y as u8, (x * y) as u8, 255]))
+    }
+
+    // A ZIP of (name, method, stored data, original file) entries.
+    fn zip(entries: &[(&str, u16, &[u8], &[u8])]) -> Vec<u8> {
+        let (mut out, mut central) = (Vec::new(), Vec::new());
+        for &(name, method, data, file) in entries {
+            // Version, flags, method, time, date, CRC, sizes, name and extra length.
+            let mut fields = vec![20, 0, 0, 0];
+            fields.extend_from_slice(&method.to_le_bytes());
+            fields.extend_from_slice(&[0; 4]);
+            fields.extend_from_slice(&crc32fast::hash(file).to_le_bytes());
+            fields.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(file.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&[0, 0]);
+
+            central.extend_from_slice(&[b'P', b'K', 1, 2, 20, 0]);
+            central.extend_from_slice(&fields);
+            // Comment length, disk, attributes, then the local header offset.
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(out.len() as u32).to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+
+            out.extend_from_slice(&[b'P', b'K', 3, 4]);
+            out.extend_from_slice(&fields);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(data);
+        }
+        let count = (entries.len() as u16).to_le_bytes();
+        let (cd_len, cd_offset) = (central.len() as u32, out.len() as u32);
+        out.extend(central);
+        out.extend_from_slice(&[
+            b'P', b'K', 5, 6, 0, 0, 0, 0, count[0], count[1], count[0], count[1],
+        ]);
+        out.extend_from_slice(&cd_len.to_le_bytes());
+        out.extend_from_slice(&cd_offset.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out
+    }
+
+    #[test]
+    fn stitches_extended_slices_back_exactly() {
+        let original = gradient(7, 5);
+        let img = DynamicImage::ImageRgba8(original.clone());
+        let slices =
+            image_slicer::slice_images_view(img.clone(), &Remainder::Extend.dimensions(&img));
+        assert_eq!(
+            spec(StitchArgs::default()).stitch(&slices).unwrap(),
+            original
+        );
+
+        // Overlapping grid cells with clipped edges come back whole too.
+        let (cols, rows, cells) = image_slicer::grid((7, 5), (4, 3), (1, 1)).unwrap();
+        let tiles = image_slicer::view_cells(&img, cells);
+        let grid = format!("{}x{}", cols, rows);
+        let stitch = spec(StitchArgs {
+            grid: Some(&grid),
+            overlap: Some("1"),
+            ..StitchArgs::default()
+        });
+        assert_eq!(stitch.stitch(&tiles).unwrap(), original);
+    }
+
+    #[test]
+    fn bezels_leave_background_gaps() {
+        let stitch = spec(StitchArgs {
+            grid: Some("2x1"),
+            bezel: Some("3x0"),
+            background: Some("#ffffff"),
+            ..StitchArgs::default()
+        });
+        let tile = ImageBuffer::from_pixel(4, 2, Rgba([10, 20, 30, 255]));
+        let out = stitch.stitch(&[tile.clone(), tile]).unwrap();
+        assert_eq!(out.dimensions(), (11, 2));
+        assert_eq!(out.get_pixel(5, 1).0, [255, 255, 255, 255]);
+        assert_eq!(out.get_pixel(7, 1).0, [10, 20, 30, 255]);
+    }
+
+    #[test]
+    fn reads_png_streams_and_zips() {
+        let png = |w| {
+            let mut buf = std::io::Cursor::new(Vec::new());
+            gradient(w, 2)
+                .write_to(&mut buf, image::ImageFormat::Png)
+                .unwrap();
+            buf.into_inner()
+        };
+        let (a, b) = (png(3), png(5));
+        let stream = [a.clone(), b.clone()].concat();
+        assert_eq!(
+            split_png_stream(&stream).unwrap(),
+            [a.as_slice(), b.as_slice()]
+        );
+        assert!(split_png_stream(&stream[..stream.len() - 1]).is_err());
+
+        // One stored and one deflated entry, plus a directory.
+        let mut deflated = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflated.write_all(&b).unwrap();
+        let deflated = deflated.finish().unwrap();
+        let zip_file = zip(&[
+            ("tiles/", 0, &[], &[]),
+            ("tiles/a.png", 0, &a, &a),
+            ("tiles/b.png", 8, &deflated, &b),
+        ]);
+        let files = read_zip(&zip_file).unwrap();
+        assert_eq!(
+            files,
+            [
+                ("tiles/a.png".to_string(), a.clone()),
+                ("tiles/b.png".to_string(), b.clone())
+            ]
+        );
+        assert!(read_zip(b"not a zip").is_err());
+
+        // Corrupt or mislabelled entries are errors, not silently cut short.
+        let err = read_zip(&zip(&[("a.png", 0, &a, &b)])).unwrap_err();
+        assert!(err.to_string().contains("CRC-32"), "{}", err);
+        let err = read_zip(&zip(&[("b.png", 8, &deflated, &b[..b.len() - 1])])).unwrap_err();
+        assert!(err.to_string().contains("CRC-32"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_bad_params_and_tile_counts() {
+        let bad = |args: StitchArgs| StitchSpec::from_args(&args).is_err();
+        assert!(bad(StitchArgs {
+            grid: Some("0x2"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            grid: Some("20x20"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            grid: Some("65536x65536"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            overlap: Some("-1"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            bezel: Some("3y2"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            bezel: Some("0x4097"),
+            ..StitchArgs::default()
+        }));
+        assert!(bad(StitchArgs {
+            overlap: Some("4294967295"),
+            ..StitchArgs::default()
+        }));
+
+        let tile = gradient(4, 4);
+        let err = spec(StitchArgs::default())
+            .stitch(&[tile.clone(), tile.clone()])
+            .unwrap_err();
+        assert_eq!(err.to_string(), "A 2x2 grid needs 4 tiles, got 2");
+        let overlap = spec(StitchArgs {
+            grid: Some("2x1"),
+            overlap: Some("4"),
+            ..StitchArgs::default()
+        });
+        assert!(overlap.stitch(&[tile.clone(), tile]).is_err());
+    }
+}
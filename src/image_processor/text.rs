@@ -0,0 +1,434 @@
+use crate::image_processor::fonts::{self, FontId, LoadedFont};
+use ab_glyph::{point, Font, Glyph, GlyphId, PxScale, ScaleFont};
+use anyhow::{Error, Result};
+use rustybuzz::{Direction, UnicodeBuffer};
+use std::ops::Range;
+use std::str::FromStr;
+use unicode_bidi::ParagraphBidiInfo;
+
+/// Horizontal alignment of lines within a multi-line text block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TextAlign {
+    Left,
+    #[default]
+    Center,
+    Right,
+}
+
+impl FromStr for TextAlign {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "left" => Ok(TextAlign::Left),
+            "center" | "centre" => Ok(TextAlign::Center),
+            "right" => Ok(TextAlign::Right),
+            _ => Err(Error::msg(format!("Unknown text alignment: {}", s))),
+        }
+    }
+}
+
+/// Layout options for watermark text. `line_spacing` multiplies the font's line height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextStyle {
+    pub align: TextAlign,
+    pub line_spacing: f32,
+    pub font: FontId,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        TextStyle {
+            align: TextAlign::Center,
+            line_spacing: 1.0,
+            font: FontId::default(),
+        }
+    }
+}
+
+impl TextStyle {
+    pub fn parse(
+        align: Option<&str>,
+        line_spacing: Option<f32>,
+        font: Option<&str>,
+    ) -> Result<TextStyle> {
+        let align = match align {
+            Some(a) if !a.is_empty() => a.parse()?,
+            _ => TextAlign::Center,
+        };
+        let line_spacing = match line_spacing {
+            None | Some(0.0) => 1.0,
+            Some(s) if s > 0.0 && s <= 10.0 => s,
+            Some(s) => {
+                return Err(Error::msg(format!(
+                    "Line spacing must be in (0, 10], got {}",
+                    s
+                )))
+            }
+        };
+        Ok(TextStyle {
+            align,
+            line_spacing,
+            font: fonts::find(font)?,
+        })
+    }
+}
+
+/// Per-tile values for watermark text templates. `index`, `row` and `col` are
+/// zero-based here and rendered one-based, the way installers count panels.
+pub struct TileContext<'a> {
+    pub index: u32,
+    pub row: u32,
+    pub col: u32,
+    pub total: u32,
+    pub filename: &'a str,
+    pub date: &'a str,
+}
+
+impl<'a> TileContext<'a> {
+    /// Context for tile `index` of a row-major grid with `cols` columns.
+    pub fn for_grid(index: u32, cols: u32, total: u32, filename: &'a str, date: &'a str) -> Self {
+        TileContext {
+            index,
+            row: index / cols,
+            col: index % cols,
+            total,
+            filename,
+            date,
+        }
+    }
+}
+
+const PLACEHOLDERS: [&str; 6] = [
+    "{row}",
+    "{col}",
+    "{index}",
+    "{total}",
+    "{filename}",
+    "{date}",
+];
+
+/// Whether `text` has any placeholder that changes from tile to tile.
+pub fn is_template(text: &str) -> bool {
+    PLACEHOLDERS.iter().any(|p| text.contains(p))
+}
+
+/// Substitute `{row}`, `{col}`, `{index}`, `{total}`, `{filename}` and `{date}`
+/// in one pass, so braces inside a substituted value (say, a file name) stay
+/// as they are. Anything else in braces is left untouched.
+pub fn expand_template(text: &str, tile: &TileContext) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(placeholder) = PLACEHOLDERS.iter().find(|p| rest.starts_with(**p)) else {
+            out.push('{');
+            rest = &rest[1..];
+            continue;
+        };
+        match *placeholder {
+            "{row}" => out.push_str(&(tile.row + 1).to_string()),
+            "{col}" => out.push_str(&(tile.col + 1).to_string()),
+            "{index}" => out.push_str(&(tile.index + 1).to_string()),
+            "{total}" => out.push_str(&tile.total.to_string()),
+            "{filename}" => out.push_str(tile.filename),
+            _ => out.push_str(tile.date),
+        }
+        rest = &rest[placeholder.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Today's UTC date as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// A positioned glyph and the index of the font in the chain it comes from.
+pub struct PlacedGlyph {
+    pub font: usize,
+    pub glyph: Glyph,
+}
+
+/// Lay out `text` as positioned glyphs in pixel space.
+///
+/// Lines are split on `\n`. Each line is split into bidi runs in visual order,
+/// and each run is shaped with rustybuzz, so kerning, ligatures and complex
+/// scripts come out right. Characters the first font in `fonts` has no glyph
+/// for are shaped with the first later font that has; line metrics come from
+/// the first font.
+pub fn layout_text(
+    fonts: &[&LoadedFont],
+    scale: PxScale,
+    text: &str,
+    style: &TextStyle,
+) -> Vec<PlacedGlyph> {
+    let scaled_font = fonts[0].font.as_scaled(scale);
+    let line_height = (scaled_font.height() + scaled_font.line_gap()) * style.line_spacing;
+
+    let lines: Vec<(Vec<PlacedGlyph>, f32)> = text
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let baseline = scaled_font.ascent() + i as f32 * line_height;
+            shape_line(fonts, scale, line.trim_end_matches('\r'), baseline)
+        })
+        .collect();
+
+    let max_width = lines.iter().map(|(_, w)| *w).fold(0.0, f32::max);
+    lines
+        .into_iter()
+        .flat_map(|(glyphs, width)| {
+            let shift = match style.align {
+                TextAlign::Left => 0.0,
+                TextAlign::Center => (max_width - width) / 2.0,
+                TextAlign::Right => max_width - width,
+            };
+            glyphs.into_iter().map(move |mut placed| {
+                placed.glyph.position.x += shift;
+                placed
+            })
+        })
+        .collect()
+}
+
+// Shape one line and return its glyphs along with the line's advance width.
+fn shape_line(
+    fonts: &[&LoadedFont],
+    scale: PxScale,
+    line: &str,
+    baseline: f32,
+) -> (Vec<PlacedGlyph>, f32) {
+    let mut glyphs = Vec::new();
+    let mut cursor_x: f32 = 0.0;
+    if line.is_empty() {
+        return (glyphs, cursor_x);
+    }
+
+    let bidi = ParagraphBidiInfo::new(line, None);
+    let (levels, runs) = bidi.visual_runs(0..line.len());
+    for run in runs {
+        let rtl = levels[run.start].is_rtl();
+        let mut segments = font_segments(fonts, &line[run.clone()]);
+        // Segments are in logical order; an RTL run is drawn from its end.
+        if rtl {
+            segments.reverse();
+        }
+        for (index, range) in segments {
+            let loaded = fonts[index];
+            let scaled_font = loaded.font.as_scaled(scale);
+            // rustybuzz positions are in font units; ab_glyph's scale factors map them to pixels.
+            let sx = scaled_font.h_scale_factor();
+            let sy = scaled_font.v_scale_factor();
+
+            let mut buffer = UnicodeBuffer::new();
+            buffer.push_str(&line[run.start + range.start..run.start + range.end]);
+            buffer.set_direction(if rtl {
+                Direction::RightToLeft
+            } else {
+                Direction::LeftToRight
+            });
+            buffer.guess_segment_properties();
+
+            let shaped = rustybuzz::shape(&loaded.face(), &[], buffer);
+            for (info, pos) in shaped.glyph_infos().iter().zip(shaped.glyph_positions()) {
+                glyphs.push(PlacedGlyph {
+                    font: index,
+                    glyph: GlyphId(info.glyph_id as u16).with_scale_and_position(
+                        scale,
+                        point(
+                            cursor_x + pos.x_offset as f32 * sx,
+                            baseline - pos.y_offset as f32 * sy,
+                        ),
+                    ),
+                });
+                cursor_x += pos.x_advance as f32 * sx;
+            }
+        }
+    }
+    (glyphs, cursor_x)
+}
+
+// Split `run` into byte ranges shaped with one font each. A word starts in the
+// first font that covers its first character and stays there while that font
+// covers the rest, so marks aren't split from their base; spaces join the
+// word before them. Characters no font covers use the first font.
+fn font_segments(fonts: &[&LoadedFont], run: &str) -> Vec<(usize, Range<usize>)> {
+    let mut segments: Vec<(usize, Range<usize>)> = Vec::new();
+    let mut in_word = false;
+    for (i, c) in run.char_indices() {
+        let end = i + c.len_utf8();
+        let current = segments.last().map(|(font, _)| *font);
+        let font = if c.is_whitespace() {
+            in_word = false;
+            current.unwrap_or(0)
+        } else if in_word && current.is_some_and(|f| fonts[f].covers(c)) {
+            current.unwrap_or(0)
+        } else {
+            in_word = true;
+            fonts.iter().position(|f| f.covers(c)).unwrap_or(0)
+        };
+        match segments.last_mut() {
+            Some((last, range)) if *last == font => range.end = end,
+            _ => segments.push((font, i..end)),
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placed(text: &str, style: &TextStyle) -> Vec<PlacedGlyph> {
+        let chain = fonts::chain(FontId::default()).unwrap();
+        layout_text(&chain, PxScale::from(40.0), text, style)
+    }
+
+    fn layout(text: &str, style: &TextStyle) -> Vec<Glyph> {
+        placed(text, style).into_iter().map(|p| p.glyph).collect()
+    }
+
+    #[test]
+    fn newline_starts_a_new_line() {
+        let glyphs = layout("A\nB", &TextStyle::default());
+        assert_eq!(glyphs.len(), 2);
+        assert!(glyphs[1].position.y > glyphs[0].position.y);
+    }
+
+    #[test]
+    fn line_spacing_scales_line_distance() {
+        let single = layout("A\nB", &TextStyle::default());
+        let double = layout("A\nB", &TextStyle::parse(None, Some(2.0), None).unwrap());
+        let gap = |g: &[Glyph]| g[1].position.y - g[0].position.y;
+        assert!((gap(&double) - 2.0 * gap(&single)).abs() < 0.01);
+    }
+
+    #[test]
+    fn alignment_shifts_short_lines() {
+        let left = layout(
+            "WWWW\nW",
+            &TextStyle::parse(Some("left"), None, None).unwrap(),
+        );
+        let right = layout(
+            "WWWW\nW",
+            &TextStyle::parse(Some("right"), None, None).unwrap(),
+        );
+        assert_eq!(left[4].position.x, 0.0);
+        assert!(right[4].position.x > left[3].position.x - 1.0);
+    }
+
+    #[test]
+    fn kerning_is_applied() {
+        let font = &fonts::get(FontId::default()).unwrap().font;
+        let scaled = font.as_scaled(PxScale::from(40.0));
+        let unkerned = scaled.h_advance(scaled.glyph_id('A'));
+        let glyphs = layout("AV", &TextStyle::parse(Some("left"), None, None).unwrap());
+        assert!(glyphs[1].position.x < unkerned, "AV pair should be kerned");
+    }
+
+    #[test]
+    fn rtl_paragraph_reorders_runs() {
+        // Hebrew first makes the paragraph RTL, so the Latin run is placed left of it.
+        let font = &fonts::get(FontId::default()).unwrap().font;
+        let glyphs = layout("\u{05e9} AB", &TextStyle::default());
+        assert_eq!(glyphs.first().map(|g| g.id), Some(font.glyph_id('A')));
+        assert_eq!(glyphs.get(1).map(|g| g.id), Some(font.glyph_id('B')));
+        assert!(glyphs
+            .windows(2)
+            .all(|w| w[0].position.x <= w[1].position.x));
+    }
+
+    #[test]
+    fn complex_scripts_shape_with_the_fallback_font() {
+        // Glyph 0 is .notdef, the box drawn for a missing character.
+        for text in [
+            "\u{0645}\u{0631}\u{062d}\u{0628}\u{0627}",
+            "\u{05e9}\u{05dc}\u{05d5}\u{05dd}",
+        ] {
+            let glyphs = placed(text, &TextStyle::default());
+            assert!(!glyphs.is_empty());
+            assert!(
+                glyphs.iter().all(|p| p.glyph.id.0 != 0),
+                "{} has .notdef glyphs",
+                text
+            );
+            assert!(glyphs.iter().all(|p| p.font == 1));
+        }
+        // Arabic letters join, so shaping picks contextual forms and not the
+        // isolated glyph of each character.
+        let fallback = fonts::chain(FontId::default()).unwrap()[1];
+        let isolated = fallback.face().glyph_index('\u{0645}').unwrap().0;
+        let glyphs = placed("\u{0645}\u{0645}", &TextStyle::default());
+        assert!(glyphs.iter().any(|p| p.glyph.id.0 != isolated));
+    }
+
+    #[test]
+    fn latin_stays_in_the_first_font_next_to_hebrew() {
+        let glyphs = placed("AB \u{05e9}\u{05dc}\u{05d5}\u{05dd}", &TextStyle::default());
+        assert_eq!(glyphs.iter().filter(|p| p.font == 0).count(), 3);
+        assert_eq!(glyphs.iter().filter(|p| p.font == 1).count(), 4);
+        assert!(glyphs
+            .windows(2)
+            .all(|w| w[0].glyph.position.x <= w[1].glyph.position.x));
+    }
+
+    #[test]
+    fn expand_template_fills_tile_values() {
+        let tile = TileContext::for_grid(3, 2, 4, "wall.png", "2026-10-18");
+        assert_eq!(
+            expand_template(
+                "Panel {row}-{col} ({index}/{total}) {filename} {date} {other}",
+                &tile
+            ),
+            "Panel 2-2 (4/4) wall.png 2026-10-18 {other}"
+        );
+        assert!(is_template("Panel {row}"));
+        assert!(!is_template("Panel {other}"));
+    }
+
+    #[test]
+    fn expand_template_leaves_placeholders_in_values_alone() {
+        let tile = TileContext::for_grid(0, 2, 4, "{date}-{row}.png", "2026-10-18");
+        assert_eq!(
+            expand_template("{filename} {date} {{row}}", &tile),
+            "{date}-{row}.png 2026-10-18 {1}"
+        );
+    }
+
+    #[test]
+    fn civil_from_days_matches_known_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(20_744), (2026, 10, 18));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        assert!(TextStyle::parse(Some("justify"), None, None).is_err());
+        assert!(TextStyle::parse(None, Some(-1.0), None).is_err());
+        assert!(TextStyle::parse(None, None, Some("no-such-font")).is_err());
+    }
+}
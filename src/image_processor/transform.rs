@@ -0,0 +1,405 @@
+use crate::image_processor::color::parse_color;
+use crate::image_processor::resize::{Rect, DEFAULT_BACKGROUND};
+use anyhow::{Error, Result};
+use image::{imageops, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use std::str::FromStr;
+
+/// Largest side a resized, rotated or padded image may have.
+pub const MAX_SIDE: u32 = 16384;
+/// Default per-channel tolerance when trimming borders.
+pub const DEFAULT_TRIM_THRESHOLD: u8 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlipDirection {
+    Horizontal,
+    Vertical,
+    Both,
+}
+
+impl FromStr for FlipDirection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "horizontal" | "h" | "x" => Ok(FlipDirection::Horizontal),
+            "vertical" | "v" | "y" => Ok(FlipDirection::Vertical),
+            "both" => Ok(FlipDirection::Both),
+            _ => Err(Error::msg(format!(
+                "Unknown flip direction: {} (use horizontal, vertical or both)",
+                s
+            ))),
+        }
+    }
+}
+
+/// A geometric operation on a whole image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Transform {
+    /// Clockwise rotation in degrees. Right angles are lossless; any other
+    /// angle grows the canvas to fit and fills the corners with `background`.
+    Rotate {
+        degrees: f32,
+        background: Rgba<u8>,
+    },
+    Flip(FlipDirection),
+    /// Keep only this rectangle, which must lie inside the image.
+    Crop(Rect),
+    Pad {
+        top: u32,
+        right: u32,
+        bottom: u32,
+        left: u32,
+        background: Rgba<u8>,
+    },
+    /// Remove borders matching the top-left pixel within `threshold` per channel.
+    Trim {
+        threshold: u8,
+    },
+}
+
+impl Transform {
+    pub fn rotate(degrees: f32, background: Option<&str>) -> Result<Transform> {
+        if !degrees.is_finite() {
+            return Err(Error::msg(format!("Invalid rotation angle: {}", degrees)));
+        }
+        Ok(Transform::Rotate {
+            degrees: degrees.rem_euclid(360.0),
+            background: background_from(background)?,
+        })
+    }
+
+    pub fn flip(direction: Option<&str>) -> Result<Transform> {
+        let direction = direction
+            .map(FlipDirection::from_str)
+            .transpose()?
+            .unwrap_or(FlipDirection::Horizontal);
+        Ok(Transform::Flip(direction))
+    }
+
+    pub fn crop(x: u32, y: u32, width: u32, height: u32) -> Result<Transform> {
+        if width == 0 || height == 0 {
+            return Err(Error::msg(
+                "Crop width and height must be greater than zero",
+            ));
+        }
+        Ok(Transform::Crop(Rect {
+            x,
+            y,
+            width,
+            height,
+        }))
+    }
+
+    /// `padding` applies to every side not given explicitly.
+    pub fn pad(
+        padding: Option<u32>,
+        [top, right, bottom, left]: [Option<u32>; 4],
+        background: Option<&str>,
+    ) -> Result<Transform> {
+        let all = padding.unwrap_or(0);
+        let (top, right, bottom, left) = (
+            top.unwrap_or(all),
+            right.unwrap_or(all),
+            bottom.unwrap_or(all),
+            left.unwrap_or(all),
+        );
+        if top.max(right).max(bottom).max(left) > MAX_SIDE {
+            return Err(Error::msg(format!("Padding must be at most {}", MAX_SIDE)));
+        }
+        Ok(Transform::Pad {
+            top,
+            right,
+            bottom,
+            left,
+            background: background_from(background)?,
+        })
+    }
+
+    pub fn trim(threshold: Option<u8>) -> Transform {
+        Transform::Trim {
+            threshold: threshold.unwrap_or(DEFAULT_TRIM_THRESHOLD),
+        }
+    }
+
+    pub fn apply(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        match *self {
+            Transform::Rotate {
+                degrees,
+                background,
+            } => rotate(img, degrees, background),
+            Transform::Flip(FlipDirection::Horizontal) => Ok(img.fliph()),
+            Transform::Flip(FlipDirection::Vertical) => Ok(img.flipv()),
+            Transform::Flip(FlipDirection::Both) => Ok(img.rotate180()),
+            Transform::Crop(rect) => crop(img, rect),
+            Transform::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                background,
+            } => {
+                let width = img.width() + left + right;
+                let height = img.height() + top + bottom;
+                check_canvas(width, height)?;
+                let mut canvas = RgbaImage::from_pixel(width, height, background);
+                imageops::overlay(&mut canvas, &img.to_rgba8(), left as i64, top as i64);
+                Ok(canvas.into())
+            }
+            Transform::Trim { threshold } => Ok(match trim_bounds(img, threshold) {
+                Some(rect) => img.crop_imm(rect.x, rect.y, rect.width, rect.height),
+                None => img.clone(),
+            }),
+        }
+    }
+}
+
+/// Short summary for logs, e.g. `rotate 90` or `crop 0,0,100,50`.
+impl std::fmt::Display for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transform::Rotate { degrees, .. } => write!(f, "rotate {}", degrees),
+            Transform::Flip(direction) => {
+                write!(f, "flip {}", format!("{:?}", direction).to_lowercase())
+            }
+            Transform::Crop(rect) => write!(f, "crop {}", rect),
+            Transform::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                ..
+            } => write!(f, "pad {},{},{},{}", top, right, bottom, left),
+            Transform::Trim { threshold } => write!(f, "trim {}", threshold),
+        }
+    }
+}
+
+fn background_from(background: Option<&str>) -> Result<Rgba<u8>> {
+    background
+        .map(parse_color)
+        .transpose()
+        .map(|bg| bg.unwrap_or(DEFAULT_BACKGROUND))
+}
+
+fn check_canvas(width: u32, height: u32) -> Result<()> {
+    if width > MAX_SIDE || height > MAX_SIDE {
+        return Err(Error::msg(format!(
+            "Output {}x{} exceeds the {}px limit",
+            width, height, MAX_SIDE
+        )));
+    }
+    Ok(())
+}
+
+fn crop(img: &DynamicImage, rect: Rect) -> Result<DynamicImage> {
+    let (w, h) = img.dimensions();
+    let fits = rect.x.checked_add(rect.width).is_some_and(|r| r <= w)
+        && rect.y.checked_add(rect.height).is_some_and(|b| b <= h);
+    if !fits {
+        return Err(Error::msg(format!(
+            "Crop rectangle {} lies outside the {}x{} image",
+            rect, w, h
+        )));
+    }
+    Ok(img.crop_imm(rect.x, rect.y, rect.width, rect.height))
+}
+
+fn rotate(img: &DynamicImage, degrees: f32, background: Rgba<u8>) -> Result<DynamicImage> {
+    if degrees == 0.0 {
+        return Ok(img.clone());
+    } else if degrees == 90.0 {
+        return Ok(img.rotate90());
+    } else if degrees == 180.0 {
+        return Ok(img.rotate180());
+    } else if degrees == 270.0 {
+        return Ok(img.rotate270());
+    }
+
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let theta = degrees.to_radians();
+    let (sin, cos) = (theta.sin().abs(), theta.cos().abs());
+    let out_w = (w * cos + h * sin).ceil() as u32;
+    let out_h = (w * sin + h * cos).ceil() as u32;
+    check_canvas(out_w, out_h)?;
+
+    // Map each output pixel back into the source and sample it bilinearly.
+    let src = img.to_rgba8();
+    let (in_cx, in_cy) = (w / 2.0, h / 2.0);
+    let (out_cx, out_cy) = (out_w as f32 / 2.0, out_h as f32 / 2.0);
+    let (sin, cos) = theta.sin_cos();
+    let out = RgbaImage::from_fn(out_w, out_h, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - out_cx, y as f32 + 0.5 - out_cy);
+        let sx = dx * cos + dy * sin + in_cx - 0.5;
+        let sy = -dx * sin + dy * cos + in_cy - 0.5;
+        sample_bilinear(&src, sx, sy, background)
+    });
+    Ok(out.into())
+}
+
+// Pixels outside the source read as `background`, so edges blend into it.
+fn sample_bilinear(src: &RgbaImage, x: f32, y: f32, background: Rgba<u8>) -> Rgba<u8> {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let at = |px: f32, py: f32| {
+        if px < 0.0 || py < 0.0 || px >= src.width() as f32 || py >= src.height() as f32 {
+            background
+        } else {
+            *src.get_pixel(px as u32, py as u32)
+        }
+    };
+    let (tl, tr) = (at(x0, y0), at(x0 + 1.0, y0));
+    let (bl, br) = (at(x0, y0 + 1.0), at(x0 + 1.0, y0 + 1.0));
+    let mut out = [0u8; 4];
+    for (c, v) in out.iter_mut().enumerate() {
+        let top = tl[c] as f32 * (1.0 - fx) + tr[c] as f32 * fx;
+        let bottom = bl[c] as f32 * (1.0 - fx) + br[c] as f32 * fx;
+        *v = (top * (1.0 - fy) + bottom * fy).round() as u8;
+    }
+    Rgba(out)
+}
+
+/// The smallest rectangle holding every pixel that differs from the top-left
+/// one by more than `threshold` in any channel, or `None` if the whole image
+/// is that colour.
+pub fn trim_bounds(img: &DynamicImage, threshold: u8) -> Option<Rect> {
+    let rgba = img.to_rgba8();
+    let reference = *rgba.get_pixel(0, 0);
+    let differs = |p: &Rgba<u8>| {
+        p.0.iter()
+            .zip(reference.0.iter())
+            .any(|(a, b)| a.abs_diff(*b) > threshold)
+    };
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, p) in rgba.enumerate_pixels() {
+        if differs(p) {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x == u32::MAX {
+        return None;
+    }
+    Some(Rect {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageBuffer;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    // White image with a red pixel in the top-left corner.
+    fn marked(w: u32, h: u32) -> DynamicImage {
+        ImageBuffer::from_fn(w, h, |x, y| if x == 0 && y == 0 { RED } else { WHITE }).into()
+    }
+
+    #[test]
+    fn right_angle_rotations_are_exact() {
+        let img = marked(4, 2);
+        let rotated = Transform::rotate(90.0, None).unwrap().apply(&img).unwrap();
+        assert_eq!(rotated.dimensions(), (2, 4));
+        assert_eq!(rotated.get_pixel(1, 0), RED);
+
+        let back = Transform::rotate(-270.0, None).unwrap();
+        assert_eq!(back, Transform::rotate(90.0, None).unwrap());
+    }
+
+    #[test]
+    fn display_is_a_short_summary() {
+        assert_eq!(
+            Transform::rotate(-90.0, None).unwrap().to_string(),
+            "rotate 270"
+        );
+        assert_eq!(
+            Transform::flip(Some("v")).unwrap().to_string(),
+            "flip vertical"
+        );
+        assert_eq!(
+            Transform::crop(1, 2, 3, 4).unwrap().to_string(),
+            "crop 1,2,3,4"
+        );
+        assert_eq!(
+            Transform::pad(Some(5), [Some(1), None, None, None], None)
+                .unwrap()
+                .to_string(),
+            "pad 1,5,5,5"
+        );
+    }
+
+    #[test]
+    fn arbitrary_rotation_grows_canvas_and_fills_corners() {
+        let img: DynamicImage = ImageBuffer::from_pixel(100, 100, WHITE).into();
+        let rotated = Transform::rotate(45.0, Some("000000"))
+            .unwrap()
+            .apply(&img)
+            .unwrap();
+        assert_eq!(rotated.dimensions(), (142, 142));
+        assert_eq!(rotated.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(rotated.get_pixel(71, 71), WHITE);
+    }
+
+    #[test]
+    fn flips_and_crop() {
+        let img = marked(4, 3);
+        let h = Transform::flip(Some("horizontal"))
+            .unwrap()
+            .apply(&img)
+            .unwrap();
+        assert_eq!(h.get_pixel(3, 0), RED);
+        let v = Transform::flip(Some("vertical"))
+            .unwrap()
+            .apply(&img)
+            .unwrap();
+        assert_eq!(v.get_pixel(0, 2), RED);
+        assert!(Transform::flip(Some("diagonal")).is_err());
+
+        let cropped = Transform::crop(0, 0, 2, 2).unwrap().apply(&img).unwrap();
+        assert_eq!(cropped.dimensions(), (2, 2));
+        assert!(Transform::crop(3, 0, 2, 2).unwrap().apply(&img).is_err());
+        assert!(Transform::crop(0, 0, 0, 2).is_err());
+    }
+
+    #[test]
+    fn pad_then_trim_round_trips() {
+        let img: DynamicImage = ImageBuffer::from_pixel(5, 3, RED).into();
+        let padded = Transform::pad(Some(4), [Some(1), None, None, None], Some("white"))
+            .unwrap()
+            .apply(&img)
+            .unwrap();
+        assert_eq!(padded.dimensions(), (13, 8));
+        assert_eq!(padded.get_pixel(4, 1), RED);
+        assert_eq!(padded.get_pixel(0, 0), WHITE);
+
+        assert_eq!(
+            trim_bounds(&padded, DEFAULT_TRIM_THRESHOLD),
+            Some(Rect {
+                x: 4,
+                y: 1,
+                width: 5,
+                height: 3
+            })
+        );
+        let trimmed = Transform::trim(None).apply(&padded).unwrap();
+        assert_eq!(trimmed.dimensions(), (5, 3));
+    }
+
+    #[test]
+    fn trimming_a_flat_image_keeps_it() {
+        let img: DynamicImage = ImageBuffer::from_pixel(6, 6, WHITE).into();
+        assert_eq!(trim_bounds(&img, 0), None);
+        assert_eq!(
+            Transform::trim(Some(0)).apply(&img).unwrap().dimensions(),
+            (6, 6)
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use crate::image_processor::image_slicer;
+use crate::image_processor::resize::{self, AspectRatio, Filter, ResizeParams};
+use crate::image_processor::transform::MAX_SIDE;
+use crate::image_processor::write_multipart_part;
+use anyhow::{Error, Result};
+use image::codecs::jpeg::{JpegEncoder, PixelDensity};
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::str::FromStr;
+
+pub const DEFAULT_WIDTHS: [u32; 4] = [320, 640, 1280, 1920];
+pub const DEFAULT_QUALITY: u8 = 80;
+/// Upper bound on widths x formats x images, so one request can't encode hundreds of files.
+const MAX_VARIANTS: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    Png,
+    Jpeg,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpeg",
+        }
+    }
+
+    pub fn encode(self, img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        let mut buf = Cursor::new(Vec::new());
+        match self {
+            Format::Png => img.write_to(&mut buf, ImageFormat::Png)?,
+            // JPEG has no alpha channel.
+            Format::Jpeg => JpegEncoder::new_with_quality(&mut buf, quality)
+                .encode_image(&DynamicImage::ImageRgb8(img.to_rgb8()))?,
+        }
+        Ok(buf.into_inner())
+    }
+
+    /// `encode` with the print resolution recorded in the file: a PNG `pHYs`
+    /// chunk or the JPEG JFIF density.
+    pub fn encode_with_dpi(self, img: &DynamicImage, quality: u8, dpi: u32) -> Result<Vec<u8>> {
+        let mut buf = Cursor::new(Vec::new());
+        match self {
+            Format::Png => {
+                let rgba = img.to_rgba8();
+                let mut encoder = png::Encoder::new(&mut buf, rgba.width(), rgba.height());
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                let per_metre = (dpi as f64 / 0.0254).round() as u32;
+                encoder.set_pixel_dims(Some(png::PixelDimensions {
+                    xppu: per_metre,
+                    yppu: per_metre,
+                    unit: png::Unit::Meter,
+                }));
+                encoder.write_header()?.write_image_data(&rgba)?;
+            }
+            Format::Jpeg => {
+                let mut encoder = JpegEncoder::new_with_quality(&mut buf, quality);
+                encoder.set_pixel_density(PixelDensity::dpi(dpi.min(u16::MAX as u32) as u16));
+                encoder.encode_image(&DynamicImage::ImageRgb8(img.to_rgb8()))?;
+            }
+        }
+        Ok(buf.into_inner())
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "png" => Ok(Format::Png),
+            "jpeg" | "jpg" => Ok(Format::Jpeg),
+            _ => Err(Error::msg(format!(
+                "Unsupported format: {} (use png or jpeg)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Output format and JPEG quality from request values: PNG and 80 unless given.
+pub fn parse_encoding(format: Option<&str>, quality: Option<u8>) -> Result<(Format, u8)> {
+    let format = match format {
+        Some(f) if !f.is_empty() => f.parse()?,
+        _ => Format::Png,
+    };
+    let quality = match quality {
+        None | Some(0) => DEFAULT_QUALITY,
+        Some(q) if q <= 100 => q,
+        Some(q) => return Err(Error::msg(format!("quality must be 1-100, got {}", q))),
+    };
+    Ok((format, quality))
+}
+
+/// Which variants to produce: every width in every format, for the whole
+/// image or for each of its four tiles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VariantSpec {
+    pub widths: Vec<u32>,
+    pub formats: Vec<Format>,
+    pub tiles: bool,
+    pub quality: u8,
+    pub filter: Filter,
+    pub allow_upscale: bool,
+}
+
+impl VariantSpec {
+    /// Empty `widths` or `formats` select the defaults (`DEFAULT_WIDTHS`, PNG).
+    /// Widths larger than the source are dropped unless `allow_upscale` is set.
+    pub fn parse(
+        widths: &[u32],
+        formats: &[&str],
+        tiles: bool,
+        quality: Option<u8>,
+        filter: Option<&str>,
+        allow_upscale: Option<bool>,
+    ) -> Result<VariantSpec> {
+        let mut widths = if widths.is_empty() {
+            DEFAULT_WIDTHS.to_vec()
+        } else {
+            widths.to_vec()
+        };
+        if widths.contains(&0) {
+            return Err(Error::msg("Variant widths must be positive"));
+        }
+        if widths.iter().any(|w| *w > MAX_SIDE) {
+            return Err(Error::msg(format!(
+                "Variant widths must be at most {}px",
+                MAX_SIDE
+            )));
+        }
+        widths.sort_unstable();
+        widths.dedup();
+
+        let mut formats = formats
+            .iter()
+            .filter(|f| !f.is_empty())
+            .map(|f| f.parse())
+            .collect::<Result<Vec<Format>>>()?;
+        if formats.is_empty() {
+            formats.push(Format::Png);
+        }
+        formats.sort_unstable();
+        formats.dedup();
+
+        let quality = match quality {
+            None | Some(0) => DEFAULT_QUALITY,
+            Some(q) if q <= 100 => q,
+            Some(q) => return Err(Error::msg(format!("JPEG quality must be 1-100, got {}", q))),
+        };
+        let filter = match filter {
+            Some(f) if !f.is_empty() => f.parse()?,
+            _ => Filter::default(),
+        };
+
+        let images = if tiles { 4 } else { 1 };
+        if widths.len() * formats.len() * images > MAX_VARIANTS {
+            return Err(Error::msg(format!(
+                "Too many variants: {} widths x {} formats x {} images, at most {}",
+                widths.len(),
+                formats.len(),
+                images,
+                MAX_VARIANTS
+            )));
+        }
+
+        Ok(VariantSpec {
+            widths,
+            formats,
+            tiles,
+            quality,
+            filter,
+            allow_upscale: allow_upscale.unwrap_or(false),
+        })
+    }
+}
+
+/// Parse a comma-separated width list such as `320,640,1280`.
+pub fn parse_widths(s: &str) -> Result<Vec<u32>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            w.parse()
+                .map_err(|_| Error::msg(format!("Invalid width: {}", w)))
+        })
+        .collect()
+}
+
+/// One encoded file.
+pub struct Variant {
+    pub name: String,
+    pub tile: Option<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: &'static str,
+    pub bytes: usize,
+}
+
+/// The variants of one image (or tile), with a ready-to-use `srcset` per format.
+#[derive(Clone, Debug, Serialize)]
+pub struct ManifestImage {
+    pub tile: Option<u32>,
+    pub srcset: BTreeMap<&'static str, String>,
+    pub variants: Vec<ManifestEntry>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Manifest {
+    pub images: Vec<ManifestImage>,
+}
+
+/// Produce every variant of `img`. Files are named `{stem}-{width}w.{ext}`, or
+/// `{stem}-{tile}-{width}w.{ext}` per tile, and `base_url` is prefixed to them
+/// in the `srcset` strings.
+pub fn generate(
+    img: DynamicImage,
+    spec: &VariantSpec,
+    stem: &str,
+    base_url: &str,
+) -> Result<(Vec<Variant>, Manifest)> {
+    let sources: Vec<(Option<u32>, DynamicImage)> = if spec.tiles {
+        let size = image_slicer::get_single_image_dimensions(&img);
+        image_slicer::slice_images_view(img, &size)
+            .into_iter()
+            .enumerate()
+            .map(|(i, tile)| (Some(i as u32), DynamicImage::ImageRgba8(tile)))
+            .collect()
+    } else {
+        vec![(None, img)]
+    };
+
+    let mut variants = Vec::new();
+    let mut images = Vec::new();
+    for (tile, source) in sources {
+        let mut entry = ManifestImage {
+            tile,
+            srcset: BTreeMap::new(),
+            variants: Vec::new(),
+        };
+        for width in widths_for(spec, source.width()) {
+            let params = ResizeParams {
+                filter: spec.filter,
+                allow_upscale: spec.allow_upscale,
+                ..ResizeParams::new(Some(width), None, AspectRatio::Preserve)
+            };
+            let resized = resize::resize_image(&source, &params)?;
+            for &format in &spec.formats {
+                let name = match tile {
+                    Some(t) => format!("{}-{}-{}w.{}", stem, t, width, format.extension()),
+                    None => format!("{}-{}w.{}", stem, width, format.extension()),
+                };
+                let data = format.encode(&resized, spec.quality)?;
+                let srcset = entry.srcset.entry(format.name()).or_default();
+                if !srcset.is_empty() {
+                    srcset.push_str(", ");
+                }
+                srcset.push_str(&format!("{}{} {}w", base_url, name, resized.width()));
+                entry.variants.push(ManifestEntry {
+                    file: name.clone(),
+                    width: resized.width(),
+                    height: resized.height(),
+                    format: format.name(),
+                    bytes: data.len(),
+                });
+                variants.push(Variant {
+                    name,
+                    tile,
+                    width: resized.width(),
+                    height: resized.height(),
+                    format,
+                    data,
+                });
+            }
+        }
+        images.push(entry);
+    }
+    Ok((variants, Manifest { images }))
+}
+
+// Widths wider than the source are left out without upscaling; if that leaves
+// nothing, the source width itself is used.
+fn widths_for(spec: &VariantSpec, source_width: u32) -> Vec<u32> {
+    if spec.allow_upscale {
+        return spec.widths.clone();
+    }
+    let widths: Vec<u32> = spec
+        .widths
+        .iter()
+        .copied()
+        .filter(|w| *w <= source_width)
+        .collect();
+    if widths.is_empty() {
+        vec![source_width]
+    } else {
+        widths
+    }
+}
+
+/// Encode the manifest and files as a `multipart/mixed` body: the JSON
+/// manifest first, then one part per file with its name in
+/// `Content-Disposition`.
+pub fn multipart_body(
+    variants: &[Variant],
+    manifest: &Manifest,
+    boundary: &str,
+) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    let manifest = serde_json::to_vec(manifest)?;
+    write_multipart_part(
+        &mut body,
+        boundary,
+        "application/json",
+        "manifest.json",
+        &manifest,
+    );
+    for variant in variants {
+        write_multipart_part(
+            &mut body,
+            boundary,
+            variant.format.mime(),
+            &variant.name,
+            &variant.data,
+        );
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    Ok(body)
+}
+
+/// File stem for variant names: the source's file name without its
+/// extension, or `image`.
+pub fn stem(file_name: Option<&str>) -> String {
+    file_name
+        .map(|n| n.rsplit_once('.').map_or(n, |(stem, _)| stem))
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| "image".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, ImageBuffer, Rgba};
+
+    fn test_image(w: u32, h: u32) -> DynamicImage {
+        ImageBuffer::from_fn(w, h, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255])
+        })
+        .into()
+    }
+
+    #[test]
+    fn generates_every_width_and_format_with_srcset() {
+        let spec =
+            VariantSpec::parse(&[100, 50], &["png", "jpg"], false, None, None, None).unwrap();
+        let (variants, manifest) = generate(test_image(200, 100), &spec, "photo", "/img/").unwrap();
+
+        assert_eq!(variants.len(), 4);
+        assert_eq!(variants[0].name, "photo-50w.png");
+        assert_eq!((variants[0].width, variants[0].height), (50, 25));
+        let jpeg = image::load_from_memory(&variants[1].data).unwrap();
+        assert_eq!(jpeg.dimensions(), (50, 25));
+
+        let image = &manifest.images[0];
+        assert_eq!(
+            image.srcset["png"],
+            "/img/photo-50w.png 50w, /img/photo-100w.png 100w"
+        );
+        assert_eq!(
+            image.srcset["jpeg"],
+            "/img/photo-50w.jpg 50w, /img/photo-100w.jpg 100w"
+        );
+    }
+
+    #[test]
+    fn dpi_is_recorded_in_png_and_jpeg() {
+        let png = Format::Png
+            .encode_with_dpi(&test_image(8, 8), DEFAULT_QUALITY, 300)
+            .unwrap();
+        let reader = png::Decoder::new(Cursor::new(png)).read_info().unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        assert_eq!((dims.xppu, dims.unit), (11811, png::Unit::Meter));
+
+        let jpeg = Format::Jpeg
+            .encode_with_dpi(&test_image(8, 8), DEFAULT_QUALITY, 300)
+            .unwrap();
+        let jfif = jpeg.windows(5).position(|w| w == b"JFIF\0").unwrap();
+        // Version, then units (1 = dots per inch) and the X/Y density.
+        assert_eq!(&jpeg[jfif + 7..jfif + 12], &[1, 1, 44, 1, 44]);
+    }
+
+    #[test]
+    fn tiles_get_their_own_variants() {
+        let spec = VariantSpec::parse(&[40], &[], true, None, None, None).unwrap();
+        let (variants, manifest) = generate(test_image(200, 100), &spec, "wall", "").unwrap();
+        assert_eq!(variants.len(), 4);
+        assert_eq!(variants[3].name, "wall-3-40w.png");
+        assert_eq!((variants[3].width, variants[3].height), (40, 20));
+        assert_eq!(manifest.images.len(), 4);
+        assert_eq!(manifest.images[2].tile, Some(2));
+    }
+
+    #[test]
+    fn wider_than_source_is_skipped_without_upscaling() {
+        let spec = VariantSpec::parse(&[], &[], false, None, None, None).unwrap();
+        let (variants, _) = generate(test_image(500, 100), &spec, "x", "").unwrap();
+        let widths: Vec<u32> = variants.iter().map(|v| v.width).collect();
+        assert_eq!(widths, vec![320]);
+
+        let (variants, _) = generate(test_image(100, 100), &spec, "x", "").unwrap();
+        assert_eq!(variants[0].width, 100);
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        assert!(VariantSpec::parse(&[0], &[], false, None, None, None).is_err());
+        assert!(VariantSpec::parse(&[MAX_SIDE + 1], &[], false, None, None, None).is_err());
+        assert!(VariantSpec::parse(&[], &["webp"], false, None, None, None).is_err());
+        assert!(VariantSpec::parse(&[], &[], false, Some(101), None, None).is_err());
+        let many: Vec<u32> = (1..=20).collect();
+        assert!(VariantSpec::parse(&many, &["png", "jpeg"], true, None, None, None).is_err());
+        assert!(parse_widths("320, 640,x").is_err());
+        assert_eq!(parse_widths("320, 640,").unwrap(), vec![320, 640]);
+    }
+
+    #[test]
+    fn stem_strips_extension_and_odd_characters() {
+        assert_eq!(stem(Some("my photo.final.jpg")), "my_photo_final");
+        assert_eq!(stem(None), "image");
+        assert_eq!(stem(Some(".png")), "image");
+    }
+}
@@ -1,41 +1,257 @@
-use ab_glyph::{point, Font, FontRef, PxScale, ScaleFont};
+use crate::image_processor::blend::{self, Blend};
+use crate::image_processor::fonts::{self, LoadedFont};
+use crate::image_processor::render_cache::{self, TextKey};
+use crate::image_processor::text::{self, TextStyle, TileContext};
+use ab_glyph::{Font, PxScale};
+use anyhow::{Error, Result};
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba};
+use std::str::FromStr;
+use std::sync::Arc;
 
 pub type Watermark = DynamicImage;
 
-pub fn create_watermark(text: &str, size: (u32, u32)) -> Watermark {
-    let font_data = include_bytes!("../../resources/OpenSans-Regular.ttf");
-    let font = FontRef::try_from_slice(font_data).unwrap();
+// Text is rasterised at this pixel size, then stretched to the target box.
+const RENDER_PX: u32 = 40;
+
+/// Anchor of the watermark inside the target image.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Position {
+    #[default]
+    Center,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl FromStr for Position {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "center" | "centre" => Ok(Position::Center),
+            "top-left" => Ok(Position::TopLeft),
+            "top-right" => Ok(Position::TopRight),
+            "bottom-left" => Ok(Position::BottomLeft),
+            "bottom-right" => Ok(Position::BottomRight),
+            _ => Err(Error::msg(format!("Unknown watermark position: {}", s))),
+        }
+    }
+}
+
+/// What a sliced watermark is measured against: each tile, or the whole
+/// source before slicing so one mark spans the reassembled wall.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Scope {
+    #[default]
+    Tile,
+    Image,
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "tile" => Ok(Scope::Tile),
+            "image" => Ok(Scope::Image),
+            _ => Err(Error::msg(format!("Unknown watermark scope: {}", s))),
+        }
+    }
+}
+
+/// Where a watermark goes and how much of the target it may cover.
+/// `scale` is a percentage (1-100) of the target width and height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Placement {
+    pub position: Position,
+    pub scale: u32,
+    pub scope: Scope,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Placement {
+            position: Position::Center,
+            scale: 100,
+            scope: Scope::Tile,
+        }
+    }
+}
+
+impl Placement {
+    pub fn parse(
+        position: Option<&str>,
+        scale: Option<u32>,
+        scope: Option<&str>,
+    ) -> Result<Placement> {
+        let position = match position {
+            Some(p) if !p.is_empty() => p.parse()?,
+            _ => Position::Center,
+        };
+        let scale = match scale {
+            None | Some(0) => 100,
+            Some(s) if s <= 100 => s,
+            Some(s) => {
+                return Err(Error::msg(format!(
+                    "Watermark scale must be 1-100, got {}",
+                    s
+                )))
+            }
+        };
+        let scope = match scope {
+            Some(s) if !s.is_empty() => s.parse()?,
+            _ => Scope::Tile,
+        };
+        Ok(Placement {
+            position,
+            scale,
+            scope,
+        })
+    }
+
+    /// Box the watermark is rendered into for a target of `size`.
+    pub fn target_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let (w, h) = size;
+        ((w * self.scale / 100).max(1), (h * self.scale / 100).max(1))
+    }
+
+    fn offset(&self, target: (u32, u32), watermark: (u32, u32)) -> (u32, u32) {
+        let free_x = target.0.saturating_sub(watermark.0);
+        let free_y = target.1.saturating_sub(watermark.1);
+        match self.position {
+            Position::Center => (free_x / 2, free_y / 2),
+            Position::TopLeft => (0, 0),
+            Position::TopRight => (free_x, 0),
+            Position::BottomLeft => (0, free_y),
+            Position::BottomRight => (free_x, free_y),
+        }
+    }
+}
+
+/// What gets stamped onto the image.
+pub enum WatermarkContent {
+    Text(String, TextStyle),
+    Logo(DynamicImage),
+}
+
+/// A fully described watermark: content plus the controls shared by text and logos.
+pub struct WatermarkSpec {
+    pub content: WatermarkContent,
+    pub transparency: u16,
+    pub placement: Placement,
+    pub blend: Blend,
+}
+
+impl WatermarkSpec {
+    /// Render the watermark for a target image of `size`. Text renders are
+    /// shared through the process-wide cache.
+    pub fn render(&self, size: (u32, u32)) -> Result<Arc<Watermark>> {
+        let target = self.placement.target_size(size);
+        match &self.content {
+            WatermarkContent::Text(text, style) => cached_text_watermark(text, target, style),
+            WatermarkContent::Logo(logo) => Ok(Arc::new(fit_watermark(logo, target))),
+        }
+    }
+
+    /// Render the watermark for one tile, expanding text placeholders first.
+    pub fn render_for_tile(&self, size: (u32, u32), tile: &TileContext) -> Result<Arc<Watermark>> {
+        match &self.content {
+            WatermarkContent::Text(text, style) => cached_text_watermark(
+                &text::expand_template(text, tile),
+                self.placement.target_size(size),
+                style,
+            ),
+            WatermarkContent::Logo(_) => self.render(size),
+        }
+    }
+
+    /// Composite an already rendered watermark onto `img`.
+    pub fn apply(
+        &self,
+        mut img: ImageBuffer<Rgba<u8>, Vec<u8>>,
+        watermark: &Watermark,
+    ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        self.apply_in_place(&mut img, watermark);
+        img
+    }
+
+    /// Same as `apply`, without taking ownership of the image.
+    pub fn apply_in_place(&self, img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, watermark: &Watermark) {
+        let alpha = self.transparency.min(100) as f32 / 100.0;
+        composite_at(img, watermark, alpha, self.placement, self.blend);
+    }
+}
 
-    let scale = PxScale::from(40.0);
+/// Resolve the watermark `transparency` (0 = opaque) from either `opacity`
+/// (100 = opaque) or `transparency`, defaulting to 30. Giving both is an error.
+pub fn transparency_from(opacity: Option<u16>, transparency: Option<u16>) -> Result<u16> {
+    match (opacity, transparency) {
+        (Some(_), Some(_)) => Err(Error::msg(
+            "Provide either opacity or transparency, not both",
+        )),
+        (Some(o), None) => Ok(100 - o.min(100)),
+        (None, t) => Ok(t.unwrap_or(30).min(100)),
+    }
+}
+
+#[allow(dead_code)]
+pub fn create_watermark(text: &str, size: (u32, u32)) -> Result<Watermark> {
+    create_watermark_with_style(text, size, &TextStyle::default())
+}
+
+/// Render multi-line `text` laid out with `style`, stretched to `size`.
+pub fn create_watermark_with_style(
+    text: &str,
+    size: (u32, u32),
+    style: &TextStyle,
+) -> Result<Watermark> {
+    let fonts = fonts::chain(style.font)?;
+    let scale = PxScale::from(RENDER_PX as f32);
     let (width, height) = size;
-    DynamicImage::ImageRgba8(render_text_to_image(&font, scale, text)).resize_exact(
-        width,
-        height,
-        image::imageops::FilterType::Lanczos3,
+    Ok(
+        DynamicImage::ImageRgba8(render_text_to_image(&fonts, scale, text, style)).resize_exact(
+            width,
+            height,
+            image::imageops::FilterType::Lanczos3,
+        ),
     )
 }
 
-fn render_text_to_image(
-    font: &FontRef,
-    scale: PxScale,
+/// `create_watermark_with_style` through the render cache.
+pub fn cached_text_watermark(
     text: &str,
-) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
-    let scaled_font = font.as_scaled(scale);
+    size: (u32, u32),
+    style: &TextStyle,
+) -> Result<Arc<Watermark>> {
+    render_cache::get_or_render(TextKey::new(text, RENDER_PX, style, size), || {
+        create_watermark_with_style(text, size, style)
+    })
+}
 
-    let baseline_y = scaled_font.ascent();
+/// Render `text` scaled to fit inside `size`, keeping its aspect ratio.
+pub fn fit_text(text: &str, size: (u32, u32), style: &TextStyle) -> Result<Watermark> {
+    let fonts = fonts::chain(style.font)?;
+    let rendered = render_text_to_image(&fonts, PxScale::from(RENDER_PX as f32), text, style);
+    Ok(fit_watermark(&DynamicImage::ImageRgba8(rendered), size))
+}
 
-    // Collect positioned glyphs for all characters.
-    let mut glyphs: Vec<ab_glyph::Glyph> = Vec::new();
-    let mut cursor_x: f32 = 0.0;
+/// Scale a logo to fit inside `size`, keeping its aspect ratio.
+pub fn fit_watermark(logo: &DynamicImage, size: (u32, u32)) -> Watermark {
+    let (width, height) = size;
+    let ratio = (width as f64 / logo.width() as f64).min(height as f64 / logo.height() as f64);
+    let fit_w = ((logo.width() as f64 * ratio).round() as u32).max(1);
+    let fit_h = ((logo.height() as f64 * ratio).round() as u32).max(1);
+    blend::resize_premultiplied(logo, fit_w, fit_h, image::imageops::FilterType::Lanczos3)
+}
 
-    for c in text.chars() {
-        let mut glyph = scaled_font.scaled_glyph(c);
-        let h_advance = scaled_font.h_advance(glyph.id);
-        glyph.position = point(cursor_x, baseline_y);
-        glyphs.push(glyph);
-        cursor_x += h_advance;
-    }
+fn render_text_to_image(
+    fonts: &[&LoadedFont],
+    scale: PxScale,
+    text: &str,
+    style: &TextStyle,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let glyphs = text::layout_text(fonts, scale, text, style);
 
     if glyphs.is_empty() {
         return ImageBuffer::new(1, 1);
@@ -48,8 +264,8 @@ fn render_text_to_image(
     let mut max_x = f32::NEG_INFINITY;
     let mut max_y = f32::NEG_INFINITY;
 
-    for glyph in &glyphs {
-        if let Some(outlined) = font.outline_glyph(glyph.clone()) {
+    for placed in &glyphs {
+        if let Some(outlined) = fonts[placed.font].font.outline_glyph(placed.glyph.clone()) {
             let bounds = outlined.px_bounds();
             min_x = min_x.min(bounds.min.x);
             min_y = min_y.min(bounds.min.y);
@@ -59,16 +275,18 @@ fn render_text_to_image(
     }
 
     if !min_x.is_finite() {
-        return ImageBuffer::new(cursor_x.ceil().max(1.0) as u32, 1);
+        return ImageBuffer::new(1, 1);
     }
 
     let img_width = ((max_x - min_x).ceil() as u32).max(1);
     let img_height = ((max_y - min_y).ceil() as u32).max(1);
-    let mut image = ImageBuffer::new(img_width, img_height);
+    // White everywhere, coverage in alpha only: resizing then can't pull dark
+    // colour into the glyph edges.
+    let mut image = ImageBuffer::from_pixel(img_width, img_height, Rgba([255, 255, 255, 0]));
 
     // Draw each glyph
-    for glyph in glyphs {
-        if let Some(outlined) = font.outline_glyph(glyph) {
+    for placed in glyphs {
+        if let Some(outlined) = fonts[placed.font].font.outline_glyph(placed.glyph) {
             let bounds = outlined.px_bounds();
             let draw_x = bounds.min.x - min_x;
             let draw_y = bounds.min.y - min_y;
@@ -78,7 +296,8 @@ fn render_text_to_image(
                 let y = (draw_y + py as f32) as u32;
                 if x < img_width && y < img_height {
                     let v = (coverage * 255.0) as u8;
-                    image.put_pixel(x, y, Rgba([v, v, v, v]));
+                    let px = image.get_pixel_mut(x, y);
+                    px[3] = px[3].max(v);
                 }
             });
         }
@@ -87,35 +306,57 @@ fn render_text_to_image(
     image
 }
 
+#[allow(dead_code)]
 pub fn add_watermark(
+    img: ImageBuffer<Rgba<u8>, Vec<u8>>,
+    watermark: &Watermark,
+    alpha: f32,
+) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    add_watermark_at(
+        img,
+        watermark,
+        alpha,
+        Placement::default(),
+        Blend::default(),
+    )
+}
+
+/// Composite `watermark` onto `img`. `alpha` is the watermark transparency:
+/// 0 is fully opaque, 1 leaves the image untouched.
+pub fn add_watermark_at(
     mut img: ImageBuffer<Rgba<u8>, Vec<u8>>,
     watermark: &Watermark,
     alpha: f32,
+    placement: Placement,
+    blend: Blend,
 ) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    composite_at(&mut img, watermark, alpha, placement, blend);
+    img
+}
+
+/// In-place form of `add_watermark_at`.
+pub fn composite_at(
+    img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    watermark: &Watermark,
+    alpha: f32,
+    placement: Placement,
+    blend: Blend,
+) {
     let alpha = alpha.clamp(0.0, 1.0);
     let (w, h) = (watermark.width(), watermark.height());
     let (iw, ih) = (img.width(), img.height());
-    let x = (iw.saturating_sub(w)) / 2;
-    let y = (ih.saturating_sub(h)) / 2;
+    let (x, y) = placement.offset((iw, ih), (w, h));
     let effective_w = w.min(iw.saturating_sub(x));
     let effective_h = h.min(ih.saturating_sub(y));
 
     for i in 0..effective_w {
         for j in 0..effective_h {
             let wm_pixel = watermark.get_pixel(i, j);
-            let wm_alpha = (wm_pixel[3] as f32 / 255.0) * (1.0 - alpha);
             let image = *img.get_pixel(x.saturating_add(i), y.saturating_add(j));
-            let inv_a = 1.0 - wm_alpha;
-            let px = Rgba([
-                (wm_alpha * wm_pixel[0] as f32 + inv_a * image[0] as f32) as u8,
-                (wm_alpha * wm_pixel[1] as f32 + inv_a * image[1] as f32) as u8,
-                (wm_alpha * wm_pixel[2] as f32 + inv_a * image[2] as f32) as u8,
-                image[3],
-            ]);
+            let px = blend.composite(image, wm_pixel, 1.0 - alpha);
             img.put_pixel(x.saturating_add(i), y.saturating_add(j), px);
         }
     }
-    img
 }
 
 // ---------------------------------------------------------------------------
@@ -125,6 +366,7 @@ pub fn add_watermark(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ab_glyph::{FontRef, ScaleFont};
     use base64::Engine;
     use image::{ImageBuffer, Rgba};
 
@@ -148,7 +390,7 @@ mod tests {
 
     #[test]
     fn create_watermark_returns_valid_image() {
-        let wm = create_watermark("IZDU", (100, 40));
+        let wm = create_watermark("IZDU", (100, 40)).unwrap();
         assert!(wm.width() > 0, "watermark width must be > 0");
         assert!(wm.height() > 0, "watermark height must be > 0");
         assert_eq!(wm.width(), 100);
@@ -157,17 +399,42 @@ mod tests {
 
     #[test]
     fn create_watermark_contains_rendered_pixels() {
-        let wm = create_watermark("X", (50, 20));
+        let wm = create_watermark("X", (50, 20)).unwrap();
         let rgba = wm.to_rgba8();
-        // At least one pixel should be non-zero (text was rendered)
-        let has_nonzero = rgba.pixels().any(|p| p[0] > 0 || p[1] > 0 || p[2] > 0);
-        assert!(has_nonzero, "watermark should contain rendered text pixels");
+        // Glyph coverage is in alpha; the colour is white everywhere.
+        let has_coverage = rgba.pixels().any(|p| p[3] > 0);
+        assert!(
+            has_coverage,
+            "watermark should contain rendered text pixels"
+        );
+        assert!(
+            rgba.pixels().any(|p| p[3] == 0),
+            "watermark should leave the background transparent"
+        );
+    }
+
+    #[test]
+    fn rendered_text_is_white_with_coverage_in_alpha() {
+        let loaded = fonts::get(fonts::FontId::default()).unwrap();
+        let image =
+            render_text_to_image(&[loaded], PxScale::from(40.0), "X", &TextStyle::default());
+        assert!(image
+            .pixels()
+            .all(|p| p[0] == 255 && p[1] == 255 && p[2] == 255));
+        assert!(
+            image.pixels().any(|p| p[3] == 255),
+            "glyph interior is opaque"
+        );
+        assert!(
+            image.pixels().any(|p| p[3] > 0 && p[3] < 255),
+            "glyph edges are antialiased in alpha, not darkened"
+        );
     }
 
     #[test]
     fn create_watermark_different_texts_produce_different_images() {
-        let wm_a = create_watermark("AAA", (80, 30));
-        let wm_b = create_watermark("BBBBB", (80, 30));
+        let wm_a = create_watermark("AAA", (80, 30)).unwrap();
+        let wm_b = create_watermark("BBBBB", (80, 30)).unwrap();
         assert_ne!(
             wm_a.to_rgba8().as_raw(),
             wm_b.to_rgba8().as_raw(),
@@ -177,13 +444,12 @@ mod tests {
 
     #[test]
     fn render_text_to_image_advances_glyphs_horizontally() {
-        let font_data = include_bytes!("../../resources/OpenSans-Regular.ttf");
-        let font = FontRef::try_from_slice(font_data).unwrap();
+        let loaded = fonts::get(fonts::FontId::default()).unwrap();
         let scale = PxScale::from(40.0);
-        let scaled_font = font.as_scaled(scale);
+        let scaled_font = loaded.font.as_scaled(scale);
         let h_advance = scaled_font.h_advance(scaled_font.glyph_id('H'));
 
-        let image = render_text_to_image(&font, scale, "HH");
+        let image = render_text_to_image(&[loaded], scale, "HH", &TextStyle::default());
         let has_second_glyph_pixels = image
             .enumerate_pixels()
             .any(|(x, _, p)| x as f32 >= h_advance && p[3] > 0);
@@ -199,6 +465,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn create_watermark_multiline_is_taller_than_single_line() {
+        let loaded = fonts::get(fonts::FontId::default()).unwrap();
+        let scale = PxScale::from(40.0);
+        let style = TextStyle::default();
+
+        let one = render_text_to_image(&[loaded], scale, "\u{a9} 2026 Brand", &style);
+        let two = render_text_to_image(
+            &[loaded],
+            scale,
+            "\u{a9} 2026 Brand\nAll rights reserved",
+            &style,
+        );
+        assert!(two.height() > one.height() * 3 / 2);
+    }
+
     // ------------------------------------------------------------------
     // add_watermark tests
     // ------------------------------------------------------------------
@@ -206,7 +488,7 @@ mod tests {
     #[test]
     fn add_watermark_alpha_zero_is_opaque() {
         let img = small_blue_img(); // solid blue [0, 0, 255, 255]
-        let wm = create_watermark("X", (img.width(), img.height()));
+        let wm = create_watermark("X", (img.width(), img.height())).unwrap();
 
         let result = add_watermark(img.clone(), &wm, 0.0); // alpha=0 → opaque
 
@@ -222,7 +504,7 @@ mod tests {
     #[test]
     fn add_watermark_alpha_one_is_invisible() {
         let img = small_blue_img(); // solid blue [0, 0, 255, 255]
-        let wm = create_watermark("X", (img.width(), img.height()));
+        let wm = create_watermark("X", (img.width(), img.height())).unwrap();
 
         let result = add_watermark(img.clone(), &wm, 1.0); // alpha=1 → invisible
 
@@ -238,7 +520,7 @@ mod tests {
     #[test]
     fn add_watermark_alpha_50_percent_blends() {
         let img = tiny_red_img(); // 1x1 red [255, 0, 0, 255]
-        let wm = create_watermark("A", (1, 1));
+        let wm = create_watermark("A", (1, 1)).unwrap();
 
         let result = add_watermark(img.clone(), &wm, 0.5); // 50% transparency
 
@@ -254,7 +536,7 @@ mod tests {
     #[test]
     fn add_watermark_preserves_image_dimensions() {
         let img = small_blue_img(); // 4x4
-        let wm = create_watermark("X", (2, 2));
+        let wm = create_watermark("X", (2, 2)).unwrap();
 
         let result = add_watermark(img, &wm, 0.3);
 
@@ -265,7 +547,7 @@ mod tests {
     #[test]
     fn add_watermark_watermark_centered() {
         let img = small_blue_img(); // 4x4
-        let wm = create_watermark("TEST", (2, 2));
+        let wm = create_watermark("TEST", (2, 2)).unwrap();
 
         let result = add_watermark(img, &wm, 0.0);
 
@@ -280,6 +562,57 @@ mod tests {
         );
     }
 
+    // ------------------------------------------------------------------
+    // Logo / placement tests
+    // ------------------------------------------------------------------
+
+    #[test]
+    fn fit_watermark_keeps_aspect_ratio() {
+        let logo =
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(40, 20, Rgba([255, 255, 255, 255])));
+        let wm = fit_watermark(&logo, (10, 10));
+        assert_eq!((wm.width(), wm.height()), (10, 5));
+    }
+
+    #[test]
+    fn placement_parse_rejects_bad_values() {
+        assert!(Placement::parse(Some("middle"), None, None).is_err());
+        assert!(Placement::parse(None, Some(150), None).is_err());
+        assert!(Placement::parse(None, None, Some("wall")).is_err());
+        let p = Placement::parse(Some("bottom-right"), Some(25), Some("image")).unwrap();
+        assert_eq!(p.scope, Scope::Image);
+        assert_eq!(p.position, Position::BottomRight);
+        assert_eq!(p.target_size((100, 40)), (25, 10));
+    }
+
+    #[test]
+    fn add_watermark_at_bottom_right_leaves_top_left_untouched() {
+        let img = small_blue_img(); // 4x4
+        let logo =
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(2, 2, Rgba([255, 255, 255, 255])));
+        let placement = Placement::parse(Some("bottom-right"), None, None).unwrap();
+
+        let result = add_watermark_at(img, &logo, 0.0, placement, Blend::default());
+
+        assert_eq!(*result.get_pixel(0, 0), Rgba([0, 0, 255, 255]));
+        assert_eq!(*result.get_pixel(3, 3), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn text_render_is_served_from_cache() {
+        let spec = WatermarkSpec {
+            content: WatermarkContent::Text("cache me".to_string(), TextStyle::default()),
+            transparency: 30,
+            placement: Placement::default(),
+            blend: Blend::default(),
+        };
+        let first = spec.render((120, 40)).unwrap();
+        let second = spec.render((120, 40)).unwrap();
+        let other_size = spec.render((121, 40)).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!Arc::ptr_eq(&first, &other_size));
+    }
+
     // ------------------------------------------------------------------
     // Font loading smoke test
     // ------------------------------------------------------------------
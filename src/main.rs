@@ -1,34 +1,194 @@
 #[cfg(test)]
+// The baseline request helpers pass `&mut app` to `call_service`.
+#[allow(clippy::unnecessary_mut_passed)]
 mod tests;
 
 mod grpc;
 mod image_processor;
 
-use crate::image_processor::get_source;
-use actix_web::{error, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use crate::image_processor::adjust::{AdjustArgs, Adjustments, TileAdjustments};
+use crate::image_processor::atlas::{AtlasArgs, AtlasSpec};
+use crate::image_processor::blend::Blend;
+use crate::image_processor::carousel::{CarouselArgs, CarouselSpec};
+use crate::image_processor::contact_sheet::{SheetArgs, SheetSpec};
+use crate::image_processor::effects::{EffectArgs, Effects};
+use crate::image_processor::image_slicer::{Remainder, TileScale, GRID_COLS};
+use crate::image_processor::invisible;
+use crate::image_processor::pdf::{self, PdfArgs, PdfSpec};
+use crate::image_processor::pipeline::{Output, Pipeline, SliceOps};
+use crate::image_processor::poster::{PosterArgs, PosterSpec};
+use crate::image_processor::presets::{self, Preset};
+use crate::image_processor::qr::QrSpec;
+use crate::image_processor::resize::{ResizeArgs, ResizeParams, Resized};
+use crate::image_processor::stitch::{StitchArgs, StitchSpec};
+use crate::image_processor::text::{self, TextStyle, TileContext};
+use crate::image_processor::transform::Transform;
+use crate::image_processor::variants::VariantSpec;
+use crate::image_processor::watermark::{
+    transparency_from, Placement, WatermarkContent, WatermarkSpec,
+};
+use crate::image_processor::{get_source, get_source_with_logo, ImageSource, LogoSource};
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{error, get, post, web, App, FromRequest, HttpRequest, HttpResponse, HttpServer};
+use futures::future::{ready, Ready};
 use futures::stream::unfold;
-use image::ImageFormat;
-use serde::Deserialize;
+use image::{DynamicImage, ImageFormat};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::io::{BufWriter, Cursor};
+use std::ops::Deref;
+
+/// Set on `/slice`, `/resize` and `/process` responses: `true` if the output was enlarged.
+const UPSCALED_HEADER: &str = "X-Upscaled";
+/// Set when `fit=cover` cropped: `x,y,width,height` of the kept part of the
+/// source, or of each slice separated by `;`.
+const CROP_HEADER: &str = "X-Crop-Rect";
+/// Set on `/carousel` responses: the number of slides.
+const CAROUSEL_SLIDES_HEADER: &str = "X-Carousel-Slides";
+/// Set on `/atlas` responses: the atlas image size as `{width}x{height}`.
+const ATLAS_SIZE_HEADER: &str = "X-Atlas-Size";
+/// Set on `/contact-sheet` responses: the cell grid as `{cols}x{rows}`.
+const SHEET_GRID_HEADER: &str = "X-Sheet-Grid";
+/// Set on `/poster` responses: the page grid as `{cols}x{rows}`.
+const POSTER_GRID_HEADER: &str = "X-Poster-Grid";
+/// Set on `/poster` responses: the orientation used, after `auto`.
+const POSTER_ORIENTATION_HEADER: &str = "X-Poster-Orientation";
 
 #[derive(Deserialize)]
 struct ImagePayload {
     image_url: Option<String>,
     image_base64: Option<String>,
+    logo_url: Option<String>,
+    logo_base64: Option<String>,
+}
+
+/// A `/contact-sheet` JSON body: the images in reading order.
+#[derive(Deserialize)]
+struct SheetPayload {
+    images: Vec<SheetImage>,
+}
+
+#[derive(Deserialize)]
+struct SheetImage {
+    image_url: Option<String>,
+    image_base64: Option<String>,
+    caption: Option<String>,
+}
+
+/// Query params with `preset=name` expanded: the preset's params first, then
+/// the request's own, which may only replace those the preset lets them.
+struct PresetQuery<T> {
+    query: T,
+    preset: Option<&'static Preset>,
+}
+
+impl<T> Deref for PresetQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.query
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for PresetQuery<T> {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let expanded = presets::expand_query(req.query_string()).and_then(|(query, preset)| {
+            let query = web::Query::<T>::from_query(&query)
+                .map_err(|e| anyhow::Error::msg(e.to_string()))?;
+            Ok(PresetQuery {
+                query: query.into_inner(),
+                preset,
+            })
+        });
+        ready(expanded.map_err(error::ErrorBadRequest))
+    }
 }
 
 #[derive(Deserialize)]
 struct SliceQuery {
     scale: Option<u32>,
+    tile_width: Option<u32>,
+    tile_height: Option<u32>,
+    fit: Option<String>,
+    filter: Option<String>,
+    gravity: Option<String>,
+    background: Option<String>,
+    allow_upscale: Option<bool>,
+    upscale_quality: Option<String>,
+    remainder: Option<String>,
     watermark: Option<String>,
+    logo: Option<String>,
     transparency: Option<u16>,
+    opacity: Option<u16>,
+    position: Option<String>,
+    wm_scale: Option<u32>,
+    watermark_scope: Option<String>,
+    align: Option<String>,
+    line_spacing: Option<f32>,
+    font: Option<String>,
+    blend: Option<String>,
+    linear_light: Option<bool>,
+    invisible: Option<String>,
+    qr: Option<String>,
+    qr_size: Option<u32>,
+    qr_position: Option<String>,
+    qr_ec: Option<String>,
+    qr_quiet_zone: Option<u32>,
+    qr_symbology: Option<String>,
+    brightness: Option<f32>,
+    contrast: Option<f32>,
+    gamma: Option<f32>,
+    saturation: Option<f32>,
+    hue: Option<f32>,
+    grayscale: Option<bool>,
+    sepia: Option<f32>,
+    invert: Option<bool>,
+    tint: Option<String>,
+    tile_adjust: Option<String>,
+    blur: Option<f32>,
+    sharpen: Option<f32>,
+    unsharp_sigma: Option<f32>,
+    unsharp_amount: Option<f32>,
+    unsharp_threshold: Option<u32>,
+    pixelate: Option<u32>,
+    output: Option<String>,
+    paper: Option<String>,
+    orientation: Option<String>,
+    margin: Option<f32>,
+    marks: Option<bool>,
+    captions: Option<bool>,
+    compression: Option<String>,
+    quality: Option<u8>,
 }
 
 #[derive(Deserialize)]
 struct WatermarkTextQuery {
-    text: String,
+    text: Option<String>,
+    logo: Option<String>,
     transparency: Option<u16>,
+    opacity: Option<u16>,
+    position: Option<String>,
+    wm_scale: Option<u32>,
+    align: Option<String>,
+    line_spacing: Option<f32>,
+    font: Option<String>,
+    blend: Option<String>,
+    linear_light: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct QrQuery {
+    data: String,
+    size: Option<u32>,
+    position: Option<String>,
+    ec: Option<String>,
+    quiet_zone: Option<u32>,
+    symbology: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -36,13 +196,268 @@ struct ResizeQuery {
     width: Option<u32>,
     height: Option<u32>,
     aspect_ratio: Option<String>,
+    filter: Option<String>,
+    fit: Option<String>,
+    gravity: Option<String>,
+    background: Option<String>,
+    allow_upscale: Option<bool>,
+    upscale_quality: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VariantsQuery {
+    widths: Option<String>,
+    formats: Option<String>,
+    tiles: Option<bool>,
+    quality: Option<u8>,
+    filter: Option<String>,
+    allow_upscale: Option<bool>,
+    base_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AdjustQuery {
+    brightness: Option<f32>,
+    contrast: Option<f32>,
+    gamma: Option<f32>,
+    saturation: Option<f32>,
+    hue: Option<f32>,
+    grayscale: Option<bool>,
+    sepia: Option<f32>,
+    invert: Option<bool>,
+    tint: Option<String>,
+}
+
+impl<'a> From<&'a AdjustQuery> for AdjustArgs<'a> {
+    fn from(query: &'a AdjustQuery) -> Self {
+        AdjustArgs {
+            brightness: query.brightness,
+            contrast: query.contrast,
+            gamma: query.gamma,
+            saturation: query.saturation,
+            hue: query.hue,
+            grayscale: query.grayscale,
+            sepia: query.sepia,
+            invert: query.invert,
+            tint: query.tint.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a SliceQuery> for AdjustArgs<'a> {
+    fn from(query: &'a SliceQuery) -> Self {
+        AdjustArgs {
+            brightness: query.brightness,
+            contrast: query.contrast,
+            gamma: query.gamma,
+            saturation: query.saturation,
+            hue: query.hue,
+            grayscale: query.grayscale,
+            sepia: query.sepia,
+            invert: query.invert,
+            tint: query.tint.as_deref(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EffectsQuery {
+    blur: Option<f32>,
+    sharpen: Option<f32>,
+    unsharp_sigma: Option<f32>,
+    unsharp_amount: Option<f32>,
+    unsharp_threshold: Option<u32>,
+    pixelate: Option<u32>,
+}
+
+impl From<&EffectsQuery> for EffectArgs {
+    fn from(query: &EffectsQuery) -> Self {
+        EffectArgs {
+            blur: query.blur,
+            sharpen: query.sharpen,
+            unsharp_sigma: query.unsharp_sigma,
+            unsharp_amount: query.unsharp_amount,
+            unsharp_threshold: query.unsharp_threshold,
+            pixelate: query.pixelate,
+        }
+    }
+}
+
+impl From<&SliceQuery> for EffectArgs {
+    fn from(query: &SliceQuery) -> Self {
+        EffectArgs {
+            blur: query.blur,
+            sharpen: query.sharpen,
+            unsharp_sigma: query.unsharp_sigma,
+            unsharp_amount: query.unsharp_amount,
+            unsharp_threshold: query.unsharp_threshold,
+            pixelate: query.pixelate,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RotateQuery {
+    angle: f32,
+    background: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FlipQuery {
+    direction: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CropQuery {
+    x: Option<u32>,
+    y: Option<u32>,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct PadQuery {
+    padding: Option<u32>,
+    top: Option<u32>,
+    right: Option<u32>,
+    bottom: Option<u32>,
+    left: Option<u32>,
+    background: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TrimQuery {
+    threshold: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct CarouselQuery {
+    aspect: Option<String>,
+    slides: Option<u32>,
+    fit: Option<String>,
+    gravity: Option<String>,
+    background: Option<String>,
+    filter: Option<String>,
+    width: Option<u32>,
+    format: Option<String>,
+    quality: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct AtlasQuery {
+    packer: Option<String>,
+    padding: Option<u32>,
+    pot: Option<bool>,
+    trim: Option<bool>,
+    max_size: Option<u32>,
+    json: Option<String>,
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SheetQuery {
+    cols: Option<u32>,
+    cell_width: Option<u32>,
+    cell_height: Option<u32>,
+    gutter: Option<u32>,
+    background: Option<String>,
+    fit: Option<String>,
+    filter: Option<String>,
+    caption: Option<String>,
+    caption_size: Option<u32>,
+    caption_color: Option<String>,
+    font: Option<String>,
+    format: Option<String>,
+    quality: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct StitchQuery {
+    grid: Option<String>,
+    overlap: Option<String>,
+    bezel: Option<String>,
+    background: Option<String>,
+    format: Option<String>,
+    quality: Option<u8>,
+}
+
+#[derive(Deserialize)]
+struct PosterQuery {
+    paper: Option<String>,
+    orientation: Option<String>,
+    dpi: Option<u32>,
+    margin: Option<f32>,
+    overlap: Option<f32>,
+    marks: Option<bool>,
+    labels: Option<bool>,
+    format: Option<String>,
+    quality: Option<u8>,
+}
+
+/// `steps` as a JSON array, for binary and multipart uploads.
+#[derive(Deserialize)]
+struct ProcessQuery {
+    steps: Option<String>,
+}
+
+/// The `steps` field of a JSON `/process` body, next to `image_url` etc.
+#[derive(Deserialize)]
+struct ProcessPayload {
+    steps: Option<Vec<serde_json::Value>>,
+}
+
+// An uploaded logo wins over a registered one.
+fn logo_source(upload: Option<ImageSource>, name: Option<&String>) -> Option<LogoSource> {
+    match (upload, name) {
+        (Some(src), _) => Some(LogoSource::Image(src)),
+        (None, Some(name)) => Some(LogoSource::Named(name.clone())),
+        (None, None) => None,
+    }
 }
 
 #[post("/slice")]
-async fn slice(req: HttpRequest, body: web::Bytes, query: web::Query<SliceQuery>) -> HttpResponse {
-    let scale = query.scale.unwrap_or(300);
+async fn slice(req: HttpRequest, body: web::Bytes, query: PresetQuery<SliceQuery>) -> HttpResponse {
+    let tile_args = ResizeArgs {
+        width: query.tile_width,
+        height: query.tile_height,
+        fit: query.fit.as_deref(),
+        gravity: query.gravity.as_deref(),
+        background: query.background.as_deref(),
+        filter: query.filter.as_deref(),
+        allow_upscale: query.allow_upscale,
+        upscale_quality: query.upscale_quality.as_deref(),
+        ..ResizeArgs::default()
+    };
+    let tile = match TileScale::parse(query.scale.unwrap_or(300), tile_args) {
+        Ok(tile) => tile,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let remainder = match Remainder::parse(query.remainder.as_deref()) {
+        Ok(remainder) => remainder,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    // `output=pdf` puts the slices on the pages of one PDF instead of streaming PNGs.
+    let pdf_spec = match query.output.as_deref().map(str::trim) {
+        None | Some("") | Some("png") => None,
+        Some("pdf") => match PdfSpec::from_args(&PdfArgs {
+            paper: query.paper.as_deref(),
+            orientation: query.orientation.as_deref(),
+            margin: query.margin,
+            marks: query.marks,
+            captions: query.captions,
+            compression: query.compression.as_deref(),
+            quality: query.quality,
+        }) {
+            Ok(spec) => Some(spec),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        },
+        Some(other) => {
+            return HttpResponse::BadRequest()
+                .body(format!("Unknown output: {} (use png or pdf)", other));
+        }
+    };
 
-    let source = match get_source(req, body).await {
+    let (source, logo_upload) = match get_source_with_logo(req, body).await {
         Ok(src) => src,
         Err(e) => {
             println!("Error: {}", e);
@@ -50,27 +465,142 @@ async fn slice(req: HttpRequest, body: web::Bytes, query: web::Query<SliceQuery>
         }
     };
 
-    let images = match query.watermark.as_ref() {
-        Some(wm) => {
-            image_processor::slice_with_watermark_text(
-                source,
-                scale,
-                wm,
-                query.transparency.unwrap_or(30),
-            )
-            .await
+    let placement = match Placement::parse(
+        query.position.as_deref(),
+        query.wm_scale,
+        query.watermark_scope.as_deref(),
+    ) {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let style = match TextStyle::parse(
+        query.align.as_deref(),
+        query.line_spacing,
+        query.font.as_deref(),
+    ) {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let blend = match Blend::parse(query.blend.as_deref(), query.linear_light) {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let transparency = match transparency_from(query.opacity, query.transparency) {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let qr_spec = match query.qr.as_deref().filter(|d| !d.is_empty()) {
+        Some(data) => match QrSpec::parse(
+            data,
+            query.qr_size,
+            query.qr_position.as_deref(),
+            query.qr_ec.as_deref(),
+            query.qr_quiet_zone,
+            query.qr_symbology.as_deref(),
+        ) {
+            Ok(spec) => Some(spec),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        },
+        None => None,
+    };
+    let adjustments = Adjustments::from_args(&AdjustArgs::from(&*query))
+        .and_then(|global| TileAdjustments::parse(global, query.tile_adjust.as_deref()));
+    let adjustments = match adjustments {
+        Ok(a) => a,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let effects = match Effects::from_args(&EffectArgs::from(&*query)) {
+        Ok(e) => e,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let filename = source.file_name().unwrap_or_default();
+
+    let wm_spec = match (
+        logo_source(logo_upload, query.logo.as_ref()),
+        query.watermark.as_ref(),
+    ) {
+        (Some(_), Some(_)) => {
+            return HttpResponse::BadRequest()
+                .body("Provide either watermark text or a logo, not both");
         }
-        None => image_processor::slice(source, scale).await,
+        (Some(logo), None) => match image_processor::load_logo(logo).await {
+            Ok(wm) => Some(WatermarkSpec {
+                content: WatermarkContent::Logo(wm),
+                transparency,
+                placement,
+                blend,
+            }),
+            Err(e) => {
+                println!("Error: {}", e);
+                return HttpResponse::BadRequest().body(format!("Error loading logo: {}", e));
+            }
+        },
+        (None, Some(wm)) => Some(WatermarkSpec {
+            content: WatermarkContent::Text(wm.to_string(), style),
+            transparency,
+            placement,
+            blend,
+        }),
+        (None, None) => None,
+    };
+    let pipeline = SliceOps {
+        watermark: wm_spec,
+        remainder,
+        tile,
+        effects,
+        adjustments,
+        qr: qr_spec,
+        invisible: query.invisible.clone().unwrap_or_default(),
+    }
+    .steps()
+    .and_then(Pipeline::new);
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
     };
 
-    let images = match images {
-        Ok(images) => images,
+    let output = image_processor::load_image(source)
+        .await
+        .and_then(|img| pipeline.run(img, &filename));
+    let Output {
+        images,
+        upscaled,
+        crops,
+        ..
+    } = match output {
+        Ok(output) => output,
         Err(e) => {
             println!("Error: {}", e);
             return HttpResponse::BadRequest().body(format!("Error processing image: {}", e));
         }
     };
 
+    if let Some(spec) = &pdf_spec {
+        let pdf = match pdf::render(&images, GRID_COLS, &filename, spec) {
+            Ok(pdf) => pdf,
+            Err(e) => {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Error writing PDF: {}", e))
+            }
+        };
+        println!("Done: {} byte PDF", pdf.len());
+        let chunks: Vec<_> = pdf
+            .chunks(pdf::STREAM_CHUNK)
+            .map(|chunk| Ok::<_, error::Error>(web::Bytes::copy_from_slice(chunk)))
+            .collect();
+        return HttpResponse::Ok()
+            .content_type("application/pdf")
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}.pdf\"",
+                    image_processor::variants::stem(Some(&filename))
+                ),
+            ))
+            .insert_header((UPSCALED_HEADER, upscaled.to_string()))
+            .streaming(futures::stream::iter(chunks));
+    }
+
     let response_images = images.into_iter().map(|img| {
         let mut buf = BufWriter::new(Cursor::new(Vec::new()));
         let written = img.write_to(&mut buf, ImageFormat::Png);
@@ -88,18 +618,24 @@ async fn slice(req: HttpRequest, body: web::Bytes, query: web::Query<SliceQuery>
     });
 
     println!("Done");
-    HttpResponse::Ok()
+    let mut response = HttpResponse::Ok();
+    response
         .content_type("application/octet-stream")
-        .streaming(stream)
+        .insert_header((UPSCALED_HEADER, upscaled.to_string()));
+    if crops.iter().all(Option::is_some) {
+        let rects: Vec<String> = crops.iter().flatten().map(|r| r.to_string()).collect();
+        response.insert_header((CROP_HEADER, rects.join(";")));
+    }
+    response.streaming(stream)
 }
 
 #[post("/watermark")]
 async fn watermark(
     req: HttpRequest,
     body: web::Bytes,
-    query: web::Query<WatermarkTextQuery>,
+    query: PresetQuery<WatermarkTextQuery>,
 ) -> HttpResponse {
-    let source = match get_source(req, body).await {
+    let (source, logo_upload) = match get_source_with_logo(req, body).await {
         Ok(src) => src,
         Err(e) => {
             println!("Error: {}", e);
@@ -107,9 +643,50 @@ async fn watermark(
         }
     };
 
-    let text = query.text.trim();
-    let transparency = query.transparency.unwrap_or(30).min(100);
-    let alpha = transparency as f32 / 100.0;
+    let placement = match Placement::parse(query.position.as_deref(), query.wm_scale, None) {
+        Ok(p) => p,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let style = match TextStyle::parse(
+        query.align.as_deref(),
+        query.line_spacing,
+        query.font.as_deref(),
+    ) {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let blend = match Blend::parse(query.blend.as_deref(), query.linear_light) {
+        Ok(b) => b,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let transparency = match transparency_from(query.opacity, query.transparency) {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let text = query.text.as_deref().map(str::trim).unwrap_or("");
+
+    let content = match logo_source(logo_upload, query.logo.as_ref()) {
+        Some(_) if !text.is_empty() => {
+            return HttpResponse::BadRequest().body("Provide either text or a logo, not both");
+        }
+        Some(logo) => match image_processor::load_logo(logo).await {
+            Ok(wm) => WatermarkContent::Logo(wm),
+            Err(e) => {
+                println!("Error: {}", e);
+                return HttpResponse::BadRequest().body(format!("Error loading logo: {}", e));
+            }
+        },
+        None if text.is_empty() => {
+            return HttpResponse::BadRequest().body("Provide watermark text or a logo");
+        }
+        None => WatermarkContent::Text(text.to_string(), style),
+    };
+    let spec = WatermarkSpec {
+        content,
+        transparency,
+        placement,
+        blend,
+    };
 
     let img = match image_processor::load_image(source).await {
         Ok(img) => img,
@@ -120,8 +697,15 @@ async fn watermark(
     };
 
     let (w, h) = (img.width(), img.height());
-    let wm_image = image_processor::watermark::create_watermark(text, (w, h));
-    let watermarked = image_processor::watermark::add_watermark(img.to_rgba8(), &wm_image, alpha);
+    let wm_image = match spec.render((w, h)) {
+        Ok(wm) => wm,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::InternalServerError()
+                .body(format!("Error rendering watermark: {}", e));
+        }
+    };
+    let watermarked = spec.apply(img.into_rgba8(), &wm_image);
 
     let mut buf = BufWriter::new(Cursor::new(Vec::new()));
     if watermarked.write_to(&mut buf, ImageFormat::Png).is_err() {
@@ -140,11 +724,184 @@ async fn watermark(
     HttpResponse::Ok().content_type("image/png").body(bytes)
 }
 
+#[post("/qr")]
+async fn qr(req: HttpRequest, body: web::Bytes, query: PresetQuery<QrQuery>) -> HttpResponse {
+    let spec = match QrSpec::parse(
+        &query.data,
+        query.size,
+        query.position.as_deref(),
+        query.ec.as_deref(),
+        query.quiet_zone,
+        query.symbology.as_deref(),
+    ) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+    let filename = source.file_name().unwrap_or_default();
+
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+
+    let (w, h) = (img.width(), img.height());
+    let date = text::today();
+    let tile = TileContext::for_grid(0, 1, 1, &filename, &date);
+    let code = match spec.render_for_tile((w, h), &tile) {
+        Ok(code) => code,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let stamped = spec.apply(img.to_rgba8(), &code);
+
+    let mut buf = BufWriter::new(Cursor::new(Vec::new()));
+    if stamped.write_to(&mut buf, ImageFormat::Png).is_err() {
+        return HttpResponse::InternalServerError().body("Error encoding image");
+    }
+    let cursor = match BufWriter::into_inner(buf) {
+        Ok(c) => c,
+        Err(_) => return HttpResponse::InternalServerError().body("Error finalizing image"),
+    };
+    let bytes = cursor.into_inner();
+
+    println!(
+        "QR code on {}x{}: {}x{} px",
+        w,
+        h,
+        code.width(),
+        code.height()
+    );
+    HttpResponse::Ok().content_type("image/png").body(bytes)
+}
+
+#[derive(Serialize)]
+struct DetectResponse {
+    found: bool,
+    payload: Option<String>,
+}
+
+#[post("/watermark/detect")]
+async fn watermark_detect(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+
+    let payload = match invisible::detect(&img) {
+        Ok(payload) => payload.map(|p| String::from_utf8_lossy(&p).into_owned()),
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    println!("Detect on {}x{}: {:?}", img.width(), img.height(), payload);
+    HttpResponse::Ok().json(DetectResponse {
+        found: payload.is_some(),
+        payload,
+    })
+}
+
+#[post("/variants")]
+async fn variants(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<VariantsQuery>,
+) -> HttpResponse {
+    let widths = match query
+        .widths
+        .as_deref()
+        .map(image_processor::variants::parse_widths)
+    {
+        Some(Ok(widths)) => widths,
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e.to_string()),
+        None => Vec::new(),
+    };
+    let formats: Vec<&str> = query
+        .formats
+        .as_deref()
+        .map(|f| f.split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    let spec = match VariantSpec::parse(
+        &widths,
+        &formats,
+        query.tiles.unwrap_or(false),
+        query.quality,
+        query.filter.as_deref(),
+        query.allow_upscale,
+    ) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+    let stem = image_processor::variants::stem(source.file_name().as_deref());
+
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+
+    let base_url = query.base_url.as_deref().unwrap_or_default();
+    let (generated, manifest) =
+        match image_processor::variants::generate(img, &spec, &stem, base_url) {
+            Ok(result) => result,
+            Err(e) => {
+                println!("Error: {}", e);
+                return HttpResponse::InternalServerError()
+                    .body(format!("Error generating variants: {}", e));
+            }
+        };
+
+    let boundary = image_processor::multipart_boundary("variants");
+    let body = match image_processor::variants::multipart_body(&generated, &manifest, &boundary) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+
+    println!("Generated {} variant(s)", generated.len());
+    HttpResponse::Ok()
+        .content_type(format!("multipart/mixed; boundary={}", boundary))
+        .body(body)
+}
+
+#[get("/cache/stats")]
+async fn cache_stats() -> HttpResponse {
+    HttpResponse::Ok().json(image_processor::render_cache::stats())
+}
+
 #[post("/resize")]
 pub async fn resize_handler(
     req: HttpRequest,
     body: web::Bytes,
-    query: web::Query<ResizeQuery>,
+    query: PresetQuery<ResizeQuery>,
 ) -> HttpResponse {
     let source = match get_source(req, body).await {
         Ok(src) => src,
@@ -154,15 +911,27 @@ pub async fn resize_handler(
         }
     };
 
-    let ar = query.aspect_ratio.as_deref().unwrap_or("preserve");
-
-    if ar == "ignore" && (query.width.is_none() || query.height.is_none()) {
-        return HttpResponse::BadRequest()
-            .body("aspect_ratio=ignore requires both width and height");
-    }
+    let params = match ResizeParams::from_args(&ResizeArgs {
+        width: query.width,
+        height: query.height,
+        aspect_ratio: query.aspect_ratio.as_deref(),
+        fit: query.fit.as_deref(),
+        gravity: query.gravity.as_deref(),
+        background: query.background.as_deref(),
+        filter: query.filter.as_deref(),
+        allow_upscale: query.allow_upscale,
+        upscale_quality: query.upscale_quality.as_deref(),
+    }) {
+        Ok(params) => params,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
 
-    let img = match image_processor::resize_image(source, query.width, query.height, ar).await {
-        Ok(img) => img,
+    let Resized {
+        image: img,
+        upscaled,
+        crop,
+    } = match image_processor::resize_image(source, &params).await {
+        Ok(resized) => resized,
         Err(e) => {
             println!("Error: {}", e);
             return HttpResponse::BadRequest().body(format!("Error resizing image: {}", e));
@@ -182,13 +951,656 @@ pub async fn resize_handler(
     let bytes = cursor.into_inner();
 
     println!("Resized image: {}x{}", img.width(), img.height());
-    HttpResponse::Ok().content_type("image/png").body(bytes)
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("image/png")
+        .insert_header((UPSCALED_HEADER, upscaled.to_string()));
+    if let Some(crop) = crop {
+        response.insert_header((CROP_HEADER, crop.to_string()));
+    }
+    response.body(bytes)
+}
+
+// Shared by the geometric transform endpoints.
+async fn transform_response(
+    req: HttpRequest,
+    body: web::Bytes,
+    transform: anyhow::Result<Transform>,
+) -> HttpResponse {
+    let transform = match transform {
+        Ok(transform) => transform,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let what = format!("Transformed image ({})", transform);
+    image_response(req, body, &what, move |img| transform.apply(&img)).await
+}
+
+// Shared by the single-image endpoints: load the source, apply `op`, return PNG.
+async fn image_response(
+    req: HttpRequest,
+    body: web::Bytes,
+    what: &str,
+    op: impl FnOnce(DynamicImage) -> anyhow::Result<DynamicImage>,
+) -> HttpResponse {
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+
+    let img = match image_processor::load_image(source).await.and_then(op) {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error processing image: {}", e));
+        }
+    };
+
+    let mut buf = BufWriter::new(Cursor::new(Vec::new()));
+    if img.write_to(&mut buf, ImageFormat::Png).is_err() {
+        return HttpResponse::InternalServerError().body("Error encoding image");
+    }
+    let cursor = match BufWriter::into_inner(buf) {
+        Ok(c) => c,
+        Err(_) => {
+            return HttpResponse::InternalServerError().body("Error finalizing image");
+        }
+    };
+
+    println!("{}: {}x{}", what, img.width(), img.height());
+    HttpResponse::Ok()
+        .content_type("image/png")
+        .body(cursor.into_inner())
+}
+
+#[post("/adjust")]
+async fn adjust(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<AdjustQuery>,
+) -> HttpResponse {
+    let adjustments = match Adjustments::from_args(&AdjustArgs::from(&*query)) {
+        Ok(a) => a,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    image_response(req, body, "Adjusted image", move |img| {
+        let mut img = img.into_rgba8();
+        adjustments.apply(&mut img);
+        Ok(img.into())
+    })
+    .await
+}
+
+#[post("/effects")]
+async fn effects_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<EffectsQuery>,
+) -> HttpResponse {
+    let effects = match Effects::from_args(&EffectArgs::from(&*query)) {
+        Ok(e) if e.is_empty() => {
+            return HttpResponse::BadRequest()
+                .body("Provide at least one of blur, sharpen, unsharp_* or pixelate")
+        }
+        Ok(e) => e,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let what = format!("Applied {}", effects);
+    image_response(req, body, &what, move |img| {
+        Ok(effects.apply(img.into_rgba8()).into())
+    })
+    .await
+}
+
+#[post("/rotate")]
+async fn rotate_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<RotateQuery>,
+) -> HttpResponse {
+    let transform = Transform::rotate(query.angle, query.background.as_deref());
+    transform_response(req, body, transform).await
+}
+
+#[post("/flip")]
+async fn flip_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<FlipQuery>,
+) -> HttpResponse {
+    let transform = Transform::flip(query.direction.as_deref());
+    transform_response(req, body, transform).await
+}
+
+#[post("/crop")]
+async fn crop_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<CropQuery>,
+) -> HttpResponse {
+    let transform = Transform::crop(
+        query.x.unwrap_or(0),
+        query.y.unwrap_or(0),
+        query.width,
+        query.height,
+    );
+    transform_response(req, body, transform).await
+}
+
+#[post("/pad")]
+async fn pad_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<PadQuery>,
+) -> HttpResponse {
+    let transform = Transform::pad(
+        query.padding,
+        [query.top, query.right, query.bottom, query.left],
+        query.background.as_deref(),
+    );
+    transform_response(req, body, transform).await
+}
+
+#[post("/trim")]
+async fn trim_handler(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<TrimQuery>,
+) -> HttpResponse {
+    transform_response(req, body, Ok(Transform::trim(query.threshold))).await
+}
+
+#[post("/process")]
+async fn process(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<ProcessQuery>,
+) -> HttpResponse {
+    let is_json = req
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let body_steps = if is_json {
+        match serde_json::from_slice::<ProcessPayload>(&body) {
+            Ok(payload) => payload.steps,
+            Err(e) => {
+                return HttpResponse::BadRequest().body(format!("Unrecognized JSON: {}", e));
+            }
+        }
+    } else {
+        None
+    };
+    // Steps in the body replace a preset's, if it allows that.
+    if let (Some(_), Some(preset)) = (&body_steps, query.preset) {
+        if let Err(e) = preset.check_override("steps") {
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+    }
+    let steps = match (body_steps, query.steps.as_deref()) {
+        (Some(steps), _) => steps,
+        (None, Some(steps)) => match serde_json::from_str::<Vec<serde_json::Value>>(steps) {
+            Ok(steps) => steps,
+            Err(e) => {
+                return HttpResponse::BadRequest()
+                    .body(format!("steps must be a JSON array: {}", e))
+            }
+        },
+        (None, None) => {
+            return HttpResponse::BadRequest()
+                .body("Provide steps in the JSON body or the steps parameter");
+        }
+    };
+
+    let (source, logo_upload) = match get_source_with_logo(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+    let logo = match logo_upload {
+        Some(upload) => match image_processor::load_logo(LogoSource::Image(upload)).await {
+            Ok(logo) => Some(logo),
+            Err(e) => {
+                println!("Error: {}", e);
+                return HttpResponse::BadRequest().body(format!("Error loading logo: {}", e));
+            }
+        },
+        None => None,
+    };
+    let pipeline = match Pipeline::from_json(&steps, logo.as_ref()) {
+        Ok(pipeline) => pipeline,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let filename = source.file_name().unwrap_or_default();
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+    let output = match pipeline.run(img, &filename) {
+        Ok(output) => output,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(e.to_string());
+        }
+    };
+    let encoded = match output.encode() {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            return HttpResponse::InternalServerError().body(format!("Error encoding image: {}", e))
+        }
+    };
+
+    println!(
+        "Processed {} step(s) into {} image(s)",
+        steps.len(),
+        encoded.len()
+    );
+    let mut response = HttpResponse::Ok();
+    response.insert_header((UPSCALED_HEADER, output.upscaled.to_string()));
+    if output.crops.iter().all(Option::is_some) {
+        let rects: Vec<String> = output
+            .crops
+            .iter()
+            .flatten()
+            .map(|r| r.to_string())
+            .collect();
+        response.insert_header((CROP_HEADER, rects.join(";")));
+    }
+    if !output.tiled {
+        return response
+            .content_type(output.format.mime())
+            .body(encoded.into_iter().next().unwrap_or_default());
+    }
+
+    let boundary = image_processor::multipart_boundary("process");
+    let mut body = Vec::new();
+    for (i, data) in encoded.iter().enumerate() {
+        image_processor::write_multipart_part(
+            &mut body,
+            &boundary,
+            output.format.mime(),
+            &output.file_name(i),
+            data,
+        );
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    response
+        .content_type(format!("multipart/mixed; boundary={}", boundary))
+        .body(body)
+}
+
+#[post("/carousel")]
+async fn carousel(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<CarouselQuery>,
+) -> HttpResponse {
+    let spec = match CarouselSpec::from_args(&CarouselArgs {
+        aspect: query.aspect.as_deref(),
+        slides: query.slides,
+        fit: query.fit.as_deref(),
+        gravity: query.gravity.as_deref(),
+        background: query.background.as_deref(),
+        filter: query.filter.as_deref(),
+        width: query.width,
+    }) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let (format, quality) =
+        match image_processor::variants::parse_encoding(query.format.as_deref(), query.quality) {
+            Ok(encoding) => encoding,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+    let carousel = match spec.split(&img) {
+        Ok(carousel) => carousel,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error processing image: {}", e));
+        }
+    };
+
+    let boundary = image_processor::multipart_boundary("carousel");
+    let mut body = Vec::new();
+    for (i, slide) in carousel.slides.into_iter().enumerate() {
+        let data = match format.encode(&slide.into(), quality) {
+            Ok(data) => data,
+            Err(e) => {
+                return HttpResponse::InternalServerError().body(format!(
+                    "Error encoding slide {}: {}",
+                    i + 1,
+                    e
+                ))
+            }
+        };
+        let name = format!("slide-{}.{}", i + 1, format.extension());
+        image_processor::write_multipart_part(&mut body, &boundary, format.mime(), &name, &data);
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let slides = spec.slide_count((img.width(), img.height()));
+    println!("Split carousel into {} slide(s)", slides);
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((CAROUSEL_SLIDES_HEADER, slides.to_string()))
+        .insert_header((UPSCALED_HEADER, carousel.upscaled.to_string()));
+    if let Some(crop) = carousel.crop {
+        response.insert_header((CROP_HEADER, crop.to_string()));
+    }
+    response
+        .content_type(format!("multipart/mixed; boundary={}", boundary))
+        .body(body)
+}
+
+#[post("/stitch")]
+async fn stitch(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<StitchQuery>,
+) -> HttpResponse {
+    let spec = match StitchSpec::from_args(&StitchArgs {
+        grid: query.grid.as_deref(),
+        overlap: query.overlap.as_deref(),
+        bezel: query.bezel.as_deref(),
+        background: query.background.as_deref(),
+    }) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let (format, quality) =
+        match image_processor::variants::parse_encoding(query.format.as_deref(), query.quality) {
+            Ok(encoding) => encoding,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+
+    let parts = image_processor::get_tiles(&req, body).await;
+    let tiles = match parts.and_then(|parts| image_processor::stitch::decode_tiles(&parts)) {
+        Ok(tiles) => tiles,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error reading tiles: {}", e));
+        }
+    };
+    let stitched = match spec.stitch(&tiles) {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error stitching tiles: {}", e));
+        }
+    };
+
+    println!(
+        "Stitched {} tiles into {}x{}",
+        tiles.len(),
+        stitched.width(),
+        stitched.height()
+    );
+    match format.encode(&stitched.into(), quality) {
+        Ok(data) => HttpResponse::Ok().content_type(format.mime()).body(data),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error encoding image: {}", e)),
+    }
+}
+
+#[post("/atlas")]
+async fn atlas(req: HttpRequest, body: web::Bytes, query: PresetQuery<AtlasQuery>) -> HttpResponse {
+    let spec = match AtlasSpec::from_args(&AtlasArgs {
+        packer: query.packer.as_deref(),
+        padding: query.padding,
+        pot: query.pot,
+        trim: query.trim,
+        max_size: query.max_size,
+        json: query.json.as_deref(),
+        name: query.name.as_deref(),
+    }) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let files = image_processor::get_sprites(&req, body).await;
+    let sprites = match files.and_then(image_processor::atlas::decode_sprites) {
+        Ok(sprites) => sprites,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error reading sprites: {}", e));
+        }
+    };
+    let packed = match spec.pack(sprites) {
+        Ok(packed) => packed,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error packing atlas: {}", e));
+        }
+    };
+
+    let (image_name, json_name) = spec.file_names();
+    let (width, height) = packed.image.dimensions();
+    let json = match spec.json(&packed) {
+        Ok(json) => json,
+        Err(e) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Error writing atlas JSON: {}", e))
+        }
+    };
+    let mut png = Cursor::new(Vec::new());
+    if let Err(e) = packed.image.write_to(&mut png, ImageFormat::Png) {
+        return HttpResponse::InternalServerError().body(format!("Error encoding atlas: {}", e));
+    }
+    let boundary = image_processor::multipart_boundary("atlas");
+    let mut body = Vec::new();
+    image_processor::write_multipart_part(
+        &mut body,
+        &boundary,
+        "application/json",
+        &json_name,
+        &json,
+    );
+    image_processor::write_multipart_part(
+        &mut body,
+        &boundary,
+        "image/png",
+        &image_name,
+        png.get_ref(),
+    );
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    println!(
+        "Packed {} sprites into a {}x{} atlas",
+        packed.frames.len(),
+        width,
+        height
+    );
+    HttpResponse::Ok()
+        .insert_header((ATLAS_SIZE_HEADER, format!("{}x{}", width, height)))
+        .content_type(format!("multipart/mixed; boundary={}", boundary))
+        .body(body)
+}
+
+#[post("/contact-sheet")]
+async fn contact_sheet(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<SheetQuery>,
+) -> HttpResponse {
+    let spec = match SheetSpec::from_args(&SheetArgs {
+        cols: query.cols,
+        cell_width: query.cell_width,
+        cell_height: query.cell_height,
+        gutter: query.gutter,
+        background: query.background.as_deref(),
+        fit: query.fit.as_deref(),
+        filter: query.filter.as_deref(),
+        caption: query.caption.as_deref(),
+        caption_size: query.caption_size,
+        caption_color: query.caption_color.as_deref(),
+        font: query.font.as_deref(),
+    }) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let (format, quality) =
+        match image_processor::variants::parse_encoding(query.format.as_deref(), query.quality) {
+            Ok(encoding) => encoding,
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        };
+
+    let entries = image_processor::get_sheet_entries(&req, body).await;
+    let cells = match entries {
+        Ok(entries) => spec.load_all(entries).await,
+        Err(e) => Err(e),
+    };
+    let cells = match cells {
+        Ok(cells) => cells,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting images: {}", e));
+        }
+    };
+    let sheet = match spec.render(&cells) {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error building contact sheet: {}", e));
+        }
+    };
+
+    let (cols, rows) = spec.grid(cells.len());
+    println!(
+        "Laid out {} images as a {}x{} contact sheet",
+        cells.len(),
+        cols,
+        rows
+    );
+    match format.encode(&sheet.into(), quality) {
+        Ok(data) => HttpResponse::Ok()
+            .insert_header((SHEET_GRID_HEADER, format!("{}x{}", cols, rows)))
+            .content_type(format.mime())
+            .body(data),
+        Err(e) => HttpResponse::InternalServerError().body(format!("Error encoding image: {}", e)),
+    }
+}
+
+#[post("/poster")]
+async fn poster(
+    req: HttpRequest,
+    body: web::Bytes,
+    query: PresetQuery<PosterQuery>,
+) -> HttpResponse {
+    let spec = match PosterSpec::from_args(&PosterArgs {
+        paper: query.paper.as_deref(),
+        orientation: query.orientation.as_deref(),
+        dpi: query.dpi,
+        margin: query.margin,
+        overlap: query.overlap,
+        marks: query.marks,
+        labels: query.labels,
+        format: query.format.as_deref(),
+        quality: query.quality,
+    }) {
+        Ok(spec) => spec,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let source = match get_source(req, body).await {
+        Ok(src) => src,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error getting image source: {}", e));
+        }
+    };
+    let img = match image_processor::load_image(source).await {
+        Ok(img) => img,
+        Err(e) => {
+            println!("Error: {}", e);
+            return HttpResponse::BadRequest().body(format!("Error loading image: {}", e));
+        }
+    };
+    let layout = match spec.layout((img.width(), img.height())) {
+        Ok(layout) => layout,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    let boundary = image_processor::multipart_boundary("poster");
+    let mut body = Vec::new();
+    for i in 0..layout.cells.len() {
+        let data = match layout
+            .render_page(&img, &spec, i)
+            .and_then(|page| spec.encode(page))
+        {
+            Ok(data) => data,
+            Err(e) => {
+                return HttpResponse::InternalServerError().body(format!(
+                    "Error rendering page {}: {}",
+                    i + 1,
+                    e
+                ))
+            }
+        };
+        image_processor::write_multipart_part(
+            &mut body,
+            &boundary,
+            spec.format.mime(),
+            &spec.file_name(i),
+            &data,
+        );
+    }
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    println!(
+        "Split poster into {}x{} pages at {} DPI",
+        layout.cols, layout.rows, spec.dpi
+    );
+    HttpResponse::Ok()
+        .insert_header((
+            POSTER_GRID_HEADER,
+            format!("{}x{}", layout.cols, layout.rows),
+        ))
+        .insert_header((POSTER_ORIENTATION_HEADER, layout.orientation.name()))
+        .content_type(format!("multipart/mixed; boundary={}", boundary))
+        .body(body)
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Running");
 
+    if let Err(e) = image_processor::logo::init_from_env() {
+        println!("Error: {}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = image_processor::fonts::init_from_env() {
+        println!("Error: {}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    }
+    if let Err(e) = presets::init_from_env() {
+        println!("Error: {}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    }
+
     let grpc_port: u16 = env::var("GRPC_PORT")
         .unwrap_or_else(|_| {
             println!("GRPC_PORT not set, using default 50051");
@@ -227,8 +1639,25 @@ async fn main() -> std::io::Result<()> {
     HttpServer::new(|| {
         App::new()
             .service(watermark)
+            .service(watermark_detect)
+            .service(qr)
+            .service(cache_stats)
             .service(slice)
             .service(resize_handler)
+            .service(variants)
+            .service(adjust)
+            .service(effects_handler)
+            .service(rotate_handler)
+            .service(flip_handler)
+            .service(crop_handler)
+            .service(pad_handler)
+            .service(trim_handler)
+            .service(process)
+            .service(poster)
+            .service(carousel)
+            .service(stitch)
+            .service(atlas)
+            .service(contact_sheet)
     })
     .bind(("0.0.0.0", http_port))?
     .run()
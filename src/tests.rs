@@ -587,3 +587,1500 @@ async fn test_slice_watermark_text_affects_pixel_pattern() {
         "Different watermark text ('IZDU' vs 'XXXX') should produce different pixel data"
     );
 }
+
+// ---------------------------------------------------------------------------
+// Logo watermark integration tests
+// ---------------------------------------------------------------------------
+
+/// Logo 1: JSON body with `logo_base64` — the red logo is composited onto every slice.
+#[tokio::test]
+async fn test_slice_with_logo_base64() {
+    use base64::Engine;
+    let logo = base64::engine::general_purpose::STANDARD.encode(TINY_PNG_BYTES);
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64,
+        "logo_base64": logo,
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![("transparency", "0"), ("scale", "0")]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = actix_web::test::read_body(resp).await;
+    let slices = decode_slices(body);
+
+    assert_eq!(slices.len(), 4);
+    for (i, slice) in slices.iter().enumerate() {
+        assert!(
+            slice_has_watermark(slice, BLUE_PIXEL),
+            "slice {} should show the logo",
+            i
+        );
+    }
+}
+
+/// Logo 2: multipart body with `image` and `logo` parts.
+#[tokio::test]
+async fn test_slice_with_logo_multipart() {
+    let boundary = "izdu-boundary";
+    let mut body = Vec::new();
+    for (name, data) in [
+        ("image", base64_decode(SMALL_PNG_BASE64)),
+        ("logo", TINY_PNG_BYTES.to_vec()),
+    ] {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}.png\"\r\nContent-Type: image/png\r\n\r\n",
+                boundary, name, name
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    let resp = slice_request(
+        body,
+        &format!("multipart/form-data; boundary={}", boundary),
+        Some(vec![("transparency", "0"), ("position", "top-left")]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = actix_web::test::read_body(resp).await;
+    let slices = decode_slices(body);
+
+    assert_eq!(slices.len(), 4);
+    assert!(slice_has_watermark(&slices[0], BLUE_PIXEL));
+}
+
+/// Logo 3: unknown registered logo name — should return 400.
+#[tokio::test]
+async fn test_slice_with_unknown_logo_name() {
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64
+    }))
+    .unwrap();
+
+    let resp = slice_request(payload, "application/json", Some(vec![("logo", "missing")])).await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+fn base64_decode(data: &str) -> Vec<u8> {
+    use base64::Engine;
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .unwrap()
+}
+
+/// Template: `{index}` renders a different number on each tile of a uniform image.
+#[tokio::test]
+async fn test_slice_watermark_template_differs_per_tile() {
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![
+            ("watermark", "{index}/{total}"),
+            ("transparency", "0"),
+        ]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = actix_web::test::read_body(resp).await;
+    let slices = decode_slices(body);
+
+    assert_eq!(slices.len(), 4);
+    assert_ne!(
+        slices[0].as_raw(),
+        slices[1].as_raw(),
+        "tiles 1/4 and 2/4 should carry different text"
+    );
+}
+
+/// Scope: `watermark_scope=image` places one logo on the full source, so only the
+/// top-left tile is covered and the bottom-right tile stays untouched.
+#[tokio::test]
+async fn test_slice_watermark_scope_image() {
+    use base64::Engine;
+    let logo = base64::engine::general_purpose::STANDARD.encode(TINY_PNG_BYTES);
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64,
+        "logo_base64": logo,
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![
+            ("transparency", "0"),
+            ("position", "top-left"),
+            ("wm_scale", "50"),
+            ("watermark_scope", "image"),
+        ]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = actix_web::test::read_body(resp).await;
+    let slices = decode_slices(body);
+
+    assert_eq!(slices.len(), 4);
+    assert!(slice_has_watermark(&slices[0], BLUE_PIXEL));
+    assert!(!slice_has_watermark(&slices[3], BLUE_PIXEL));
+}
+
+/// Blend: white text multiplied onto blue leaves every pixel blue.
+#[tokio::test]
+async fn test_slice_watermark_multiply_white_text_is_invisible() {
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![
+            ("watermark", "IZDU"),
+            ("opacity", "100"),
+            ("blend", "multiply"),
+        ]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let body = actix_web::test::read_body(resp).await;
+    let slices = decode_slices(body);
+
+    assert_eq!(slices.len(), 4);
+    for slice in &slices {
+        assert!(!slice_has_watermark(slice, BLUE_PIXEL));
+    }
+}
+
+/// Blend: opacity and transparency are mutually exclusive.
+#[tokio::test]
+async fn test_slice_watermark_opacity_and_transparency_conflict() {
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![
+            ("watermark", "IZDU"),
+            ("opacity", "80"),
+            ("transparency", "20"),
+        ]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+// ---------------------------------------------------------------------------
+// Invisible watermark integration tests
+// ---------------------------------------------------------------------------
+
+fn textured_png(w: u32, h: u32) -> Vec<u8> {
+    let img = ImageBuffer::from_fn(w, h, |x, y| {
+        let v = ((x * 5 + y * 11) % 48) as u8;
+        Rgba([80 + v, 120 + v / 2, 160 + v / 3, 255])
+    });
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+async fn detect_request(body: Vec<u8>) -> ServiceResponse {
+    let app = test::init_service(actix_web::App::new().service(crate::watermark_detect)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/watermark/detect")
+        .set_payload(body)
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+
+    actix_web::test::call_service(&app, req).await
+}
+
+/// Invisible 1: every slice carries the payload and `/watermark/detect` finds it.
+#[tokio::test]
+async fn test_slice_invisible_watermark_is_detected_per_tile() {
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![("invisible", "cust-7"), ("scale", "0")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let slices = decode_slices(actix_web::test::read_body(resp).await);
+    assert_eq!(slices.len(), 4);
+
+    for (i, slice) in slices.iter().enumerate() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        slice.write_to(&mut png, image::ImageFormat::Png).unwrap();
+
+        let resp = detect_request(png.into_inner()).await;
+        assert_eq!(resp.status().as_u16(), 200);
+        let json: serde_json::Value =
+            serde_json::from_slice(&actix_web::test::read_body(resp).await).unwrap();
+        assert_eq!(json["found"], true, "slice {} should carry the payload", i);
+        assert_eq!(json["payload"], "cust-7");
+    }
+}
+
+/// Invisible 2: an unmarked image reports no payload.
+#[tokio::test]
+async fn test_detect_unmarked_image() {
+    let resp = detect_request(textured_png(120, 100)).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let json: serde_json::Value =
+        serde_json::from_slice(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(json["found"], false);
+}
+
+/// Invisible 3: tiles too small to hold the payload — should return 400.
+#[tokio::test]
+async fn test_slice_invisible_watermark_too_small() {
+    let payload = serde_json::to_vec(&serde_json::json!({
+        "image_base64": SMALL_PNG_BASE64
+    }))
+    .unwrap();
+
+    let resp = slice_request(
+        payload,
+        "application/json",
+        Some(vec![("invisible", "cust-7")]),
+    )
+    .await;
+
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+// ---------------------------------------------------------------------------
+// QR code integration tests
+// ---------------------------------------------------------------------------
+
+/// QR 1: templated QR data gives every tile its own code in the bottom-right corner.
+#[tokio::test]
+async fn test_slice_with_qr_per_tile() {
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![
+            ("qr", "tile-{index}"),
+            ("qr_size", "100"),
+            ("scale", "0"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let slices = decode_slices(actix_web::test::read_body(resp).await);
+    assert_eq!(slices.len(), 4);
+
+    // Quiet zone in the tile corner, untouched texture in the opposite one.
+    let (w, h) = slices[0].dimensions();
+    assert_eq!(
+        *slices[0].get_pixel(w - 1, h - 1),
+        Rgba([255, 255, 255, 255])
+    );
+    assert_ne!(*slices[0].get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+    assert_ne!(slices[0].as_raw(), slices[3].as_raw());
+}
+
+/// QR 2: standalone `/qr` stamps a single image; bad EC level is a 400.
+#[tokio::test]
+async fn test_qr_endpoint() {
+    let app = test::init_service(actix_web::App::new().service(crate::qr)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/qr?data=https://example.com/a&size=50&position=top-left&ec=H")
+        .set_payload(textured_png(200, 200))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(get_ct(&resp), "image/png");
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (200, 200));
+    assert_eq!(img.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+
+    let req = test::TestRequest::post()
+        .uri("/qr?data=x&ec=Z")
+        .set_payload(textured_png(200, 200))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn test_qr_endpoint_code128() {
+    let app = test::init_service(actix_web::App::new().service(crate::qr)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/qr?data=TILE-7&size=100&position=top-left&symbology=code128")
+        .set_payload(textured_png(300, 200))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    // Quiet zone first, then the start symbol's first bar.
+    assert_eq!(img.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+    let bars = (0..200)
+        .filter(|&x| img.get_pixel(x, 0) == Rgba([0, 0, 0, 255]))
+        .count();
+    assert!(bars > 0);
+
+    let req = test::TestRequest::post()
+        .uri("/qr?data=caf%C3%A9&symbology=code128")
+        .set_payload(textured_png(300, 200))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+// ---------------------------------------------------------------------------
+// Render cache integration tests
+// ---------------------------------------------------------------------------
+
+/// Cache 1: the same watermark on every tile is rendered once and then served from cache.
+#[tokio::test]
+async fn test_cache_stats_report_hits() {
+    for _ in 0..2 {
+        let resp = slice_request(
+            textured_png(120, 100),
+            "image/png",
+            Some(vec![("watermark", "cache-stats-test"), ("scale", "0")]),
+        )
+        .await;
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    let app = test::init_service(actix_web::App::new().service(crate::cache_stats)).await;
+    let req = test::TestRequest::get().uri("/cache/stats").to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let json: serde_json::Value =
+        serde_json::from_slice(&actix_web::test::read_body(resp).await).unwrap();
+    assert!(json["hits"].as_u64().unwrap() >= 1);
+    assert!(json["misses"].as_u64().unwrap() >= 1);
+    assert!(json["hit_rate"].as_f64().unwrap() > 0.0);
+}
+
+/// Cache 2: unknown font name — should return 400.
+#[tokio::test]
+async fn test_slice_with_unknown_font() {
+    let resp = slice_request(
+        textured_png(120, 100),
+        "image/png",
+        Some(vec![("watermark", "IZDU"), ("font", "no-such-font")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+// ---------------------------------------------------------------------------
+// Resize subsystem integration tests
+// ---------------------------------------------------------------------------
+
+/// Resize 1: explicit filter is honoured and the output has the requested size.
+#[tokio::test]
+async fn test_resize_with_filter() {
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "80"), ("filter", "nearest")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (80, 40));
+}
+
+/// Resize 2: unknown filter and `ignore` without both dimensions are 400s.
+#[tokio::test]
+async fn test_resize_rejects_bad_params() {
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "80"), ("filter", "bspline")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "80"), ("aspect_ratio", "ignore")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Resize 3: `fit=cover` crops to the exact box, `fit=contain` letterboxes in the background colour.
+#[tokio::test]
+async fn test_resize_fit_cover_and_contain() {
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![
+            ("width", "30"),
+            ("height", "30"),
+            ("fit", "cover"),
+            ("gravity", "east"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (30, 30));
+
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![
+            ("width", "30"),
+            ("height", "30"),
+            ("fit", "contain"),
+            ("background", "ff0000"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (30, 30));
+    assert_eq!(img.get_pixel(15, 0), image::Rgba([255, 0, 0, 255]));
+
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "30"), ("fit", "contain")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Resize 4: slice `scale` fits non-square slices without distorting them.
+#[tokio::test]
+async fn test_slice_scale_keeps_tile_aspect_ratio() {
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![("scale", "60")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+
+    let slices = decode_slices(actix_web::test::read_body(resp).await);
+    assert_eq!(slices.len(), 4);
+    for slice in &slices {
+        assert_eq!(slice.dimensions(), (60, 50));
+    }
+}
+
+/// Resize 5: per-tile targets give exact, correctly sized tiles for non-square slices.
+#[tokio::test]
+async fn test_slice_tile_size_with_fit() {
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![
+            ("tile_width", "50"),
+            ("tile_height", "70"),
+            ("fit", "cover"),
+            ("filter", "triangle"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    for slice in decode_slices(actix_web::test::read_body(resp).await) {
+        assert_eq!(slice.dimensions(), (50, 70));
+    }
+
+    // Width only keeps each 120x100 slice's aspect ratio, and may enlarge.
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![("tile_width", "180")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    for slice in decode_slices(actix_web::test::read_body(resp).await) {
+        assert_eq!(slice.dimensions(), (180, 150));
+    }
+
+    let resp = slice_request(
+        textured_png(240, 200),
+        "image/png",
+        Some(vec![("tile_width", "50"), ("fit", "fill")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Resize 6: `allow_upscale=false` keeps small sources at their size, and
+/// `X-Upscaled` reports whether the output was enlarged.
+#[tokio::test]
+async fn test_resize_upscale_policy_and_header() {
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "80"), ("upscale_quality", "high")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Upscaled").unwrap(), "true");
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (80, 40));
+
+    let resp = resize_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("width", "80"), ("allow_upscale", "false")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Upscaled").unwrap(), "false");
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (40, 20));
+
+    // Legacy slice `scale` never enlarges unless asked to.
+    let resp = slice_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("scale", "100")]),
+    )
+    .await;
+    assert_eq!(resp.headers().get("X-Upscaled").unwrap(), "false");
+    let resp = slice_request(
+        textured_png(40, 20),
+        "image/png",
+        Some(vec![("scale", "100"), ("allow_upscale", "true")]),
+    )
+    .await;
+    assert_eq!(resp.headers().get("X-Upscaled").unwrap(), "true");
+    for slice in decode_slices(actix_web::test::read_body(resp).await) {
+        assert_eq!(slice.dimensions(), (100, 50));
+    }
+}
+
+async fn variants_request(body: Vec<u8>, query: &str) -> ServiceResponse {
+    let app = test::init_service(actix_web::App::new().service(crate::variants)).await;
+
+    let req = test::TestRequest::post()
+        .uri(&format!("/variants?{}", query))
+        .set_payload(body)
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+
+    actix_web::test::call_service(&app, req).await
+}
+
+/// Variants 1: multipart response with a manifest first and one part per width and format.
+#[tokio::test]
+async fn test_variants_multipart_with_manifest() {
+    let resp = variants_request(
+        textured_png(200, 100),
+        "widths=50,100,400&formats=png,jpeg&base_url=/img/",
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let ct = get_ct(&resp);
+    let boundary = ct.split("boundary=").nth(1).unwrap().to_string();
+    assert!(ct.starts_with("multipart/mixed"));
+
+    let body = actix_web::test::read_body(resp).await;
+    let delimiter = format!("--{}", boundary);
+    let parts: Vec<&[u8]> = split_bytes(&body, delimiter.as_bytes())
+        .into_iter()
+        .filter(|p| !p.is_empty() && !p.starts_with(b"--"))
+        .collect();
+    // Manifest plus 2 widths (400 is wider than the source) x 2 formats.
+    assert_eq!(parts.len(), 5);
+
+    let content = |part: &[u8]| {
+        let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let end = if part.ends_with(b"\r\n") {
+            part.len() - 2
+        } else {
+            part.len()
+        };
+        part[start..end].to_vec()
+    };
+    let manifest: serde_json::Value = serde_json::from_slice(&content(parts[0])).unwrap();
+    assert_eq!(
+        manifest["images"][0]["srcset"]["jpeg"],
+        "/img/image-50w.jpg 50w, /img/image-100w.jpg 100w"
+    );
+    let last = image::load_from_memory(&content(parts[4])).unwrap();
+    assert_eq!(last.dimensions(), (100, 50));
+
+    let resp = variants_request(textured_png(200, 100), "formats=webp").await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+fn split_bytes<'a>(data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + delimiter.len() <= data.len() {
+        if &data[i..i + delimiter.len()] == delimiter {
+            parts.push(&data[start..i]);
+            i += delimiter.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    parts.push(&data[start..]);
+    parts
+}
+
+/// Smart crop 1: `cover` resizes and slices report their crop rectangles in `X-Crop-Rect`.
+/// Where content-aware gravities put the crop is covered by the resize unit tests.
+#[tokio::test]
+async fn test_resize_smart_crop_reports_rect() {
+    let resp = resize_request(
+        textured_png(300, 100),
+        "image/png",
+        Some(vec![
+            ("width", "50"),
+            ("height", "50"),
+            ("fit", "cover"),
+            ("gravity", "attention"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let rect: Vec<u32> = resp
+        .headers()
+        .get("X-Crop-Rect")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .split(',')
+        .map(|v| v.parse().unwrap())
+        .collect();
+    assert_eq!(rect.len(), 4);
+    assert_eq!(&rect[2..], &[100, 100]);
+
+    // No crop, no header.
+    let resp = resize_request(
+        textured_png(300, 100),
+        "image/png",
+        Some(vec![("width", "50")]),
+    )
+    .await;
+    assert!(resp.headers().get("X-Crop-Rect").is_none());
+
+    // Slices report one rectangle each.
+    let resp = slice_request(
+        textured_png(300, 100),
+        "image/png",
+        Some(vec![
+            ("tile_width", "40"),
+            ("tile_height", "40"),
+            ("fit", "cover"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let rects = resp
+        .headers()
+        .get("X-Crop-Rect")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(rects, "50,0,50,50;50,0,50,50;50,0,50,50;50,0,50,50");
+}
+
+// ---------------------------------------------------------------------------
+// Geometric transforms
+// ---------------------------------------------------------------------------
+
+async fn transform_request(uri: &str, body: Vec<u8>) -> ServiceResponse {
+    let app = test::init_service(
+        actix_web::App::new()
+            .service(crate::rotate_handler)
+            .service(crate::flip_handler)
+            .service(crate::crop_handler)
+            .service(crate::pad_handler)
+            .service(crate::trim_handler),
+    )
+    .await;
+
+    let req = test::TestRequest::post()
+        .uri(uri)
+        .set_payload(body)
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+
+    actix_web::test::call_service(&app, req).await
+}
+
+/// Transform 1: each endpoint returns a PNG of the expected size; pad then trim round-trips.
+#[tokio::test]
+async fn test_transform_endpoints() {
+    use image::GenericImageView;
+
+    let cases = [
+        ("/rotate?angle=90", (20, 40)),
+        ("/rotate?angle=30&background=ffffff", (45, 38)),
+        ("/flip?direction=vertical", (40, 20)),
+        ("/crop?x=5&y=5&width=10&height=8", (10, 8)),
+        ("/pad?padding=3&left=7&background=00ff00", (50, 26)),
+    ];
+    for (uri, dimensions) in cases {
+        let resp = transform_request(uri, textured_png(40, 20)).await;
+        assert_eq!(resp.status().as_u16(), 200, "{}", uri);
+        assert_eq!(get_ct(&resp), "image/png");
+        let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+        assert_eq!(img.dimensions(), dimensions, "{}", uri);
+    }
+
+    let resp = transform_request("/pad?padding=6&background=ffffff", textured_png(40, 20)).await;
+    let padded = actix_web::test::read_body(resp).await.to_vec();
+    let resp = transform_request("/trim", padded).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (40, 20));
+
+    for uri in [
+        "/crop?x=35&width=10&height=10",
+        "/flip?direction=sideways",
+        "/rotate?angle=10&background=nope",
+    ] {
+        let resp = transform_request(uri, textured_png(40, 20)).await;
+        assert_eq!(resp.status().as_u16(), 400, "{}", uri);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Colour adjustments
+// ---------------------------------------------------------------------------
+
+fn flat_png(w: u32, h: u32, rgba: [u8; 4]) -> Vec<u8> {
+    let img = ImageBuffer::from_pixel(w, h, Rgba(rgba));
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+    buf.into_inner()
+}
+
+/// Adjust 1: `/adjust` applies global adjustments and rejects out-of-range values.
+#[tokio::test]
+async fn test_adjust_endpoint() {
+    let app = test::init_service(actix_web::App::new().service(crate::adjust)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/adjust?grayscale=true&invert=true")
+        .set_payload(flat_png(4, 4, [255, 0, 0, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await)
+        .unwrap()
+        .to_rgba8();
+    let [r, g, b, a] = img.get_pixel(0, 0).0;
+    assert!(r == g && g == b && r > 128, "got {:?}", (r, g, b));
+    assert_eq!(a, 255);
+
+    let req = test::TestRequest::post()
+        .uri("/adjust?gamma=0")
+        .set_payload(flat_png(4, 4, [255, 0, 0, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Adjust 2: `/slice` applies global brightness to every tile and `tile_adjust` per tile.
+#[tokio::test]
+async fn test_slice_per_tile_adjustments() {
+    let resp = slice_request(
+        flat_png(40, 40, [100, 100, 100, 255]),
+        "image/png",
+        Some(vec![
+            ("brightness", "10"),
+            ("tile_adjust", "brightness:-10;;invert"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let slices = decode_slices(actix_web::test::read_body(resp).await);
+    let values: Vec<u8> = slices.iter().map(|s| s.get_pixel(5, 5)[0]).collect();
+    // Tile 0 is brightened then darkened again, give or take rounding.
+    assert!(values[0].abs_diff(100) <= 1, "tile 0 is {}", values[0]);
+    assert_eq!(&values[1..], &[126, 129, 126]);
+
+    let resp = slice_request(
+        flat_png(40, 40, [100, 100, 100, 255]),
+        "image/png",
+        Some(vec![("tile_adjust", "glow:1")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Effects 1: `/effects` pixelates, needs at least one filter, and `/slice` filters each tile.
+#[tokio::test]
+async fn test_effects_endpoint_and_slice() {
+    let app = test::init_service(actix_web::App::new().service(crate::effects_handler)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/effects?pixelate=8")
+        .set_payload(textured_png(32, 16))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await)
+        .unwrap()
+        .to_rgba8();
+    assert_eq!(img.get_pixel(0, 0), img.get_pixel(7, 7));
+    assert_eq!(img.get_pixel(8, 8), img.get_pixel(15, 15));
+
+    for uri in [
+        "/effects",
+        "/effects?blur=-1",
+        "/effects?unsharp_threshold=256",
+    ] {
+        let req = test::TestRequest::post()
+            .uri(uri)
+            .set_payload(textured_png(32, 16))
+            .insert_header((header::CONTENT_TYPE, "image/png"))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status().as_u16(), 400, "{}", uri);
+    }
+
+    // A hard edge inside each tile gets softened.
+    let img = ImageBuffer::from_fn(40, 40, |x, _| {
+        if x % 20 < 10 {
+            Rgba([0u8, 0, 0, 255])
+        } else {
+            Rgba([255u8, 255, 255, 255])
+        }
+    });
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png).unwrap();
+    let resp = slice_request(buf.into_inner(), "image/png", Some(vec![("blur", "2")])).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    for slice in decode_slices(actix_web::test::read_body(resp).await) {
+        let v = slice.get_pixel(9, 5)[0];
+        assert!(v > 0 && v < 128, "edge pixel is {}", v);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Process pipeline
+// ---------------------------------------------------------------------------
+
+async fn process_request(body: serde_json::Value) -> ServiceResponse {
+    let app = test::init_service(actix_web::App::new().service(crate::process)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/process")
+        .set_json(body)
+        .to_request();
+
+    actix_web::test::call_service(&app, req).await
+}
+
+/// Process 1: JSON steps run in order on one image; an encode step picks the format.
+#[tokio::test]
+async fn test_process_single_image() {
+    use base64::Engine;
+    use image::GenericImageView;
+
+    let image_base64 = base64::engine::general_purpose::STANDARD.encode(textured_png(80, 40));
+    let resp = process_request(serde_json::json!({
+        "image_base64": image_base64,
+        "steps": [
+            {"op": "crop", "x": 0, "y": 0, "width": 40, "height": 40},
+            {"op": "resize", "width": 20, "height": 10, "fit": "cover"},
+            {"op": "adjust", "grayscale": true},
+            {"op": "encode", "format": "jpeg", "quality": 90}
+        ]
+    }))
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(get_ct(&resp), "image/jpeg");
+    assert_eq!(resp.headers().get("X-Crop-Rect").unwrap(), "0,10,40,20");
+    let img = image::load_from_memory(&actix_web::test::read_body(resp).await).unwrap();
+    assert_eq!(img.dimensions(), (20, 10));
+
+    // Validation errors name the step before any image work happens.
+    let resp = process_request(serde_json::json!({
+        "image_base64": image_base64,
+        "steps": [{"op": "flip"}, {"op": "adjust", "gamma": 50}]
+    }))
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body = actix_web::test::read_body(resp).await;
+    assert!(body.starts_with(b"Step 1 (adjust):"), "{:?}", body);
+
+    // A JSON body that doesn't parse is reported, not treated as missing steps.
+    let app = test::init_service(actix_web::App::new().service(crate::process)).await;
+    let req = test::TestRequest::post()
+        .uri("/process")
+        .insert_header((header::CONTENT_TYPE, "application/json"))
+        .set_payload(r#"{"steps": [{"op": "flip"}"#)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body = actix_web::test::read_body(resp).await;
+    assert!(body.starts_with(b"Unrecognized JSON:"), "{:?}", body);
+}
+
+/// Process 2: steps after a slice apply to every tile, or to the one named by `tile`.
+#[tokio::test]
+async fn test_process_slice_with_tile_steps() {
+    let app = test::init_service(actix_web::App::new().service(crate::process)).await;
+    let steps =
+        r#"[{"op":"slice"},{"op":"pad","padding":2},{"op":"adjust","invert":true,"tile":3}]"#;
+    let req = test::TestRequest::post()
+        .uri(&format!(
+            "/process?steps={}",
+            steps
+                .replace('"', "%22")
+                .replace('{', "%7B")
+                .replace('}', "%7D")
+        ))
+        .set_payload(flat_png(20, 20, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let ct = get_ct(&resp);
+    assert!(ct.starts_with("multipart/mixed"));
+    let boundary = ct.split("boundary=").nth(1).unwrap().to_string();
+
+    let body = actix_web::test::read_body(resp).await;
+    let delimiter = format!("--{}", boundary);
+    let tiles: Vec<_> = split_bytes(&body, delimiter.as_bytes())
+        .into_iter()
+        .filter(|p| !p.is_empty() && !p.starts_with(b"--"))
+        .enumerate()
+        .map(|(i, part)| {
+            let name = format!("filename=\"tile-{}.png\"", i);
+            assert!(
+                part.windows(name.len()).any(|w| w == name.as_bytes()),
+                "part {} is not {}",
+                i,
+                name
+            );
+            let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            let end = if part.ends_with(b"\r\n") {
+                part.len() - 2
+            } else {
+                part.len()
+            };
+            image::load_from_memory(&part[start..end])
+                .unwrap()
+                .to_rgba8()
+        })
+        .collect();
+    assert_eq!(tiles.len(), 4);
+    for (i, tile) in tiles.iter().enumerate() {
+        assert_eq!(tile.dimensions(), (14, 14));
+        let expected = if i == 3 {
+            [245, 235, 225, 255]
+        } else {
+            [10, 20, 30, 255]
+        };
+        assert_eq!(tile.get_pixel(7, 7).0, expected, "tile {}", i);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Presets
+// ---------------------------------------------------------------------------
+
+static PRESETS: std::sync::Once = std::sync::Once::new();
+
+fn init_presets() {
+    use crate::image_processor::presets::{self, Syntax};
+    const TOML: &str = r#"
+[test-tiles]
+tile_width = 6
+tile_height = 6
+fit = "fill"
+invert = true
+overridable = ["blur"]
+"#;
+    PRESETS.call_once(|| presets::init(presets::parse(TOML, Syntax::Toml).unwrap()).unwrap());
+}
+
+/// Preset 1: `preset=` expands into its params; overriding a locked param is rejected.
+#[tokio::test]
+async fn test_slice_with_preset() {
+    init_presets();
+
+    let resp = slice_request(
+        flat_png(20, 20, [10, 20, 30, 255]),
+        "image/png",
+        Some(vec![("preset", "test-tiles")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let slices = decode_slices(actix_web::test::read_body(resp).await);
+    assert_eq!(slices.len(), 4);
+    for slice in &slices {
+        assert_eq!(slice.dimensions(), (6, 6));
+        assert_eq!(slice.get_pixel(3, 3).0, [245, 235, 225, 255]);
+    }
+
+    let resp = slice_request(
+        flat_png(20, 20, [10, 20, 30, 255]),
+        "image/png",
+        Some(vec![("preset", "test-tiles"), ("invert", "false")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body = actix_web::test::read_body(resp).await;
+    assert_eq!(body, "Preset test-tiles does not allow overriding invert");
+
+    let resp = slice_request(
+        flat_png(20, 20, [10, 20, 30, 255]),
+        "image/png",
+        Some(vec![("preset", "nope")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+// ---------------------------------------------------------------------------
+// Print posters
+// ---------------------------------------------------------------------------
+
+/// Poster 1: the page grid follows paper, orientation, margins and DPI; pages record the DPI.
+#[tokio::test]
+async fn test_poster_pages() {
+    let app = test::init_service(actix_web::App::new().service(crate::poster)).await;
+
+    // 100x50mm landscape at 127 DPI: 500x250 px pages, 50 px margins, 400x150 printable.
+    let req = test::TestRequest::post()
+        .uri("/poster?paper=100x50&orientation=landscape&dpi=127&margin=10&marks=true&labels=true")
+        .set_payload(flat_png(900, 150, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Poster-Grid").unwrap(), "3x1");
+    assert_eq!(
+        resp.headers().get("X-Poster-Orientation").unwrap(),
+        "landscape"
+    );
+    let boundary = get_ct(&resp).split("boundary=").nth(1).unwrap().to_string();
+
+    let body = actix_web::test::read_body(resp).await;
+    let delimiter = format!("--{}", boundary);
+    let pages: Vec<_> = split_bytes(&body, delimiter.as_bytes())
+        .into_iter()
+        .filter(|p| !p.is_empty() && !p.starts_with(b"--"))
+        .collect();
+    assert_eq!(pages.len(), 3);
+    for (i, part) in pages.iter().enumerate() {
+        let name = format!("filename=\"page-{}.png\"", i + 1);
+        assert!(
+            part.windows(name.len()).any(|w| w == name.as_bytes()),
+            "part {} is not {}",
+            i,
+            name
+        );
+        let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let end = if part.ends_with(b"\r\n") {
+            part.len() - 2
+        } else {
+            part.len()
+        };
+        let reader = png::Decoder::new(&part[start..end]).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (500, 250));
+        assert_eq!(reader.info().pixel_dims.unwrap().xppu, 5000);
+
+        let page = image::load_from_memory(&part[start..end])
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(page.get_pixel(60, 60).0, [10, 20, 30, 255]);
+        assert_eq!(page.get_pixel(5, 5).0, [255, 255, 255, 255]);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/poster?margin=0&marks=true")
+        .set_payload(flat_png(90, 15, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// PDF 1: `output=pdf` puts the four slices on the pages of one PDF.
+#[tokio::test]
+async fn test_slice_pdf_output() {
+    let resp = slice_request(
+        flat_png(40, 20, [10, 20, 30, 255]),
+        "image/png",
+        Some(vec![
+            ("output", "pdf"),
+            ("paper", "a5"),
+            ("marks", "true"),
+            ("captions", "true"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(get_ct(&resp), "application/pdf");
+    let disposition = resp
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert_eq!(disposition, "attachment; filename=\"image.pdf\"");
+
+    let body = actix_web::test::read_body(resp).await;
+    assert!(body.starts_with(b"%PDF-1.4"));
+    assert!(body.ends_with(b"%%EOF\n"));
+    let pages = body.windows(12).filter(|w| *w == b"/Type /Page ").count();
+    assert_eq!(pages, 4);
+    let caption = b"(Tile 3 of 4 - row 2, col 1) Tj";
+    assert!(body.windows(caption.len()).any(|w| w == caption));
+
+    let resp = slice_request(
+        flat_png(40, 20, [10, 20, 30, 255]),
+        "image/png",
+        Some(vec![("output", "tiff")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Carousel 1: a 3:1 panorama becomes three square slides with no crop
+/// needed; a fixed count crops and reports the crop.
+#[tokio::test]
+async fn test_carousel_slides() {
+    let app = test::init_service(actix_web::App::new().service(crate::carousel)).await;
+
+    let req = test::TestRequest::post()
+        .uri("/carousel?aspect=square&width=50")
+        .set_payload(flat_png(300, 100, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Carousel-Slides").unwrap(), "3");
+    assert_eq!(resp.headers().get("X-Upscaled").unwrap(), "false");
+    let boundary = get_ct(&resp).split("boundary=").nth(1).unwrap().to_string();
+
+    let body = actix_web::test::read_body(resp).await;
+    let delimiter = format!("--{}", boundary);
+    let slides: Vec<_> = split_bytes(&body, delimiter.as_bytes())
+        .into_iter()
+        .filter(|p| !p.is_empty() && !p.starts_with(b"--"))
+        .collect();
+    assert_eq!(slides.len(), 3);
+    for (i, part) in slides.iter().enumerate() {
+        let name = format!("filename=\"slide-{}.png\"", i + 1);
+        assert!(
+            part.windows(name.len()).any(|w| w == name.as_bytes()),
+            "part {} is not {}",
+            i,
+            name
+        );
+        let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let end = if part.ends_with(b"\r\n") {
+            part.len() - 2
+        } else {
+            part.len()
+        };
+        let slide = image::load_from_memory(&part[start..end])
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(slide.dimensions(), (50, 50));
+        assert_eq!(slide.get_pixel(25, 25).0, [10, 20, 30, 255]);
+    }
+
+    let req = test::TestRequest::post()
+        .uri("/carousel?aspect=square&width=50&slides=2")
+        .set_payload(flat_png(300, 100, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Carousel-Slides").unwrap(), "2");
+    assert_eq!(resp.headers().get("X-Crop-Rect").unwrap(), "50,0,200,100");
+
+    let req = test::TestRequest::post()
+        .uri("/carousel?aspect=wide")
+        .set_payload(flat_png(300, 100, [10, 20, 30, 255]))
+        .insert_header((header::CONTENT_TYPE, "image/png"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Stitch 1: `/slice?remainder=extend` then `/stitch` gives back the original
+/// odd-sized image, from the raw slice stream or from multipart parts.
+#[tokio::test]
+async fn test_stitch_reverses_slice() {
+    let original = textured_png(41, 27);
+    let resp = slice_request(
+        original.clone(),
+        "image/png",
+        Some(vec![("scale", "0"), ("remainder", "extend")]),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let sliced = actix_web::test::read_body(resp).await;
+
+    let app = test::init_service(actix_web::App::new().service(crate::stitch)).await;
+    let req = test::TestRequest::post()
+        .uri("/stitch")
+        .set_payload(sliced.clone())
+        .insert_header((header::CONTENT_TYPE, "application/octet-stream"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(get_ct(&resp), "image/png");
+    let body = actix_web::test::read_body(resp).await;
+    let stitched = image::load_from_memory(&body).unwrap().to_rgba8();
+    assert_eq!(
+        stitched,
+        image::load_from_memory(&original).unwrap().to_rgba8()
+    );
+
+    // The same tiles as multipart parts, with a bezel between the columns.
+    let boundary = "stitch-test";
+    let mut multipart = Vec::new();
+    for (i, tile) in decode_slices(sliced.clone()).into_iter().enumerate() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        tile.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        crate::image_processor::write_multipart_part(
+            &mut multipart,
+            boundary,
+            "image/png",
+            &format!("tile-{}.png", i),
+            &png.into_inner(),
+        );
+    }
+    multipart.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let req = test::TestRequest::post()
+        .uri("/stitch?bezel=4x0&background=%23ff0000")
+        .set_payload(multipart)
+        .insert_header((
+            header::CONTENT_TYPE,
+            format!("multipart/mixed; boundary={}", boundary),
+        ))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let body = actix_web::test::read_body(resp).await;
+    let stitched = image::load_from_memory(&body).unwrap().to_rgba8();
+    assert_eq!(stitched.dimensions(), (45, 27));
+    assert_eq!(stitched.get_pixel(21, 10).0, [255, 0, 0, 255]);
+
+    let req = test::TestRequest::post()
+        .uri("/stitch?grid=3x2")
+        .set_payload(sliced)
+        .insert_header((header::CONTENT_TYPE, "application/octet-stream"))
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Atlas 1: multipart sprites come back as a JSON map and a packed PNG whose
+/// frames hold the sprites; a duplicate name is a 400.
+#[tokio::test]
+async fn test_atlas_packs_sprites() {
+    let app = test::init_service(actix_web::App::new().service(crate::atlas)).await;
+    let upload = |names: &[&str]| {
+        let boundary = "atlas-test";
+        let mut body = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            let size = 8 + 8 * i as u32;
+            let png = flat_png(size, size, [i as u8 * 50, 100, 200, 255]);
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"sprite\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\n",
+                    boundary, name
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(&png);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+        test::TestRequest::post()
+            .uri("/atlas?json=phaser&pot=true")
+            .set_payload(body)
+            .insert_header((
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            ))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&app, upload(&["a.png", "b.png", "c.png"])).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    let size = resp
+        .headers()
+        .get("X-Atlas-Size")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let boundary = get_ct(&resp).split("boundary=").nth(1).unwrap().to_string();
+    let body = actix_web::test::read_body(resp).await;
+    let delimiter = format!("--{}", boundary);
+    let parts: Vec<_> = split_bytes(&body, delimiter.as_bytes())
+        .into_iter()
+        .filter(|p| !p.is_empty() && !p.starts_with(b"--"))
+        .map(|part| {
+            let start = part.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            let end = if part.ends_with(b"\r\n") {
+                part.len() - 2
+            } else {
+                part.len()
+            };
+            &part[start..end]
+        })
+        .collect();
+    assert_eq!(parts.len(), 2);
+
+    let json: serde_json::Value = serde_json::from_slice(parts[0]).unwrap();
+    let atlas = image::load_from_memory(parts[1]).unwrap().to_rgba8();
+    assert_eq!(size, format!("{}x{}", atlas.width(), atlas.height()));
+    assert!(atlas.width().is_power_of_two() && atlas.height().is_power_of_two());
+    let texture = &json["textures"][0];
+    assert_eq!(texture["image"], "atlas.png");
+    for (i, frame) in texture["frames"].as_array().unwrap().iter().enumerate() {
+        assert_eq!(frame["filename"], ["a.png", "b.png", "c.png"][i]);
+        assert_eq!(frame["frame"]["w"], 8 + 8 * i);
+        let (x, y) = (
+            frame["frame"]["x"].as_u64().unwrap(),
+            frame["frame"]["y"].as_u64().unwrap(),
+        );
+        assert_eq!(
+            atlas.get_pixel(x as u32, y as u32).0,
+            [i as u8 * 50, 100, 200, 255]
+        );
+    }
+
+    let resp = actix_web::test::call_service(&app, upload(&["a.png", "a.png"])).await;
+    assert_eq!(resp.status().as_u16(), 400);
+}
+
+/// Contact sheet 1: `/contact-sheet` lays JSON sources out in a grid with gutters,
+/// and names the failing image when one doesn't load.
+#[tokio::test]
+async fn test_contact_sheet_grid() {
+    use base64::Engine;
+    let app = test::init_service(actix_web::App::new().service(crate::contact_sheet)).await;
+    let encode = |rgba| base64::engine::general_purpose::STANDARD.encode(flat_png(40, 40, rgba));
+    let payload = serde_json::json!({
+        "images": [
+            { "image_base64": encode([255, 0, 0, 255]), "caption": "red" },
+            { "image_base64": encode([0, 255, 0, 255]) },
+            { "image_base64": encode([0, 0, 255, 255]) },
+        ]
+    });
+
+    let req = test::TestRequest::post()
+        .uri("/contact-sheet?cell_width=40&gutter=10&background=black")
+        .set_json(&payload)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 200);
+    assert_eq!(resp.headers().get("X-Sheet-Grid").unwrap(), "2x2");
+    assert_eq!(get_ct(&resp), "image/png");
+    let body = actix_web::test::read_body(resp).await;
+    let sheet = image::load_from_memory(&body).unwrap().to_rgba8();
+    // One caption makes every row 12 px taller for its strip.
+    assert_eq!(sheet.dimensions(), (10 + 2 * 50, 10 + 2 * 62));
+    assert_eq!(sheet.get_pixel(5, 5).0, [0, 0, 0, 255]);
+    assert_eq!(sheet.get_pixel(30, 30).0, [255, 0, 0, 255]);
+    assert_eq!(sheet.get_pixel(80, 30).0, [0, 255, 0, 255]);
+    assert_eq!(sheet.get_pixel(30, 92).0, [0, 0, 255, 255]);
+
+    let bad = serde_json::json!({
+        "images": [
+            { "image_base64": encode([255, 0, 0, 255]) },
+            { "image_base64": "not an image" },
+        ]
+    });
+    let req = test::TestRequest::post()
+        .uri("/contact-sheet")
+        .set_json(&bad)
+        .to_request();
+    let resp = actix_web::test::call_service(&app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+    let body = actix_web::test::read_body(resp).await;
+    assert!(String::from_utf8_lossy(&body).contains("Image 2"));
+}
+
+/// Template 3: a multipart upload's file name fills `{filename}`.
+#[tokio::test]
+async fn test_slice_filename_placeholder_uses_upload_name() {
+    let boundary = "izdu-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"wall.png\"\r\nContent-Type: image/png\r\n\r\n",
+            boundary
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(&flat_png(200, 100, [0, 0, 255, 255]));
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let content_type = format!("multipart/form-data; boundary={}", boundary);
+
+    let templated = slice_request(
+        body.clone(),
+        &content_type,
+        Some(vec![("watermark", "{filename}"), ("transparency", "0")]),
+    )
+    .await;
+    assert_eq!(templated.status().as_u16(), 200);
+    let literal = slice_request(
+        body,
+        &content_type,
+        Some(vec![("watermark", "wall.png"), ("transparency", "0")]),
+    )
+    .await;
+    assert_eq!(literal.status().as_u16(), 200);
+
+    let templated = decode_slices(actix_web::test::read_body(templated).await);
+    let literal = decode_slices(actix_web::test::read_body(literal).await);
+    assert!(slice_has_watermark(&templated[0], BLUE_PIXEL));
+    assert_eq!(templated, literal);
+}